@@ -0,0 +1,10 @@
+fn main() {
+    // The R flavour leaves R API symbols for the host R process to resolve;
+    // macOS linkers reject undefined symbols unless told otherwise.
+    if std::env::var_os("CARGO_FEATURE_R").is_some()
+        && std::env::var("CARGO_CFG_TARGET_OS").as_deref() == Ok("macos")
+    {
+        println!("cargo:rustc-cdylib-link-arg=-undefined");
+        println!("cargo:rustc-cdylib-link-arg=dynamic_lookup");
+    }
+}
@@ -0,0 +1,1659 @@
+#ifndef ULCMS_H
+#define ULCMS_H
+
+/* Generated by cbindgen from core/src/ffi/; run `make header` to refresh. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * Mass of the electron, in Da.
+ */
+#define ELECTRON_MASS 0.000548579909
+
+/**
+ * Mass difference between 13C and 12C.
+ */
+#define C13_SPACING 1.003354835
+
+#define ULCMS_PROFILE_TIC 0
+
+#define ULCMS_PROFILE_BPC 1
+
+#define ULCMS_PROFILE_BINNED 2
+
+#define ULCMS_BIN_DA 0
+
+#define ULCMS_BIN_PPM 1
+
+#define ULCMS_AGGREGATE_SUM 0
+
+#define ULCMS_AGGREGATE_MAX 1
+
+#define ULCMS_NO_SPECTRUM ~0
+
+#define ULCMS_CALIBRATION_CONSTANT 0
+
+#define ULCMS_CALIBRATION_LINEAR 1
+
+#define ULCMS_CALIBRATION_QUADRATIC 2
+
+#define ULCMS_CALIBRATION_GLOBAL 0
+
+#define ULCMS_CALIBRATION_PER_SCAN 1
+
+#define ULCMS_HIT_SPECTRUM 0
+
+#define ULCMS_HIT_REFERENCE_MZ 1
+
+#define ULCMS_HIT_OBSERVED_MZ 2
+
+#define ULCMS_HIT_INTENSITY 3
+
+#define ULCMS_HIT_PPM_BEFORE 4
+
+#define ULCMS_HIT_PPM_AFTER 5
+
+#define ULCMS_HIT_OUTLIER 6
+
+#define ULCMS_CANDIDATE_NEUTRAL_MASS 0
+
+#define ULCMS_CANDIDATE_MZ 1
+
+#define ULCMS_CANDIDATE_PPM_ERROR 2
+
+#define ULCMS_CANDIDATE_RDBE 3
+
+#define ULCMS_CANDIDATE_ISOTOPE_SCORE 4
+
+#define ULCMS_CANDIDATE_SCORE 5
+
+#define ULCMS_DIA_WINDOW_TARGET 0
+
+#define ULCMS_DIA_WINDOW_LOWER 1
+
+#define ULCMS_DIA_WINDOW_UPPER 2
+
+#define ULCMS_DIA_WINDOW_MS_LEVEL 3
+
+#define ULCMS_DIA_WINDOW_N_SPECTRA 4
+
+#define ULCMS_CHROM_PEAK_CWT 0
+
+#define ULCMS_CHROM_PEAK_MATCHED_FILTER 1
+
+#define ULCMS_FEATURE_MZ 0
+
+#define ULCMS_FEATURE_MZ_MIN 1
+
+#define ULCMS_FEATURE_MZ_MAX 2
+
+#define ULCMS_FEATURE_RT 3
+
+#define ULCMS_FEATURE_RT_MIN 4
+
+#define ULCMS_FEATURE_RT_MAX 5
+
+#define ULCMS_FEATURE_AREA 6
+
+#define ULCMS_FEATURE_HEIGHT 7
+
+#define ULCMS_FEATURE_SNR 8
+
+#define ULCMS_FEATURE_SCANS 9
+
+/**
+ * Bumped whenever an exported signature or `#[repr(C)]` layout changes.
+ * Fields added through the accessors below do not require a bump.
+ */
+#define ULCMS_ABI_VERSION 4
+
+#define ULCMS_FIELD_INDEX 0
+
+#define ULCMS_FIELD_ARRAY_LENGTH 1
+
+#define ULCMS_FIELD_MS_LEVEL 2
+
+#define ULCMS_FIELD_RT 3
+
+#define ULCMS_FIELD_SCAN_WINDOW_LOWER 4
+
+#define ULCMS_FIELD_SCAN_WINDOW_UPPER 5
+
+#define ULCMS_FIELD_TIC 6
+
+#define ULCMS_FIELD_BASE_PEAK_INTENSITY 7
+
+#define ULCMS_FIELD_BASE_PEAK_MZ 8
+
+#define ULCMS_FIELD_PRECURSOR_MZ 9
+
+#define ULCMS_FIELD_PRECURSOR_CHARGE 10
+
+#define ULCMS_FIELD_ISOLATION_WINDOW_TARGET 11
+
+#define ULCMS_FIELD_ISOLATION_WINDOW_LOWER 12
+
+#define ULCMS_FIELD_ISOLATION_WINDOW_UPPER 13
+
+#define ULCMS_FIELD_ION_MOBILITY 14
+
+#define ULCMS_FIELD_ID 100
+
+#define ULCMS_FIELD_SCAN_TYPE 101
+
+#define ULCMS_FIELD_POLARITY 102
+
+#define ULCMS_FIELD_SPECTRUM_TYPE 103
+
+#define ULCMS_FIELD_ION_MOBILITY_TYPE 104
+
+#define ULCMS_POLARITY_ANY 0
+
+#define ULCMS_POLARITY_POSITIVE 1
+
+#define ULCMS_POLARITY_NEGATIVE 2
+
+#define ULCMS_PICKER_PARABOLIC 0
+
+#define ULCMS_PICKER_GAUSSIAN 1
+
+/**
+ * Wavelet picker over scales of 1 to 8 profile points.
+ */
+#define ULCMS_PICKER_CWT 2
+
+#define ULCMS_GROUP_MZ 0
+
+#define ULCMS_GROUP_MZ_MIN 1
+
+#define ULCMS_GROUP_MZ_MAX 2
+
+#define ULCMS_GROUP_RT 3
+
+#define ULCMS_GROUP_RT_MIN 4
+
+#define ULCMS_GROUP_RT_MAX 5
+
+#define ULCMS_GROUP_N_SAMPLES 6
+
+#define ULCMS_ENTRY_PRECURSOR_MZ 0
+
+#define ULCMS_ENTRY_CHARGE 1
+
+#define ULCMS_ENTRY_N_PEAKS 2
+
+#define ULCMS_MATCH_SPECTRUM 0
+
+#define ULCMS_MATCH_RANK 1
+
+#define ULCMS_MATCH_ENTRY 2
+
+#define ULCMS_MATCH_SCORE 3
+
+#define ULCMS_MATCH_MATCHED_PEAKS 4
+
+#define ULCMS_MATCH_PRECURSOR_PPM 5
+
+#define ULCMS_MERGE_MEAN 0
+
+#define ULCMS_MERGE_SUM 1
+
+#define ULCMS_MERGED_MS_LEVEL 0
+
+#define ULCMS_MERGED_RETENTION_TIME 1
+
+#define ULCMS_MERGED_RT_MIN 2
+
+#define ULCMS_MERGED_RT_MAX 3
+
+#define ULCMS_MERGED_PRECURSOR_MZ 4
+
+#define ULCMS_MERGED_PRECURSOR_CHARGE 5
+
+#define ULCMS_MERGED_N_SPECTRA 6
+
+#define ULCMS_MERGED_N_PEAKS 7
+
+#define ULCMS_IMS_TIMS 0
+
+#define ULCMS_IMS_SINGLE_FIELD 1
+
+#define ULCMS_CURVE_LINEAR 0
+
+#define ULCMS_CURVE_QUADRATIC 1
+
+#define ULCMS_WEIGHTING_NONE 0
+
+#define ULCMS_WEIGHTING_INVERSE_X 1
+
+#define ULCMS_WEIGHTING_INVERSE_X2 2
+
+/**
+ * QC flags, or'ed together in `ULCMS_QUANT_FLAGS`.
+ */
+#define ULCMS_QC_NOT_FOUND 1
+
+#define ULCMS_QC_LOW_SIGNAL_TO_NOISE 2
+
+#define ULCMS_QC_ION_RATIO 4
+
+#define ULCMS_QC_BELOW_RANGE 8
+
+#define ULCMS_QC_ABOVE_RANGE 16
+
+#define ULCMS_QC_NO_CALIBRATION 32
+
+#define ULCMS_QC_ACCURACY 64
+
+#define ULCMS_QC_INTERNAL_STANDARD 128
+
+#define ULCMS_SAMPLE_UNKNOWN 0
+
+#define ULCMS_SAMPLE_STANDARD 1
+
+#define ULCMS_SAMPLE_QC 2
+
+#define ULCMS_SAMPLE_BLANK 3
+
+#define ULCMS_QUANT_SAMPLE 0
+
+#define ULCMS_QUANT_COMPOUND 1
+
+#define ULCMS_QUANT_SAMPLE_TYPE 2
+
+#define ULCMS_QUANT_RT 3
+
+#define ULCMS_QUANT_RT_START 4
+
+#define ULCMS_QUANT_RT_END 5
+
+#define ULCMS_QUANT_AREA 6
+
+#define ULCMS_QUANT_HEIGHT 7
+
+#define ULCMS_QUANT_SIGNAL_TO_NOISE 8
+
+#define ULCMS_QUANT_INTERNAL_STANDARD_AREA 9
+
+#define ULCMS_QUANT_RESPONSE 10
+
+#define ULCMS_QUANT_ION_RATIO 11
+
+#define ULCMS_QUANT_CONCENTRATION 12
+
+#define ULCMS_QUANT_NOMINAL 13
+
+#define ULCMS_QUANT_ACCURACY 14
+
+#define ULCMS_QUANT_FLAGS 15
+
+#define ULCMS_ARRAY_NONE 0
+
+#define ULCMS_ARRAY_F32 1
+
+#define ULCMS_ARRAY_F64 2
+
+#define ULCMS_SMOOTH_MOVING_AVERAGE 0
+
+#define ULCMS_SMOOTH_GAUSSIAN 1
+
+#define ULCMS_SMOOTH_SAVITZKY_GOLAY 2
+
+#define ULCMS_BASELINE_ALS 0
+
+#define ULCMS_BASELINE_SNIP 1
+
+#define ULCMS_BASELINE_ROLLING_MIN 2
+
+#define ULCMS_NOISE_MAD 0
+
+#define ULCMS_NOISE_DIFF_MAD 1
+
+#define ULCMS_NOISE_PERCENTILE 2
+
+#define ULCMS_SIMILARITY_COSINE 0
+
+#define ULCMS_SIMILARITY_MODIFIED_COSINE 1
+
+#define ULCMS_SIMILARITY_ENTROPY 2
+
+#define ULCMS_SIMILARITY_WEIGHTED_ENTROPY 3
+
+#define ULCMS_TRANSFORM_NONE 0
+
+#define ULCMS_TRANSFORM_SQRT 1
+
+#define ULCMS_TRANSFORM_LOG 2
+
+/**
+ * Label of spectra left out of clustering.
+ */
+#define ULCMS_NO_CLUSTER ~0
+
+/**
+ * Mass of N2, in Da.
+ */
+#define N2_MASS 28.0134
+
+/**
+ * Mass of helium, in Da.
+ */
+#define HE_MASS 4.002602
+
+/**
+ * QC flags of a [`QuantResult`], or'ed together.
+ */
+#define QC_NOT_FOUND 1
+
+#define QC_LOW_SIGNAL_TO_NOISE (1 << 1)
+
+#define QC_ION_RATIO (1 << 2)
+
+#define QC_BELOW_RANGE (1 << 3)
+
+#define QC_ABOVE_RANGE (1 << 4)
+
+#define QC_NO_CALIBRATION (1 << 5)
+
+#define QC_ACCURACY (1 << 6)
+
+#define QC_INTERNAL_STANDARD (1 << 7)
+
+/**
+ * Scale factor that makes the MAD a consistent estimator of the standard
+ * deviation for normally distributed data.
+ */
+#define MAD_NORMAL_SCALE 1.482602218505602
+
+/**
+ * Opaque binned intensity matrix; see `BinnedMatrix`.
+ */
+typedef struct UlcmsBinnedMatrix UlcmsBinnedMatrix;
+
+/**
+ * Opaque fitted recalibration; see `Calibration`.
+ */
+typedef struct UlcmsCalibration UlcmsCalibration;
+
+/**
+ * Opaque isolation windows and cycles of a run; see `DiaCycles`.
+ */
+typedef struct UlcmsDia UlcmsDia;
+
+/**
+ * Opaque feature groups x samples table of areas; see `FeatureTable`.
+ */
+typedef struct UlcmsFeatureTable UlcmsFeatureTable;
+
+/**
+ * Opaque feature table, read column-wise with [`ulcms_features_column`] or
+ * cell-wise with [`ulcms_feature_get_f64`].
+ */
+typedef struct UlcmsFeatures UlcmsFeatures;
+
+/**
+ * Opaque handle over a parsed mzML file. Spectrum fields are read through
+ * accessors, so adding fields never changes a layout wrappers depend on.
+ */
+typedef struct UlcmsFile UlcmsFile;
+
+/**
+ * Opaque ranked formula candidates; see `FormulaCandidate`.
+ */
+typedef struct UlcmsFormulaCandidates UlcmsFormulaCandidates;
+
+/**
+ * Opaque list of fragment XICs; see `FragmentXic`.
+ */
+typedef struct UlcmsFragmentXics UlcmsFragmentXics;
+
+/**
+ * Opaque ion mobility frames of a run; see `Frame`.
+ */
+typedef struct UlcmsFrames UlcmsFrames;
+
+/**
+ * Opaque in-memory spectral library; see `SpectralLibrary`.
+ */
+typedef struct UlcmsLibrary UlcmsLibrary;
+
+/**
+ * Opaque library search result, one row per hit; see `LibraryMatch`.
+ */
+typedef struct UlcmsLibraryMatches UlcmsLibraryMatches;
+
+/**
+ * Opaque list of consensus spectra; see `MergedSpectrum`.
+ */
+typedef struct UlcmsMerged UlcmsMerged;
+
+/**
+ * Opaque extracted ion mobilogram; see `Mobilogram`.
+ */
+typedef struct UlcmsMobilogram UlcmsMobilogram;
+
+/**
+ * Opaque concentration table; see `QuantTable`.
+ */
+typedef struct UlcmsQuant UlcmsQuant;
+
+/**
+ * Opaque handle for decoding spectrum arrays straight into buffers the
+ * caller allocated (e.g. R or NumPy numeric vectors).
+ */
+typedef struct UlcmsReader UlcmsReader;
+
+/**
+ * Opaque push parser handle; see [`SpectrumStream`].
+ */
+typedef struct UlcmsStream UlcmsStream;
+
+/**
+ * Opaque set of retention time warps, one per aligned run, in the order
+ * the runs were given.
+ */
+typedef struct UlcmsWarps UlcmsWarps;
+
+/**
+ * Mirrors `LoessOptions`; start from [`ulcms_loess_options_default`].
+ */
+typedef struct {
+  double ppm;
+  double rt_window;
+  double span;
+  size_t min_anchors;
+} UlcmsLoessOptions;
+
+/**
+ * Mirrors `DtwOptions`; start from [`ulcms_dtw_options_default`].
+ * `profile` is a `ULCMS_PROFILE_*` constant; `mz_bin` only applies to
+ * `ULCMS_PROFILE_BINNED`.
+ */
+typedef struct {
+  uint32_t profile;
+  double mz_bin;
+  double max_shift;
+  double gap_penalty;
+} UlcmsDtwOptions;
+
+/**
+ * Mirrors `BinningOptions`; start from [`ulcms_binning_options_default`].
+ * `bin_width` is in Da or ppm as `bin_unit` says (`ULCMS_BIN_*`),
+ * `aggregation` is a `ULCMS_AGGREGATE_*` constant and `polarity` a
+ * `ULCMS_POLARITY_*` one. NaN range limits and a 0 `ms_level` are unset.
+ */
+typedef struct {
+  double bin_width;
+  uint32_t bin_unit;
+  double mz_min;
+  double mz_max;
+  uint32_t aggregation;
+  double rt_step;
+  double rt_min;
+  double rt_max;
+  uint32_t ms_level;
+  uint32_t polarity;
+} UlcmsBinningOptions;
+
+/**
+ * Mirrors `CalibrationOptions` less the references, which are passed to
+ * [`ulcms_calibrate`]; start from [`ulcms_calibration_options_default`].
+ * `model` is a `ULCMS_CALIBRATION_CONSTANT/LINEAR/QUADRATIC` constant and
+ * `scope` `ULCMS_CALIBRATION_GLOBAL/PER_SCAN`.
+ */
+typedef struct {
+  double ppm;
+  double min_intensity;
+  uint32_t model;
+  uint32_t scope;
+  double rt_window;
+} UlcmsCalibrationOptions;
+
+/**
+ * Mirrors `FormulaSearchOptions`; start from
+ * [`ulcms_formula_search_options_default`]. `adduct` (such as `[M+H]+`)
+ * and `elements` (such as `C0-80H0-150N0-10O0-20`) fall back to the
+ * defaults when null.
+ */
+typedef struct {
+  double ppm;
+  const char *adduct;
+  const char *elements;
+  int golden_rules;
+  size_t max_candidates;
+} UlcmsFormulaSearchOptions;
+
+/**
+ * Mirrors `FeatureOptions`; start from [`ulcms_feature_options_default`].
+ * `method` is a `ULCMS_CHROM_PEAK_*` constant and peak widths are in minutes.
+ */
+typedef struct {
+  double ppm;
+  size_t min_scans;
+  size_t max_gap;
+  double min_intensity;
+  double peak_width_min;
+  double peak_width_max;
+  double snr;
+  uint32_t method;
+} UlcmsFeatureOptions;
+
+/**
+ * Spectrum filters for [`ulcms_file_open_with`]; start from
+ * [`ulcms_parse_options_default`]. `ms_levels` points to `n_ms_levels`
+ * levels to keep (all when 0), NaN retention time limits are unset and
+ * `polarity` is a `ULCMS_POLARITY_*` constant. Skipped spectra are never
+ * decoded.
+ */
+typedef struct {
+  const uint32_t *ms_levels;
+  size_t n_ms_levels;
+  double rt_min;
+  double rt_max;
+  uint32_t polarity;
+} UlcmsParseOptions;
+
+/**
+ * A numeric array in its native precision. `element_type` is one of the
+ * `ULCMS_ARRAY_*` constants and tells how to read `data`.
+ */
+typedef struct {
+  void *data;
+  size_t len;
+  uint32_t element_type;
+} ArrayFFI;
+
+/**
+ * Mirrors `GroupingOptions`; start from [`ulcms_grouping_options_default`].
+ */
+typedef struct {
+  double ppm;
+  double bandwidth;
+  double min_fraction;
+  size_t min_samples;
+} UlcmsGroupingOptions;
+
+/**
+ * Mirrors `LibraryOptions`; start from [`ulcms_library_options_default`].
+ * `transform` is a `ULCMS_TRANSFORM_*` constant.
+ */
+typedef struct {
+  double noise;
+  size_t top_n;
+  uint32_t transform;
+  int fragment_index;
+  double fragment_bin;
+} UlcmsLibraryOptions;
+
+/**
+ * Mirrors `SimilarityOptions` and the `FilterOptions` applied to both
+ * spectra; start from [`ulcms_similarity_options_default`]. `metric` is a
+ * `ULCMS_SIMILARITY_*` and `transform` a `ULCMS_TRANSFORM_*` constant.
+ */
+typedef struct {
+  uint32_t metric;
+  double mz_tolerance;
+  double ppm;
+  double noise;
+  size_t top_n;
+  uint32_t transform;
+} UlcmsSimilarityOptions;
+
+/**
+ * Mirrors `SearchOptions`; start from [`ulcms_search_options_default`].
+ * The filter fields of `similarity` are ignored: queries are filtered like
+ * the library.
+ */
+typedef struct {
+  UlcmsSimilarityOptions similarity;
+  double precursor_ppm;
+  double min_score;
+  size_t min_matched_peaks;
+  size_t top_n;
+  size_t min_shared_fragments;
+} UlcmsSearchOptions;
+
+/**
+ * Mirrors `MergeOptions`; start from [`ulcms_merge_options_default`].
+ * `intensity` is a `ULCMS_MERGE_*` constant.
+ */
+typedef struct {
+  double ppm;
+  double mz_tolerance;
+  uint32_t intensity;
+  double min_occurrence;
+} UlcmsMergeOptions;
+
+/**
+ * Mirrors `MobilogramOptions`; start from
+ * [`ulcms_mobilogram_options_default`]. NaN range limits and a 0
+ * `ms_level` are unset.
+ */
+typedef struct {
+  double rt_min;
+  double rt_max;
+  uint32_t ms_level;
+  double bin_width;
+} UlcmsMobilogramOptions;
+
+/**
+ * Mirrors `CcsOptions`; start from [`ulcms_ccs_options_default`].
+ * `method` is a `ULCMS_IMS_*` constant.
+ */
+typedef struct {
+  uint32_t method;
+  double gas_mass;
+  double temperature;
+} UlcmsCcsOptions;
+
+/**
+ * Mirrors `CcsCalibration`; filled by [`ulcms_ccs_calibrate`] or
+ * [`ulcms_ccs_uncalibrated`].
+ */
+typedef struct {
+  UlcmsCcsOptions options;
+  double intercept;
+  double slope;
+  double r_squared;
+} UlcmsCcsCalibration;
+
+/**
+ * Mirrors `QuantOptions`; start from [`ulcms_quant_options_default`].
+ * `model` is a `ULCMS_CURVE_*` constant and `weighting` a
+ * `ULCMS_WEIGHTING_*` one.
+ */
+typedef struct {
+  double mz_tolerance;
+  double ppm;
+  double rt_window;
+  double min_signal_to_noise;
+  double ratio_tolerance;
+  uint32_t model;
+  uint32_t weighting;
+  double accuracy_tolerance;
+} UlcmsQuantOptions;
+
+typedef struct {
+  size_t index;
+  char *id;
+  size_t array_length;
+  uint32_t ms_level;
+  char *scan_type;
+  char *polarity;
+  char *spectrum_type;
+  double retention_time;
+  double scan_window_lower_limit;
+  double scan_window_upper_limit;
+  double total_ion_current;
+  double base_peak_intensity;
+  double base_peak_mz;
+  double precursor_mz;
+  uint32_t precursor_charge;
+  double isolation_window_target;
+  double isolation_window_lower;
+  double isolation_window_upper;
+  double ion_mobility;
+  char *ion_mobility_type;
+  ArrayFFI mz_array;
+  ArrayFFI intensity_array;
+  ArrayFFI mobility_array;
+} SpectrumSummaryFFI;
+
+/**
+ * Mirrors `Smoother`; start from [`ulcms_smooth_options_default`]. `width`
+ * is the half width, or sigma for `ULCMS_SMOOTH_GAUSSIAN`, in units of `x`
+ * or in points without it; `order` is used by Savitzky-Golay only.
+ */
+typedef struct {
+  uint32_t method;
+  double width;
+  uint32_t order;
+} UlcmsSmoothOptions;
+
+/**
+ * Mirrors `Baseline`; start from [`ulcms_baseline_options_default`].
+ * `lambda`, `p` and `iterations` are used by ALS, `width` (a half width)
+ * by the others.
+ */
+typedef struct {
+  uint32_t method;
+  double lambda;
+  double p;
+  size_t iterations;
+  double width;
+} UlcmsBaselineOptions;
+
+/**
+ * A peak list passed in by the caller. `precursor_mz` is NaN when unknown.
+ */
+typedef struct {
+  const double *mz;
+  const double *intensity;
+  size_t len;
+  double precursor_mz;
+} UlcmsPeakList;
+
+/**
+ * Mirrors `ClusterOptions`; start from [`ulcms_cluster_options_default`].
+ */
+typedef struct {
+  UlcmsSimilarityOptions similarity;
+  double min_score;
+  double precursor_tolerance;
+  size_t min_matched_peaks;
+} UlcmsClusterOptions;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+UlcmsLoessOptions ulcms_loess_options_default(void);
+
+UlcmsDtwOptions ulcms_dtw_options_default(void);
+
+/**
+ * Aligns each of the `n_runs` feature tables in `runs` to
+ * `runs[reference]` by LOESS on anchor features. Runs with too few anchors
+ * get the identity warp.
+ */
+int ulcms_align_features(const UlcmsFeatures *const *runs,
+                         size_t n_runs,
+                         size_t reference,
+                         const UlcmsLoessOptions *opts,
+                         UlcmsWarps **out);
+
+/**
+ * Aligns each of the `n_runs` files in `runs` to `runs[reference]` by
+ * dynamic time warping of their MS1 profiles. Fails with 4 when some run
+ * cannot be aligned within `max_shift`.
+ */
+int ulcms_align_profiles(const UlcmsFile *const *runs,
+                         size_t n_runs,
+                         size_t reference,
+                         const UlcmsDtwOptions *opts,
+                         UlcmsWarps **out);
+
+size_t ulcms_warps_count(const UlcmsWarps *warps);
+
+/**
+ * Number of knots of the warp of `run`; 0 for the identity.
+ */
+size_t ulcms_warp_knot_count(const UlcmsWarps *warps, size_t run);
+
+/**
+ * Copies the first `cap` knots of the warp of `run` into `raw` and
+ * `aligned` and returns how many were written. Together the knots define
+ * the warp: linear in between, constant offset beyond either end.
+ */
+size_t ulcms_warp_knots(const UlcmsWarps *warps,
+                        size_t run,
+                        double *raw,
+                        double *aligned,
+                        size_t cap);
+
+/**
+ * Aligned retention time of `rt` in `run`; NaN when `run` is out of range.
+ */
+double ulcms_warp_apply(const UlcmsWarps *warps, size_t run, double rt);
+
+/**
+ * Rewrites the spectrum retention times of `file` with the warp of `run`.
+ */
+int ulcms_file_apply_warp(UlcmsFile *file, const UlcmsWarps *warps, size_t run);
+
+/**
+ * Rewrites the retention times of `features` with the warp of `run`.
+ */
+int ulcms_features_apply_warp(UlcmsFeatures *features, const UlcmsWarps *warps, size_t run);
+
+void ulcms_warps_free(UlcmsWarps *warps);
+
+UlcmsBinningOptions ulcms_binning_options_default(void);
+
+/**
+ * Bins the spectra of `file` into a retention time by m/z matrix.
+ */
+int ulcms_bin_spectra(const UlcmsFile *file,
+                      const UlcmsBinningOptions *opts,
+                      UlcmsBinnedMatrix **out);
+
+/**
+ * Number of rows, columns and stored values of `matrix`.
+ */
+int ulcms_binned_shape(const UlcmsBinnedMatrix *matrix,
+                       size_t *n_rows,
+                       size_t *n_cols,
+                       size_t *nnz);
+
+/**
+ * Borrows the axes of `matrix`: `n_rows` retention times and
+ * `n_cols + 1` m/z bin edges. Valid until [`ulcms_binned_free`].
+ */
+int ulcms_binned_axes(const UlcmsBinnedMatrix *matrix, const double **rt, const double **mz_edges);
+
+/**
+ * Source spectrum index of row `row`, or `ULCMS_NO_SPECTRUM` on a
+ * resampled grid or out of range.
+ */
+size_t ulcms_binned_row_spectrum(const UlcmsBinnedMatrix *matrix, size_t row);
+
+/**
+ * Borrows the CSR arrays of `matrix`: `n_rows + 1` row pointers and `nnz`
+ * column indices and values. Valid until [`ulcms_binned_free`].
+ */
+int ulcms_binned_csr(const UlcmsBinnedMatrix *matrix,
+                     const size_t **indptr,
+                     const uint32_t **indices,
+                     const float **values);
+
+/**
+ * Writes `matrix` densely into `out`, which holds `cap` values and needs
+ * `n_rows * n_cols` of them (4 otherwise, or when that overflows):
+ * row-major, or column-major as R matrices are when `column_major` is set.
+ */
+int ulcms_binned_to_dense(const UlcmsBinnedMatrix *matrix,
+                          bool column_major,
+                          float *out,
+                          size_t cap);
+
+void ulcms_binned_free(UlcmsBinnedMatrix *matrix);
+
+UlcmsCalibrationOptions ulcms_calibration_options_default(void);
+
+/**
+ * Fits an m/z correction for `file` from the `n_references` reference m/z
+ * in `references`. Returns 4 when none is found.
+ */
+int ulcms_calibrate(const UlcmsFile *file,
+                    const double *references,
+                    size_t n_references,
+                    const UlcmsCalibrationOptions *opts,
+                    UlcmsCalibration **out);
+
+size_t ulcms_calibration_hits_count(const UlcmsCalibration *calibration);
+
+/**
+ * Field `field` (a `ULCMS_HIT_*` constant) of reference hit `index`; NaN
+ * when out of range. `ULCMS_HIT_OUTLIER` is 1 or 0.
+ */
+double ulcms_calibration_hit_get_f64(const UlcmsCalibration *calibration,
+                                     size_t index,
+                                     uint32_t field);
+
+/**
+ * Median absolute ppm error of the reference hits, outliers aside, before
+ * and after correction.
+ */
+int ulcms_calibration_summary(const UlcmsCalibration *calibration, double *before, double *after);
+
+/**
+ * Fitted ppm error of spectrum `spectrum` at `mz`.
+ */
+double ulcms_calibration_ppm_error(const UlcmsCalibration *calibration, size_t spectrum, double mz);
+
+/**
+ * Rewrites the m/z arrays, precursor and base peak m/z of `file`, which
+ * must be the file the calibration was fitted on.
+ */
+int ulcms_calibration_apply(const UlcmsCalibration *calibration, UlcmsFile *file);
+
+void ulcms_calibration_free(UlcmsCalibration *calibration);
+
+/**
+ * Writes `file`, parsed from the mzML at `source_path`, to `out_path`
+ * with its current arrays, e.g. after [`ulcms_calibration_apply`].
+ */
+int ulcms_write_mzml(const char *source_path, const UlcmsFile *file, const char *out_path);
+
+/**
+ * Monoisotopic and average mass of a formula such as `C6H12O6` or
+ * `C6H13O6+`; see `Formula`. Either output may be null.
+ */
+int ulcms_formula_mass(const char *formula, double *monoisotopic, double *average);
+
+/**
+ * m/z of the ion `adduct` (such as `[M+H]+`) of a molecule of neutral
+ * monoisotopic mass `neutral_mass`.
+ */
+int ulcms_adduct_mz(const char *adduct, double neutral_mass, double *out);
+
+/**
+ * Isotope distribution of `formula` in ascending mass order (m/z when the
+ * formula is charged). Peaks are merged by nominal mass unless `fine` is
+ * non-zero, and those below `min_abundance` dropped. The first `cap` peaks
+ * are written to `masses` and `abundances`, and the total number to
+ * `n_peaks`, so a call with `cap` 0 sizes the buffers.
+ */
+int ulcms_isotope_distribution(const char *formula,
+                               int fine,
+                               double min_abundance,
+                               double *masses,
+                               double *abundances,
+                               size_t cap,
+                               size_t *n_peaks);
+
+UlcmsFormulaSearchOptions ulcms_formula_search_options_default(void);
+
+/**
+ * Candidate formulas for an ion observed at `mz`, best first. `iso_mz` and
+ * `iso_intensity` hold `n_isotopes` observed isotope peaks, monoisotopic
+ * first, and may be null when `n_isotopes` is 0.
+ */
+int ulcms_generate_formulas(double mz,
+                            const double *iso_mz,
+                            const double *iso_intensity,
+                            size_t n_isotopes,
+                            const UlcmsFormulaSearchOptions *opts,
+                            UlcmsFormulaCandidates **out);
+
+size_t ulcms_formula_candidates_count(const UlcmsFormulaCandidates *candidates);
+
+/**
+ * Field `field` (a `ULCMS_CANDIDATE_*` constant) of candidate `index`;
+ * NaN when either is out of range.
+ */
+double ulcms_formula_candidate_get_f64(const UlcmsFormulaCandidates *candidates,
+                                       size_t index,
+                                       uint32_t field);
+
+/**
+ * Neutral formula of candidate `index`, in Hill order, as a borrowed,
+ * non-terminated UTF-8 slice valid until the handle is freed. Returns 3
+ * when the index is out of range.
+ */
+int ulcms_formula_candidate_formula(const UlcmsFormulaCandidates *candidates,
+                                    size_t index,
+                                    const uint8_t **out_ptr,
+                                    size_t *out_len);
+
+void ulcms_formula_candidates_free(UlcmsFormulaCandidates *candidates);
+
+/**
+ * Groups the MSn spectra of `file` by isolation window, with bounds
+ * matching within `tolerance` m/z, and lays the run out in cycles.
+ */
+int ulcms_dia_cycles(const UlcmsFile *file, double tolerance, UlcmsDia **out);
+
+/**
+ * Number of isolation windows and of cycles, and the median cycle time in
+ * minutes (NaN with fewer than two cycles).
+ */
+int ulcms_dia_shape(const UlcmsDia *dia, size_t *n_windows, size_t *n_cycles, double *cycle_time);
+
+/**
+ * Field `field` (a `ULCMS_DIA_WINDOW_*` constant) of window `index`,
+ * windows being ordered by lower bound; NaN when missing or out of range.
+ */
+double ulcms_dia_window_get_f64(const UlcmsDia *dia, size_t index, uint32_t field);
+
+/**
+ * Borrows the indices of the spectra of window `index`, by retention
+ * time. Valid until [`ulcms_dia_free`].
+ */
+int ulcms_dia_window_spectra(const UlcmsDia *dia,
+                             size_t index,
+                             const size_t **spectra,
+                             size_t *len);
+
+/**
+ * Cycle `index`: the MS1 scan opening it (`ULCMS_NO_SPECTRUM` for none),
+ * its retention time, and a borrowed array of `n_windows` spectrum
+ * indices, one per window with `ULCMS_NO_SPECTRUM` for windows not
+ * acquired in it. Valid until [`ulcms_dia_free`].
+ */
+int ulcms_dia_cycle(const UlcmsDia *dia,
+                    size_t index,
+                    size_t *ms1,
+                    double *rt,
+                    const size_t **spectra);
+
+void ulcms_dia_free(UlcmsDia *dia);
+
+/**
+ * XICs of `n` precursor/fragment pairs over the spectra of `file` in the
+ * window of `dia` isolating each precursor, within `ppm` of the fragment
+ * and retention times in `[rt_min, rt_max]` (NaN for no limit). `dia` must
+ * have been built from `file`.
+ */
+int ulcms_dia_fragment_xics(const UlcmsFile *file,
+                            const UlcmsDia *dia,
+                            const double *precursor_mz,
+                            const double *fragment_mz,
+                            size_t n,
+                            double ppm,
+                            double rt_min,
+                            double rt_max,
+                            UlcmsFragmentXics **out);
+
+/**
+ * XIC `index`: the window it was taken from (`ULCMS_NO_SPECTRUM` when no
+ * window isolates the precursor) and borrowed arrays of `len` retention
+ * times and intensities. Valid until [`ulcms_fragment_xics_free`].
+ */
+int ulcms_fragment_xic(const UlcmsFragmentXics *xics,
+                       size_t index,
+                       size_t *window,
+                       const double **rt,
+                       const double **intensity,
+                       size_t *len);
+
+void ulcms_fragment_xics_free(UlcmsFragmentXics *xics);
+
+UlcmsFeatureOptions ulcms_feature_options_default(void);
+
+/**
+ * Detects chromatographic features in the MS1 spectra of `file`.
+ */
+int ulcms_find_features(const UlcmsFile *file,
+                        const UlcmsFeatureOptions *opts,
+                        UlcmsFeatures **out);
+
+size_t ulcms_features_count(const UlcmsFeatures *features);
+
+/**
+ * Field `field` (a `ULCMS_FEATURE_*` constant) of feature `index`; NaN
+ * when either is out of range.
+ */
+double ulcms_feature_get_f64(const UlcmsFeatures *features, size_t index, uint32_t field);
+
+/**
+ * Copies column `field` of the first `cap` features into `out` and returns
+ * how many values were written.
+ */
+size_t ulcms_features_column(const UlcmsFeatures *features,
+                             uint32_t field,
+                             double *out,
+                             size_t cap);
+
+void ulcms_features_free(UlcmsFeatures *features);
+
+uint32_t ulcms_abi_version(void);
+
+int ulcms_file_open(const char *path, UlcmsFile **out);
+
+UlcmsParseOptions ulcms_parse_options_default(void);
+
+/**
+ * [`ulcms_file_open`] keeping only the spectra `opts` selects. Returns 3
+ * for an unknown polarity.
+ */
+int ulcms_file_open_with(const char *path, const UlcmsParseOptions *opts, UlcmsFile **out);
+
+int ulcms_file_open_from_bytes(const uint8_t *data_ptr, size_t data_len, UlcmsFile **out);
+
+void ulcms_file_close(UlcmsFile *file);
+
+size_t ulcms_spectrum_count(const UlcmsFile *file);
+
+/**
+ * Numeric field `field` (a `ULCMS_FIELD_*` constant) of spectrum `index`.
+ * Returns NaN when the spectrum, the field or its value is missing.
+ */
+double ulcms_spectrum_get_f64(const UlcmsFile *file, size_t index, uint32_t field);
+
+/**
+ * String field `field` of spectrum `index` as a borrowed, non-terminated
+ * UTF-8 slice valid until the file is closed. Returns 3 when the spectrum
+ * or field is unknown, and a null pointer when the value is missing.
+ */
+int ulcms_spectrum_get_str(const UlcmsFile *file,
+                           size_t index,
+                           uint32_t field,
+                           const uint8_t **out_ptr,
+                           size_t *out_len);
+
+/**
+ * Borrows the m/z array of spectrum `index` as doubles; valid until the
+ * file is closed or its arrays are rewritten. An array stored as f32 is
+ * copied once into a cache owned by the file, leaving the stored array and
+ * views of it untouched. A missing array yields a null pointer and length 0.
+ */
+int ulcms_spectrum_mz(const UlcmsFile *file, size_t index, const double **out_ptr, size_t *out_len);
+
+/**
+ * Intensity counterpart of [`ulcms_spectrum_mz`].
+ */
+int ulcms_spectrum_intensity(const UlcmsFile *file,
+                             size_t index,
+                             const double **out_ptr,
+                             size_t *out_len);
+
+/**
+ * Borrows one array of spectrum `index` in its stored precision. `which`
+ * is 0 for m/z, 1 for intensity and 2 for per-peak ion mobility; `data`
+ * in `out` is owned by the file.
+ */
+int ulcms_spectrum_array(const UlcmsFile *file, size_t index, uint32_t which, ArrayFFI *out);
+
+/**
+ * Data pointer of one array of spectrum `index` (`which` as in
+ * [`ulcms_spectrum_array`]), or null when missing. These three accessors
+ * return plain scalars for hosts, such as wasm, where out-pointers are
+ * awkward.
+ */
+const void *ulcms_spectrum_array_data(const UlcmsFile *file, size_t index, uint32_t which);
+
+size_t ulcms_spectrum_array_len(const UlcmsFile *file, size_t index, uint32_t which);
+
+/**
+ * `ULCMS_ARRAY_*` element type of one array of spectrum `index`.
+ */
+uint32_t ulcms_spectrum_array_type(const UlcmsFile *file, size_t index, uint32_t which);
+
+/**
+ * Centroids every profile spectrum of `file` in place with the picker
+ * `picker` (a `ULCMS_PICKER_*` constant); see `PeakPickingOptions` for
+ * `snr` and `min_width`. Array pointers obtained earlier are invalidated.
+ */
+int ulcms_file_centroid(UlcmsFile *file, uint32_t picker, double snr, size_t min_width);
+
+UlcmsGroupingOptions ulcms_grouping_options_default(void);
+
+/**
+ * Groups the feature tables of `n_samples` samples, given in sample order.
+ */
+int ulcms_group_features(const UlcmsFeatures *const *samples,
+                         size_t n_samples,
+                         const UlcmsGroupingOptions *opts,
+                         UlcmsFeatureTable **out);
+
+size_t ulcms_feature_table_groups(const UlcmsFeatureTable *table);
+
+size_t ulcms_feature_table_samples(const UlcmsFeatureTable *table);
+
+/**
+ * Field `field` (a `ULCMS_GROUP_*` constant) of group `group`; NaN when
+ * either is out of range.
+ */
+double ulcms_feature_group_get_f64(const UlcmsFeatureTable *table, size_t group, uint32_t field);
+
+/**
+ * Area of group `group` in sample `sample`; NaN when missing.
+ */
+double ulcms_feature_table_area(const UlcmsFeatureTable *table, size_t group, size_t sample);
+
+/**
+ * 1 if the area of `group` in `sample` came from gap filling, else 0.
+ */
+int ulcms_feature_table_is_filled(const UlcmsFeatureTable *table, size_t group, size_t sample);
+
+/**
+ * Copies up to `cap` areas, row-major groups x samples, into `out` and
+ * returns how many were written.
+ */
+size_t ulcms_feature_table_areas(const UlcmsFeatureTable *table, double *out, size_t cap);
+
+/**
+ * Fills the missing areas of `sample` from the raw spectra in `file`,
+ * whose retention times must already be aligned (see
+ * `ulcms_file_apply_warp`). The m/z window of each group is widened by
+ * `ppm`. Writes the number of filled cells to `filled` when non-null.
+ */
+int ulcms_feature_table_fill_gaps(UlcmsFeatureTable *table,
+                                  size_t sample,
+                                  const UlcmsFile *file,
+                                  double ppm,
+                                  size_t *filled);
+
+/**
+ * Writes the table as CSV to `path`. `sample_names` may be null, or hold
+ * one UTF-8 name per sample for the area column headers.
+ */
+int ulcms_feature_table_write_csv(const UlcmsFeatureTable *table,
+                                  const char *path,
+                                  const char *const *sample_names);
+
+#if defined(ULCMS_PARQUET)
+/**
+ * Writes the table as Parquet to `path`, with the columns and
+ * `sample_names` of [`ulcms_feature_table_write_csv`]. Only built with
+ * the `parquet` cargo feature.
+ */
+int ulcms_feature_table_write_parquet(const UlcmsFeatureTable *table,
+                                      const char *path,
+                                      const char *const *sample_names);
+#endif
+
+void ulcms_feature_table_free(UlcmsFeatureTable *table);
+
+UlcmsLibraryOptions ulcms_library_options_default(void);
+
+/**
+ * Reads an MSP or MGF library (MGF when the file has a `BEGIN IONS`
+ * line) and indexes it.
+ */
+int ulcms_library_open(const char *path, const UlcmsLibraryOptions *opts, UlcmsLibrary **out);
+
+size_t ulcms_library_count(const UlcmsLibrary *library);
+
+/**
+ * Field `field` (a `ULCMS_ENTRY_*` constant) of library entry `index`;
+ * NaN when missing or out of range.
+ */
+double ulcms_library_entry_get_f64(const UlcmsLibrary *library, size_t index, uint32_t field);
+
+/**
+ * Field `key` (such as `Name`, `Precursor_type` or `InChIKey`, compared
+ * ignoring case and `_`) of library entry `index`, as a borrowed,
+ * non-terminated UTF-8 slice valid until the library is freed. Returns 3
+ * when the entry is out of range, and a null pointer when the field is
+ * missing.
+ */
+int ulcms_library_entry_get_str(const UlcmsLibrary *library,
+                                size_t index,
+                                const char *key,
+                                const uint8_t **out_ptr,
+                                size_t *out_len);
+
+void ulcms_library_free(UlcmsLibrary *library);
+
+UlcmsSearchOptions ulcms_search_options_default(void);
+
+/**
+ * Searches the MSn spectra of `file` against `library`.
+ */
+int ulcms_library_search(const UlcmsLibrary *library,
+                         const UlcmsFile *file,
+                         const UlcmsSearchOptions *opts,
+                         UlcmsLibraryMatches **out);
+
+size_t ulcms_library_matches_count(const UlcmsLibraryMatches *matches);
+
+/**
+ * Field `field` (a `ULCMS_MATCH_*` constant) of match `index`; NaN when
+ * missing or out of range.
+ */
+double ulcms_library_match_get_f64(const UlcmsLibraryMatches *matches,
+                                   size_t index,
+                                   uint32_t field);
+
+void ulcms_library_matches_free(UlcmsLibraryMatches *matches);
+
+UlcmsMergeOptions ulcms_merge_options_default(void);
+
+/**
+ * Merges the `n` spectra of `file` at `indices` into one. Returns 4 when
+ * an index is out of range or the MS levels differ.
+ */
+int ulcms_merge_spectra(const UlcmsFile *file,
+                        const size_t *indices,
+                        size_t n,
+                        const UlcmsMergeOptions *opts,
+                        UlcmsMerged **out);
+
+/**
+ * Merges the spectra of `file` with retention time in `[rt_min, rt_max]`
+ * and MS level `ms_level` (0 for any) into one.
+ */
+int ulcms_merge_rt_window(const UlcmsFile *file,
+                          double rt_min,
+                          double rt_max,
+                          uint32_t ms_level,
+                          const UlcmsMergeOptions *opts,
+                          UlcmsMerged **out);
+
+/**
+ * Merges the MSn spectra of `file` sharing a precursor, within
+ * `precursor_ppm` and no more than `rt_gap` minutes apart (0 for no
+ * limit), into one spectrum per precursor.
+ */
+int ulcms_merge_by_precursor(const UlcmsFile *file,
+                             double precursor_ppm,
+                             double rt_gap,
+                             const UlcmsMergeOptions *opts,
+                             UlcmsMerged **out);
+
+size_t ulcms_merged_count(const UlcmsMerged *merged);
+
+/**
+ * Field `field` (a `ULCMS_MERGED_*` constant) of consensus spectrum
+ * `index`; NaN when missing or out of range.
+ */
+double ulcms_merged_get_f64(const UlcmsMerged *merged, size_t index, uint32_t field);
+
+/**
+ * Borrows the peaks of consensus spectrum `index`: `len` m/z values,
+ * intensities and occurrence counts. Valid until [`ulcms_merged_free`].
+ */
+int ulcms_merged_peaks(const UlcmsMerged *merged,
+                       size_t index,
+                       const double **mz,
+                       const double **intensity,
+                       const size_t **occurrence,
+                       size_t *len);
+
+/**
+ * Borrows the indices of the spectra merged into consensus spectrum
+ * `index`. Valid until [`ulcms_merged_free`].
+ */
+int ulcms_merged_spectra(const UlcmsMerged *merged,
+                         size_t index,
+                         const size_t **spectra,
+                         size_t *len);
+
+void ulcms_merged_free(UlcmsMerged *merged);
+
+/**
+ * Groups the spectra of `file` that carry ion mobility into frames.
+ */
+int ulcms_frames(const UlcmsFile *file, UlcmsFrames **out);
+
+size_t ulcms_frames_count(const UlcmsFrames *frames);
+
+/**
+ * Frame `index`: its retention time (NaN when missing), MS level (0 when
+ * missing) and a borrowed array of `len` spectrum indices by mobility.
+ * Valid until [`ulcms_frames_free`].
+ */
+int ulcms_frame(const UlcmsFrames *frames,
+                size_t index,
+                double *rt,
+                uint32_t *ms_level,
+                const size_t **spectra,
+                size_t *len);
+
+void ulcms_frames_free(UlcmsFrames *frames);
+
+UlcmsMobilogramOptions ulcms_mobilogram_options_default(void);
+
+/**
+ * Extracts the mobilogram of `[mz_min, mz_max]` from the spectra of
+ * `file`.
+ */
+int ulcms_mobilogram(const UlcmsFile *file,
+                     double mz_min,
+                     double mz_max,
+                     const UlcmsMobilogramOptions *opts,
+                     UlcmsMobilogram **out);
+
+/**
+ * Borrows the `len` mobilities, ascending, and summed intensities of
+ * `mobilogram`. Valid until [`ulcms_mobilogram_free`].
+ */
+int ulcms_mobilogram_data(const UlcmsMobilogram *mobilogram,
+                          const double **mobility,
+                          const double **intensity,
+                          size_t *len);
+
+/**
+ * Mobility type of `mobilogram`, as `ULCMS_FIELD_ION_MOBILITY_TYPE`
+ * gives it, as a borrowed, non-terminated UTF-8 slice; null when the
+ * mobilogram is empty.
+ */
+int ulcms_mobilogram_type(const UlcmsMobilogram *mobilogram,
+                          const uint8_t **out_ptr,
+                          size_t *out_len);
+
+void ulcms_mobilogram_free(UlcmsMobilogram *mobilogram);
+
+UlcmsCcsOptions ulcms_ccs_options_default(void);
+
+/**
+ * TIMS conversion taking observed 1/K0 values as they are, in the gas of
+ * `opts`.
+ */
+UlcmsCcsCalibration ulcms_ccs_uncalibrated(UlcmsCcsOptions opts);
+
+/**
+ * Fits a cross section calibration to `n` calibrants given by m/z,
+ * charge, reference CCS in Å² and observed mobility (1/K0 for TIMS,
+ * arrival time for single-field). Returns 3 for an unknown method and 4
+ * when the calibrants do not determine a fit.
+ */
+int ulcms_ccs_calibrate(const UlcmsCcsOptions *opts,
+                        const double *mz,
+                        const uint32_t *charge,
+                        const double *ccs,
+                        const double *observed,
+                        size_t n,
+                        UlcmsCcsCalibration *out);
+
+/**
+ * Writes into `out` the cross sections, in Å², of `n` ions given by m/z,
+ * charge and observed mobility, NaN for charge 0. Returns 3 for an
+ * unknown method.
+ */
+int ulcms_ccs(const UlcmsCcsCalibration *cal,
+              const double *mz,
+              const uint32_t *charge,
+              const double *observed,
+              size_t n,
+              double *out);
+
+UlcmsQuantOptions ulcms_quant_options_default(void);
+
+/**
+ * Quantifies the transitions of the CSV at `transitions_path` in the
+ * samples of the CSV at `sample_list_path`, whose mzML files are read
+ * relative to the sample list's directory. Returns 3 for an unknown
+ * model or weighting and 4 when a file cannot be read or parsed.
+ */
+int ulcms_quantify(const char *transitions_path,
+                   const char *sample_list_path,
+                   const UlcmsQuantOptions *opts,
+                   UlcmsQuant **out);
+
+/**
+ * Number of samples and compounds; the table has a row per pair, sample
+ * after sample.
+ */
+int ulcms_quant_shape(const UlcmsQuant *quant, size_t *n_samples, size_t *n_compounds);
+
+/**
+ * Field `field` (a `ULCMS_QUANT_*` constant) of row `row`; NaN when
+ * missing or out of range.
+ */
+double ulcms_quant_get_f64(const UlcmsQuant *quant, size_t row, uint32_t field);
+
+/**
+ * Name of compound `index`, as a borrowed, non-terminated UTF-8 slice
+ * valid until the table is freed. Returns 3 when out of range.
+ */
+int ulcms_quant_compound(const UlcmsQuant *quant,
+                         size_t index,
+                         const uint8_t **out_ptr,
+                         size_t *out_len);
+
+/**
+ * Name of sample `index`, as for [`ulcms_quant_compound`].
+ */
+int ulcms_quant_sample(const UlcmsQuant *quant,
+                       size_t index,
+                       const uint8_t **out_ptr,
+                       size_t *out_len);
+
+/**
+ * Calibration curve of compound `compound`: intercept, slope and
+ * quadratic coefficient into `coefficients` (3 values), R², and the
+ * concentration range of the standards into `range` (2 values). All NaN
+ * when the compound has no curve; returns 3 when out of range.
+ */
+int ulcms_quant_curve(const UlcmsQuant *quant,
+                      size_t compound,
+                      double *coefficients,
+                      double *r_squared,
+                      double *range);
+
+/**
+ * Writes the table as CSV to `path`, with QC flags by name.
+ */
+int ulcms_quant_write_csv(const UlcmsQuant *quant, const char *path);
+
+void ulcms_quant_free(UlcmsQuant *quant);
+
+int ulcms_parse_mzml(const char *path, SpectrumSummaryFFI **out_ptr, size_t *out_len);
+
+int ulcms_parse_mzml_from_bytes(const uint8_t *data_ptr,
+                                size_t data_len,
+                                SpectrumSummaryFFI **out_ptr,
+                                size_t *out_len);
+
+void ulcms_free_spectra(SpectrumSummaryFFI *ptr, size_t len);
+
+/**
+ * Widens `array` into `out[..cap]`, returning the number of values written.
+ * Lets callers that only handle doubles opt in to conversion per array.
+ */
+size_t ulcms_array_to_f64(const ArrayFFI *array, double *out, size_t cap);
+
+int ulcms_reader_open(const char *path, UlcmsReader **out);
+
+int ulcms_reader_open_from_bytes(const uint8_t *data_ptr, size_t data_len, UlcmsReader **out);
+
+size_t ulcms_reader_len(const UlcmsReader *reader);
+
+/**
+ * Number of values in each array of spectrum `index`, so the caller can
+ * size the buffers passed to [`ulcms_reader_decode_into`].
+ */
+int ulcms_reader_array_length(const UlcmsReader *reader, size_t index, size_t *out_len);
+
+/**
+ * Decodes spectrum `index` into `mz_out[..mz_cap]` and
+ * `intensity_out[..intensity_cap]`; either buffer may be null to skip it.
+ * `out_*_len` receive the full length of each array, and 5 is returned
+ * when a non-null buffer is shorter than that; it then holds a prefix.
+ */
+int ulcms_reader_decode_into(UlcmsReader *reader,
+                             size_t index,
+                             double *mz_out,
+                             size_t mz_cap,
+                             double *intensity_out,
+                             size_t intensity_cap,
+                             size_t *out_mz_len,
+                             size_t *out_intensity_len);
+
+void ulcms_reader_free(UlcmsReader *reader);
+
+UlcmsSmoothOptions ulcms_smooth_options_default(void);
+
+UlcmsBaselineOptions ulcms_baseline_options_default(void);
+
+/**
+ * Smooths `y[..n]` into `out[..n]`. `x` holds the positions, or is null
+ * for evenly spaced points. Returns 4 for unsorted `x` or a bad width.
+ */
+int ulcms_smooth(const double *x,
+                 const double *y,
+                 size_t n,
+                 const UlcmsSmoothOptions *opts,
+                 double *out);
+
+/**
+ * Baseline of `y[..n]` into `out[..n]`; `x` as in [`ulcms_smooth`].
+ */
+int ulcms_baseline(const double *x,
+                   const double *y,
+                   size_t n,
+                   const UlcmsBaselineOptions *opts,
+                   double *out);
+
+/**
+ * Noise level of `y[..n]` by `method`, a `ULCMS_NOISE_*` constant; `q` is
+ * the quantile for `ULCMS_NOISE_PERCENTILE`.
+ */
+int ulcms_noise(const double *y, size_t n, uint32_t method, double q, double *out);
+
+/**
+ * Noise level around each point of `y[..n]`, from the points within
+ * `width` of it, into `out[..n]`; `x` as in [`ulcms_smooth`].
+ */
+int ulcms_local_noise(const double *x,
+                      const double *y,
+                      size_t n,
+                      uint32_t method,
+                      double q,
+                      double width,
+                      double *out);
+
+UlcmsSimilarityOptions ulcms_similarity_options_default(void);
+
+/**
+ * Similarity of two spectra, from 0 to 1, and optionally the number of
+ * matched peaks (`matched` may be null).
+ */
+int ulcms_spectral_similarity(const UlcmsPeakList *a,
+                              const UlcmsPeakList *b,
+                              const UlcmsSimilarityOptions *opts,
+                              double *score,
+                              size_t *matched);
+
+UlcmsClusterOptions ulcms_cluster_options_default(void);
+
+/**
+ * Clusters the MSn spectra of `file` by similarity. The first `cap`
+ * labels, one per spectrum of the file, are written to `labels`: clusters
+ * are numbered from 0 and MS1 spectra, or spectra without arrays, get
+ * `ULCMS_NO_CLUSTER`. The number of clusters goes to `n_clusters`.
+ */
+int ulcms_cluster_spectra(const UlcmsFile *file,
+                          const UlcmsClusterOptions *opts,
+                          size_t *labels,
+                          size_t cap,
+                          size_t *n_clusters);
+
+/**
+ * Allocates `size` bytes aligned for `f64`, for callers (the wasm host)
+ * that need to place input in this module's memory. Pair with
+ * [`ulcms_free`] using the same size.
+ */
+uint8_t *ulcms_alloc(size_t size);
+
+void ulcms_free(uint8_t *ptr, size_t size);
+
+int ulcms_mean_f64(const double *ptr, size_t len, double *out);
+
+/**
+ * Sample standard deviation (n - 1 denominator).
+ */
+int ulcms_std_f64(const double *ptr, size_t len, double *out);
+
+int ulcms_median_f64(const double *ptr, size_t len, double *out);
+
+/**
+ * Compensated sum of `ptr[..len]`.
+ */
+int sum_f64(const double *ptr, size_t len, double *out);
+
+int32_t add_i32(int32_t a, int32_t b);
+
+void ulcms_mean_f64_r(const double *x, const int *n, double *out);
+
+void ulcms_std_f64_r(const double *x, const int *n, double *out);
+
+void ulcms_median_f64_r(const double *x, const int *n, double *out);
+
+UlcmsStream *ulcms_stream_new(void);
+
+/**
+ * Feeds the next chunk of the file; chunks may split anywhere.
+ */
+int ulcms_stream_feed(UlcmsStream *stream, const uint8_t *data_ptr, size_t data_len);
+
+/**
+ * Moves the spectra completed so far into a new file handle, read with the
+ * `ulcms_spectrum_*` accessors and released with `ulcms_file_close`.
+ */
+int ulcms_stream_take_spectra(UlcmsStream *stream, UlcmsFile **out);
+
+/**
+ * Ends the stream and frees it, handing the remaining spectra over like
+ * [`ulcms_stream_take_spectra`]. Returns 4 if the input ended inside a
+ * spectrum; the stream is freed either way.
+ */
+int ulcms_stream_finish(UlcmsStream *stream, UlcmsFile **out);
+
+/**
+ * Discards a stream without collecting its spectra.
+ */
+void ulcms_stream_free(UlcmsStream *stream);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* ULCMS_H */
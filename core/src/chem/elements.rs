@@ -0,0 +1,221 @@
+//! Element and isotope table: masses and natural abundances from the NIST
+//! Atomic Weights and Isotopic Compositions tables, for the elements that
+//! turn up in small molecule mass spectrometry.
+
+/// Mass of the electron, in Da.
+pub const ELECTRON_MASS: f64 = 0.000_548_579_909;
+
+/// Mass difference between 13C and 12C.
+pub const C13_SPACING: f64 = 1.003_354_835;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Isotope {
+    pub mass_number: u16,
+    /// Exact mass, in Da.
+    pub mass: f64,
+    /// Natural abundance as a fraction.
+    pub abundance: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Element {
+    pub symbol: &'static str,
+    /// Ordered by mass number.
+    pub isotopes: &'static [Isotope],
+}
+
+impl Element {
+    /// The most abundant isotope, whose mass is used for monoisotopic
+    /// masses.
+    pub fn principal(&self) -> &'static Isotope {
+        let mut best = &self.isotopes[0];
+        for i in self.isotopes {
+            if i.abundance > best.abundance {
+                best = i;
+            }
+        }
+        best
+    }
+
+    pub fn isotope(&self, mass_number: u16) -> Option<&'static Isotope> {
+        self.isotopes.iter().find(|i| i.mass_number == mass_number)
+    }
+
+    /// Abundance-weighted mean isotope mass.
+    pub fn average_mass(&self) -> f64 {
+        let (mut m, mut w) = (0.0, 0.0);
+        for i in self.isotopes {
+            m += i.mass * i.abundance;
+            w += i.abundance;
+        }
+        m / w
+    }
+}
+
+const fn iso(mass_number: u16, mass: f64, abundance: f64) -> Isotope {
+    Isotope {
+        mass_number,
+        mass,
+        abundance,
+    }
+}
+
+const fn el(symbol: &'static str, isotopes: &'static [Isotope]) -> Element {
+    Element { symbol, isotopes }
+}
+
+pub const ELEMENTS: &[Element] = &[
+    el(
+        "H",
+        &[
+            iso(1, 1.007_825_032_23, 0.999_885),
+            iso(2, 2.014_101_778_12, 0.000_115),
+        ],
+    ),
+    // Deuterium as its own symbol, as written in labelled formulas.
+    el("D", &[iso(2, 2.014_101_778_12, 1.0)]),
+    el(
+        "Li",
+        &[
+            iso(6, 6.015_122_887_4, 0.0759),
+            iso(7, 7.016_003_436_6, 0.9241),
+        ],
+    ),
+    el(
+        "B",
+        &[iso(10, 10.012_936_95, 0.199), iso(11, 11.009_305_36, 0.801)],
+    ),
+    el(
+        "C",
+        &[iso(12, 12.0, 0.9893), iso(13, 13.003_354_835_07, 0.0107)],
+    ),
+    el(
+        "N",
+        &[
+            iso(14, 14.003_074_004_43, 0.996_36),
+            iso(15, 15.000_108_898_88, 0.003_64),
+        ],
+    ),
+    el(
+        "O",
+        &[
+            iso(16, 15.994_914_619_57, 0.997_57),
+            iso(17, 16.999_131_756_50, 0.000_38),
+            iso(18, 17.999_159_612_86, 0.002_05),
+        ],
+    ),
+    el("F", &[iso(19, 18.998_403_162_73, 1.0)]),
+    el("Na", &[iso(23, 22.989_769_282_0, 1.0)]),
+    el(
+        "Mg",
+        &[
+            iso(24, 23.985_041_697, 0.7899),
+            iso(25, 24.985_836_976, 0.1000),
+            iso(26, 25.982_592_968, 0.1101),
+        ],
+    ),
+    el("Al", &[iso(27, 26.981_538_53, 1.0)]),
+    el(
+        "Si",
+        &[
+            iso(28, 27.976_926_534_65, 0.922_23),
+            iso(29, 28.976_494_664_90, 0.046_85),
+            iso(30, 29.973_770_136, 0.030_92),
+        ],
+    ),
+    el("P", &[iso(31, 30.973_761_998_42, 1.0)]),
+    el(
+        "S",
+        &[
+            iso(32, 31.972_071_174_4, 0.9499),
+            iso(33, 32.971_458_909_8, 0.0075),
+            iso(34, 33.967_867_004, 0.0425),
+            iso(36, 35.967_080_71, 0.0001),
+        ],
+    ),
+    el(
+        "Cl",
+        &[
+            iso(35, 34.968_852_682, 0.7576),
+            iso(37, 36.965_902_602, 0.2424),
+        ],
+    ),
+    el(
+        "K",
+        &[
+            iso(39, 38.963_706_486_4, 0.932_581),
+            iso(40, 39.963_998_166, 0.000_117),
+            iso(41, 40.961_825_257_9, 0.067_302),
+        ],
+    ),
+    el(
+        "Ca",
+        &[
+            iso(40, 39.962_590_863, 0.969_41),
+            iso(42, 41.958_617_83, 0.006_47),
+            iso(43, 42.958_766_44, 0.001_35),
+            iso(44, 43.955_481_6, 0.020_86),
+            iso(46, 45.953_689_0, 0.000_04),
+            iso(48, 47.952_522_76, 0.001_87),
+        ],
+    ),
+    el("Mn", &[iso(55, 54.938_043_91, 1.0)]),
+    el(
+        "Fe",
+        &[
+            iso(54, 53.939_608_99, 0.058_45),
+            iso(56, 55.934_936_33, 0.917_54),
+            iso(57, 56.935_392_84, 0.021_19),
+            iso(58, 57.933_274_43, 0.002_82),
+        ],
+    ),
+    el("Co", &[iso(59, 58.933_194_29, 1.0)]),
+    el(
+        "Ni",
+        &[
+            iso(58, 57.935_342_41, 0.680_77),
+            iso(60, 59.930_785_88, 0.262_23),
+            iso(61, 60.931_055_57, 0.011_399),
+            iso(62, 61.928_345_37, 0.036_346),
+            iso(64, 63.927_966_82, 0.009_255),
+        ],
+    ),
+    el(
+        "Cu",
+        &[
+            iso(63, 62.929_597_72, 0.6915),
+            iso(65, 64.927_789_70, 0.3085),
+        ],
+    ),
+    el(
+        "Zn",
+        &[
+            iso(64, 63.929_142_01, 0.4917),
+            iso(66, 65.926_033_81, 0.2773),
+            iso(67, 66.927_127_75, 0.0404),
+            iso(68, 67.924_844_55, 0.1845),
+            iso(70, 69.925_319_2, 0.0061),
+        ],
+    ),
+    el("As", &[iso(75, 74.921_594_57, 1.0)]),
+    el(
+        "Se",
+        &[
+            iso(74, 73.922_475_934, 0.0089),
+            iso(76, 75.919_213_704, 0.0937),
+            iso(77, 76.919_914_154, 0.0763),
+            iso(78, 77.917_309_28, 0.2377),
+            iso(80, 79.916_521_8, 0.4961),
+            iso(82, 81.916_699_5, 0.0873),
+        ],
+    ),
+    el(
+        "Br",
+        &[iso(79, 78.918_337_6, 0.5069), iso(81, 80.916_289_7, 0.4931)],
+    ),
+    el("I", &[iso(127, 126.904_471_9, 1.0)]),
+];
+
+pub fn element(symbol: &str) -> Option<&'static Element> {
+    ELEMENTS.iter().find(|e| e.symbol == symbol)
+}
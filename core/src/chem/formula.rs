@@ -0,0 +1,437 @@
+//! Molecular formulas, ion charges and adduct notation.
+//!
+//! Formulas are written as in `C6H12O6`, with parenthesised groups
+//! (`Ca(OH)2`), isotope labels in brackets (`C5[13C]H12O6`, `CD3`) and an
+//! optional trailing charge (`C2H5O+`, `C6H5O7-3`). Adducts are written as
+//! in `[M+H]+`, `[2M+Na]+`, `[M-H2O+NH4]+` or `[M+2H]2+`.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use super::elements::{ELECTRON_MASS, Element, element};
+
+/// An element, or one isotope of it when `mass_number` is set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Atom {
+    pub symbol: &'static str,
+    pub mass_number: Option<u16>,
+}
+
+impl Atom {
+    pub fn element(&self) -> &'static Element {
+        // Atoms are only built from symbols found in the table.
+        element(self.symbol).unwrap()
+    }
+
+    /// Mass of the labelled isotope, or of the principal one.
+    pub fn monoisotopic_mass(&self) -> f64 {
+        let e = self.element();
+        match self.mass_number.and_then(|a| e.isotope(a)) {
+            Some(i) => i.mass,
+            None => e.principal().mass,
+        }
+    }
+
+    pub fn average_mass(&self) -> f64 {
+        let e = self.element();
+        match self.mass_number.and_then(|a| e.isotope(a)) {
+            Some(i) => i.mass,
+            None => e.average_mass(),
+        }
+    }
+}
+
+/// Element counts and a charge. Counts may be negative in intermediate
+/// results, such as the composition an adduct adds.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Formula {
+    pub atoms: BTreeMap<Atom, i32>,
+    pub charge: i32,
+}
+
+impl Formula {
+    pub fn parse(s: &str) -> Result<Formula, String> {
+        let mut p = Parser {
+            s: s.trim().as_bytes(),
+            pos: 0,
+        };
+        let mut f = p.group()?;
+        f.charge = p.charge()?;
+        if p.pos != p.s.len() {
+            return Err(format!("unexpected '{}' in formula {s:?}", p.rest()));
+        }
+        Ok(f)
+    }
+
+    pub fn count(&self, symbol: &str) -> i32 {
+        self.atoms
+            .iter()
+            .filter(|(a, _)| a.symbol == symbol)
+            .map(|(_, &n)| n)
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.atoms.values().all(|&n| n == 0)
+    }
+
+    /// Monoisotopic mass, less the electrons of a positive charge (or plus
+    /// those of a negative one).
+    pub fn monoisotopic_mass(&self) -> f64 {
+        self.mass_with(Atom::monoisotopic_mass)
+    }
+
+    pub fn average_mass(&self) -> f64 {
+        self.mass_with(Atom::average_mass)
+    }
+
+    /// Monoisotopic m/z; the mass itself when uncharged.
+    pub fn mz(&self) -> f64 {
+        let m = self.monoisotopic_mass();
+        if self.charge == 0 {
+            m
+        } else {
+            m / self.charge.unsigned_abs() as f64
+        }
+    }
+
+    fn mass_with(&self, f: fn(&Atom) -> f64) -> f64 {
+        let mut m = 0.0;
+        for (a, &n) in &self.atoms {
+            m += n as f64 * f(a);
+        }
+        m - self.charge as f64 * ELECTRON_MASS
+    }
+
+    pub fn add(&mut self, other: &Formula, times: i32) {
+        for (a, &n) in &other.atoms {
+            *self.atoms.entry(*a).or_insert(0) += n * times;
+        }
+        self.atoms.retain(|_, n| *n != 0);
+        self.charge += other.charge * times;
+    }
+
+    fn check_non_negative(&self) -> Result<(), String> {
+        match self.atoms.iter().find(|(_, n)| **n < 0) {
+            Some((a, _)) => Err(format!("negative count of {} in {self}", a.symbol)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Hill order (C, H, then alphabetical; alphabetical without carbon) and
+/// a trailing charge such as `+` or `-2`.
+impl fmt::Display for Formula {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let has_c = self.atoms.keys().any(|a| a.symbol == "C");
+        let rank = |a: &Atom| match (has_c, a.symbol) {
+            (true, "C") => 0,
+            (true, "H") => 1,
+            _ => 2,
+        };
+        let mut atoms: Vec<(&Atom, &i32)> = self.atoms.iter().filter(|(_, n)| **n != 0).collect();
+        atoms.sort_by(|a, b| {
+            (rank(a.0), a.0.symbol, a.0.mass_number).cmp(&(rank(b.0), b.0.symbol, b.0.mass_number))
+        });
+        for (a, &n) in atoms {
+            match a.mass_number {
+                Some(m) => write!(f, "[{m}{}]", a.symbol)?,
+                None => write!(f, "{}", a.symbol)?,
+            }
+            if n != 1 {
+                write!(f, "{n}")?;
+            }
+        }
+        match self.charge {
+            0 => Ok(()),
+            1 => write!(f, "+"),
+            -1 => write!(f, "-"),
+            z if z > 0 => write!(f, "+{z}"),
+            z => write!(f, "{z}"),
+        }
+    }
+}
+
+struct Parser<'a> {
+    s: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.s.get(self.pos).copied()
+    }
+
+    fn rest(&self) -> String {
+        String::from_utf8_lossy(&self.s[self.pos..]).into_owned()
+    }
+
+    fn number(&mut self) -> Option<u32> {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        std::str::from_utf8(&self.s[start..self.pos])
+            .ok()?
+            .parse()
+            .ok()
+    }
+
+    fn count(&mut self) -> Result<i32, String> {
+        match self.number() {
+            None => Ok(1),
+            Some(n) => i32::try_from(n).map_err(|_| format!("count {n} is too large")),
+        }
+    }
+
+    // Element and group terms up to a ')' or the end.
+    fn group(&mut self) -> Result<Formula, String> {
+        let mut f = Formula::default();
+        while let Some(c) = self.peek() {
+            match c {
+                b'(' => {
+                    self.pos += 1;
+                    let inner = self.group()?;
+                    if self.peek() != Some(b')') {
+                        return Err("unclosed '('".to_string());
+                    }
+                    self.pos += 1;
+                    let n = self.count()?;
+                    f.add(&inner, n);
+                }
+                b'[' => {
+                    self.pos += 1;
+                    let mass_number = self
+                        .number()
+                        .and_then(|a| u16::try_from(a).ok())
+                        .ok_or("expected a mass number after '['")?;
+                    let atom = self.atom(Some(mass_number))?;
+                    if self.peek() != Some(b']') {
+                        return Err("unclosed '['".to_string());
+                    }
+                    self.pos += 1;
+                    let n = self.count()?;
+                    *f.atoms.entry(atom).or_insert(0) += n;
+                }
+                c if c.is_ascii_uppercase() => {
+                    let atom = self.atom(None)?;
+                    let n = self.count()?;
+                    *f.atoms.entry(atom).or_insert(0) += n;
+                }
+                _ => break,
+            }
+        }
+        f.atoms.retain(|_, n| *n != 0);
+        Ok(f)
+    }
+
+    fn atom(&mut self, mass_number: Option<u16>) -> Result<Atom, String> {
+        let start = self.pos;
+        if !self.peek().is_some_and(|c| c.is_ascii_uppercase()) {
+            return Err(format!("expected an element at {:?}", self.rest()));
+        }
+        self.pos += 1;
+        if self.peek().is_some_and(|c| c.is_ascii_lowercase()) {
+            self.pos += 1;
+        }
+        let symbol = std::str::from_utf8(&self.s[start..self.pos]).unwrap_or("");
+        let e = element(symbol).ok_or_else(|| format!("unknown element {symbol:?}"))?;
+        if let Some(a) = mass_number
+            && e.isotope(a).is_none()
+        {
+            return Err(format!("unknown isotope {a}{symbol}"));
+        }
+        Ok(Atom {
+            symbol: e.symbol,
+            mass_number,
+        })
+    }
+
+    // Optional `+`, `-`, `+n` or `-n`.
+    fn charge(&mut self) -> Result<i32, String> {
+        let sign = match self.peek() {
+            Some(b'+') => 1,
+            Some(b'-') => -1,
+            _ => return Ok(0),
+        };
+        self.pos += 1;
+        Ok(sign * self.count()?)
+    }
+}
+
+/// An ion type such as `[M+H]+`: `n_molecules` copies of the molecule plus
+/// the composition `delta`, with charge `charge`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Adduct {
+    pub n_molecules: u32,
+    pub delta: Formula,
+    pub charge: i32,
+}
+
+impl Adduct {
+    pub fn parse(s: &str) -> Result<Adduct, String> {
+        let s = s.trim();
+        let err = || format!("invalid adduct {s:?}");
+        let inner_start = s.find('[').ok_or_else(err)?;
+        let inner_end = s.rfind(']').ok_or_else(err)?;
+        if inner_start != 0 || inner_end < inner_start {
+            return Err(err());
+        }
+        let inner = &s[1..inner_end];
+        let m = inner.find('M').ok_or_else(err)?;
+        let n_molecules = match &inner[..m] {
+            "" => 1,
+            n => n.parse().map_err(|_| err())?,
+        };
+        if n_molecules == 0 {
+            return Err(err());
+        }
+
+        // Terms such as `+H`, `-H2O` or `+2Na`.
+        let mut delta = Formula::default();
+        let mut rest = &inner[m + 1..];
+        while !rest.is_empty() {
+            let sign = match rest.as_bytes()[0] {
+                b'+' => 1,
+                b'-' => -1,
+                _ => return Err(err()),
+            };
+            rest = &rest[1..];
+            let end = rest.find(['+', '-']).unwrap_or(rest.len());
+            let term = &rest[..end];
+            let digits = term.bytes().take_while(u8::is_ascii_digit).count();
+            let times: i32 = match &term[..digits] {
+                "" => 1,
+                n => n.parse().map_err(|_| err())?,
+            };
+            let f = Formula::parse(&term[digits..])?;
+            if f.is_empty() || f.charge != 0 {
+                return Err(err());
+            }
+            delta.add(&f, sign * times);
+            rest = &rest[end..];
+        }
+
+        // Trailing charge: `+`, `2+`, `-`, `3-`.
+        let charge = &s[inner_end + 1..];
+        let (digits, sign) = charge.split_at(charge.len().saturating_sub(1));
+        let sign = match sign {
+            "+" => 1,
+            "-" => -1,
+            _ => return Err(err()),
+        };
+        let z: i32 = match digits {
+            "" => 1,
+            n => n.parse().map_err(|_| err())?,
+        };
+        if z == 0 {
+            return Err(err());
+        }
+        Ok(Adduct {
+            n_molecules,
+            delta,
+            charge: sign * z,
+        })
+    }
+
+    /// m/z of this ion of a molecule of neutral monoisotopic mass
+    /// `neutral_mass`.
+    pub fn mz(&self, neutral_mass: f64) -> f64 {
+        let m = self.n_molecules as f64 * neutral_mass + self.delta.monoisotopic_mass()
+            - self.charge as f64 * ELECTRON_MASS;
+        m / self.charge.unsigned_abs() as f64
+    }
+
+    /// Neutral monoisotopic mass of a molecule seen at `mz` as this ion.
+    pub fn neutral_mass(&self, mz: f64) -> f64 {
+        let m = mz * self.charge.unsigned_abs() as f64 + self.charge as f64 * ELECTRON_MASS
+            - self.delta.monoisotopic_mass();
+        m / self.n_molecules as f64
+    }
+
+    /// Formula of this ion of `molecule`, which must be neutral.
+    pub fn ion(&self, molecule: &Formula) -> Result<Formula, String> {
+        if molecule.charge != 0 {
+            return Err(format!("molecule {molecule} is already charged"));
+        }
+        let mut f = Formula::default();
+        f.add(molecule, self.n_molecules as i32);
+        f.add(&self.delta, 1);
+        f.charge = self.charge;
+        f.check_non_negative()?;
+        Ok(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GLUCOSE: f64 = 180.063_388;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-5
+    }
+
+    #[test]
+    fn glucose_monoisotopic_mass() {
+        let f = Formula::parse("C6H12O6").unwrap();
+        assert_eq!((f.count("C"), f.count("H"), f.count("O")), (6, 12, 6));
+        assert!(close(f.monoisotopic_mass(), GLUCOSE));
+        assert!((f.average_mass() - 180.156).abs() < 0.01);
+        assert_eq!(f.to_string(), "C6H12O6");
+    }
+
+    #[test]
+    fn groups_isotopes_and_charges_parse() {
+        let f = Formula::parse("Ca(OH)2").unwrap();
+        assert_eq!((f.count("Ca"), f.count("O"), f.count("H")), (1, 2, 2));
+        assert_eq!(f.to_string(), "CaH2O2");
+
+        let f = Formula::parse("C5[13C]H12O6").unwrap();
+        assert_eq!(f.count("C"), 6);
+        assert!(close(f.monoisotopic_mass(), GLUCOSE + 1.003_355));
+        assert_eq!(f.to_string(), "C5[13C]H12O6");
+
+        let f = Formula::parse("C2H5O+").unwrap();
+        assert_eq!(f.charge, 1);
+        assert!(close(f.mz(), 45.033_491));
+        assert_eq!(Formula::parse("C6H5O7-3").unwrap().charge, -3);
+    }
+
+    #[test]
+    fn malformed_formulas_are_rejected() {
+        for s in [
+            "Xx2", "C6H12O6)", "Ca(OH", "[13C", "[C]", "[99C]", "c6", "C6 H12",
+        ] {
+            assert!(Formula::parse(s).is_err(), "{s}");
+        }
+    }
+
+    #[test]
+    fn adduct_mz() {
+        let mz = |a: &str| Adduct::parse(a).unwrap().mz(GLUCOSE);
+        assert!(close(mz("[M+H]+"), 181.070_665));
+        assert!(close(mz("[M-H2O+NH4]+"), 180.086_649));
+        assert!(close(mz("[2M+Na]+"), 383.115_998));
+        assert!(close(mz("[M+2H]2+"), 91.038_970));
+        assert!(close(mz("[M-H]-"), 179.056_112));
+
+        let a = Adduct::parse("[M+H]+").unwrap();
+        assert!(close(a.neutral_mass(181.070_665), GLUCOSE));
+        let glucose = Formula::parse("C6H12O6").unwrap();
+        assert_eq!(a.ion(&glucose).unwrap().to_string(), "C6H13O6+");
+        assert!(a.ion(&Formula::parse("C2H5O+").unwrap()).is_err());
+        let water_loss = Adduct::parse("[M-H2O+H]+").unwrap();
+        assert!(water_loss.ion(&Formula::parse("CH4").unwrap()).is_err());
+    }
+
+    #[test]
+    fn malformed_adducts_are_rejected() {
+        for s in [
+            "M+H", "[M+H]", "[0M+H]+", "[M+H]0+", "[M*H]+", "[M+Xx]+", "[X+H]+",
+        ] {
+            assert!(Adduct::parse(s).is_err(), "{s}");
+        }
+    }
+}
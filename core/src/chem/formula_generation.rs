@@ -0,0 +1,423 @@
+//! Candidate molecular formulas for an observed m/z.
+//!
+//! Element counts are enumerated within the given ranges for neutral masses
+//! whose ion falls within the ppm window, filtered with the Seven Golden
+//! Rules of Kind and Fiehn (2007), and ranked by mass error and by how well
+//! their isotope distribution matches the observed isotope peaks.
+
+use super::elements::{C13_SPACING, element};
+use super::formula::{Adduct, Atom, Formula};
+use super::isotopes::{IsotopeOptions, isotope_distribution};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ElementRange {
+    pub symbol: &'static str,
+    pub min: u32,
+    pub max: u32,
+}
+
+/// Parses ranges written as `C0-80H0-150N0-10O0-20`; a single number, as
+/// in `Cl2`, fixes the count.
+pub fn parse_element_ranges(s: &str) -> Result<Vec<ElementRange>, String> {
+    let b = s.trim().as_bytes();
+    let mut out: Vec<ElementRange> = Vec::new();
+    let mut i = 0;
+    let number = |i: &mut usize| -> Option<u32> {
+        let start = *i;
+        while *i < b.len() && b[*i].is_ascii_digit() {
+            *i += 1;
+        }
+        std::str::from_utf8(&b[start..*i]).ok()?.parse().ok()
+    };
+    while i < b.len() {
+        let start = i;
+        if !b[i].is_ascii_uppercase() {
+            return Err(format!("invalid element ranges {s:?}"));
+        }
+        i += 1;
+        if i < b.len() && b[i].is_ascii_lowercase() {
+            i += 1;
+        }
+        let symbol = std::str::from_utf8(&b[start..i]).unwrap_or("");
+        let e = element(symbol).ok_or_else(|| format!("unknown element {symbol:?}"))?;
+        let min = number(&mut i).ok_or_else(|| format!("missing count for {symbol}"))?;
+        let max = if i < b.len() && b[i] == b'-' {
+            i += 1;
+            number(&mut i).ok_or_else(|| format!("missing maximum for {symbol}"))?
+        } else {
+            min
+        };
+        if max < min {
+            return Err(format!("empty range for {symbol}"));
+        }
+        if out.iter().any(|r| r.symbol == e.symbol) {
+            return Err(format!("{symbol} given twice"));
+        }
+        out.push(ElementRange {
+            symbol: e.symbol,
+            min,
+            max,
+        });
+    }
+    Ok(out)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FormulaSearchOptions {
+    /// Window around the observed m/z, in ppm.
+    pub ppm: f64,
+    /// Ion the observed m/z is assumed to be.
+    pub adduct: Adduct,
+    pub elements: Vec<ElementRange>,
+    /// Apply the Seven Golden Rules (valence, H/C and heteroatom ratios,
+    /// element combinations). Formulas without carbon are then rejected.
+    pub golden_rules: bool,
+    /// Best candidates returned.
+    pub max_candidates: usize,
+}
+
+impl Default for FormulaSearchOptions {
+    fn default() -> Self {
+        FormulaSearchOptions {
+            ppm: 5.0,
+            adduct: Adduct::parse("[M+H]+").unwrap(),
+            elements: parse_element_ranges("C0-80H0-150N0-10O0-20P0-4S0-4").unwrap(),
+            golden_rules: true,
+            max_candidates: 50,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FormulaCandidate {
+    /// Neutral formula.
+    pub formula: Formula,
+    pub neutral_mass: f64,
+    /// Theoretical m/z of the ion.
+    pub mz: f64,
+    /// `(observed - theoretical) / theoretical`, in ppm.
+    pub ppm_error: f64,
+    /// Ring and double bond equivalents.
+    pub rdbe: f64,
+    /// Agreement, from 0 to 1, of the theoretical isotope pattern with the
+    /// observed one; 1 without observed isotopes.
+    pub isotope_score: f64,
+    /// Product of the mass and isotope scores; candidates are sorted by it.
+    pub score: f64,
+}
+
+/// Candidate formulas for an ion observed at `mz`, best first. `isotopes`
+/// holds the observed `(m/z, intensity)` peaks of its isotope pattern,
+/// monoisotopic first (see [`isotope_peaks`]), and may be empty.
+pub fn generate_formulas(
+    mz: f64,
+    isotopes: &[(f64, f64)],
+    opts: &FormulaSearchOptions,
+) -> Result<Vec<FormulaCandidate>, String> {
+    if !mz.is_finite() || mz <= 0.0 {
+        return Err(format!("invalid m/z {mz}"));
+    }
+    let adduct = &opts.adduct;
+    let z = adduct.charge.unsigned_abs() as f64;
+    let n = adduct.n_molecules as f64;
+    let target = adduct.neutral_mass(mz);
+    // The m/z window mapped onto the neutral mass.
+    let tol = mz * opts.ppm * 1e-6 * z / n;
+
+    // Heaviest first, so the last, lightest element closes each formula.
+    let mut elems: Vec<(ElementRange, f64)> = opts
+        .elements
+        .iter()
+        .filter_map(|r| Some((*r, element(r.symbol)?.principal().mass)))
+        .collect();
+    elems.sort_by(|a, b| b.1.total_cmp(&a.1));
+    if elems.is_empty() {
+        return Err("no elements to search".to_string());
+    }
+
+    let mut hits: Vec<Vec<u32>> = Vec::new();
+    let mut counts = vec![0u32; elems.len()];
+    enumerate(
+        &elems,
+        0,
+        0.0,
+        target - tol,
+        target + tol,
+        &mut counts,
+        &mut hits,
+    );
+
+    let mut out = Vec::new();
+    for counts in hits {
+        let mut formula = Formula::default();
+        for ((r, _), &c) in elems.iter().zip(&counts) {
+            if c > 0 {
+                let atom = Atom {
+                    symbol: r.symbol,
+                    mass_number: None,
+                };
+                formula.atoms.insert(atom, c as i32);
+            }
+        }
+        if opts.golden_rules && !golden_rules(&formula) {
+            continue;
+        }
+        let Ok(ion) = adduct.ion(&formula) else {
+            continue;
+        };
+        let theo = ion.mz();
+        let ppm_error = (mz - theo) / theo * 1e6;
+        let isotope_score = if isotopes.len() > 1 {
+            isotope_score(&ion, isotopes)?
+        } else {
+            1.0
+        };
+        let sigma = (opts.ppm / 2.0).max(f64::EPSILON);
+        let mass_score = (-0.5 * (ppm_error / sigma).powi(2)).exp();
+        out.push(FormulaCandidate {
+            neutral_mass: formula.monoisotopic_mass(),
+            rdbe: rdbe(&formula),
+            formula,
+            mz: theo,
+            ppm_error,
+            isotope_score,
+            score: mass_score * isotope_score,
+        });
+    }
+    out.sort_by(|a, b| b.score.total_cmp(&a.score));
+    out.truncate(opts.max_candidates);
+    Ok(out)
+}
+
+fn enumerate(
+    elems: &[(ElementRange, f64)],
+    k: usize,
+    mass: f64,
+    lo: f64,
+    hi: f64,
+    counts: &mut [u32],
+    out: &mut Vec<Vec<u32>>,
+) {
+    let (r, m) = elems[k];
+    if k + 1 == elems.len() {
+        // Solve for the last count directly.
+        let first = ((lo - mass) / m).ceil().max(r.min as f64);
+        let last = ((hi - mass) / m).floor().min(r.max as f64);
+        let mut c = first;
+        while c <= last {
+            counts[k] = c as u32;
+            out.push(counts.to_vec());
+            c += 1.0;
+        }
+        return;
+    }
+    for c in r.min..=r.max {
+        let total = mass + c as f64 * m;
+        if total > hi {
+            break;
+        }
+        counts[k] = c;
+        enumerate(elems, k + 1, total, lo, hi, counts, out);
+    }
+}
+
+// Lowest common valence; the parity, which is what the rules test, is the
+// same for the higher valences of P and S.
+fn valence(symbol: &str) -> i32 {
+    match symbol {
+        "C" | "Si" => 4,
+        "N" | "P" | "B" | "As" => 3,
+        "O" | "S" | "Se" | "Mg" | "Ca" | "Fe" | "Zn" | "Cu" | "Ni" | "Co" | "Mn" => 2,
+        _ => 1,
+    }
+}
+
+/// Ring and double bond equivalents, `1 + sum(n * (valence - 2)) / 2`.
+pub fn rdbe(f: &Formula) -> f64 {
+    let mut s = 0;
+    for (a, &n) in &f.atoms {
+        s += n * (valence(a.symbol) - 2);
+    }
+    1.0 + s as f64 / 2.0
+}
+
+// Rules 2 (LEWIS and SENIOR), 4 (H/C), 5 (heteroatom ratios) and 6
+// (element combinations) of the Seven Golden Rules, with the ranges
+// covering 99.7% of the compounds in their databases.
+fn golden_rules(f: &Formula) -> bool {
+    let c = f.count("C");
+    if c == 0 {
+        return false;
+    }
+    let (mut atoms, mut valences, mut max_valence) = (0, 0, 0);
+    for (a, &n) in &f.atoms {
+        let v = valence(a.symbol);
+        atoms += n;
+        valences += n * v;
+        max_valence = max_valence.max(v);
+    }
+    if valences % 2 != 0 || valences < 2 * max_valence || valences < 2 * (atoms - 1) {
+        return false;
+    }
+    if rdbe(f) < 0.0 {
+        return false;
+    }
+
+    let ratio = |s: &str| f.count(s) as f64 / c as f64;
+    let hc = ratio("H") + ratio("D");
+    if !(0.2..=3.1).contains(&hc) {
+        return false;
+    }
+    let limits = [
+        ("N", 1.3),
+        ("O", 1.2),
+        ("P", 0.3),
+        ("S", 0.8),
+        ("F", 1.5),
+        ("Cl", 0.8),
+        ("Br", 0.8),
+        ("Si", 0.5),
+    ];
+    if limits.iter().any(|&(s, max)| ratio(s) > max) {
+        return false;
+    }
+
+    let (n, o, p, s) = (f.count("N"), f.count("O"), f.count("P"), f.count("S"));
+    let all_above = |xs: &[i32], t: i32| xs.iter().all(|&x| x > t);
+    if all_above(&[n, o, p, s], 1) && (n >= 10 || o >= 20 || p >= 4 || s >= 3) {
+        return false;
+    }
+    if all_above(&[n, o, p], 3) && (n >= 11 || o >= 22 || p >= 6) {
+        return false;
+    }
+    if all_above(&[o, p, s], 1) && (o >= 14 || p >= 3 || s >= 3) {
+        return false;
+    }
+    if all_above(&[p, s, n], 1) && (p >= 3 || s >= 3 || n >= 4) {
+        return false;
+    }
+    if all_above(&[n, o, s], 6) && (n >= 19 || o >= 14 || s >= 8) {
+        return false;
+    }
+    true
+}
+
+// exp(-mean(z^2) / 2), where z is the difference between observed and
+// theoretical intensities relative to the monoisotopic peak, in units of
+// 10% of the theoretical value plus 1% of the monoisotopic peak.
+fn isotope_score(ion: &Formula, observed: &[(f64, f64)]) -> Result<f64, String> {
+    let theo = isotope_distribution(ion, &IsotopeOptions::default())?;
+    let near = |mz: f64| -> f64 {
+        theo.iter()
+            .filter(|p| (p.mass - mz).abs() < 0.05)
+            .map(|p| p.abundance)
+            .sum()
+    };
+    let mono = near(observed[0].0);
+    if mono <= 0.0 || observed[0].1 <= 0.0 {
+        return Ok(0.0);
+    }
+    let mut sq = 0.0;
+    for &(mz, y) in &observed[1..] {
+        let t = near(mz) / mono;
+        let o = y / observed[0].1;
+        let sigma = 0.1 * t + 0.01;
+        sq += ((o - t) / sigma).powi(2);
+    }
+    Ok((-0.5 * sq / (observed.len() - 1) as f64).exp())
+}
+
+/// The isotope peaks of an ion at `mono_mz` with charge `charge` in one
+/// spectrum: the most intense point within `ppm` of each of the
+/// monoisotopic and the next `max_isotopes` 13C positions, stopping at the
+/// first one missing. `mz` must be sorted ascending.
+pub fn isotope_peaks(
+    mz: &[f64],
+    intensity: &[f64],
+    mono_mz: f64,
+    charge: u32,
+    ppm: f64,
+    max_isotopes: usize,
+) -> Vec<(f64, f64)> {
+    let z = charge.max(1) as f64;
+    let mut out = Vec::new();
+    for k in 0..=max_isotopes {
+        let target = mono_mz + k as f64 * C13_SPACING / z;
+        let tol = target * ppm * 1e-6;
+        let lo = mz.partition_point(|&m| m < target - tol);
+        let hi = mz.partition_point(|&m| m <= target + tol);
+        let best =
+            (lo..hi.min(intensity.len())).max_by(|&a, &b| intensity[a].total_cmp(&intensity[b]));
+        match best {
+            Some(i) if intensity[i] > 0.0 => out.push((mz[i], intensity[i])),
+            _ => break,
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn element_ranges_parse() {
+        let r = parse_element_ranges("C0-80H0-150Cl2").unwrap();
+        assert_eq!(r.len(), 3);
+        assert_eq!((r[0].symbol, r[0].min, r[0].max), ("C", 0, 80));
+        assert_eq!((r[2].symbol, r[2].min, r[2].max), ("Cl", 2, 2));
+        for s in ["C5-2", "CC", "C1C2", "Xx1", "c1", "C1-"] {
+            assert!(parse_element_ranges(s).is_err(), "{s}");
+        }
+    }
+
+    #[test]
+    fn glucose_is_found_from_its_protonated_ion() {
+        let opts = FormulaSearchOptions {
+            elements: parse_element_ranges("C0-20H0-40N0-5O0-10").unwrap(),
+            ..FormulaSearchOptions::default()
+        };
+        let found = generate_formulas(181.070_665, &[], &opts).unwrap();
+        let glucose = found
+            .iter()
+            .find(|c| c.formula.to_string() == "C6H12O6")
+            .unwrap();
+        assert!(glucose.ppm_error.abs() < 0.1);
+        assert_eq!(glucose.rdbe, 1.0);
+        assert!(found.iter().all(|c| c.ppm_error.abs() <= opts.ppm));
+        assert!(found.windows(2).all(|w| w[0].score >= w[1].score));
+        assert!(generate_formulas(-1.0, &[], &opts).is_err());
+    }
+
+    #[test]
+    fn isotope_pattern_ranks_candidates() {
+        // C6H13O6+ with its M+1 at the theoretical 6.7%, then far too weak.
+        let ion = Formula::parse("C6H13O6+").unwrap();
+        let mono = ion.mz();
+        let observed = [(mono, 1000.0), (mono + C13_SPACING, 67.0)];
+        assert!(isotope_score(&ion, &observed).unwrap() > 0.8);
+        let weak = [(mono, 1000.0), (mono + C13_SPACING, 10.0)];
+        assert!(isotope_score(&ion, &weak).unwrap() < 0.1);
+    }
+
+    #[test]
+    fn golden_rules_reject_impossible_formulas() {
+        let ok = |s: &str| golden_rules(&Formula::parse(s).unwrap());
+        assert!(ok("C6H12O6"));
+        assert!(ok("C5H5N5"));
+        assert!(!ok("C6H13O6"), "odd valence sum");
+        assert!(!ok("CH40"), "H/C ratio");
+        assert!(!ok("H2O"), "no carbon");
+        assert!(!ok("C2O10"), "O/C ratio");
+    }
+
+    #[test]
+    fn isotope_peaks_stop_at_the_first_gap() {
+        let mz = [181.0707, 182.0740, 182.5, 184.0790];
+        let y = [100.0, 7.0, 50.0, 1.0];
+        let peaks = isotope_peaks(&mz, &y, 181.0707, 1, 10.0, 3);
+        assert_eq!(peaks, [(181.0707, 100.0), (182.0740, 7.0)]);
+        let doubly = isotope_peaks(&mz, &y, 181.0707, 2, 20.0, 3);
+        assert_eq!(doubly.len(), 1);
+    }
+}
@@ -0,0 +1,212 @@
+//! Isotope distributions of formulas.
+//!
+//! Each element's pattern is raised to its count by repeated squaring, and
+//! the element patterns are then convolved together. After every
+//! convolution peaks below `min_abundance` are pruned and the rest merged:
+//! by nominal mass for the aggregated distribution, or when closer than
+//! `resolution` for the fine one.
+
+use super::elements::ELECTRON_MASS;
+use super::formula::Formula;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IsotopePeak {
+    /// Mass, or m/z when the formula is charged.
+    pub mass: f64,
+    /// Probability of the peak; all peaks sum to 1 before pruning.
+    pub abundance: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IsotopeOptions {
+    /// Keep peaks of separate isotopologues apart rather than merging them
+    /// by nominal mass.
+    pub fine: bool,
+    /// Peaks less probable than this are dropped.
+    pub min_abundance: f64,
+    /// Fine peaks closer than this, in Da, are merged.
+    pub resolution: f64,
+}
+
+impl Default for IsotopeOptions {
+    fn default() -> Self {
+        IsotopeOptions {
+            fine: false,
+            min_abundance: 1e-6,
+            resolution: 1e-5,
+        }
+    }
+}
+
+// `offset` is the nominal mass difference from the monoisotopic peak.
+#[derive(Debug, Clone, Copy)]
+struct Peak {
+    mass: f64,
+    p: f64,
+    offset: i32,
+}
+
+/// Isotope distribution of `formula`, in ascending mass order. Isotope
+/// labelled atoms contribute their one isotope. Counts must not be
+/// negative.
+pub fn isotope_distribution(
+    formula: &Formula,
+    opts: &IsotopeOptions,
+) -> Result<Vec<IsotopePeak>, String> {
+    let mut dist = vec![Peak {
+        mass: 0.0,
+        p: 1.0,
+        offset: 0,
+    }];
+    for (atom, &n) in &formula.atoms {
+        if n < 0 {
+            return Err(format!("negative count of {} in {formula}", atom.symbol));
+        }
+        let e = atom.element();
+        let pattern: Vec<Peak> = match atom.mass_number.and_then(|a| e.isotope(a)) {
+            Some(i) => vec![Peak {
+                mass: i.mass,
+                p: 1.0,
+                offset: 0,
+            }],
+            None => {
+                let base = e.principal().mass_number as i32;
+                e.isotopes
+                    .iter()
+                    .filter(|i| i.abundance > 0.0)
+                    .map(|i| Peak {
+                        mass: i.mass,
+                        p: i.abundance,
+                        offset: i.mass_number as i32 - base,
+                    })
+                    .collect()
+            }
+        };
+        let power = pow(&pattern, n as u32, opts);
+        dist = convolve(&dist, &power, opts);
+    }
+
+    let z = formula.charge;
+    let shift = -(z as f64) * ELECTRON_MASS;
+    let scale = if z == 0 {
+        1.0
+    } else {
+        1.0 / z.unsigned_abs() as f64
+    };
+    dist.sort_by(|a, b| a.mass.total_cmp(&b.mass));
+    Ok(dist
+        .into_iter()
+        .map(|p| IsotopePeak {
+            mass: (p.mass + shift) * scale,
+            abundance: p.p,
+        })
+        .collect())
+}
+
+fn pow(pattern: &[Peak], mut n: u32, opts: &IsotopeOptions) -> Vec<Peak> {
+    let mut out = vec![Peak {
+        mass: 0.0,
+        p: 1.0,
+        offset: 0,
+    }];
+    let mut base = pattern.to_vec();
+    while n > 0 {
+        if n & 1 == 1 {
+            out = convolve(&out, &base, opts);
+        }
+        n >>= 1;
+        if n > 0 {
+            base = convolve(&base, &base, opts);
+        }
+    }
+    out
+}
+
+fn convolve(a: &[Peak], b: &[Peak], opts: &IsotopeOptions) -> Vec<Peak> {
+    let mut out = Vec::with_capacity(a.len() * b.len());
+    for x in a {
+        for y in b {
+            let p = x.p * y.p;
+            if p >= opts.min_abundance {
+                out.push(Peak {
+                    mass: x.mass + y.mass,
+                    p,
+                    offset: x.offset + y.offset,
+                });
+            }
+        }
+    }
+    if opts.fine {
+        out.sort_by(|a, b| a.mass.total_cmp(&b.mass));
+    } else {
+        out.sort_by_key(|p| p.offset);
+    }
+
+    // Merge neighbours into their probability-weighted mean mass.
+    let mut merged: Vec<Peak> = Vec::with_capacity(out.len());
+    for p in out {
+        match merged.last_mut() {
+            Some(m)
+                if (opts.fine && p.mass - m.mass <= opts.resolution)
+                    || (!opts.fine && p.offset == m.offset) =>
+            {
+                let total = m.p + p.p;
+                m.mass = (m.mass * m.p + p.mass * p.p) / total;
+                m.p = total;
+            }
+            _ => merged.push(p),
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn distribution(formula: &str, opts: &IsotopeOptions) -> Vec<IsotopePeak> {
+        isotope_distribution(&Formula::parse(formula).unwrap(), opts).unwrap()
+    }
+
+    #[test]
+    fn glucose_m_plus_1() {
+        let d = distribution("C6H12O6", &IsotopeOptions::default());
+        assert!((d[0].mass - 180.063_388).abs() < 1e-5);
+        assert!((d[1].mass - d[0].mass - 1.0).abs() < 0.01);
+        let ratio = d[1].abundance / d[0].abundance;
+        assert!((ratio - 0.067).abs() < 0.002, "{ratio}");
+        let total: f64 = d.iter().map(|p| p.abundance).sum();
+        assert!((total - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn fine_peaks_keep_isotopologues_apart() {
+        let opts = IsotopeOptions {
+            fine: true,
+            ..IsotopeOptions::default()
+        };
+        let d = distribution("C6H12O6", &opts);
+        let near_m1: Vec<&IsotopePeak> = d
+            .iter()
+            .filter(|p| (p.mass - 181.07).abs() < 0.01)
+            .collect();
+        // 13C, 2H and 17O substitutions.
+        assert_eq!(near_m1.len(), 3);
+        assert!(d.windows(2).all(|w| w[0].mass < w[1].mass));
+    }
+
+    #[test]
+    fn labels_and_charges_shift_the_pattern() {
+        let opts = IsotopeOptions::default();
+        let d = distribution("[13C]", &opts);
+        assert_eq!(d.len(), 1);
+        assert!((d[0].mass - 13.003_355).abs() < 1e-5);
+        let d = distribution("C6H13O6+", &opts);
+        assert!((d[0].mass - 181.070_665).abs() < 1e-5);
+        let d = distribution("C6H10O6-2", &opts);
+        assert!((d[0].mass - 89.024_418).abs() < 1e-5);
+        let mut negative = Formula::default();
+        negative.add(&Formula::parse("H2O").unwrap(), -1);
+        assert!(isotope_distribution(&negative, &opts).is_err());
+    }
+}
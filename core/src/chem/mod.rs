@@ -0,0 +1,7 @@
+//! Chemistry: element data, molecular formulas and adducts, exact masses,
+//! isotope distributions and formula generation from accurate mass.
+
+pub mod elements;
+pub mod formula;
+pub mod formula_generation;
+pub mod isotopes;
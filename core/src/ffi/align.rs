@@ -0,0 +1,267 @@
+//! Retention time alignment of feature lists and files.
+
+use core::ffi::c_int;
+use std::panic::{AssertUnwindSafe, catch_unwind};
+
+use crate::utilities::alignment::{
+    DtwOptions, LoessOptions, Profile, Warp, align_features, align_profiles,
+};
+use crate::utilities::feature_detection::Feature;
+use crate::utilities::parse_mzml::SpectrumSummary;
+
+use super::features::UlcmsFeatures;
+use super::file::UlcmsFile;
+
+/// Mirrors `LoessOptions`; start from [`ulcms_loess_options_default`].
+#[repr(C)]
+pub struct UlcmsLoessOptions {
+    pub ppm: f64,
+    pub rt_window: f64,
+    pub span: f64,
+    pub min_anchors: usize,
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_loess_options_default() -> UlcmsLoessOptions {
+    let o = LoessOptions::default();
+    UlcmsLoessOptions {
+        ppm: o.ppm,
+        rt_window: o.rt_window,
+        span: o.span,
+        min_anchors: o.min_anchors,
+    }
+}
+
+pub const ULCMS_PROFILE_TIC: u32 = 0;
+pub const ULCMS_PROFILE_BPC: u32 = 1;
+pub const ULCMS_PROFILE_BINNED: u32 = 2;
+
+/// Mirrors `DtwOptions`; start from [`ulcms_dtw_options_default`].
+/// `profile` is a `ULCMS_PROFILE_*` constant; `mz_bin` only applies to
+/// `ULCMS_PROFILE_BINNED`.
+#[repr(C)]
+pub struct UlcmsDtwOptions {
+    pub profile: u32,
+    pub mz_bin: f64,
+    pub max_shift: f64,
+    pub gap_penalty: f64,
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_dtw_options_default() -> UlcmsDtwOptions {
+    let o = DtwOptions::default();
+    let (profile, mz_bin) = match o.profile {
+        Profile::Tic => (ULCMS_PROFILE_TIC, 1.0),
+        Profile::Bpc => (ULCMS_PROFILE_BPC, 1.0),
+        Profile::Binned { mz_bin } => (ULCMS_PROFILE_BINNED, mz_bin),
+    };
+    UlcmsDtwOptions {
+        profile,
+        mz_bin,
+        max_shift: o.max_shift,
+        gap_penalty: o.gap_penalty,
+    }
+}
+
+/// Opaque set of retention time warps, one per aligned run, in the order
+/// the runs were given.
+pub struct UlcmsWarps {
+    warps: Vec<Warp>,
+}
+
+fn warps_out(
+    res: std::thread::Result<Result<Vec<Warp>, String>>,
+    out: *mut *mut UlcmsWarps,
+) -> c_int {
+    match res {
+        Ok(Ok(warps)) => {
+            unsafe { *out = Box::into_raw(Box::new(UlcmsWarps { warps })) };
+            0
+        }
+        Ok(Err(_)) => 4,
+        Err(_) => 2,
+    }
+}
+
+/// Aligns each of the `n_runs` feature tables in `runs` to
+/// `runs[reference]` by LOESS on anchor features. Runs with too few anchors
+/// get the identity warp.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_align_features(
+    runs: *const *const UlcmsFeatures,
+    n_runs: usize,
+    reference: usize,
+    opts: *const UlcmsLoessOptions,
+    out: *mut *mut UlcmsWarps,
+) -> c_int {
+    if runs.is_null() || opts.is_null() || out.is_null() {
+        return 1;
+    }
+    let runs = unsafe { std::slice::from_raw_parts(runs, n_runs) };
+    if runs.iter().any(|r| r.is_null()) {
+        return 1;
+    }
+    if reference >= n_runs {
+        return 3;
+    }
+    let o = unsafe { &*opts };
+    let opts = LoessOptions {
+        ppm: o.ppm,
+        rt_window: o.rt_window,
+        span: o.span,
+        min_anchors: o.min_anchors,
+    };
+
+    let res = catch_unwind(AssertUnwindSafe(|| {
+        let tables: Vec<&[Feature]> = runs
+            .iter()
+            .map(|&r| unsafe { (*r).features.as_slice() })
+            .collect();
+        align_features(&tables, reference, &opts)
+    }));
+    warps_out(res, out)
+}
+
+/// Aligns each of the `n_runs` files in `runs` to `runs[reference]` by
+/// dynamic time warping of their MS1 profiles. Fails with 4 when some run
+/// cannot be aligned within `max_shift`.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_align_profiles(
+    runs: *const *const UlcmsFile,
+    n_runs: usize,
+    reference: usize,
+    opts: *const UlcmsDtwOptions,
+    out: *mut *mut UlcmsWarps,
+) -> c_int {
+    if runs.is_null() || opts.is_null() || out.is_null() {
+        return 1;
+    }
+    let runs = unsafe { std::slice::from_raw_parts(runs, n_runs) };
+    if runs.iter().any(|r| r.is_null()) {
+        return 1;
+    }
+    if reference >= n_runs {
+        return 3;
+    }
+    let o = unsafe { &*opts };
+    let profile = match o.profile {
+        ULCMS_PROFILE_TIC => Profile::Tic,
+        ULCMS_PROFILE_BPC => Profile::Bpc,
+        ULCMS_PROFILE_BINNED => Profile::Binned { mz_bin: o.mz_bin },
+        _ => return 3,
+    };
+    let opts = DtwOptions {
+        profile,
+        max_shift: o.max_shift,
+        gap_penalty: o.gap_penalty,
+    };
+
+    let res = catch_unwind(AssertUnwindSafe(|| {
+        let files: Vec<&[SpectrumSummary]> = runs
+            .iter()
+            .map(|&r| unsafe { (*r).spectra.as_slice() })
+            .collect();
+        align_profiles(&files, reference, &opts)
+    }));
+    warps_out(res, out)
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_warps_count(warps: *const UlcmsWarps) -> usize {
+    if warps.is_null() {
+        return 0;
+    }
+    unsafe { (*warps).warps.len() }
+}
+
+/// Number of knots of the warp of `run`; 0 for the identity.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_warp_knot_count(warps: *const UlcmsWarps, run: usize) -> usize {
+    if warps.is_null() {
+        return 0;
+    }
+    let warps = unsafe { &*warps };
+    warps.warps.get(run).map_or(0, |w| w.raw.len())
+}
+
+/// Copies the first `cap` knots of the warp of `run` into `raw` and
+/// `aligned` and returns how many were written. Together the knots define
+/// the warp: linear in between, constant offset beyond either end.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_warp_knots(
+    warps: *const UlcmsWarps,
+    run: usize,
+    raw: *mut f64,
+    aligned: *mut f64,
+    cap: usize,
+) -> usize {
+    if warps.is_null() || raw.is_null() || aligned.is_null() {
+        return 0;
+    }
+    let warps = unsafe { &*warps };
+    let Some(w) = warps.warps.get(run) else {
+        return 0;
+    };
+    let n = w.raw.len().min(cap);
+    unsafe {
+        std::slice::from_raw_parts_mut(raw, n).copy_from_slice(&w.raw[..n]);
+        std::slice::from_raw_parts_mut(aligned, n).copy_from_slice(&w.aligned[..n]);
+    }
+    n
+}
+
+/// Aligned retention time of `rt` in `run`; NaN when `run` is out of range.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_warp_apply(warps: *const UlcmsWarps, run: usize, rt: f64) -> f64 {
+    if warps.is_null() {
+        return f64::NAN;
+    }
+    let warps = unsafe { &*warps };
+    warps.warps.get(run).map_or(f64::NAN, |w| w.apply(rt))
+}
+
+/// Rewrites the spectrum retention times of `file` with the warp of `run`.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_file_apply_warp(
+    file: *mut UlcmsFile,
+    warps: *const UlcmsWarps,
+    run: usize,
+) -> c_int {
+    if file.is_null() || warps.is_null() {
+        return 1;
+    }
+    let (file, warps) = unsafe { (&mut *file, &*warps) };
+    let Some(w) = warps.warps.get(run) else {
+        return 3;
+    };
+    w.apply_to_spectra(&mut file.spectra);
+    0
+}
+
+/// Rewrites the retention times of `features` with the warp of `run`.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_features_apply_warp(
+    features: *mut UlcmsFeatures,
+    warps: *const UlcmsWarps,
+    run: usize,
+) -> c_int {
+    if features.is_null() || warps.is_null() {
+        return 1;
+    }
+    let (features, warps) = unsafe { (&mut *features, &*warps) };
+    let Some(w) = warps.warps.get(run) else {
+        return 3;
+    };
+    w.apply_to_features(&mut features.features);
+    0
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_warps_free(warps: *mut UlcmsWarps) {
+    if warps.is_null() {
+        return;
+    }
+    unsafe {
+        let _ = Box::from_raw(warps);
+    }
+}
@@ -0,0 +1,227 @@
+//! Binning a run into a retention time by m/z matrix.
+
+use core::ffi::c_int;
+use std::panic::{AssertUnwindSafe, catch_unwind};
+
+use crate::utilities::annotation::Polarity;
+use crate::utilities::binning::{Aggregation, BinnedMatrix, BinningOptions, MzBins, bin_spectra};
+
+use super::file::{
+    ULCMS_POLARITY_ANY, ULCMS_POLARITY_NEGATIVE, ULCMS_POLARITY_POSITIVE, UlcmsFile,
+};
+
+pub const ULCMS_BIN_DA: u32 = 0;
+pub const ULCMS_BIN_PPM: u32 = 1;
+
+pub const ULCMS_AGGREGATE_SUM: u32 = 0;
+pub const ULCMS_AGGREGATE_MAX: u32 = 1;
+
+/// Mirrors `BinningOptions`; start from [`ulcms_binning_options_default`].
+/// `bin_width` is in Da or ppm as `bin_unit` says (`ULCMS_BIN_*`),
+/// `aggregation` is a `ULCMS_AGGREGATE_*` constant and `polarity` a
+/// `ULCMS_POLARITY_*` one. NaN range limits and a 0 `ms_level` are unset.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct UlcmsBinningOptions {
+    pub bin_width: f64,
+    pub bin_unit: u32,
+    pub mz_min: f64,
+    pub mz_max: f64,
+    pub aggregation: u32,
+    pub rt_step: f64,
+    pub rt_min: f64,
+    pub rt_max: f64,
+    pub ms_level: u32,
+    pub polarity: u32,
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_binning_options_default() -> UlcmsBinningOptions {
+    let o = BinningOptions::default();
+    let MzBins::Width(bin_width) = o.bins else {
+        unreachable!()
+    };
+    UlcmsBinningOptions {
+        bin_width,
+        bin_unit: ULCMS_BIN_DA,
+        mz_min: f64::NAN,
+        mz_max: f64::NAN,
+        aggregation: ULCMS_AGGREGATE_SUM,
+        rt_step: o.rt_step,
+        rt_min: f64::NAN,
+        rt_max: f64::NAN,
+        ms_level: o.ms_level.unwrap_or(0),
+        polarity: ULCMS_POLARITY_ANY,
+    }
+}
+
+/// Opaque binned intensity matrix; see `BinnedMatrix`.
+pub struct UlcmsBinnedMatrix {
+    matrix: BinnedMatrix,
+}
+
+/// Bins the spectra of `file` into a retention time by m/z matrix.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_bin_spectra(
+    file: *const UlcmsFile,
+    opts: *const UlcmsBinningOptions,
+    out: *mut *mut UlcmsBinnedMatrix,
+) -> c_int {
+    if file.is_null() || opts.is_null() || out.is_null() {
+        return 1;
+    }
+    let o = unsafe { &*opts };
+    let bins = match o.bin_unit {
+        ULCMS_BIN_DA => MzBins::Width(o.bin_width),
+        ULCMS_BIN_PPM => MzBins::Ppm(o.bin_width),
+        _ => return 3,
+    };
+    let aggregation = match o.aggregation {
+        ULCMS_AGGREGATE_SUM => Aggregation::Sum,
+        ULCMS_AGGREGATE_MAX => Aggregation::Max,
+        _ => return 3,
+    };
+    let polarity = match o.polarity {
+        ULCMS_POLARITY_ANY => None,
+        ULCMS_POLARITY_POSITIVE => Some(Polarity::Positive),
+        ULCMS_POLARITY_NEGATIVE => Some(Polarity::Negative),
+        _ => return 3,
+    };
+    let set = |v: f64| (!v.is_nan()).then_some(v);
+    let opts = BinningOptions {
+        bins,
+        mz_min: set(o.mz_min),
+        mz_max: set(o.mz_max),
+        aggregation,
+        rt_step: o.rt_step,
+        rt_min: set(o.rt_min),
+        rt_max: set(o.rt_max),
+        ms_level: (o.ms_level != 0).then_some(o.ms_level),
+        polarity,
+    };
+
+    let res = catch_unwind(AssertUnwindSafe(|| {
+        let spectra = unsafe { &(*file).spectra };
+        bin_spectra(spectra, &opts)
+    }));
+
+    match res {
+        Ok(Ok(matrix)) => {
+            unsafe { *out = Box::into_raw(Box::new(UlcmsBinnedMatrix { matrix })) };
+            0
+        }
+        Ok(Err(_)) => 4,
+        Err(_) => 2,
+    }
+}
+
+/// Number of rows, columns and stored values of `matrix`.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_binned_shape(
+    matrix: *const UlcmsBinnedMatrix,
+    n_rows: *mut usize,
+    n_cols: *mut usize,
+    nnz: *mut usize,
+) -> c_int {
+    if matrix.is_null() || n_rows.is_null() || n_cols.is_null() || nnz.is_null() {
+        return 1;
+    }
+    let m = unsafe { &(*matrix).matrix };
+    unsafe {
+        *n_rows = m.n_rows();
+        *n_cols = m.n_cols();
+        *nnz = m.nnz();
+    }
+    0
+}
+
+/// Borrows the axes of `matrix`: `n_rows` retention times and
+/// `n_cols + 1` m/z bin edges. Valid until [`ulcms_binned_free`].
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_binned_axes(
+    matrix: *const UlcmsBinnedMatrix,
+    rt: *mut *const f64,
+    mz_edges: *mut *const f64,
+) -> c_int {
+    if matrix.is_null() || rt.is_null() || mz_edges.is_null() {
+        return 1;
+    }
+    let m = unsafe { &(*matrix).matrix };
+    unsafe {
+        *rt = m.rt.as_ptr();
+        *mz_edges = m.mz_edges.as_ptr();
+    }
+    0
+}
+
+pub const ULCMS_NO_SPECTRUM: usize = !0;
+
+/// Source spectrum index of row `row`, or `ULCMS_NO_SPECTRUM` on a
+/// resampled grid or out of range.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_binned_row_spectrum(matrix: *const UlcmsBinnedMatrix, row: usize) -> usize {
+    if matrix.is_null() {
+        return ULCMS_NO_SPECTRUM;
+    }
+    let m = unsafe { &(*matrix).matrix };
+    m.spectra.get(row).copied().unwrap_or(ULCMS_NO_SPECTRUM)
+}
+
+/// Borrows the CSR arrays of `matrix`: `n_rows + 1` row pointers and `nnz`
+/// column indices and values. Valid until [`ulcms_binned_free`].
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_binned_csr(
+    matrix: *const UlcmsBinnedMatrix,
+    indptr: *mut *const usize,
+    indices: *mut *const u32,
+    values: *mut *const f32,
+) -> c_int {
+    if matrix.is_null() || indptr.is_null() || indices.is_null() || values.is_null() {
+        return 1;
+    }
+    let m = unsafe { &(*matrix).matrix };
+    unsafe {
+        *indptr = m.indptr.as_ptr();
+        *indices = m.indices.as_ptr();
+        *values = m.values.as_ptr();
+    }
+    0
+}
+
+/// Writes `matrix` densely into `out`, which holds `cap` values and needs
+/// `n_rows * n_cols` of them (4 otherwise, or when that overflows):
+/// row-major, or column-major as R matrices are when `column_major` is set.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_binned_to_dense(
+    matrix: *const UlcmsBinnedMatrix,
+    column_major: bool,
+    out: *mut f32,
+    cap: usize,
+) -> c_int {
+    if matrix.is_null() || out.is_null() {
+        return 1;
+    }
+    let m = unsafe { &(*matrix).matrix };
+    let Some(need) = m.n_rows().checked_mul(m.n_cols()) else {
+        return 4;
+    };
+    if cap < need {
+        return 4;
+    }
+    let out = unsafe { std::slice::from_raw_parts_mut(out, need) };
+    match catch_unwind(AssertUnwindSafe(|| m.fill_dense(out, column_major))) {
+        Ok(Ok(())) => 0,
+        Ok(Err(_)) => 4,
+        Err(_) => 2,
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_binned_free(matrix: *mut UlcmsBinnedMatrix) {
+    if matrix.is_null() {
+        return;
+    }
+    unsafe {
+        let _ = Box::from_raw(matrix);
+    }
+}
@@ -0,0 +1,237 @@
+//! m/z recalibration against reference ions, and writing the corrected
+//! file back to mzML.
+
+use core::ffi::{c_char, c_int};
+use std::ffi::CStr;
+use std::fs;
+use std::panic::{AssertUnwindSafe, catch_unwind};
+
+use crate::utilities::calibration::{
+    Calibration, CalibrationModel, CalibrationOptions, CalibrationScope, calibrate,
+};
+use crate::utilities::write_mzml::write_mzml;
+
+use super::file::UlcmsFile;
+
+pub const ULCMS_CALIBRATION_CONSTANT: u32 = 0;
+pub const ULCMS_CALIBRATION_LINEAR: u32 = 1;
+pub const ULCMS_CALIBRATION_QUADRATIC: u32 = 2;
+
+pub const ULCMS_CALIBRATION_GLOBAL: u32 = 0;
+pub const ULCMS_CALIBRATION_PER_SCAN: u32 = 1;
+
+/// Mirrors `CalibrationOptions` less the references, which are passed to
+/// [`ulcms_calibrate`]; start from [`ulcms_calibration_options_default`].
+/// `model` is a `ULCMS_CALIBRATION_CONSTANT/LINEAR/QUADRATIC` constant and
+/// `scope` `ULCMS_CALIBRATION_GLOBAL/PER_SCAN`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct UlcmsCalibrationOptions {
+    pub ppm: f64,
+    pub min_intensity: f64,
+    pub model: u32,
+    pub scope: u32,
+    pub rt_window: f64,
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_calibration_options_default() -> UlcmsCalibrationOptions {
+    let o = CalibrationOptions::default();
+    UlcmsCalibrationOptions {
+        ppm: o.ppm,
+        min_intensity: o.min_intensity,
+        model: ULCMS_CALIBRATION_LINEAR,
+        scope: ULCMS_CALIBRATION_PER_SCAN,
+        rt_window: o.rt_window,
+    }
+}
+
+pub const ULCMS_HIT_SPECTRUM: u32 = 0;
+pub const ULCMS_HIT_REFERENCE_MZ: u32 = 1;
+pub const ULCMS_HIT_OBSERVED_MZ: u32 = 2;
+pub const ULCMS_HIT_INTENSITY: u32 = 3;
+pub const ULCMS_HIT_PPM_BEFORE: u32 = 4;
+pub const ULCMS_HIT_PPM_AFTER: u32 = 5;
+pub const ULCMS_HIT_OUTLIER: u32 = 6;
+
+/// Opaque fitted recalibration; see `Calibration`.
+pub struct UlcmsCalibration {
+    calibration: Calibration,
+}
+
+/// Fits an m/z correction for `file` from the `n_references` reference m/z
+/// in `references`. Returns 4 when none is found.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_calibrate(
+    file: *const UlcmsFile,
+    references: *const f64,
+    n_references: usize,
+    opts: *const UlcmsCalibrationOptions,
+    out: *mut *mut UlcmsCalibration,
+) -> c_int {
+    if file.is_null() || references.is_null() || opts.is_null() || out.is_null() {
+        return 1;
+    }
+    let o = unsafe { &*opts };
+    let model = match o.model {
+        ULCMS_CALIBRATION_CONSTANT => CalibrationModel::Constant,
+        ULCMS_CALIBRATION_LINEAR => CalibrationModel::Linear,
+        ULCMS_CALIBRATION_QUADRATIC => CalibrationModel::Quadratic,
+        _ => return 3,
+    };
+    let scope = match o.scope {
+        ULCMS_CALIBRATION_GLOBAL => CalibrationScope::Global,
+        ULCMS_CALIBRATION_PER_SCAN => CalibrationScope::PerScan,
+        _ => return 3,
+    };
+    let opts = CalibrationOptions {
+        references: unsafe { std::slice::from_raw_parts(references, n_references) }.to_vec(),
+        ppm: o.ppm,
+        min_intensity: o.min_intensity,
+        model,
+        scope,
+        rt_window: o.rt_window,
+    };
+
+    let res = catch_unwind(AssertUnwindSafe(|| {
+        let spectra = unsafe { &(*file).spectra };
+        calibrate(spectra, &opts)
+    }));
+
+    match res {
+        Ok(Ok(calibration)) => {
+            unsafe { *out = Box::into_raw(Box::new(UlcmsCalibration { calibration })) };
+            0
+        }
+        Ok(Err(_)) => 4,
+        Err(_) => 2,
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_calibration_hits_count(calibration: *const UlcmsCalibration) -> usize {
+    if calibration.is_null() {
+        return 0;
+    }
+    unsafe { (*calibration).calibration.hits.len() }
+}
+
+/// Field `field` (a `ULCMS_HIT_*` constant) of reference hit `index`; NaN
+/// when out of range. `ULCMS_HIT_OUTLIER` is 1 or 0.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_calibration_hit_get_f64(
+    calibration: *const UlcmsCalibration,
+    index: usize,
+    field: u32,
+) -> f64 {
+    if calibration.is_null() {
+        return f64::NAN;
+    }
+    let hits = unsafe { &(*calibration).calibration.hits };
+    let Some(h) = hits.get(index) else {
+        return f64::NAN;
+    };
+    match field {
+        ULCMS_HIT_SPECTRUM => h.spectrum as f64,
+        ULCMS_HIT_REFERENCE_MZ => h.reference_mz,
+        ULCMS_HIT_OBSERVED_MZ => h.observed_mz,
+        ULCMS_HIT_INTENSITY => h.intensity,
+        ULCMS_HIT_PPM_BEFORE => h.ppm_before,
+        ULCMS_HIT_PPM_AFTER => h.ppm_after,
+        ULCMS_HIT_OUTLIER => h.outlier as u8 as f64,
+        _ => f64::NAN,
+    }
+}
+
+/// Median absolute ppm error of the reference hits, outliers aside, before
+/// and after correction.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_calibration_summary(
+    calibration: *const UlcmsCalibration,
+    before: *mut f64,
+    after: *mut f64,
+) -> c_int {
+    if calibration.is_null() || before.is_null() || after.is_null() {
+        return 1;
+    }
+    let (b, a) = unsafe { &(*calibration).calibration }.median_abs_ppm();
+    unsafe {
+        *before = b;
+        *after = a;
+    }
+    0
+}
+
+/// Fitted ppm error of spectrum `spectrum` at `mz`.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_calibration_ppm_error(
+    calibration: *const UlcmsCalibration,
+    spectrum: usize,
+    mz: f64,
+) -> f64 {
+    if calibration.is_null() {
+        return f64::NAN;
+    }
+    unsafe { &(*calibration).calibration }.ppm_error(spectrum, mz)
+}
+
+/// Rewrites the m/z arrays, precursor and base peak m/z of `file`, which
+/// must be the file the calibration was fitted on.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_calibration_apply(
+    calibration: *const UlcmsCalibration,
+    file: *mut UlcmsFile,
+) -> c_int {
+    if calibration.is_null() || file.is_null() {
+        return 1;
+    }
+    let (calibration, file) = unsafe { (&(*calibration).calibration, &mut *file) };
+    match catch_unwind(AssertUnwindSafe(|| calibration.apply(file.spectra_mut()))) {
+        Ok(()) => 0,
+        Err(_) => 2,
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_calibration_free(calibration: *mut UlcmsCalibration) {
+    if calibration.is_null() {
+        return;
+    }
+    unsafe {
+        let _ = Box::from_raw(calibration);
+    }
+}
+
+/// Writes `file`, parsed from the mzML at `source_path`, to `out_path`
+/// with its current arrays, e.g. after [`ulcms_calibration_apply`].
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_write_mzml(
+    source_path: *const c_char,
+    file: *const UlcmsFile,
+    out_path: *const c_char,
+) -> c_int {
+    if source_path.is_null() || file.is_null() || out_path.is_null() {
+        return 1;
+    }
+
+    let res = catch_unwind(AssertUnwindSafe(|| -> Result<(), String> {
+        let source_path = unsafe { CStr::from_ptr(source_path) }
+            .to_str()
+            .map_err(|_| "invalid UTF-8".to_string())?;
+        let out_path = unsafe { CStr::from_ptr(out_path) }
+            .to_str()
+            .map_err(|_| "invalid UTF-8".to_string())?;
+        let source = fs::read(source_path).map_err(|e| format!("read {source_path}: {e}"))?;
+        let spectra = unsafe { &(*file).spectra };
+        let f = fs::File::create(out_path).map_err(|e| format!("create {out_path}: {e}"))?;
+        let mut w = std::io::BufWriter::new(f);
+        write_mzml(&source, spectra, &mut w)?;
+        std::io::Write::flush(&mut w).map_err(|e| format!("write {out_path}: {e}"))
+    }));
+
+    match res {
+        Ok(Ok(())) => 0,
+        Ok(Err(_)) => 4,
+        Err(_) => 2,
+    }
+}
@@ -0,0 +1,284 @@
+//! Formula masses, adduct m/z, isotope distributions and formula
+//! generation.
+
+use core::ffi::{c_char, c_int};
+use std::panic::{AssertUnwindSafe, catch_unwind};
+
+use crate::chem::formula::{Adduct, Formula};
+use crate::chem::formula_generation::{
+    FormulaCandidate, FormulaSearchOptions, generate_formulas, parse_element_ranges,
+};
+use crate::chem::isotopes::{IsotopeOptions, isotope_distribution};
+
+use super::c_str;
+
+/// Monoisotopic and average mass of a formula such as `C6H12O6` or
+/// `C6H13O6+`; see `Formula`. Either output may be null.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_formula_mass(
+    formula: *const c_char,
+    monoisotopic: *mut f64,
+    average: *mut f64,
+) -> c_int {
+    if formula.is_null() {
+        return 1;
+    }
+
+    let res = catch_unwind(AssertUnwindSafe(|| -> Result<(f64, f64), String> {
+        let f = Formula::parse(c_str(formula)?)?;
+        Ok((f.monoisotopic_mass(), f.average_mass()))
+    }));
+
+    match res {
+        Ok(Ok((mono, avg))) => {
+            unsafe {
+                if !monoisotopic.is_null() {
+                    *monoisotopic = mono;
+                }
+                if !average.is_null() {
+                    *average = avg;
+                }
+            }
+            0
+        }
+        Ok(Err(_)) => 4,
+        Err(_) => 2,
+    }
+}
+
+/// m/z of the ion `adduct` (such as `[M+H]+`) of a molecule of neutral
+/// monoisotopic mass `neutral_mass`.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_adduct_mz(
+    adduct: *const c_char,
+    neutral_mass: f64,
+    out: *mut f64,
+) -> c_int {
+    if adduct.is_null() || out.is_null() {
+        return 1;
+    }
+
+    let res = catch_unwind(AssertUnwindSafe(|| -> Result<f64, String> {
+        Ok(Adduct::parse(c_str(adduct)?)?.mz(neutral_mass))
+    }));
+
+    match res {
+        Ok(Ok(mz)) => {
+            unsafe { *out = mz };
+            0
+        }
+        Ok(Err(_)) => 4,
+        Err(_) => 2,
+    }
+}
+
+/// Isotope distribution of `formula` in ascending mass order (m/z when the
+/// formula is charged). Peaks are merged by nominal mass unless `fine` is
+/// non-zero, and those below `min_abundance` dropped. The first `cap` peaks
+/// are written to `masses` and `abundances`, and the total number to
+/// `n_peaks`, so a call with `cap` 0 sizes the buffers.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_isotope_distribution(
+    formula: *const c_char,
+    fine: c_int,
+    min_abundance: f64,
+    masses: *mut f64,
+    abundances: *mut f64,
+    cap: usize,
+    n_peaks: *mut usize,
+) -> c_int {
+    if formula.is_null()
+        || n_peaks.is_null()
+        || (cap > 0 && (masses.is_null() || abundances.is_null()))
+    {
+        return 1;
+    }
+    let opts = IsotopeOptions {
+        fine: fine != 0,
+        min_abundance,
+        ..IsotopeOptions::default()
+    };
+
+    let res = catch_unwind(AssertUnwindSafe(|| {
+        isotope_distribution(&Formula::parse(c_str(formula)?)?, &opts)
+    }));
+
+    match res {
+        Ok(Ok(peaks)) => {
+            let n = peaks.len().min(cap);
+            unsafe {
+                for (i, p) in peaks.iter().take(n).enumerate() {
+                    *masses.add(i) = p.mass;
+                    *abundances.add(i) = p.abundance;
+                }
+                *n_peaks = peaks.len();
+            }
+            0
+        }
+        Ok(Err(_)) => 4,
+        Err(_) => 2,
+    }
+}
+
+/// Mirrors `FormulaSearchOptions`; start from
+/// [`ulcms_formula_search_options_default`]. `adduct` (such as `[M+H]+`)
+/// and `elements` (such as `C0-80H0-150N0-10O0-20`) fall back to the
+/// defaults when null.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct UlcmsFormulaSearchOptions {
+    pub ppm: f64,
+    pub adduct: *const c_char,
+    pub elements: *const c_char,
+    pub golden_rules: c_int,
+    pub max_candidates: usize,
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_formula_search_options_default() -> UlcmsFormulaSearchOptions {
+    let d = FormulaSearchOptions::default();
+    UlcmsFormulaSearchOptions {
+        ppm: d.ppm,
+        adduct: core::ptr::null(),
+        elements: core::ptr::null(),
+        golden_rules: d.golden_rules as c_int,
+        max_candidates: d.max_candidates,
+    }
+}
+
+pub const ULCMS_CANDIDATE_NEUTRAL_MASS: u32 = 0;
+pub const ULCMS_CANDIDATE_MZ: u32 = 1;
+pub const ULCMS_CANDIDATE_PPM_ERROR: u32 = 2;
+pub const ULCMS_CANDIDATE_RDBE: u32 = 3;
+pub const ULCMS_CANDIDATE_ISOTOPE_SCORE: u32 = 4;
+pub const ULCMS_CANDIDATE_SCORE: u32 = 5;
+
+/// Opaque ranked formula candidates; see `FormulaCandidate`.
+pub struct UlcmsFormulaCandidates {
+    candidates: Vec<FormulaCandidate>,
+    formulas: Vec<String>,
+}
+
+/// Candidate formulas for an ion observed at `mz`, best first. `iso_mz` and
+/// `iso_intensity` hold `n_isotopes` observed isotope peaks, monoisotopic
+/// first, and may be null when `n_isotopes` is 0.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_generate_formulas(
+    mz: f64,
+    iso_mz: *const f64,
+    iso_intensity: *const f64,
+    n_isotopes: usize,
+    opts: *const UlcmsFormulaSearchOptions,
+    out: *mut *mut UlcmsFormulaCandidates,
+) -> c_int {
+    if opts.is_null()
+        || out.is_null()
+        || (n_isotopes > 0 && (iso_mz.is_null() || iso_intensity.is_null()))
+    {
+        return 1;
+    }
+    let o = unsafe { &*opts };
+    let isotopes: Vec<(f64, f64)> = (0..n_isotopes)
+        .map(|i| unsafe { (*iso_mz.add(i), *iso_intensity.add(i)) })
+        .collect();
+
+    let res = catch_unwind(AssertUnwindSafe(|| {
+        let mut opts = FormulaSearchOptions {
+            ppm: o.ppm,
+            golden_rules: o.golden_rules != 0,
+            max_candidates: o.max_candidates,
+            ..FormulaSearchOptions::default()
+        };
+        if !o.adduct.is_null() {
+            opts.adduct = Adduct::parse(c_str(o.adduct)?)?;
+        }
+        if !o.elements.is_null() {
+            opts.elements = parse_element_ranges(c_str(o.elements)?)?;
+        }
+        generate_formulas(mz, &isotopes, &opts)
+    }));
+
+    match res {
+        Ok(Ok(candidates)) => {
+            let formulas = candidates.iter().map(|c| c.formula.to_string()).collect();
+            let h = UlcmsFormulaCandidates {
+                candidates,
+                formulas,
+            };
+            unsafe { *out = Box::into_raw(Box::new(h)) };
+            0
+        }
+        Ok(Err(_)) => 4,
+        Err(_) => 2,
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_formula_candidates_count(
+    candidates: *const UlcmsFormulaCandidates,
+) -> usize {
+    if candidates.is_null() {
+        return 0;
+    }
+    unsafe { (*candidates).candidates.len() }
+}
+
+/// Field `field` (a `ULCMS_CANDIDATE_*` constant) of candidate `index`;
+/// NaN when either is out of range.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_formula_candidate_get_f64(
+    candidates: *const UlcmsFormulaCandidates,
+    index: usize,
+    field: u32,
+) -> f64 {
+    if candidates.is_null() {
+        return f64::NAN;
+    }
+    let candidates = unsafe { &*candidates };
+    let Some(c) = candidates.candidates.get(index) else {
+        return f64::NAN;
+    };
+    match field {
+        ULCMS_CANDIDATE_NEUTRAL_MASS => c.neutral_mass,
+        ULCMS_CANDIDATE_MZ => c.mz,
+        ULCMS_CANDIDATE_PPM_ERROR => c.ppm_error,
+        ULCMS_CANDIDATE_RDBE => c.rdbe,
+        ULCMS_CANDIDATE_ISOTOPE_SCORE => c.isotope_score,
+        ULCMS_CANDIDATE_SCORE => c.score,
+        _ => f64::NAN,
+    }
+}
+
+/// Neutral formula of candidate `index`, in Hill order, as a borrowed,
+/// non-terminated UTF-8 slice valid until the handle is freed. Returns 3
+/// when the index is out of range.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_formula_candidate_formula(
+    candidates: *const UlcmsFormulaCandidates,
+    index: usize,
+    out_ptr: *mut *const u8,
+    out_len: *mut usize,
+) -> c_int {
+    if candidates.is_null() || out_ptr.is_null() || out_len.is_null() {
+        return 1;
+    }
+    let candidates = unsafe { &*candidates };
+    let Some(f) = candidates.formulas.get(index) else {
+        return 3;
+    };
+    unsafe {
+        *out_ptr = f.as_ptr();
+        *out_len = f.len();
+    }
+    0
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_formula_candidates_free(candidates: *mut UlcmsFormulaCandidates) {
+    if candidates.is_null() {
+        return;
+    }
+    unsafe {
+        let _ = Box::from_raw(candidates);
+    }
+}
@@ -0,0 +1,271 @@
+//! DIA isolation windows, cycles and fragment XICs.
+
+use core::ffi::c_int;
+use std::panic::{AssertUnwindSafe, catch_unwind};
+
+use crate::utilities::dia::{DiaCycles, FragmentXic, dia_cycles, fragment_xics};
+
+use super::binning::ULCMS_NO_SPECTRUM;
+use super::file::UlcmsFile;
+
+pub const ULCMS_DIA_WINDOW_TARGET: u32 = 0;
+pub const ULCMS_DIA_WINDOW_LOWER: u32 = 1;
+pub const ULCMS_DIA_WINDOW_UPPER: u32 = 2;
+pub const ULCMS_DIA_WINDOW_MS_LEVEL: u32 = 3;
+pub const ULCMS_DIA_WINDOW_N_SPECTRA: u32 = 4;
+
+/// Opaque isolation windows and cycles of a run; see `DiaCycles`.
+pub struct UlcmsDia {
+    dia: DiaCycles,
+    // Spectrum of each window per cycle, cycle after cycle, with
+    // `ULCMS_NO_SPECTRUM` for gaps.
+    cycle_spectra: Vec<usize>,
+}
+
+/// Groups the MSn spectra of `file` by isolation window, with bounds
+/// matching within `tolerance` m/z, and lays the run out in cycles.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_dia_cycles(
+    file: *const UlcmsFile,
+    tolerance: f64,
+    out: *mut *mut UlcmsDia,
+) -> c_int {
+    if file.is_null() || out.is_null() {
+        return 1;
+    }
+
+    let res = catch_unwind(AssertUnwindSafe(|| {
+        let spectra = unsafe { &(*file).spectra };
+        let dia = dia_cycles(spectra, tolerance);
+        let cycle_spectra = dia
+            .cycles
+            .iter()
+            .flat_map(|c| c.spectra.iter().map(|s| s.unwrap_or(ULCMS_NO_SPECTRUM)))
+            .collect();
+        UlcmsDia { dia, cycle_spectra }
+    }));
+
+    match res {
+        Ok(dia) => {
+            unsafe { *out = Box::into_raw(Box::new(dia)) };
+            0
+        }
+        Err(_) => 2,
+    }
+}
+
+/// Number of isolation windows and of cycles, and the median cycle time in
+/// minutes (NaN with fewer than two cycles).
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_dia_shape(
+    dia: *const UlcmsDia,
+    n_windows: *mut usize,
+    n_cycles: *mut usize,
+    cycle_time: *mut f64,
+) -> c_int {
+    if dia.is_null() || n_windows.is_null() || n_cycles.is_null() || cycle_time.is_null() {
+        return 1;
+    }
+    let dia = unsafe { &(*dia).dia };
+    unsafe {
+        *n_windows = dia.windows.len();
+        *n_cycles = dia.cycles.len();
+        *cycle_time = dia.cycle_time();
+    }
+    0
+}
+
+/// Field `field` (a `ULCMS_DIA_WINDOW_*` constant) of window `index`,
+/// windows being ordered by lower bound; NaN when missing or out of range.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_dia_window_get_f64(dia: *const UlcmsDia, index: usize, field: u32) -> f64 {
+    if dia.is_null() {
+        return f64::NAN;
+    }
+    let dia = unsafe { &(*dia).dia };
+    let Some(w) = dia.windows.get(index) else {
+        return f64::NAN;
+    };
+    let v = match field {
+        ULCMS_DIA_WINDOW_TARGET => Some(w.window.target),
+        ULCMS_DIA_WINDOW_LOWER => Some(w.window.lower),
+        ULCMS_DIA_WINDOW_UPPER => Some(w.window.upper),
+        ULCMS_DIA_WINDOW_MS_LEVEL => w.ms_level.map(f64::from),
+        ULCMS_DIA_WINDOW_N_SPECTRA => Some(w.spectra.len() as f64),
+        _ => None,
+    };
+    v.unwrap_or(f64::NAN)
+}
+
+/// Borrows the indices of the spectra of window `index`, by retention
+/// time. Valid until [`ulcms_dia_free`].
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_dia_window_spectra(
+    dia: *const UlcmsDia,
+    index: usize,
+    spectra: *mut *const usize,
+    len: *mut usize,
+) -> c_int {
+    if dia.is_null() || spectra.is_null() || len.is_null() {
+        return 1;
+    }
+    let dia = unsafe { &(*dia).dia };
+    let Some(w) = dia.windows.get(index) else {
+        return 3;
+    };
+    unsafe {
+        *spectra = w.spectra.as_ptr();
+        *len = w.spectra.len();
+    }
+    0
+}
+
+/// Cycle `index`: the MS1 scan opening it (`ULCMS_NO_SPECTRUM` for none),
+/// its retention time, and a borrowed array of `n_windows` spectrum
+/// indices, one per window with `ULCMS_NO_SPECTRUM` for windows not
+/// acquired in it. Valid until [`ulcms_dia_free`].
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_dia_cycle(
+    dia: *const UlcmsDia,
+    index: usize,
+    ms1: *mut usize,
+    rt: *mut f64,
+    spectra: *mut *const usize,
+) -> c_int {
+    if dia.is_null() || ms1.is_null() || rt.is_null() || spectra.is_null() {
+        return 1;
+    }
+    let handle = unsafe { &*dia };
+    let Some(c) = handle.dia.cycles.get(index) else {
+        return 3;
+    };
+    let n = handle.dia.windows.len();
+    unsafe {
+        *ms1 = c.ms1.unwrap_or(ULCMS_NO_SPECTRUM);
+        *rt = c.rt;
+        *spectra = handle.cycle_spectra[index * n..].as_ptr();
+    }
+    0
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_dia_free(dia: *mut UlcmsDia) {
+    if dia.is_null() {
+        return;
+    }
+    unsafe {
+        let _ = Box::from_raw(dia);
+    }
+}
+
+/// Opaque list of fragment XICs; see `FragmentXic`.
+pub struct UlcmsFragmentXics {
+    xics: Vec<FragmentXic>,
+}
+
+/// XICs of `n` precursor/fragment pairs over the spectra of `file` in the
+/// window of `dia` isolating each precursor, within `ppm` of the fragment
+/// and retention times in `[rt_min, rt_max]` (NaN for no limit). `dia` must
+/// have been built from `file`.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_dia_fragment_xics(
+    file: *const UlcmsFile,
+    dia: *const UlcmsDia,
+    precursor_mz: *const f64,
+    fragment_mz: *const f64,
+    n: usize,
+    ppm: f64,
+    rt_min: f64,
+    rt_max: f64,
+    out: *mut *mut UlcmsFragmentXics,
+) -> c_int {
+    if file.is_null()
+        || dia.is_null()
+        || precursor_mz.is_null()
+        || fragment_mz.is_null()
+        || out.is_null()
+    {
+        return 1;
+    }
+
+    let res = catch_unwind(AssertUnwindSafe(|| {
+        let spectra = unsafe { &(*file).spectra };
+        let windows = unsafe { &(*dia).dia.windows };
+        if windows
+            .iter()
+            .flat_map(|w| &w.spectra)
+            .any(|&i| i >= spectra.len())
+        {
+            return Err("windows of another file".to_string());
+        }
+        let precursors = unsafe { std::slice::from_raw_parts(precursor_mz, n) };
+        let fragments = unsafe { std::slice::from_raw_parts(fragment_mz, n) };
+        let pairs: Vec<(f64, f64)> = precursors
+            .iter()
+            .copied()
+            .zip(fragments.iter().copied())
+            .collect();
+        let rt = (!rt_min.is_nan() || !rt_max.is_nan()).then(|| {
+            (
+                if rt_min.is_nan() {
+                    f64::NEG_INFINITY
+                } else {
+                    rt_min
+                },
+                if rt_max.is_nan() {
+                    f64::INFINITY
+                } else {
+                    rt_max
+                },
+            )
+        });
+        Ok(fragment_xics(spectra, windows, &pairs, ppm, rt))
+    }));
+
+    match res {
+        Ok(Ok(xics)) => {
+            unsafe { *out = Box::into_raw(Box::new(UlcmsFragmentXics { xics })) };
+            0
+        }
+        Ok(Err(_)) => 4,
+        Err(_) => 2,
+    }
+}
+
+/// XIC `index`: the window it was taken from (`ULCMS_NO_SPECTRUM` when no
+/// window isolates the precursor) and borrowed arrays of `len` retention
+/// times and intensities. Valid until [`ulcms_fragment_xics_free`].
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_fragment_xic(
+    xics: *const UlcmsFragmentXics,
+    index: usize,
+    window: *mut usize,
+    rt: *mut *const f64,
+    intensity: *mut *const f64,
+    len: *mut usize,
+) -> c_int {
+    if xics.is_null() || window.is_null() || rt.is_null() || intensity.is_null() || len.is_null() {
+        return 1;
+    }
+    let xics = unsafe { &(*xics).xics };
+    let Some(x) = xics.get(index) else {
+        return 3;
+    };
+    unsafe {
+        *window = x.window.unwrap_or(ULCMS_NO_SPECTRUM);
+        *rt = x.rt.as_ptr();
+        *intensity = x.intensity.as_ptr();
+        *len = x.rt.len();
+    }
+    0
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_fragment_xics_free(xics: *mut UlcmsFragmentXics) {
+    if xics.is_null() {
+        return;
+    }
+    unsafe {
+        let _ = Box::from_raw(xics);
+    }
+}
@@ -0,0 +1,174 @@
+//! Feature detection on a parsed file.
+
+use core::ffi::c_int;
+use std::panic::{AssertUnwindSafe, catch_unwind};
+
+use crate::utilities::feature_detection::{
+    ChromPeakMethod, Feature, FeatureOptions, find_features,
+};
+
+use super::file::UlcmsFile;
+
+pub const ULCMS_CHROM_PEAK_CWT: u32 = 0;
+pub const ULCMS_CHROM_PEAK_MATCHED_FILTER: u32 = 1;
+
+/// Mirrors `FeatureOptions`; start from [`ulcms_feature_options_default`].
+/// `method` is a `ULCMS_CHROM_PEAK_*` constant and peak widths are in minutes.
+#[repr(C)]
+pub struct UlcmsFeatureOptions {
+    pub ppm: f64,
+    pub min_scans: usize,
+    pub max_gap: usize,
+    pub min_intensity: f64,
+    pub peak_width_min: f64,
+    pub peak_width_max: f64,
+    pub snr: f64,
+    pub method: u32,
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_feature_options_default() -> UlcmsFeatureOptions {
+    let o = FeatureOptions::default();
+    UlcmsFeatureOptions {
+        ppm: o.ppm,
+        min_scans: o.min_scans,
+        max_gap: o.max_gap,
+        min_intensity: o.min_intensity,
+        peak_width_min: o.peak_width.0,
+        peak_width_max: o.peak_width.1,
+        snr: o.snr,
+        method: ULCMS_CHROM_PEAK_CWT,
+    }
+}
+
+pub const ULCMS_FEATURE_MZ: u32 = 0;
+pub const ULCMS_FEATURE_MZ_MIN: u32 = 1;
+pub const ULCMS_FEATURE_MZ_MAX: u32 = 2;
+pub const ULCMS_FEATURE_RT: u32 = 3;
+pub const ULCMS_FEATURE_RT_MIN: u32 = 4;
+pub const ULCMS_FEATURE_RT_MAX: u32 = 5;
+pub const ULCMS_FEATURE_AREA: u32 = 6;
+pub const ULCMS_FEATURE_HEIGHT: u32 = 7;
+pub const ULCMS_FEATURE_SNR: u32 = 8;
+pub const ULCMS_FEATURE_SCANS: u32 = 9;
+
+/// Opaque feature table, read column-wise with [`ulcms_features_column`] or
+/// cell-wise with [`ulcms_feature_get_f64`].
+pub struct UlcmsFeatures {
+    pub(super) features: Vec<Feature>,
+}
+
+fn feature_field(f: &Feature, field: u32) -> Option<f64> {
+    match field {
+        ULCMS_FEATURE_MZ => Some(f.mz),
+        ULCMS_FEATURE_MZ_MIN => Some(f.mz_min),
+        ULCMS_FEATURE_MZ_MAX => Some(f.mz_max),
+        ULCMS_FEATURE_RT => Some(f.rt),
+        ULCMS_FEATURE_RT_MIN => Some(f.rt_min),
+        ULCMS_FEATURE_RT_MAX => Some(f.rt_max),
+        ULCMS_FEATURE_AREA => Some(f.area),
+        ULCMS_FEATURE_HEIGHT => Some(f.height),
+        ULCMS_FEATURE_SNR => Some(f.snr),
+        ULCMS_FEATURE_SCANS => Some(f.scans as f64),
+        _ => None,
+    }
+}
+
+/// Detects chromatographic features in the MS1 spectra of `file`.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_find_features(
+    file: *const UlcmsFile,
+    opts: *const UlcmsFeatureOptions,
+    out: *mut *mut UlcmsFeatures,
+) -> c_int {
+    if file.is_null() || opts.is_null() || out.is_null() {
+        return 1;
+    }
+    let o = unsafe { &*opts };
+    let method = match o.method {
+        ULCMS_CHROM_PEAK_CWT => ChromPeakMethod::Cwt,
+        ULCMS_CHROM_PEAK_MATCHED_FILTER => ChromPeakMethod::MatchedFilter,
+        _ => return 3,
+    };
+    let opts = FeatureOptions {
+        ppm: o.ppm,
+        min_scans: o.min_scans,
+        max_gap: o.max_gap,
+        min_intensity: o.min_intensity,
+        peak_width: (o.peak_width_min, o.peak_width_max),
+        snr: o.snr,
+        method,
+    };
+
+    let res = catch_unwind(AssertUnwindSafe(|| {
+        let file = unsafe { &*file };
+        find_features(&file.spectra, &opts)
+    }));
+
+    match res {
+        Ok(features) => {
+            unsafe { *out = Box::into_raw(Box::new(UlcmsFeatures { features })) };
+            0
+        }
+        Err(_) => 2,
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_features_count(features: *const UlcmsFeatures) -> usize {
+    if features.is_null() {
+        return 0;
+    }
+    unsafe { (*features).features.len() }
+}
+
+/// Field `field` (a `ULCMS_FEATURE_*` constant) of feature `index`; NaN
+/// when either is out of range.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_feature_get_f64(
+    features: *const UlcmsFeatures,
+    index: usize,
+    field: u32,
+) -> f64 {
+    if features.is_null() {
+        return f64::NAN;
+    }
+    let features = unsafe { &*features };
+    features
+        .features
+        .get(index)
+        .and_then(|f| feature_field(f, field))
+        .unwrap_or(f64::NAN)
+}
+
+/// Copies column `field` of the first `cap` features into `out` and returns
+/// how many values were written.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_features_column(
+    features: *const UlcmsFeatures,
+    field: u32,
+    out: *mut f64,
+    cap: usize,
+) -> usize {
+    if features.is_null() || out.is_null() {
+        return 0;
+    }
+    let features = unsafe { &*features };
+    let out = unsafe { std::slice::from_raw_parts_mut(out, cap) };
+    let mut n = 0;
+    for (dst, f) in out.iter_mut().zip(&features.features) {
+        *dst = feature_field(f, field).unwrap_or(f64::NAN);
+        n += 1;
+    }
+    n
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_features_free(features: *mut UlcmsFeatures) {
+    if features.is_null() {
+        return;
+    }
+    unsafe {
+        let _ = Box::from_raw(features);
+    }
+}
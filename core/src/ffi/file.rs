@@ -0,0 +1,517 @@
+//! Parsed files behind an opaque handle, with per-field accessors for
+//! their spectra, centroiding in place and the ABI version.
+
+use core::ffi::{c_char, c_int, c_void};
+use std::ffi::CStr;
+use std::fs;
+use std::panic::{AssertUnwindSafe, catch_unwind};
+use std::sync::OnceLock;
+
+use crate::utilities::parse_mzml::{ArrayData, ParseOptions, SpectrumSummary, parse_mzml_with};
+use crate::utilities::peak_picking::{ApexFit, PeakPicker, PeakPickingOptions, centroid_in_place};
+
+use super::c_str;
+use super::reader::{ArrayFFI, ULCMS_ARRAY_F32, ULCMS_ARRAY_F64, ULCMS_ARRAY_NONE};
+
+/// Bumped whenever an exported signature or `#[repr(C)]` layout changes.
+/// Fields added through the accessors below do not require a bump.
+pub const ULCMS_ABI_VERSION: u32 = 4;
+
+pub const ULCMS_FIELD_INDEX: u32 = 0;
+pub const ULCMS_FIELD_ARRAY_LENGTH: u32 = 1;
+pub const ULCMS_FIELD_MS_LEVEL: u32 = 2;
+pub const ULCMS_FIELD_RT: u32 = 3;
+pub const ULCMS_FIELD_SCAN_WINDOW_LOWER: u32 = 4;
+pub const ULCMS_FIELD_SCAN_WINDOW_UPPER: u32 = 5;
+pub const ULCMS_FIELD_TIC: u32 = 6;
+pub const ULCMS_FIELD_BASE_PEAK_INTENSITY: u32 = 7;
+pub const ULCMS_FIELD_BASE_PEAK_MZ: u32 = 8;
+pub const ULCMS_FIELD_PRECURSOR_MZ: u32 = 9;
+pub const ULCMS_FIELD_PRECURSOR_CHARGE: u32 = 10;
+pub const ULCMS_FIELD_ISOLATION_WINDOW_TARGET: u32 = 11;
+pub const ULCMS_FIELD_ISOLATION_WINDOW_LOWER: u32 = 12;
+pub const ULCMS_FIELD_ISOLATION_WINDOW_UPPER: u32 = 13;
+pub const ULCMS_FIELD_ION_MOBILITY: u32 = 14;
+
+pub const ULCMS_FIELD_ID: u32 = 100;
+pub const ULCMS_FIELD_SCAN_TYPE: u32 = 101;
+pub const ULCMS_FIELD_POLARITY: u32 = 102;
+pub const ULCMS_FIELD_SPECTRUM_TYPE: u32 = 103;
+pub const ULCMS_FIELD_ION_MOBILITY_TYPE: u32 = 104;
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_abi_version() -> u32 {
+    ULCMS_ABI_VERSION
+}
+
+/// Opaque handle over a parsed mzML file. Spectrum fields are read through
+/// accessors, so adding fields never changes a layout wrappers depend on.
+pub struct UlcmsFile {
+    pub(super) spectra: Vec<SpectrumSummary>,
+    // f64 copies of the m/z and intensity arrays stored as f32, made on the
+    // first `ulcms_spectrum_mz`/`_intensity` call. The stored arrays are
+    // never replaced, so pointers from `ulcms_spectrum_array` stay valid.
+    widened: Vec<[OnceLock<Vec<f64>>; 2]>,
+}
+
+impl UlcmsFile {
+    fn new(spectra: Vec<SpectrumSummary>) -> Self {
+        let widened = std::iter::repeat_with(Default::default)
+            .take(spectra.len())
+            .collect();
+        Self { spectra, widened }
+    }
+
+    // Spectra whose arrays are about to be rewritten; drops the stale copies.
+    pub(super) fn spectra_mut(&mut self) -> &mut [SpectrumSummary] {
+        self.widened.fill_with(Default::default);
+        &mut self.spectra
+    }
+}
+
+fn file_from_data(
+    data: &[u8],
+    opts: &ParseOptions,
+    out: *mut *mut UlcmsFile,
+) -> Result<(), String> {
+    let spectra = parse_mzml_with(data, opts)?;
+    file_from_spectra(spectra, out);
+    Ok(())
+}
+
+pub(super) fn file_from_spectra(spectra: Vec<SpectrumSummary>, out: *mut *mut UlcmsFile) {
+    unsafe {
+        *out = Box::into_raw(Box::new(UlcmsFile::new(spectra)));
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_file_open(path: *const c_char, out: *mut *mut UlcmsFile) -> c_int {
+    if path.is_null() || out.is_null() {
+        return 1;
+    }
+
+    let res = catch_unwind(AssertUnwindSafe(|| -> Result<(), String> {
+        let cstr = unsafe { CStr::from_ptr(path) };
+        let path_str = cstr.to_str().map_err(|_| "invalid UTF-8".to_string())?;
+        let data = fs::read(path_str).map_err(|e| format!("open/read: {e}"))?;
+        file_from_data(&data, &ParseOptions::default(), out)
+    }));
+
+    match res {
+        Ok(Ok(())) => 0,
+        Ok(Err(_)) => 4,
+        Err(_) => 2,
+    }
+}
+
+pub const ULCMS_POLARITY_ANY: u32 = 0;
+pub const ULCMS_POLARITY_POSITIVE: u32 = 1;
+pub const ULCMS_POLARITY_NEGATIVE: u32 = 2;
+
+/// Spectrum filters for [`ulcms_file_open_with`]; start from
+/// [`ulcms_parse_options_default`]. `ms_levels` points to `n_ms_levels`
+/// levels to keep (all when 0), NaN retention time limits are unset and
+/// `polarity` is a `ULCMS_POLARITY_*` constant. Skipped spectra are never
+/// decoded.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct UlcmsParseOptions {
+    pub ms_levels: *const u32,
+    pub n_ms_levels: usize,
+    pub rt_min: f64,
+    pub rt_max: f64,
+    pub polarity: u32,
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_parse_options_default() -> UlcmsParseOptions {
+    UlcmsParseOptions {
+        ms_levels: std::ptr::null(),
+        n_ms_levels: 0,
+        rt_min: f64::NAN,
+        rt_max: f64::NAN,
+        polarity: ULCMS_POLARITY_ANY,
+    }
+}
+
+/// [`ulcms_file_open`] keeping only the spectra `opts` selects. Returns 3
+/// for an unknown polarity.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_file_open_with(
+    path: *const c_char,
+    opts: *const UlcmsParseOptions,
+    out: *mut *mut UlcmsFile,
+) -> c_int {
+    if path.is_null() || opts.is_null() || out.is_null() {
+        return 1;
+    }
+    let o = unsafe { &*opts };
+    if o.n_ms_levels > 0 && o.ms_levels.is_null() {
+        return 1;
+    }
+    let polarity = match o.polarity {
+        ULCMS_POLARITY_ANY => None,
+        ULCMS_POLARITY_POSITIVE => Some("positive".to_string()),
+        ULCMS_POLARITY_NEGATIVE => Some("negative".to_string()),
+        _ => return 3,
+    };
+    let ms_levels = if o.n_ms_levels == 0 {
+        Vec::new()
+    } else {
+        unsafe { std::slice::from_raw_parts(o.ms_levels, o.n_ms_levels) }.to_vec()
+    };
+    let rt = (!o.rt_min.is_nan() || !o.rt_max.is_nan()).then(|| {
+        let lo = if o.rt_min.is_nan() {
+            f64::NEG_INFINITY
+        } else {
+            o.rt_min
+        };
+        let hi = if o.rt_max.is_nan() {
+            f64::INFINITY
+        } else {
+            o.rt_max
+        };
+        (lo, hi)
+    });
+    let opts = ParseOptions {
+        ms_levels,
+        rt,
+        polarity,
+        ..ParseOptions::default()
+    };
+
+    let res = catch_unwind(AssertUnwindSafe(|| -> Result<(), String> {
+        let path = c_str(path)?;
+        let data = fs::read(path).map_err(|e| format!("open/read: {e}"))?;
+        file_from_data(&data, &opts, out)
+    }));
+
+    match res {
+        Ok(Ok(())) => 0,
+        Ok(Err(_)) => 4,
+        Err(_) => 2,
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_file_open_from_bytes(
+    data_ptr: *const u8,
+    data_len: usize,
+    out: *mut *mut UlcmsFile,
+) -> c_int {
+    if data_ptr.is_null() || out.is_null() {
+        return 1;
+    }
+
+    let res = catch_unwind(AssertUnwindSafe(|| -> Result<(), String> {
+        let data = unsafe { std::slice::from_raw_parts(data_ptr, data_len) };
+        file_from_data(data, &ParseOptions::default(), out)
+    }));
+
+    match res {
+        Ok(Ok(())) => 0,
+        Ok(Err(_)) => 4,
+        Err(_) => 2,
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_file_close(file: *mut UlcmsFile) {
+    if file.is_null() {
+        return;
+    }
+    unsafe {
+        let _ = Box::from_raw(file);
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_spectrum_count(file: *const UlcmsFile) -> usize {
+    if file.is_null() {
+        return 0;
+    }
+    unsafe { (*file).spectra.len() }
+}
+
+fn spectrum_at<'a>(file: *const UlcmsFile, index: usize) -> Option<&'a SpectrumSummary> {
+    if file.is_null() {
+        return None;
+    }
+    let file = unsafe { &*file };
+    file.spectra.get(index)
+}
+
+/// Numeric field `field` (a `ULCMS_FIELD_*` constant) of spectrum `index`.
+/// Returns NaN when the spectrum, the field or its value is missing.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_spectrum_get_f64(file: *const UlcmsFile, index: usize, field: u32) -> f64 {
+    let Some(s) = spectrum_at(file, index) else {
+        return f64::NAN;
+    };
+    let v = match field {
+        ULCMS_FIELD_INDEX => Some(s.index as f64),
+        ULCMS_FIELD_ARRAY_LENGTH => Some(s.array_length as f64),
+        ULCMS_FIELD_MS_LEVEL => s.ms_level.map(f64::from),
+        ULCMS_FIELD_RT => s.retention_time,
+        ULCMS_FIELD_SCAN_WINDOW_LOWER => s.scan_window_lower_limit,
+        ULCMS_FIELD_SCAN_WINDOW_UPPER => s.scan_window_upper_limit,
+        ULCMS_FIELD_TIC => s.total_ion_current,
+        ULCMS_FIELD_BASE_PEAK_INTENSITY => s.base_peak_intensity,
+        ULCMS_FIELD_BASE_PEAK_MZ => s.base_peak_mz,
+        ULCMS_FIELD_PRECURSOR_MZ => s.precursor_mz,
+        ULCMS_FIELD_PRECURSOR_CHARGE => s.precursor_charge.map(f64::from),
+        ULCMS_FIELD_ISOLATION_WINDOW_TARGET => s.isolation_window_target,
+        ULCMS_FIELD_ISOLATION_WINDOW_LOWER => s.isolation_window_lower,
+        ULCMS_FIELD_ISOLATION_WINDOW_UPPER => s.isolation_window_upper,
+        ULCMS_FIELD_ION_MOBILITY => s.ion_mobility,
+        _ => None,
+    };
+    v.unwrap_or(f64::NAN)
+}
+
+/// String field `field` of spectrum `index` as a borrowed, non-terminated
+/// UTF-8 slice valid until the file is closed. Returns 3 when the spectrum
+/// or field is unknown, and a null pointer when the value is missing.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_spectrum_get_str(
+    file: *const UlcmsFile,
+    index: usize,
+    field: u32,
+    out_ptr: *mut *const u8,
+    out_len: *mut usize,
+) -> c_int {
+    if file.is_null() || out_ptr.is_null() || out_len.is_null() {
+        return 1;
+    }
+    let Some(s) = spectrum_at(file, index) else {
+        return 3;
+    };
+    let v = match field {
+        ULCMS_FIELD_ID => Some(s.id.as_str()),
+        ULCMS_FIELD_SCAN_TYPE => s.scan_type.as_deref(),
+        ULCMS_FIELD_POLARITY => s.polarity.as_deref(),
+        ULCMS_FIELD_SPECTRUM_TYPE => s.spectrum_type.as_deref(),
+        ULCMS_FIELD_ION_MOBILITY_TYPE => s.ion_mobility_type.as_deref(),
+        _ => return 3,
+    };
+    unsafe {
+        match v {
+            Some(v) => {
+                *out_ptr = v.as_ptr();
+                *out_len = v.len();
+            }
+            None => {
+                *out_ptr = core::ptr::null();
+                *out_len = 0;
+            }
+        }
+    }
+    0
+}
+
+// Borrows one array of spectrum `index` as f64, widening an f32 array into
+// the file's cache the first time.
+fn spectrum_array_f64(
+    file: *const UlcmsFile,
+    index: usize,
+    which: usize,
+    out_ptr: *mut *const f64,
+    out_len: *mut usize,
+) -> c_int {
+    if file.is_null() || out_ptr.is_null() || out_len.is_null() {
+        return 1;
+    }
+    let file = unsafe { &*file };
+    let Some(s) = file.spectra.get(index) else {
+        return 3;
+    };
+    let array = if which == 0 {
+        &s.mz_array
+    } else {
+        &s.intensity_array
+    };
+    let v: &[f64] = match array {
+        Some(ArrayData::F64(v)) => v,
+        Some(ArrayData::F32(v)) => {
+            file.widened[index][which].get_or_init(|| v.iter().map(|&x| x as f64).collect())
+        }
+        None => &[],
+    };
+    unsafe {
+        *out_ptr = if array.is_some() {
+            v.as_ptr()
+        } else {
+            core::ptr::null()
+        };
+        *out_len = v.len();
+    }
+    0
+}
+
+/// Borrows the m/z array of spectrum `index` as doubles; valid until the
+/// file is closed or its arrays are rewritten. An array stored as f32 is
+/// copied once into a cache owned by the file, leaving the stored array and
+/// views of it untouched. A missing array yields a null pointer and length 0.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_spectrum_mz(
+    file: *const UlcmsFile,
+    index: usize,
+    out_ptr: *mut *const f64,
+    out_len: *mut usize,
+) -> c_int {
+    spectrum_array_f64(file, index, 0, out_ptr, out_len)
+}
+
+/// Intensity counterpart of [`ulcms_spectrum_mz`].
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_spectrum_intensity(
+    file: *const UlcmsFile,
+    index: usize,
+    out_ptr: *mut *const f64,
+    out_len: *mut usize,
+) -> c_int {
+    spectrum_array_f64(file, index, 1, out_ptr, out_len)
+}
+
+/// Borrows one array of spectrum `index` in its stored precision. `which`
+/// is 0 for m/z, 1 for intensity and 2 for per-peak ion mobility; `data`
+/// in `out` is owned by the file.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_spectrum_array(
+    file: *const UlcmsFile,
+    index: usize,
+    which: u32,
+    out: *mut ArrayFFI,
+) -> c_int {
+    if file.is_null() || out.is_null() {
+        return 1;
+    }
+    let Some(s) = spectrum_at(file, index) else {
+        return 3;
+    };
+    let arr = match which {
+        0 => &s.mz_array,
+        1 => &s.intensity_array,
+        2 => &s.mobility_array,
+        _ => return 3,
+    };
+    let view = match arr {
+        Some(ArrayData::F32(v)) => ArrayFFI {
+            data: v.as_ptr() as *mut c_void,
+            len: v.len(),
+            element_type: ULCMS_ARRAY_F32,
+        },
+        Some(ArrayData::F64(v)) => ArrayFFI {
+            data: v.as_ptr() as *mut c_void,
+            len: v.len(),
+            element_type: ULCMS_ARRAY_F64,
+        },
+        None => ArrayFFI {
+            data: core::ptr::null_mut(),
+            len: 0,
+            element_type: ULCMS_ARRAY_NONE,
+        },
+    };
+    unsafe {
+        *out = view;
+    }
+    0
+}
+
+fn spectrum_array_at<'a>(
+    file: *const UlcmsFile,
+    index: usize,
+    which: u32,
+) -> Option<&'a ArrayData> {
+    let s = spectrum_at(file, index)?;
+    match which {
+        0 => s.mz_array.as_ref(),
+        1 => s.intensity_array.as_ref(),
+        2 => s.mobility_array.as_ref(),
+        _ => None,
+    }
+}
+
+/// Data pointer of one array of spectrum `index` (`which` as in
+/// [`ulcms_spectrum_array`]), or null when missing. These three accessors
+/// return plain scalars for hosts, such as wasm, where out-pointers are
+/// awkward.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_spectrum_array_data(
+    file: *const UlcmsFile,
+    index: usize,
+    which: u32,
+) -> *const c_void {
+    match spectrum_array_at(file, index, which) {
+        Some(ArrayData::F32(v)) => v.as_ptr() as *const c_void,
+        Some(ArrayData::F64(v)) => v.as_ptr() as *const c_void,
+        None => core::ptr::null(),
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_spectrum_array_len(
+    file: *const UlcmsFile,
+    index: usize,
+    which: u32,
+) -> usize {
+    spectrum_array_at(file, index, which).map_or(0, ArrayData::len)
+}
+
+/// `ULCMS_ARRAY_*` element type of one array of spectrum `index`.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_spectrum_array_type(
+    file: *const UlcmsFile,
+    index: usize,
+    which: u32,
+) -> u32 {
+    match spectrum_array_at(file, index, which) {
+        Some(ArrayData::F32(_)) => ULCMS_ARRAY_F32,
+        Some(ArrayData::F64(_)) => ULCMS_ARRAY_F64,
+        None => ULCMS_ARRAY_NONE,
+    }
+}
+
+pub const ULCMS_PICKER_PARABOLIC: u32 = 0;
+pub const ULCMS_PICKER_GAUSSIAN: u32 = 1;
+/// Wavelet picker over scales of 1 to 8 profile points.
+pub const ULCMS_PICKER_CWT: u32 = 2;
+
+/// Centroids every profile spectrum of `file` in place with the picker
+/// `picker` (a `ULCMS_PICKER_*` constant); see `PeakPickingOptions` for
+/// `snr` and `min_width`. Array pointers obtained earlier are invalidated.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_file_centroid(
+    file: *mut UlcmsFile,
+    picker: u32,
+    snr: f64,
+    min_width: usize,
+) -> c_int {
+    if file.is_null() {
+        return 1;
+    }
+    let picker = match picker {
+        ULCMS_PICKER_PARABOLIC => PeakPicker::LocalMaxima(ApexFit::Parabolic),
+        ULCMS_PICKER_GAUSSIAN => PeakPicker::LocalMaxima(ApexFit::Gaussian),
+        ULCMS_PICKER_CWT => PeakPicker::Cwt { max_scale: 8 },
+        _ => return 3,
+    };
+    let opts = PeakPickingOptions {
+        picker,
+        snr,
+        min_width,
+    };
+
+    let res = catch_unwind(AssertUnwindSafe(|| {
+        let file = unsafe { &mut *file };
+        for s in file.spectra_mut() {
+            centroid_in_place(s, &opts);
+        }
+    }));
+
+    match res {
+        Ok(()) => 0,
+        Err(_) => 2,
+    }
+}
@@ -0,0 +1,299 @@
+//! Grouping features across samples and filling gaps.
+
+use core::ffi::{c_char, c_int};
+use std::ffi::CStr;
+use std::fs;
+use std::panic::{AssertUnwindSafe, catch_unwind};
+
+use crate::utilities::correspondence::{FeatureTable, GroupingOptions, fill_gaps, group_features};
+use crate::utilities::feature_detection::Feature;
+
+use super::features::UlcmsFeatures;
+use super::file::UlcmsFile;
+
+/// Mirrors `GroupingOptions`; start from [`ulcms_grouping_options_default`].
+#[repr(C)]
+pub struct UlcmsGroupingOptions {
+    pub ppm: f64,
+    pub bandwidth: f64,
+    pub min_fraction: f64,
+    pub min_samples: usize,
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_grouping_options_default() -> UlcmsGroupingOptions {
+    let o = GroupingOptions::default();
+    UlcmsGroupingOptions {
+        ppm: o.ppm,
+        bandwidth: o.bandwidth,
+        min_fraction: o.min_fraction,
+        min_samples: o.min_samples,
+    }
+}
+
+pub const ULCMS_GROUP_MZ: u32 = 0;
+pub const ULCMS_GROUP_MZ_MIN: u32 = 1;
+pub const ULCMS_GROUP_MZ_MAX: u32 = 2;
+pub const ULCMS_GROUP_RT: u32 = 3;
+pub const ULCMS_GROUP_RT_MIN: u32 = 4;
+pub const ULCMS_GROUP_RT_MAX: u32 = 5;
+pub const ULCMS_GROUP_N_SAMPLES: u32 = 6;
+
+/// Opaque feature groups x samples table of areas; see `FeatureTable`.
+pub struct UlcmsFeatureTable {
+    table: FeatureTable,
+}
+
+/// Groups the feature tables of `n_samples` samples, given in sample order.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_group_features(
+    samples: *const *const UlcmsFeatures,
+    n_samples: usize,
+    opts: *const UlcmsGroupingOptions,
+    out: *mut *mut UlcmsFeatureTable,
+) -> c_int {
+    if samples.is_null() || opts.is_null() || out.is_null() {
+        return 1;
+    }
+    let samples = unsafe { std::slice::from_raw_parts(samples, n_samples) };
+    if samples.iter().any(|s| s.is_null()) {
+        return 1;
+    }
+    let o = unsafe { &*opts };
+    let opts = GroupingOptions {
+        ppm: o.ppm,
+        bandwidth: o.bandwidth,
+        min_fraction: o.min_fraction,
+        min_samples: o.min_samples,
+    };
+
+    let res = catch_unwind(AssertUnwindSafe(|| {
+        let tables: Vec<&[Feature]> = samples
+            .iter()
+            .map(|&s| unsafe { (*s).features.as_slice() })
+            .collect();
+        group_features(&tables, &opts)
+    }));
+
+    match res {
+        Ok(table) => {
+            unsafe { *out = Box::into_raw(Box::new(UlcmsFeatureTable { table })) };
+            0
+        }
+        Err(_) => 2,
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_feature_table_groups(table: *const UlcmsFeatureTable) -> usize {
+    if table.is_null() {
+        return 0;
+    }
+    unsafe { (*table).table.groups.len() }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_feature_table_samples(table: *const UlcmsFeatureTable) -> usize {
+    if table.is_null() {
+        return 0;
+    }
+    unsafe { (*table).table.n_samples }
+}
+
+/// Field `field` (a `ULCMS_GROUP_*` constant) of group `group`; NaN when
+/// either is out of range.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_feature_group_get_f64(
+    table: *const UlcmsFeatureTable,
+    group: usize,
+    field: u32,
+) -> f64 {
+    if table.is_null() {
+        return f64::NAN;
+    }
+    let table = unsafe { &(*table).table };
+    let Some(g) = table.groups.get(group) else {
+        return f64::NAN;
+    };
+    match field {
+        ULCMS_GROUP_MZ => g.mz,
+        ULCMS_GROUP_MZ_MIN => g.mz_min,
+        ULCMS_GROUP_MZ_MAX => g.mz_max,
+        ULCMS_GROUP_RT => g.rt,
+        ULCMS_GROUP_RT_MIN => g.rt_min,
+        ULCMS_GROUP_RT_MAX => g.rt_max,
+        ULCMS_GROUP_N_SAMPLES => g.n_samples as f64,
+        _ => f64::NAN,
+    }
+}
+
+/// Area of group `group` in sample `sample`; NaN when missing.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_feature_table_area(
+    table: *const UlcmsFeatureTable,
+    group: usize,
+    sample: usize,
+) -> f64 {
+    if table.is_null() {
+        return f64::NAN;
+    }
+    unsafe { (*table).table.area(group, sample) }
+}
+
+/// 1 if the area of `group` in `sample` came from gap filling, else 0.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_feature_table_is_filled(
+    table: *const UlcmsFeatureTable,
+    group: usize,
+    sample: usize,
+) -> c_int {
+    if table.is_null() {
+        return 0;
+    }
+    let table = unsafe { &(*table).table };
+    if sample >= table.n_samples {
+        return 0;
+    }
+    let filled = table.filled.get(group * table.n_samples + sample);
+    c_int::from(filled.copied().unwrap_or(false))
+}
+
+/// Copies up to `cap` areas, row-major groups x samples, into `out` and
+/// returns how many were written.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_feature_table_areas(
+    table: *const UlcmsFeatureTable,
+    out: *mut f64,
+    cap: usize,
+) -> usize {
+    if table.is_null() || out.is_null() {
+        return 0;
+    }
+    let areas = unsafe { &(*table).table.areas };
+    let n = areas.len().min(cap);
+    unsafe { std::slice::from_raw_parts_mut(out, n).copy_from_slice(&areas[..n]) };
+    n
+}
+
+/// Fills the missing areas of `sample` from the raw spectra in `file`,
+/// whose retention times must already be aligned (see
+/// `ulcms_file_apply_warp`). The m/z window of each group is widened by
+/// `ppm`. Writes the number of filled cells to `filled` when non-null.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_feature_table_fill_gaps(
+    table: *mut UlcmsFeatureTable,
+    sample: usize,
+    file: *const UlcmsFile,
+    ppm: f64,
+    filled: *mut usize,
+) -> c_int {
+    if table.is_null() || file.is_null() {
+        return 1;
+    }
+    let (table, file) = unsafe { (&mut (*table).table, &*file) };
+    if sample >= table.n_samples {
+        return 3;
+    }
+
+    let res = catch_unwind(AssertUnwindSafe(|| {
+        fill_gaps(table, sample, &file.spectra, ppm)
+    }));
+
+    match res {
+        Ok(n) => {
+            if !filled.is_null() {
+                unsafe { *filled = n };
+            }
+            0
+        }
+        Err(_) => 2,
+    }
+}
+
+/// Writes the table as CSV to `path`. `sample_names` may be null, or hold
+/// one UTF-8 name per sample for the area column headers.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_feature_table_write_csv(
+    table: *const UlcmsFeatureTable,
+    path: *const c_char,
+    sample_names: *const *const c_char,
+) -> c_int {
+    if table.is_null() || path.is_null() {
+        return 1;
+    }
+    let table = unsafe { &(*table).table };
+
+    let res = catch_unwind(AssertUnwindSafe(|| -> Result<(), String> {
+        let path = unsafe { CStr::from_ptr(path) }
+            .to_str()
+            .map_err(|_| "invalid UTF-8".to_string())?;
+        let names = names_from_c(sample_names, table.n_samples)?;
+        let mut f = fs::File::create(path).map_err(|e| format!("create {path}: {e}"))?;
+        table.write_csv(&mut f, &names)
+    }));
+
+    match res {
+        Ok(Ok(())) => 0,
+        Ok(Err(_)) => 4,
+        Err(_) => 2,
+    }
+}
+
+/// Writes the table as Parquet to `path`, with the columns and
+/// `sample_names` of [`ulcms_feature_table_write_csv`]. Only built with
+/// the `parquet` cargo feature.
+#[cfg(feature = "parquet")]
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_feature_table_write_parquet(
+    table: *const UlcmsFeatureTable,
+    path: *const c_char,
+    sample_names: *const *const c_char,
+) -> c_int {
+    if table.is_null() || path.is_null() {
+        return 1;
+    }
+    let table = unsafe { &(*table).table };
+
+    let res = catch_unwind(AssertUnwindSafe(|| -> Result<(), String> {
+        let path = unsafe { CStr::from_ptr(path) }
+            .to_str()
+            .map_err(|_| "invalid UTF-8".to_string())?;
+        let names = names_from_c(sample_names, table.n_samples)?;
+        let f = fs::File::create(path).map_err(|e| format!("create {path}: {e}"))?;
+        table.write_parquet(f, &names)
+    }));
+
+    match res {
+        Ok(Ok(())) => 0,
+        Ok(Err(_)) => 4,
+        Err(_) => 2,
+    }
+}
+
+// `n` UTF-8 sample names, or none when `names` is null.
+fn names_from_c<'a>(names: *const *const c_char, n: usize) -> Result<Vec<&'a str>, String> {
+    if names.is_null() {
+        return Ok(Vec::new());
+    }
+    let ptrs = unsafe { std::slice::from_raw_parts(names, n) };
+    ptrs.iter()
+        .map(|&p| {
+            if p.is_null() {
+                return Err("null sample name".to_string());
+            }
+            unsafe { CStr::from_ptr(p) }
+                .to_str()
+                .map_err(|_| "invalid UTF-8".to_string())
+        })
+        .collect()
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_feature_table_free(table: *mut UlcmsFeatureTable) {
+    if table.is_null() {
+        return;
+    }
+    unsafe {
+        let _ = Box::from_raw(table);
+    }
+}
@@ -0,0 +1,293 @@
+//! Spectral library loading and search.
+
+use core::ffi::{c_char, c_int};
+use std::fs;
+use std::panic::{AssertUnwindSafe, catch_unwind};
+
+use crate::utilities::library::{
+    LibraryMatch, LibraryOptions, SearchOptions, SpectralLibrary, parse_library, search_spectra,
+};
+use crate::utilities::similarity::FilterOptions;
+
+use super::c_str;
+use super::file::UlcmsFile;
+use super::similarity::{
+    ULCMS_TRANSFORM_NONE, UlcmsSimilarityOptions, intensity_transform, similarity_options,
+    ulcms_similarity_options_default,
+};
+
+/// Mirrors `LibraryOptions`; start from [`ulcms_library_options_default`].
+/// `transform` is a `ULCMS_TRANSFORM_*` constant.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct UlcmsLibraryOptions {
+    pub noise: f64,
+    pub top_n: usize,
+    pub transform: u32,
+    pub fragment_index: c_int,
+    pub fragment_bin: f64,
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_library_options_default() -> UlcmsLibraryOptions {
+    let o = LibraryOptions::default();
+    UlcmsLibraryOptions {
+        noise: o.filter.noise,
+        top_n: o.filter.top_n,
+        transform: ULCMS_TRANSFORM_NONE,
+        fragment_index: o.fragment_index as c_int,
+        fragment_bin: o.fragment_bin,
+    }
+}
+
+/// Opaque in-memory spectral library; see `SpectralLibrary`.
+pub struct UlcmsLibrary {
+    library: SpectralLibrary,
+}
+
+/// Reads an MSP or MGF library (MGF when the file has a `BEGIN IONS`
+/// line) and indexes it.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_library_open(
+    path: *const c_char,
+    opts: *const UlcmsLibraryOptions,
+    out: *mut *mut UlcmsLibrary,
+) -> c_int {
+    if path.is_null() || opts.is_null() || out.is_null() {
+        return 1;
+    }
+    let o = unsafe { &*opts };
+    let Some(transform) = intensity_transform(o.transform) else {
+        return 3;
+    };
+    let opts = LibraryOptions {
+        filter: FilterOptions {
+            noise: o.noise,
+            top_n: o.top_n,
+            transform,
+        },
+        fragment_index: o.fragment_index != 0,
+        fragment_bin: o.fragment_bin,
+    };
+
+    let res = catch_unwind(AssertUnwindSafe(|| -> Result<SpectralLibrary, String> {
+        let text = fs::read_to_string(c_str(path)?).map_err(|e| e.to_string())?;
+        Ok(SpectralLibrary::new(parse_library(&text)?, opts))
+    }));
+
+    match res {
+        Ok(Ok(library)) => {
+            unsafe { *out = Box::into_raw(Box::new(UlcmsLibrary { library })) };
+            0
+        }
+        Ok(Err(_)) => 4,
+        Err(_) => 2,
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_library_count(library: *const UlcmsLibrary) -> usize {
+    if library.is_null() {
+        return 0;
+    }
+    unsafe { (*library).library.len() }
+}
+
+pub const ULCMS_ENTRY_PRECURSOR_MZ: u32 = 0;
+pub const ULCMS_ENTRY_CHARGE: u32 = 1;
+pub const ULCMS_ENTRY_N_PEAKS: u32 = 2;
+
+/// Field `field` (a `ULCMS_ENTRY_*` constant) of library entry `index`;
+/// NaN when missing or out of range.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_library_entry_get_f64(
+    library: *const UlcmsLibrary,
+    index: usize,
+    field: u32,
+) -> f64 {
+    if library.is_null() {
+        return f64::NAN;
+    }
+    let library = unsafe { &(*library).library };
+    let Some(s) = library.spectra.get(index) else {
+        return f64::NAN;
+    };
+    let v = match field {
+        ULCMS_ENTRY_PRECURSOR_MZ => s.precursor_mz,
+        ULCMS_ENTRY_CHARGE => s.charge.map(f64::from),
+        ULCMS_ENTRY_N_PEAKS => Some(s.mz.len() as f64),
+        _ => None,
+    };
+    v.unwrap_or(f64::NAN)
+}
+
+/// Field `key` (such as `Name`, `Precursor_type` or `InChIKey`, compared
+/// ignoring case and `_`) of library entry `index`, as a borrowed,
+/// non-terminated UTF-8 slice valid until the library is freed. Returns 3
+/// when the entry is out of range, and a null pointer when the field is
+/// missing.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_library_entry_get_str(
+    library: *const UlcmsLibrary,
+    index: usize,
+    key: *const c_char,
+    out_ptr: *mut *const u8,
+    out_len: *mut usize,
+) -> c_int {
+    if library.is_null() || key.is_null() || out_ptr.is_null() || out_len.is_null() {
+        return 1;
+    }
+    let library = unsafe { &(*library).library };
+    let Some(s) = library.spectra.get(index) else {
+        return 3;
+    };
+    let Ok(key) = c_str(key) else {
+        return 4;
+    };
+    unsafe {
+        match s.get(key) {
+            Some(v) => {
+                *out_ptr = v.as_ptr();
+                *out_len = v.len();
+            }
+            None => {
+                *out_ptr = core::ptr::null();
+                *out_len = 0;
+            }
+        }
+    }
+    0
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_library_free(library: *mut UlcmsLibrary) {
+    if library.is_null() {
+        return;
+    }
+    unsafe {
+        let _ = Box::from_raw(library);
+    }
+}
+
+/// Mirrors `SearchOptions`; start from [`ulcms_search_options_default`].
+/// The filter fields of `similarity` are ignored: queries are filtered like
+/// the library.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct UlcmsSearchOptions {
+    pub similarity: UlcmsSimilarityOptions,
+    pub precursor_ppm: f64,
+    pub min_score: f64,
+    pub min_matched_peaks: usize,
+    pub top_n: usize,
+    pub min_shared_fragments: usize,
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_search_options_default() -> UlcmsSearchOptions {
+    let o = SearchOptions::default();
+    UlcmsSearchOptions {
+        similarity: ulcms_similarity_options_default(),
+        precursor_ppm: o.precursor_ppm,
+        min_score: o.min_score,
+        min_matched_peaks: o.min_matched_peaks,
+        top_n: o.top_n,
+        min_shared_fragments: o.min_shared_fragments,
+    }
+}
+
+pub const ULCMS_MATCH_SPECTRUM: u32 = 0;
+pub const ULCMS_MATCH_RANK: u32 = 1;
+pub const ULCMS_MATCH_ENTRY: u32 = 2;
+pub const ULCMS_MATCH_SCORE: u32 = 3;
+pub const ULCMS_MATCH_MATCHED_PEAKS: u32 = 4;
+pub const ULCMS_MATCH_PRECURSOR_PPM: u32 = 5;
+
+/// Opaque library search result, one row per hit; see `LibraryMatch`.
+pub struct UlcmsLibraryMatches {
+    matches: Vec<LibraryMatch>,
+}
+
+/// Searches the MSn spectra of `file` against `library`.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_library_search(
+    library: *const UlcmsLibrary,
+    file: *const UlcmsFile,
+    opts: *const UlcmsSearchOptions,
+    out: *mut *mut UlcmsLibraryMatches,
+) -> c_int {
+    if library.is_null() || file.is_null() || opts.is_null() || out.is_null() {
+        return 1;
+    }
+    let o = unsafe { &*opts };
+    let Some((_, similarity)) = similarity_options(&o.similarity) else {
+        return 3;
+    };
+    let opts = SearchOptions {
+        similarity,
+        precursor_ppm: o.precursor_ppm,
+        min_score: o.min_score,
+        min_matched_peaks: o.min_matched_peaks,
+        top_n: o.top_n,
+        min_shared_fragments: o.min_shared_fragments,
+    };
+
+    let res = catch_unwind(AssertUnwindSafe(|| {
+        let library = unsafe { &(*library).library };
+        let spectra = unsafe { &(*file).spectra };
+        search_spectra(library, spectra, &opts)
+    }));
+
+    match res {
+        Ok(matches) => {
+            unsafe { *out = Box::into_raw(Box::new(UlcmsLibraryMatches { matches })) };
+            0
+        }
+        Err(_) => 2,
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_library_matches_count(matches: *const UlcmsLibraryMatches) -> usize {
+    if matches.is_null() {
+        return 0;
+    }
+    unsafe { (*matches).matches.len() }
+}
+
+/// Field `field` (a `ULCMS_MATCH_*` constant) of match `index`; NaN when
+/// missing or out of range.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_library_match_get_f64(
+    matches: *const UlcmsLibraryMatches,
+    index: usize,
+    field: u32,
+) -> f64 {
+    if matches.is_null() {
+        return f64::NAN;
+    }
+    let matches = unsafe { &(*matches).matches };
+    let Some(m) = matches.get(index) else {
+        return f64::NAN;
+    };
+    let v = match field {
+        ULCMS_MATCH_SPECTRUM => Some(m.spectrum as f64),
+        ULCMS_MATCH_RANK => Some(m.rank as f64),
+        ULCMS_MATCH_ENTRY => Some(m.hit.entry as f64),
+        ULCMS_MATCH_SCORE => Some(m.hit.score),
+        ULCMS_MATCH_MATCHED_PEAKS => Some(m.hit.matched_peaks as f64),
+        ULCMS_MATCH_PRECURSOR_PPM => m.hit.precursor_ppm,
+        _ => None,
+    };
+    v.unwrap_or(f64::NAN)
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_library_matches_free(matches: *mut UlcmsLibraryMatches) {
+    if matches.is_null() {
+        return;
+    }
+    unsafe {
+        let _ = Box::from_raw(matches);
+    }
+}
@@ -0,0 +1,258 @@
+//! Merging spectra by index, retention time window or precursor.
+
+use core::ffi::c_int;
+use std::panic::{AssertUnwindSafe, catch_unwind};
+
+use crate::utilities::merge::{
+    MergeIntensity, MergeOptions, MergedSpectrum, group_by_precursor, merge_spectra,
+    spectra_in_rt_window,
+};
+use crate::utilities::parse_mzml::SpectrumSummary;
+
+use super::file::UlcmsFile;
+
+pub const ULCMS_MERGE_MEAN: u32 = 0;
+pub const ULCMS_MERGE_SUM: u32 = 1;
+
+/// Mirrors `MergeOptions`; start from [`ulcms_merge_options_default`].
+/// `intensity` is a `ULCMS_MERGE_*` constant.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct UlcmsMergeOptions {
+    pub ppm: f64,
+    pub mz_tolerance: f64,
+    pub intensity: u32,
+    pub min_occurrence: f64,
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_merge_options_default() -> UlcmsMergeOptions {
+    let o = MergeOptions::default();
+    UlcmsMergeOptions {
+        ppm: o.ppm,
+        mz_tolerance: o.mz_tolerance,
+        intensity: ULCMS_MERGE_MEAN,
+        min_occurrence: o.min_occurrence,
+    }
+}
+
+fn merge_options(o: &UlcmsMergeOptions) -> Option<MergeOptions> {
+    let intensity = match o.intensity {
+        ULCMS_MERGE_MEAN => MergeIntensity::Mean,
+        ULCMS_MERGE_SUM => MergeIntensity::Sum,
+        _ => return None,
+    };
+    Some(MergeOptions {
+        ppm: o.ppm,
+        mz_tolerance: o.mz_tolerance,
+        intensity,
+        min_occurrence: o.min_occurrence,
+    })
+}
+
+pub const ULCMS_MERGED_MS_LEVEL: u32 = 0;
+pub const ULCMS_MERGED_RETENTION_TIME: u32 = 1;
+pub const ULCMS_MERGED_RT_MIN: u32 = 2;
+pub const ULCMS_MERGED_RT_MAX: u32 = 3;
+pub const ULCMS_MERGED_PRECURSOR_MZ: u32 = 4;
+pub const ULCMS_MERGED_PRECURSOR_CHARGE: u32 = 5;
+pub const ULCMS_MERGED_N_SPECTRA: u32 = 6;
+pub const ULCMS_MERGED_N_PEAKS: u32 = 7;
+
+/// Opaque list of consensus spectra; see `MergedSpectrum`.
+pub struct UlcmsMerged {
+    merged: Vec<MergedSpectrum>,
+}
+
+// Runs a merge over the spectra of `file` and boxes the result into `out`.
+fn merged_into(
+    file: *const UlcmsFile,
+    out: *mut *mut UlcmsMerged,
+    f: impl FnOnce(&[SpectrumSummary]) -> Result<Vec<MergedSpectrum>, String>,
+) -> c_int {
+    let res = catch_unwind(AssertUnwindSafe(|| {
+        let spectra = unsafe { &(*file).spectra };
+        f(spectra)
+    }));
+    match res {
+        Ok(Ok(merged)) => {
+            unsafe { *out = Box::into_raw(Box::new(UlcmsMerged { merged })) };
+            0
+        }
+        Ok(Err(_)) => 4,
+        Err(_) => 2,
+    }
+}
+
+/// Merges the `n` spectra of `file` at `indices` into one. Returns 4 when
+/// an index is out of range or the MS levels differ.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_merge_spectra(
+    file: *const UlcmsFile,
+    indices: *const usize,
+    n: usize,
+    opts: *const UlcmsMergeOptions,
+    out: *mut *mut UlcmsMerged,
+) -> c_int {
+    if file.is_null() || indices.is_null() || opts.is_null() || out.is_null() {
+        return 1;
+    }
+    let Some(opts) = merge_options(unsafe { &*opts }) else {
+        return 3;
+    };
+    let indices = unsafe { std::slice::from_raw_parts(indices, n) };
+    merged_into(file, out, |spectra| {
+        Ok(vec![merge_spectra(spectra, indices, &opts)?])
+    })
+}
+
+/// Merges the spectra of `file` with retention time in `[rt_min, rt_max]`
+/// and MS level `ms_level` (0 for any) into one.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_merge_rt_window(
+    file: *const UlcmsFile,
+    rt_min: f64,
+    rt_max: f64,
+    ms_level: u32,
+    opts: *const UlcmsMergeOptions,
+    out: *mut *mut UlcmsMerged,
+) -> c_int {
+    if file.is_null() || opts.is_null() || out.is_null() {
+        return 1;
+    }
+    let Some(opts) = merge_options(unsafe { &*opts }) else {
+        return 3;
+    };
+    let level = (ms_level != 0).then_some(ms_level);
+    merged_into(file, out, |spectra| {
+        let indices = spectra_in_rt_window(spectra, rt_min, rt_max, level);
+        Ok(vec![merge_spectra(spectra, &indices, &opts)?])
+    })
+}
+
+/// Merges the MSn spectra of `file` sharing a precursor, within
+/// `precursor_ppm` and no more than `rt_gap` minutes apart (0 for no
+/// limit), into one spectrum per precursor.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_merge_by_precursor(
+    file: *const UlcmsFile,
+    precursor_ppm: f64,
+    rt_gap: f64,
+    opts: *const UlcmsMergeOptions,
+    out: *mut *mut UlcmsMerged,
+) -> c_int {
+    if file.is_null() || opts.is_null() || out.is_null() {
+        return 1;
+    }
+    let Some(opts) = merge_options(unsafe { &*opts }) else {
+        return 3;
+    };
+    merged_into(file, out, |spectra| {
+        group_by_precursor(spectra, precursor_ppm, rt_gap)
+            .iter()
+            .map(|g| merge_spectra(spectra, g, &opts))
+            .collect()
+    })
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_merged_count(merged: *const UlcmsMerged) -> usize {
+    if merged.is_null() {
+        return 0;
+    }
+    unsafe { (*merged).merged.len() }
+}
+
+/// Field `field` (a `ULCMS_MERGED_*` constant) of consensus spectrum
+/// `index`; NaN when missing or out of range.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_merged_get_f64(
+    merged: *const UlcmsMerged,
+    index: usize,
+    field: u32,
+) -> f64 {
+    if merged.is_null() {
+        return f64::NAN;
+    }
+    let merged = unsafe { &(*merged).merged };
+    let Some(m) = merged.get(index) else {
+        return f64::NAN;
+    };
+    let v = match field {
+        ULCMS_MERGED_MS_LEVEL => m.ms_level.map(f64::from),
+        ULCMS_MERGED_RETENTION_TIME => m.retention_time,
+        ULCMS_MERGED_RT_MIN => m.rt_min,
+        ULCMS_MERGED_RT_MAX => m.rt_max,
+        ULCMS_MERGED_PRECURSOR_MZ => m.precursor_mz,
+        ULCMS_MERGED_PRECURSOR_CHARGE => m.precursor_charge.map(f64::from),
+        ULCMS_MERGED_N_SPECTRA => Some(m.spectra.len() as f64),
+        ULCMS_MERGED_N_PEAKS => Some(m.mz.len() as f64),
+        _ => None,
+    };
+    v.unwrap_or(f64::NAN)
+}
+
+/// Borrows the peaks of consensus spectrum `index`: `len` m/z values,
+/// intensities and occurrence counts. Valid until [`ulcms_merged_free`].
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_merged_peaks(
+    merged: *const UlcmsMerged,
+    index: usize,
+    mz: *mut *const f64,
+    intensity: *mut *const f64,
+    occurrence: *mut *const usize,
+    len: *mut usize,
+) -> c_int {
+    if merged.is_null()
+        || mz.is_null()
+        || intensity.is_null()
+        || occurrence.is_null()
+        || len.is_null()
+    {
+        return 1;
+    }
+    let merged = unsafe { &(*merged).merged };
+    let Some(m) = merged.get(index) else {
+        return 3;
+    };
+    unsafe {
+        *mz = m.mz.as_ptr();
+        *intensity = m.intensity.as_ptr();
+        *occurrence = m.occurrence.as_ptr();
+        *len = m.mz.len();
+    }
+    0
+}
+
+/// Borrows the indices of the spectra merged into consensus spectrum
+/// `index`. Valid until [`ulcms_merged_free`].
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_merged_spectra(
+    merged: *const UlcmsMerged,
+    index: usize,
+    spectra: *mut *const usize,
+    len: *mut usize,
+) -> c_int {
+    if merged.is_null() || spectra.is_null() || len.is_null() {
+        return 1;
+    }
+    let merged = unsafe { &(*merged).merged };
+    let Some(m) = merged.get(index) else {
+        return 3;
+    };
+    unsafe {
+        *spectra = m.spectra.as_ptr();
+        *len = m.spectra.len();
+    }
+    0
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_merged_free(merged: *mut UlcmsMerged) {
+    if merged.is_null() {
+        return;
+    }
+    unsafe {
+        let _ = Box::from_raw(merged);
+    }
+}
@@ -0,0 +1,372 @@
+//! Ion mobility frames, mobilograms and collision cross sections.
+
+use core::ffi::c_int;
+use std::panic::{AssertUnwindSafe, catch_unwind};
+
+use crate::utilities::mobility::{
+    Calibrant, CcsCalibration, CcsOptions, Frame, ImsMethod, Mobilogram, MobilogramOptions,
+    calibrate_ccs, frames, mobilogram,
+};
+
+use super::file::UlcmsFile;
+
+/// Opaque ion mobility frames of a run; see `Frame`.
+pub struct UlcmsFrames {
+    frames: Vec<Frame>,
+}
+
+/// Groups the spectra of `file` that carry ion mobility into frames.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_frames(file: *const UlcmsFile, out: *mut *mut UlcmsFrames) -> c_int {
+    if file.is_null() || out.is_null() {
+        return 1;
+    }
+
+    let res = catch_unwind(AssertUnwindSafe(|| {
+        let spectra = unsafe { &(*file).spectra };
+        frames(spectra)
+    }));
+
+    match res {
+        Ok(frames) => {
+            unsafe { *out = Box::into_raw(Box::new(UlcmsFrames { frames })) };
+            0
+        }
+        Err(_) => 2,
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_frames_count(frames: *const UlcmsFrames) -> usize {
+    if frames.is_null() {
+        return 0;
+    }
+    unsafe { (*frames).frames.len() }
+}
+
+/// Frame `index`: its retention time (NaN when missing), MS level (0 when
+/// missing) and a borrowed array of `len` spectrum indices by mobility.
+/// Valid until [`ulcms_frames_free`].
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_frame(
+    frames: *const UlcmsFrames,
+    index: usize,
+    rt: *mut f64,
+    ms_level: *mut u32,
+    spectra: *mut *const usize,
+    len: *mut usize,
+) -> c_int {
+    if frames.is_null() || rt.is_null() || ms_level.is_null() || spectra.is_null() || len.is_null()
+    {
+        return 1;
+    }
+    let frames = unsafe { &(*frames).frames };
+    let Some(f) = frames.get(index) else {
+        return 3;
+    };
+    unsafe {
+        *rt = f.rt.unwrap_or(f64::NAN);
+        *ms_level = f.ms_level.unwrap_or(0);
+        *spectra = f.spectra.as_ptr();
+        *len = f.spectra.len();
+    }
+    0
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_frames_free(frames: *mut UlcmsFrames) {
+    if frames.is_null() {
+        return;
+    }
+    unsafe {
+        let _ = Box::from_raw(frames);
+    }
+}
+
+/// Mirrors `MobilogramOptions`; start from
+/// [`ulcms_mobilogram_options_default`]. NaN range limits and a 0
+/// `ms_level` are unset.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct UlcmsMobilogramOptions {
+    pub rt_min: f64,
+    pub rt_max: f64,
+    pub ms_level: u32,
+    pub bin_width: f64,
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_mobilogram_options_default() -> UlcmsMobilogramOptions {
+    let o = MobilogramOptions::default();
+    UlcmsMobilogramOptions {
+        rt_min: f64::NAN,
+        rt_max: f64::NAN,
+        ms_level: o.ms_level.unwrap_or(0),
+        bin_width: o.bin_width,
+    }
+}
+
+/// Opaque extracted ion mobilogram; see `Mobilogram`.
+pub struct UlcmsMobilogram {
+    mobilogram: Mobilogram,
+}
+
+/// Extracts the mobilogram of `[mz_min, mz_max]` from the spectra of
+/// `file`.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_mobilogram(
+    file: *const UlcmsFile,
+    mz_min: f64,
+    mz_max: f64,
+    opts: *const UlcmsMobilogramOptions,
+    out: *mut *mut UlcmsMobilogram,
+) -> c_int {
+    if file.is_null() || opts.is_null() || out.is_null() {
+        return 1;
+    }
+    let o = unsafe { &*opts };
+    let set = |v: f64| (!v.is_nan()).then_some(v);
+    let opts = MobilogramOptions {
+        rt_min: set(o.rt_min),
+        rt_max: set(o.rt_max),
+        ms_level: (o.ms_level != 0).then_some(o.ms_level),
+        bin_width: o.bin_width,
+    };
+
+    let res = catch_unwind(AssertUnwindSafe(|| {
+        let spectra = unsafe { &(*file).spectra };
+        mobilogram(spectra, mz_min, mz_max, &opts)
+    }));
+
+    match res {
+        Ok(mobilogram) => {
+            unsafe { *out = Box::into_raw(Box::new(UlcmsMobilogram { mobilogram })) };
+            0
+        }
+        Err(_) => 2,
+    }
+}
+
+/// Borrows the `len` mobilities, ascending, and summed intensities of
+/// `mobilogram`. Valid until [`ulcms_mobilogram_free`].
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_mobilogram_data(
+    mobilogram: *const UlcmsMobilogram,
+    mobility: *mut *const f64,
+    intensity: *mut *const f64,
+    len: *mut usize,
+) -> c_int {
+    if mobilogram.is_null() || mobility.is_null() || intensity.is_null() || len.is_null() {
+        return 1;
+    }
+    let m = unsafe { &(*mobilogram).mobilogram };
+    unsafe {
+        *mobility = m.mobility.as_ptr();
+        *intensity = m.intensity.as_ptr();
+        *len = m.mobility.len();
+    }
+    0
+}
+
+/// Mobility type of `mobilogram`, as `ULCMS_FIELD_ION_MOBILITY_TYPE`
+/// gives it, as a borrowed, non-terminated UTF-8 slice; null when the
+/// mobilogram is empty.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_mobilogram_type(
+    mobilogram: *const UlcmsMobilogram,
+    out_ptr: *mut *const u8,
+    out_len: *mut usize,
+) -> c_int {
+    if mobilogram.is_null() || out_ptr.is_null() || out_len.is_null() {
+        return 1;
+    }
+    let m = unsafe { &(*mobilogram).mobilogram };
+    unsafe {
+        match &m.mobility_type {
+            Some(v) => {
+                *out_ptr = v.as_ptr();
+                *out_len = v.len();
+            }
+            None => {
+                *out_ptr = core::ptr::null();
+                *out_len = 0;
+            }
+        }
+    }
+    0
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_mobilogram_free(mobilogram: *mut UlcmsMobilogram) {
+    if mobilogram.is_null() {
+        return;
+    }
+    unsafe {
+        let _ = Box::from_raw(mobilogram);
+    }
+}
+
+pub const ULCMS_IMS_TIMS: u32 = 0;
+pub const ULCMS_IMS_SINGLE_FIELD: u32 = 1;
+
+/// Mirrors `CcsOptions`; start from [`ulcms_ccs_options_default`].
+/// `method` is a `ULCMS_IMS_*` constant.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct UlcmsCcsOptions {
+    pub method: u32,
+    pub gas_mass: f64,
+    pub temperature: f64,
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_ccs_options_default() -> UlcmsCcsOptions {
+    let o = CcsOptions::default();
+    UlcmsCcsOptions {
+        method: ULCMS_IMS_TIMS,
+        gas_mass: o.gas_mass,
+        temperature: o.temperature,
+    }
+}
+
+/// Mirrors `CcsCalibration`; filled by [`ulcms_ccs_calibrate`] or
+/// [`ulcms_ccs_uncalibrated`].
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct UlcmsCcsCalibration {
+    pub options: UlcmsCcsOptions,
+    pub intercept: f64,
+    pub slope: f64,
+    pub r_squared: f64,
+}
+
+fn ccs_options(o: &UlcmsCcsOptions) -> Option<CcsOptions> {
+    let method = match o.method {
+        ULCMS_IMS_TIMS => ImsMethod::Tims,
+        ULCMS_IMS_SINGLE_FIELD => ImsMethod::SingleField,
+        _ => return None,
+    };
+    Some(CcsOptions {
+        method,
+        gas_mass: o.gas_mass,
+        temperature: o.temperature,
+    })
+}
+
+impl From<CcsCalibration> for UlcmsCcsCalibration {
+    fn from(c: CcsCalibration) -> Self {
+        UlcmsCcsCalibration {
+            options: UlcmsCcsOptions {
+                method: match c.options.method {
+                    ImsMethod::Tims => ULCMS_IMS_TIMS,
+                    ImsMethod::SingleField => ULCMS_IMS_SINGLE_FIELD,
+                },
+                gas_mass: c.options.gas_mass,
+                temperature: c.options.temperature,
+            },
+            intercept: c.intercept,
+            slope: c.slope,
+            r_squared: c.r_squared,
+        }
+    }
+}
+
+/// TIMS conversion taking observed 1/K0 values as they are, in the gas of
+/// `opts`.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_ccs_uncalibrated(opts: UlcmsCcsOptions) -> UlcmsCcsCalibration {
+    let o = CcsOptions {
+        gas_mass: opts.gas_mass,
+        temperature: opts.temperature,
+        ..CcsOptions::default()
+    };
+    CcsCalibration::uncalibrated(o).into()
+}
+
+/// Fits a cross section calibration to `n` calibrants given by m/z,
+/// charge, reference CCS in Å² and observed mobility (1/K0 for TIMS,
+/// arrival time for single-field). Returns 3 for an unknown method and 4
+/// when the calibrants do not determine a fit.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_ccs_calibrate(
+    opts: *const UlcmsCcsOptions,
+    mz: *const f64,
+    charge: *const u32,
+    ccs: *const f64,
+    observed: *const f64,
+    n: usize,
+    out: *mut UlcmsCcsCalibration,
+) -> c_int {
+    if opts.is_null()
+        || mz.is_null()
+        || charge.is_null()
+        || ccs.is_null()
+        || observed.is_null()
+        || out.is_null()
+    {
+        return 1;
+    }
+    let Some(opts) = ccs_options(unsafe { &*opts }) else {
+        return 3;
+    };
+
+    let res = catch_unwind(AssertUnwindSafe(|| {
+        let mz = unsafe { std::slice::from_raw_parts(mz, n) };
+        let charge = unsafe { std::slice::from_raw_parts(charge, n) };
+        let ccs = unsafe { std::slice::from_raw_parts(ccs, n) };
+        let observed = unsafe { std::slice::from_raw_parts(observed, n) };
+        let calibrants: Vec<Calibrant> = (0..n)
+            .map(|i| Calibrant {
+                mz: mz[i],
+                charge: charge[i],
+                ccs: ccs[i],
+                observed: observed[i],
+            })
+            .collect();
+        calibrate_ccs(&calibrants, &opts)
+    }));
+
+    match res {
+        Ok(Ok(cal)) => {
+            unsafe { *out = cal.into() };
+            0
+        }
+        Ok(Err(_)) => 4,
+        Err(_) => 2,
+    }
+}
+
+/// Writes into `out` the cross sections, in Å², of `n` ions given by m/z,
+/// charge and observed mobility, NaN for charge 0. Returns 3 for an
+/// unknown method.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_ccs(
+    cal: *const UlcmsCcsCalibration,
+    mz: *const f64,
+    charge: *const u32,
+    observed: *const f64,
+    n: usize,
+    out: *mut f64,
+) -> c_int {
+    if cal.is_null() || mz.is_null() || charge.is_null() || observed.is_null() || out.is_null() {
+        return 1;
+    }
+    let c = unsafe { &*cal };
+    let Some(options) = ccs_options(&c.options) else {
+        return 3;
+    };
+    let cal = CcsCalibration {
+        options,
+        intercept: c.intercept,
+        slope: c.slope,
+        r_squared: c.r_squared,
+    };
+    let mz = unsafe { std::slice::from_raw_parts(mz, n) };
+    let charge = unsafe { std::slice::from_raw_parts(charge, n) };
+    let observed = unsafe { std::slice::from_raw_parts(observed, n) };
+    let out = unsafe { std::slice::from_raw_parts_mut(out, n) };
+    for i in 0..n {
+        out[i] = cal.ccs(mz[i], charge[i], observed[i]);
+    }
+    0
+}
@@ -0,0 +1,47 @@
+//! C ABI exports, one module per area. Every item is re-exported from the
+//! crate root, which is what `ulcms.h` is generated from.
+
+use core::ffi::c_char;
+use std::ffi::CStr;
+
+mod align;
+mod binning;
+mod calibration;
+mod chem;
+mod dia;
+mod features;
+mod file;
+mod group;
+mod library;
+mod merge;
+mod mobility;
+mod quant;
+mod reader;
+mod signal;
+mod similarity;
+mod stats;
+mod stream;
+
+pub use align::*;
+pub use binning::*;
+pub use calibration::*;
+pub use chem::*;
+pub use dia::*;
+pub use features::*;
+pub use file::*;
+pub use group::*;
+pub use library::*;
+pub use merge::*;
+pub use mobility::*;
+pub use quant::*;
+pub use reader::*;
+pub use signal::*;
+pub use similarity::*;
+pub use stats::*;
+pub use stream::*;
+
+fn c_str<'a>(s: *const c_char) -> Result<&'a str, String> {
+    unsafe { CStr::from_ptr(s) }
+        .to_str()
+        .map_err(|_| "invalid UTF-8".to_string())
+}
@@ -0,0 +1,319 @@
+//! Targeted quantification from transition and sample lists.
+
+use core::ffi::{c_char, c_int};
+use std::fs;
+use std::panic::{AssertUnwindSafe, catch_unwind};
+
+use crate::utilities::quant::{
+    CurveModel, CurveWeighting, QuantOptions, QuantTable, Run, Sample, SampleType,
+    parse_sample_list, parse_transitions, quantify,
+};
+
+use super::c_str;
+
+pub const ULCMS_CURVE_LINEAR: u32 = 0;
+pub const ULCMS_CURVE_QUADRATIC: u32 = 1;
+
+pub const ULCMS_WEIGHTING_NONE: u32 = 0;
+pub const ULCMS_WEIGHTING_INVERSE_X: u32 = 1;
+pub const ULCMS_WEIGHTING_INVERSE_X2: u32 = 2;
+
+/// Mirrors `QuantOptions`; start from [`ulcms_quant_options_default`].
+/// `model` is a `ULCMS_CURVE_*` constant and `weighting` a
+/// `ULCMS_WEIGHTING_*` one.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct UlcmsQuantOptions {
+    pub mz_tolerance: f64,
+    pub ppm: f64,
+    pub rt_window: f64,
+    pub min_signal_to_noise: f64,
+    pub ratio_tolerance: f64,
+    pub model: u32,
+    pub weighting: u32,
+    pub accuracy_tolerance: f64,
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_quant_options_default() -> UlcmsQuantOptions {
+    let o = QuantOptions::default();
+    UlcmsQuantOptions {
+        mz_tolerance: o.mz_tolerance,
+        ppm: o.ppm,
+        rt_window: o.rt_window,
+        min_signal_to_noise: o.min_signal_to_noise,
+        ratio_tolerance: o.ratio_tolerance,
+        model: ULCMS_CURVE_LINEAR,
+        weighting: ULCMS_WEIGHTING_NONE,
+        accuracy_tolerance: o.accuracy_tolerance,
+    }
+}
+
+/// QC flags, or'ed together in `ULCMS_QUANT_FLAGS`.
+pub const ULCMS_QC_NOT_FOUND: u32 = 1;
+pub const ULCMS_QC_LOW_SIGNAL_TO_NOISE: u32 = 2;
+pub const ULCMS_QC_ION_RATIO: u32 = 4;
+pub const ULCMS_QC_BELOW_RANGE: u32 = 8;
+pub const ULCMS_QC_ABOVE_RANGE: u32 = 16;
+pub const ULCMS_QC_NO_CALIBRATION: u32 = 32;
+pub const ULCMS_QC_ACCURACY: u32 = 64;
+pub const ULCMS_QC_INTERNAL_STANDARD: u32 = 128;
+
+pub const ULCMS_SAMPLE_UNKNOWN: u32 = 0;
+pub const ULCMS_SAMPLE_STANDARD: u32 = 1;
+pub const ULCMS_SAMPLE_QC: u32 = 2;
+pub const ULCMS_SAMPLE_BLANK: u32 = 3;
+
+pub const ULCMS_QUANT_SAMPLE: u32 = 0;
+pub const ULCMS_QUANT_COMPOUND: u32 = 1;
+pub const ULCMS_QUANT_SAMPLE_TYPE: u32 = 2;
+pub const ULCMS_QUANT_RT: u32 = 3;
+pub const ULCMS_QUANT_RT_START: u32 = 4;
+pub const ULCMS_QUANT_RT_END: u32 = 5;
+pub const ULCMS_QUANT_AREA: u32 = 6;
+pub const ULCMS_QUANT_HEIGHT: u32 = 7;
+pub const ULCMS_QUANT_SIGNAL_TO_NOISE: u32 = 8;
+pub const ULCMS_QUANT_INTERNAL_STANDARD_AREA: u32 = 9;
+pub const ULCMS_QUANT_RESPONSE: u32 = 10;
+pub const ULCMS_QUANT_ION_RATIO: u32 = 11;
+pub const ULCMS_QUANT_CONCENTRATION: u32 = 12;
+pub const ULCMS_QUANT_NOMINAL: u32 = 13;
+pub const ULCMS_QUANT_ACCURACY: u32 = 14;
+pub const ULCMS_QUANT_FLAGS: u32 = 15;
+
+/// Opaque concentration table; see `QuantTable`.
+pub struct UlcmsQuant {
+    table: QuantTable,
+}
+
+/// Quantifies the transitions of the CSV at `transitions_path` in the
+/// samples of the CSV at `sample_list_path`, whose mzML files are read
+/// relative to the sample list's directory. Returns 3 for an unknown
+/// model or weighting and 4 when a file cannot be read or parsed.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_quantify(
+    transitions_path: *const c_char,
+    sample_list_path: *const c_char,
+    opts: *const UlcmsQuantOptions,
+    out: *mut *mut UlcmsQuant,
+) -> c_int {
+    if transitions_path.is_null() || sample_list_path.is_null() || opts.is_null() || out.is_null() {
+        return 1;
+    }
+    let o = unsafe { &*opts };
+    let model = match o.model {
+        ULCMS_CURVE_LINEAR => CurveModel::Linear,
+        ULCMS_CURVE_QUADRATIC => CurveModel::Quadratic,
+        _ => return 3,
+    };
+    let weighting = match o.weighting {
+        ULCMS_WEIGHTING_NONE => CurveWeighting::None,
+        ULCMS_WEIGHTING_INVERSE_X => CurveWeighting::InverseX,
+        ULCMS_WEIGHTING_INVERSE_X2 => CurveWeighting::InverseX2,
+        _ => return 3,
+    };
+    let opts = QuantOptions {
+        mz_tolerance: o.mz_tolerance,
+        ppm: o.ppm,
+        rt_window: o.rt_window,
+        min_signal_to_noise: o.min_signal_to_noise,
+        ratio_tolerance: o.ratio_tolerance,
+        model,
+        weighting,
+        accuracy_tolerance: o.accuracy_tolerance,
+    };
+
+    let res = catch_unwind(AssertUnwindSafe(|| -> Result<QuantTable, String> {
+        let read = |p: &std::path::Path| {
+            fs::read_to_string(p).map_err(|e| format!("read {}: {e}", p.display()))
+        };
+        let transitions = parse_transitions(&read(c_str(transitions_path)?.as_ref())?)?;
+        let list = std::path::Path::new(c_str(sample_list_path)?);
+        let samples = parse_sample_list(&read(list)?)?;
+        let dir = list.parent().unwrap_or(std::path::Path::new(""));
+        let read_run = |s: &Sample| {
+            let path = dir.join(&s.file);
+            let data = fs::read(&path).map_err(|e| format!("read {}: {e}", path.display()))?;
+            Run::from_mzml(&data)
+        };
+        quantify(&transitions, &samples, read_run, &opts)
+    }));
+
+    match res {
+        Ok(Ok(table)) => {
+            unsafe { *out = Box::into_raw(Box::new(UlcmsQuant { table })) };
+            0
+        }
+        Ok(Err(_)) => 4,
+        Err(_) => 2,
+    }
+}
+
+/// Number of samples and compounds; the table has a row per pair, sample
+/// after sample.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_quant_shape(
+    quant: *const UlcmsQuant,
+    n_samples: *mut usize,
+    n_compounds: *mut usize,
+) -> c_int {
+    if quant.is_null() || n_samples.is_null() || n_compounds.is_null() {
+        return 1;
+    }
+    let table = unsafe { &(*quant).table };
+    unsafe {
+        *n_samples = table.samples.len();
+        *n_compounds = table.compounds.len();
+    }
+    0
+}
+
+/// Field `field` (a `ULCMS_QUANT_*` constant) of row `row`; NaN when
+/// missing or out of range.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_quant_get_f64(quant: *const UlcmsQuant, row: usize, field: u32) -> f64 {
+    if quant.is_null() {
+        return f64::NAN;
+    }
+    let table = unsafe { &(*quant).table };
+    let Some(r) = table.results.get(row) else {
+        return f64::NAN;
+    };
+    let v = match field {
+        ULCMS_QUANT_SAMPLE => Some(r.sample as f64),
+        ULCMS_QUANT_COMPOUND => Some(r.compound as f64),
+        ULCMS_QUANT_SAMPLE_TYPE => Some(match table.samples[r.sample].sample_type {
+            SampleType::Unknown => ULCMS_SAMPLE_UNKNOWN,
+            SampleType::Standard => ULCMS_SAMPLE_STANDARD,
+            SampleType::Qc => ULCMS_SAMPLE_QC,
+            SampleType::Blank => ULCMS_SAMPLE_BLANK,
+        } as f64),
+        ULCMS_QUANT_RT => r.peak.map(|p| p.rt),
+        ULCMS_QUANT_RT_START => r.peak.map(|p| p.rt_start),
+        ULCMS_QUANT_RT_END => r.peak.map(|p| p.rt_end),
+        ULCMS_QUANT_AREA => r.peak.map(|p| p.area),
+        ULCMS_QUANT_HEIGHT => r.peak.map(|p| p.height),
+        ULCMS_QUANT_SIGNAL_TO_NOISE => r.peak.map(|p| p.signal_to_noise),
+        ULCMS_QUANT_INTERNAL_STANDARD_AREA => r.internal_standard_area,
+        ULCMS_QUANT_RESPONSE => Some(r.response),
+        ULCMS_QUANT_ION_RATIO => r.ion_ratio,
+        ULCMS_QUANT_CONCENTRATION => Some(r.concentration),
+        ULCMS_QUANT_NOMINAL => r.nominal,
+        ULCMS_QUANT_ACCURACY => r.nominal.filter(|&n| n > 0.0).map(|n| r.concentration / n),
+        ULCMS_QUANT_FLAGS => Some(r.flags as f64),
+        _ => None,
+    };
+    v.unwrap_or(f64::NAN)
+}
+
+/// Name of compound `index`, as a borrowed, non-terminated UTF-8 slice
+/// valid until the table is freed. Returns 3 when out of range.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_quant_compound(
+    quant: *const UlcmsQuant,
+    index: usize,
+    out_ptr: *mut *const u8,
+    out_len: *mut usize,
+) -> c_int {
+    if quant.is_null() || out_ptr.is_null() || out_len.is_null() {
+        return 1;
+    }
+    let table = unsafe { &(*quant).table };
+    let Some(name) = table.compounds.get(index) else {
+        return 3;
+    };
+    unsafe {
+        *out_ptr = name.as_ptr();
+        *out_len = name.len();
+    }
+    0
+}
+
+/// Name of sample `index`, as for [`ulcms_quant_compound`].
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_quant_sample(
+    quant: *const UlcmsQuant,
+    index: usize,
+    out_ptr: *mut *const u8,
+    out_len: *mut usize,
+) -> c_int {
+    if quant.is_null() || out_ptr.is_null() || out_len.is_null() {
+        return 1;
+    }
+    let table = unsafe { &(*quant).table };
+    let Some(s) = table.samples.get(index) else {
+        return 3;
+    };
+    unsafe {
+        *out_ptr = s.name.as_ptr();
+        *out_len = s.name.len();
+    }
+    0
+}
+
+/// Calibration curve of compound `compound`: intercept, slope and
+/// quadratic coefficient into `coefficients` (3 values), R², and the
+/// concentration range of the standards into `range` (2 values). All NaN
+/// when the compound has no curve; returns 3 when out of range.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_quant_curve(
+    quant: *const UlcmsQuant,
+    compound: usize,
+    coefficients: *mut f64,
+    r_squared: *mut f64,
+    range: *mut f64,
+) -> c_int {
+    if quant.is_null() || coefficients.is_null() || r_squared.is_null() || range.is_null() {
+        return 1;
+    }
+    let table = unsafe { &(*quant).table };
+    let Some(curve) = table.curves.get(compound) else {
+        return 3;
+    };
+    let coefficients = unsafe { std::slice::from_raw_parts_mut(coefficients, 3) };
+    let range = unsafe { std::slice::from_raw_parts_mut(range, 2) };
+    match curve {
+        Some(c) => {
+            coefficients.copy_from_slice(&c.coefficients);
+            range.copy_from_slice(&[c.min_concentration, c.max_concentration]);
+            unsafe { *r_squared = c.r_squared };
+        }
+        None => {
+            coefficients.fill(f64::NAN);
+            range.fill(f64::NAN);
+            unsafe { *r_squared = f64::NAN };
+        }
+    }
+    0
+}
+
+/// Writes the table as CSV to `path`, with QC flags by name.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_quant_write_csv(quant: *const UlcmsQuant, path: *const c_char) -> c_int {
+    if quant.is_null() || path.is_null() {
+        return 1;
+    }
+    let table = unsafe { &(*quant).table };
+
+    let res = catch_unwind(AssertUnwindSafe(|| -> Result<(), String> {
+        let path = c_str(path)?;
+        let mut f = fs::File::create(path).map_err(|e| format!("create {path}: {e}"))?;
+        table.write_csv(&mut f)
+    }));
+
+    match res {
+        Ok(Ok(())) => 0,
+        Ok(Err(_)) => 4,
+        Err(_) => 2,
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_quant_free(quant: *mut UlcmsQuant) {
+    if quant.is_null() {
+        return;
+    }
+    unsafe {
+        let _ = Box::from_raw(quant);
+    }
+}
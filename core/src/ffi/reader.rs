@@ -0,0 +1,508 @@
+//! Spectra parsed in one call into C structs, and the reader that decodes
+//! arrays of a file on demand into caller-owned buffers.
+
+use core::ffi::{c_char, c_int, c_void};
+use std::ffi::{CStr, CString};
+use std::fs;
+use std::ops::Range;
+use std::panic::{AssertUnwindSafe, catch_unwind};
+
+use crate::utilities::parse_mzml::{
+    ArrayData, ArrayDecoder, SpectrumSummary, parse_mzml, spectrum_spans,
+};
+
+#[repr(C)]
+pub struct SpectrumSummaryFFI {
+    pub index: usize,
+    pub id: *mut c_char,
+    pub array_length: usize,
+    pub ms_level: u32,
+    pub scan_type: *mut c_char,
+    pub polarity: *mut c_char,
+    pub spectrum_type: *mut c_char,
+    pub retention_time: f64,
+    pub scan_window_lower_limit: f64,
+    pub scan_window_upper_limit: f64,
+    pub total_ion_current: f64,
+    pub base_peak_intensity: f64,
+    pub base_peak_mz: f64,
+    pub precursor_mz: f64,
+    pub precursor_charge: u32,
+    pub isolation_window_target: f64,
+    pub isolation_window_lower: f64,
+    pub isolation_window_upper: f64,
+    pub ion_mobility: f64,
+    pub ion_mobility_type: *mut c_char,
+    pub mz_array: ArrayFFI,
+    pub intensity_array: ArrayFFI,
+    pub mobility_array: ArrayFFI,
+}
+
+pub const ULCMS_ARRAY_NONE: u32 = 0;
+pub const ULCMS_ARRAY_F32: u32 = 1;
+pub const ULCMS_ARRAY_F64: u32 = 2;
+
+/// A numeric array in its native precision. `element_type` is one of the
+/// `ULCMS_ARRAY_*` constants and tells how to read `data`.
+#[repr(C)]
+pub struct ArrayFFI {
+    pub data: *mut c_void,
+    pub len: usize,
+    pub element_type: u32,
+}
+
+fn str_opt_to_c(opt: Option<String>) -> *mut c_char {
+    match opt {
+        Some(s) => CString::new(s).unwrap().into_raw(),
+        None => core::ptr::null_mut(),
+    }
+}
+
+impl From<Option<ArrayData>> for ArrayFFI {
+    fn from(opt: Option<ArrayData>) -> Self {
+        match opt {
+            Some(ArrayData::F32(v)) => {
+                let boxed: Box<[f32]> = v.into_boxed_slice();
+                let len = boxed.len();
+                ArrayFFI {
+                    data: Box::into_raw(boxed) as *mut c_void,
+                    len,
+                    element_type: ULCMS_ARRAY_F32,
+                }
+            }
+            Some(ArrayData::F64(v)) => {
+                let boxed: Box<[f64]> = v.into_boxed_slice();
+                let len = boxed.len();
+                ArrayFFI {
+                    data: Box::into_raw(boxed) as *mut c_void,
+                    len,
+                    element_type: ULCMS_ARRAY_F64,
+                }
+            }
+            None => ArrayFFI {
+                data: core::ptr::null_mut(),
+                len: 0,
+                element_type: ULCMS_ARRAY_NONE,
+            },
+        }
+    }
+}
+
+impl ArrayFFI {
+    unsafe fn free(&mut self) {
+        if self.data.is_null() {
+            return;
+        }
+        unsafe {
+            match self.element_type {
+                ULCMS_ARRAY_F32 => {
+                    let _ = Box::from_raw(std::ptr::slice_from_raw_parts_mut(
+                        self.data as *mut f32,
+                        self.len,
+                    ));
+                }
+                ULCMS_ARRAY_F64 => {
+                    let _ = Box::from_raw(std::ptr::slice_from_raw_parts_mut(
+                        self.data as *mut f64,
+                        self.len,
+                    ));
+                }
+                _ => {}
+            }
+        }
+        self.data = core::ptr::null_mut();
+        self.len = 0;
+        self.element_type = ULCMS_ARRAY_NONE;
+    }
+}
+
+impl From<SpectrumSummary> for SpectrumSummaryFFI {
+    fn from(s: SpectrumSummary) -> Self {
+        SpectrumSummaryFFI {
+            index: s.index,
+            id: CString::new(s.id).unwrap().into_raw(),
+            array_length: s.array_length,
+            ms_level: s.ms_level.unwrap_or(0),
+            scan_type: str_opt_to_c(s.scan_type),
+            polarity: str_opt_to_c(s.polarity),
+            spectrum_type: str_opt_to_c(s.spectrum_type),
+            retention_time: s.retention_time.unwrap_or(f64::NAN),
+            scan_window_lower_limit: s.scan_window_lower_limit.unwrap_or(f64::NAN),
+            scan_window_upper_limit: s.scan_window_upper_limit.unwrap_or(f64::NAN),
+            total_ion_current: s.total_ion_current.unwrap_or(f64::NAN),
+            base_peak_intensity: s.base_peak_intensity.unwrap_or(f64::NAN),
+            base_peak_mz: s.base_peak_mz.unwrap_or(f64::NAN),
+            precursor_mz: s.precursor_mz.unwrap_or(f64::NAN),
+            precursor_charge: s.precursor_charge.unwrap_or(0),
+            isolation_window_target: s.isolation_window_target.unwrap_or(f64::NAN),
+            isolation_window_lower: s.isolation_window_lower.unwrap_or(f64::NAN),
+            isolation_window_upper: s.isolation_window_upper.unwrap_or(f64::NAN),
+            ion_mobility: s.ion_mobility.unwrap_or(f64::NAN),
+            ion_mobility_type: str_opt_to_c(s.ion_mobility_type),
+            mz_array: ArrayFFI::from(s.mz_array),
+            intensity_array: ArrayFFI::from(s.intensity_array),
+            mobility_array: ArrayFFI::from(s.mobility_array),
+        }
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_parse_mzml(
+    path: *const c_char,
+    out_ptr: *mut *mut SpectrumSummaryFFI,
+    out_len: *mut usize,
+) -> c_int {
+    if path.is_null() || out_ptr.is_null() || out_len.is_null() {
+        return 1;
+    }
+
+    let res = catch_unwind(AssertUnwindSafe(|| -> Result<(), String> {
+        let cstr = unsafe { CStr::from_ptr(path) };
+        let path_str = cstr.to_str().map_err(|_| "invalid UTF-8".to_string())?;
+        let data = fs::read(path_str).map_err(|e| format!("open/read: {e}"))?;
+
+        let spectra = parse_mzml(&data)?;
+
+        let buf: Box<[SpectrumSummaryFFI]> = spectra
+            .into_iter()
+            .map(SpectrumSummaryFFI::from)
+            .collect::<Vec<_>>()
+            .into_boxed_slice();
+
+        let len = buf.len();
+        let ptr = Box::into_raw(buf) as *mut SpectrumSummaryFFI;
+
+        unsafe {
+            *out_ptr = ptr;
+            *out_len = len;
+        }
+        Ok(())
+    }));
+
+    match res {
+        Ok(Ok(())) => 0,
+        Ok(Err(_)) => 4,
+        Err(_) => 2,
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_parse_mzml_from_bytes(
+    data_ptr: *const u8,
+    data_len: usize,
+    out_ptr: *mut *mut SpectrumSummaryFFI,
+    out_len: *mut usize,
+) -> c_int {
+    if data_ptr.is_null() || out_ptr.is_null() || out_len.is_null() {
+        return 1;
+    }
+
+    let res = catch_unwind(AssertUnwindSafe(|| -> Result<(), String> {
+        let data = unsafe { std::slice::from_raw_parts(data_ptr, data_len) };
+        let spectra = parse_mzml(data)?;
+
+        let buf: Box<[SpectrumSummaryFFI]> = spectra
+            .into_iter()
+            .map(SpectrumSummaryFFI::from)
+            .collect::<Vec<_>>()
+            .into_boxed_slice();
+
+        let len = buf.len();
+        let ptr = Box::into_raw(buf) as *mut SpectrumSummaryFFI;
+
+        unsafe {
+            *out_ptr = ptr;
+            *out_len = len;
+        }
+        Ok(())
+    }));
+
+    match res {
+        Ok(Ok(())) => 0,
+        Ok(Err(_)) => 4,
+        Err(_) => 2,
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_free_spectra(ptr: *mut SpectrumSummaryFFI, len: usize) {
+    if ptr.is_null() {
+        return;
+    }
+    unsafe {
+        let slice = std::slice::from_raw_parts_mut(ptr, len);
+
+        for it in slice.iter_mut() {
+            if !it.id.is_null() {
+                let _ = CString::from_raw(it.id);
+                it.id = core::ptr::null_mut();
+            }
+            if !it.scan_type.is_null() {
+                let _ = CString::from_raw(it.scan_type);
+                it.scan_type = core::ptr::null_mut();
+            }
+            if !it.polarity.is_null() {
+                let _ = CString::from_raw(it.polarity);
+                it.polarity = core::ptr::null_mut();
+            }
+            if !it.spectrum_type.is_null() {
+                let _ = CString::from_raw(it.spectrum_type);
+                it.spectrum_type = core::ptr::null_mut();
+            }
+            if !it.ion_mobility_type.is_null() {
+                let _ = CString::from_raw(it.ion_mobility_type);
+                it.ion_mobility_type = core::ptr::null_mut();
+            }
+            it.mz_array.free();
+            it.intensity_array.free();
+            it.mobility_array.free();
+        }
+
+        let _ = Box::from_raw(std::ptr::slice_from_raw_parts_mut(ptr, len));
+    }
+}
+
+/// Widens `array` into `out[..cap]`, returning the number of values written.
+/// Lets callers that only handle doubles opt in to conversion per array.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_array_to_f64(array: *const ArrayFFI, out: *mut f64, cap: usize) -> usize {
+    if array.is_null() || out.is_null() {
+        return 0;
+    }
+    let array = unsafe { &*array };
+    if array.data.is_null() {
+        return 0;
+    }
+    let n = array.len.min(cap);
+    let out = unsafe { std::slice::from_raw_parts_mut(out, n) };
+    match array.element_type {
+        ULCMS_ARRAY_F32 => {
+            let src = unsafe { std::slice::from_raw_parts(array.data as *const f32, n) };
+            for (o, &x) in out.iter_mut().zip(src) {
+                *o = x as f64;
+            }
+            n
+        }
+        ULCMS_ARRAY_F64 => {
+            let src = unsafe { std::slice::from_raw_parts(array.data as *const f64, n) };
+            out.copy_from_slice(src);
+            n
+        }
+        _ => 0,
+    }
+}
+
+/// Opaque handle for decoding spectrum arrays straight into buffers the
+/// caller allocated (e.g. R or NumPy numeric vectors).
+pub struct UlcmsReader {
+    data: Vec<u8>,
+    spans: Vec<Range<usize>>,
+    decoder: ArrayDecoder,
+}
+
+fn reader_from_data(data: Vec<u8>, out: *mut *mut UlcmsReader) -> Result<(), String> {
+    let spans = spectrum_spans(&data)?;
+    let reader = Box::new(UlcmsReader {
+        data,
+        spans,
+        decoder: ArrayDecoder::new(),
+    });
+    unsafe {
+        *out = Box::into_raw(reader);
+    }
+    Ok(())
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_reader_open(path: *const c_char, out: *mut *mut UlcmsReader) -> c_int {
+    if path.is_null() || out.is_null() {
+        return 1;
+    }
+
+    let res = catch_unwind(AssertUnwindSafe(|| -> Result<(), String> {
+        let cstr = unsafe { CStr::from_ptr(path) };
+        let path_str = cstr.to_str().map_err(|_| "invalid UTF-8".to_string())?;
+        let data = fs::read(path_str).map_err(|e| format!("open/read: {e}"))?;
+        reader_from_data(data, out)
+    }));
+
+    match res {
+        Ok(Ok(())) => 0,
+        Ok(Err(_)) => 4,
+        Err(_) => 2,
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_reader_open_from_bytes(
+    data_ptr: *const u8,
+    data_len: usize,
+    out: *mut *mut UlcmsReader,
+) -> c_int {
+    if data_ptr.is_null() || out.is_null() {
+        return 1;
+    }
+
+    let res = catch_unwind(AssertUnwindSafe(|| -> Result<(), String> {
+        let data = unsafe { std::slice::from_raw_parts(data_ptr, data_len) };
+        reader_from_data(data.to_vec(), out)
+    }));
+
+    match res {
+        Ok(Ok(())) => 0,
+        Ok(Err(_)) => 4,
+        Err(_) => 2,
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_reader_len(reader: *const UlcmsReader) -> usize {
+    if reader.is_null() {
+        return 0;
+    }
+    unsafe { (*reader).spans.len() }
+}
+
+/// Number of values in each array of spectrum `index`, so the caller can
+/// size the buffers passed to [`ulcms_reader_decode_into`].
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_reader_array_length(
+    reader: *const UlcmsReader,
+    index: usize,
+    out_len: *mut usize,
+) -> c_int {
+    if reader.is_null() || out_len.is_null() {
+        return 1;
+    }
+    let reader = unsafe { &*reader };
+    let Some(span) = reader.spans.get(index) else {
+        return 3;
+    };
+    unsafe {
+        *out_len = ArrayDecoder::array_length(&reader.data[span.clone()]);
+    }
+    0
+}
+
+/// Decodes spectrum `index` into `mz_out[..mz_cap]` and
+/// `intensity_out[..intensity_cap]`; either buffer may be null to skip it.
+/// `out_*_len` receive the full length of each array, and 5 is returned
+/// when a non-null buffer is shorter than that; it then holds a prefix.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_reader_decode_into(
+    reader: *mut UlcmsReader,
+    index: usize,
+    mz_out: *mut f64,
+    mz_cap: usize,
+    intensity_out: *mut f64,
+    intensity_cap: usize,
+    out_mz_len: *mut usize,
+    out_intensity_len: *mut usize,
+) -> c_int {
+    if reader.is_null() || out_mz_len.is_null() || out_intensity_len.is_null() {
+        return 1;
+    }
+
+    let res = catch_unwind(AssertUnwindSafe(|| -> Result<(), c_int> {
+        let reader = unsafe { &mut *reader };
+        let span = reader.spans.get(index).ok_or(3)?.clone();
+        let mz: &mut [f64] = if mz_out.is_null() {
+            &mut []
+        } else {
+            unsafe { std::slice::from_raw_parts_mut(mz_out, mz_cap) }
+        };
+        let intensity: &mut [f64] = if intensity_out.is_null() {
+            &mut []
+        } else {
+            unsafe { std::slice::from_raw_parts_mut(intensity_out, intensity_cap) }
+        };
+        let (mz_len, int_len) =
+            reader
+                .decoder
+                .decode_into_slices(&reader.data[span], mz, intensity);
+        unsafe {
+            *out_mz_len = mz_len;
+            *out_intensity_len = int_len;
+        }
+        if (!mz_out.is_null() && mz_len > mz_cap)
+            || (!intensity_out.is_null() && int_len > intensity_cap)
+        {
+            return Err(5);
+        }
+        Ok(())
+    }));
+
+    match res {
+        Ok(Ok(())) => 0,
+        Ok(Err(code)) => code,
+        Err(_) => 2,
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_reader_free(reader: *mut UlcmsReader) {
+    if reader.is_null() {
+        return;
+    }
+    unsafe {
+        let _ = Box::from_raw(reader);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utilities::parse_mzml::test_spectra;
+
+    #[test]
+    fn decode_into_reports_short_buffers() {
+        let mut reader = std::ptr::null_mut();
+        let data = test_spectra::MZML;
+        assert_eq!(
+            ulcms_reader_open_from_bytes(data.as_ptr(), data.len(), &mut reader),
+            0
+        );
+        let mut n = 0;
+        assert_eq!(ulcms_reader_array_length(reader, 2, &mut n), 0);
+        assert_eq!(n, 400);
+
+        let decode = |mz: &mut [f64], intensity: *mut f64, cap| {
+            let (mut mz_len, mut int_len) = (0, 0);
+            let rc = ulcms_reader_decode_into(
+                reader,
+                2,
+                mz.as_mut_ptr(),
+                mz.len(),
+                intensity,
+                cap,
+                &mut mz_len,
+                &mut int_len,
+            );
+            (rc, mz_len, int_len)
+        };
+        let (mut mz, mut intensity) = (vec![0.0; n], vec![0.0; n]);
+        assert_eq!(decode(&mut mz, intensity.as_mut_ptr(), n), (0, n, n));
+        let mut short = vec![0.0; 10];
+        assert_eq!(decode(&mut short, intensity.as_mut_ptr(), n), (5, n, n));
+        assert_eq!(short, mz[..10]);
+        // A skipped array is not too short.
+        assert_eq!(decode(&mut mz, std::ptr::null_mut(), 0), (0, n, n));
+        assert_eq!(decode(&mut mz, intensity.as_mut_ptr(), 0), (5, n, n));
+        assert_eq!(decode(&mut mz, intensity.as_mut_ptr(), n), (0, n, n));
+        let mut len = 0;
+        assert_eq!(
+            ulcms_reader_decode_into(
+                reader,
+                4,
+                std::ptr::null_mut(),
+                0,
+                std::ptr::null_mut(),
+                0,
+                &mut len,
+                &mut len
+            ),
+            3
+        );
+        ulcms_reader_free(reader);
+    }
+}
@@ -0,0 +1,223 @@
+//! Smoothing, baseline and noise estimation of caller buffers.
+
+use core::ffi::c_int;
+use std::panic::{AssertUnwindSafe, catch_unwind};
+
+use crate::utilities::signal::{
+    Baseline, NoiseEstimator, Smoother, baseline, local_noise, noise, smooth,
+};
+
+pub const ULCMS_SMOOTH_MOVING_AVERAGE: u32 = 0;
+pub const ULCMS_SMOOTH_GAUSSIAN: u32 = 1;
+pub const ULCMS_SMOOTH_SAVITZKY_GOLAY: u32 = 2;
+
+/// Mirrors `Smoother`; start from [`ulcms_smooth_options_default`]. `width`
+/// is the half width, or sigma for `ULCMS_SMOOTH_GAUSSIAN`, in units of `x`
+/// or in points without it; `order` is used by Savitzky-Golay only.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct UlcmsSmoothOptions {
+    pub method: u32,
+    pub width: f64,
+    pub order: u32,
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_smooth_options_default() -> UlcmsSmoothOptions {
+    let Smoother::SavitzkyGolay { half_width, order } = Smoother::default() else {
+        unreachable!()
+    };
+    UlcmsSmoothOptions {
+        method: ULCMS_SMOOTH_SAVITZKY_GOLAY,
+        width: half_width,
+        order: order as u32,
+    }
+}
+
+pub const ULCMS_BASELINE_ALS: u32 = 0;
+pub const ULCMS_BASELINE_SNIP: u32 = 1;
+pub const ULCMS_BASELINE_ROLLING_MIN: u32 = 2;
+
+/// Mirrors `Baseline`; start from [`ulcms_baseline_options_default`].
+/// `lambda`, `p` and `iterations` are used by ALS, `width` (a half width)
+/// by the others.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct UlcmsBaselineOptions {
+    pub method: u32,
+    pub lambda: f64,
+    pub p: f64,
+    pub iterations: usize,
+    pub width: f64,
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_baseline_options_default() -> UlcmsBaselineOptions {
+    let Baseline::Als {
+        lambda,
+        p,
+        iterations,
+    } = Baseline::default()
+    else {
+        unreachable!()
+    };
+    UlcmsBaselineOptions {
+        method: ULCMS_BASELINE_ALS,
+        lambda,
+        p,
+        iterations,
+        width: 50.0,
+    }
+}
+
+pub const ULCMS_NOISE_MAD: u32 = 0;
+pub const ULCMS_NOISE_DIFF_MAD: u32 = 1;
+pub const ULCMS_NOISE_PERCENTILE: u32 = 2;
+
+fn noise_estimator(method: u32, q: f64) -> Option<NoiseEstimator> {
+    match method {
+        ULCMS_NOISE_MAD => Some(NoiseEstimator::Mad),
+        ULCMS_NOISE_DIFF_MAD => Some(NoiseEstimator::DiffMad),
+        ULCMS_NOISE_PERCENTILE => Some(NoiseEstimator::Percentile(q)),
+        _ => None,
+    }
+}
+
+// Runs a signal filter over `y[..n]`, with positions `x[..n]` unless `x` is
+// null, and writes the `n` results to `out`.
+fn signal_f64(
+    x: *const f64,
+    y: *const f64,
+    n: usize,
+    out: *mut f64,
+    f: impl FnOnce(Option<&[f64]>, &[f64]) -> Result<Vec<f64>, String>,
+) -> c_int {
+    if (y.is_null() || out.is_null()) && n > 0 {
+        return 1;
+    }
+    let res = catch_unwind(AssertUnwindSafe(|| {
+        if n == 0 {
+            return f(None, &[]);
+        }
+        let y = unsafe { std::slice::from_raw_parts(y, n) };
+        let x = (!x.is_null()).then(|| unsafe { std::slice::from_raw_parts(x, n) });
+        f(x, y)
+    }));
+    match res {
+        Ok(Ok(v)) => {
+            if n > 0 {
+                unsafe { std::slice::from_raw_parts_mut(out, n) }.copy_from_slice(&v);
+            }
+            0
+        }
+        Ok(Err(_)) => 4,
+        Err(_) => 2,
+    }
+}
+
+/// Smooths `y[..n]` into `out[..n]`. `x` holds the positions, or is null
+/// for evenly spaced points. Returns 4 for unsorted `x` or a bad width.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_smooth(
+    x: *const f64,
+    y: *const f64,
+    n: usize,
+    opts: *const UlcmsSmoothOptions,
+    out: *mut f64,
+) -> c_int {
+    if opts.is_null() {
+        return 1;
+    }
+    let o = unsafe { &*opts };
+    let smoother = match o.method {
+        ULCMS_SMOOTH_MOVING_AVERAGE => Smoother::MovingAverage {
+            half_width: o.width,
+        },
+        ULCMS_SMOOTH_GAUSSIAN => Smoother::Gaussian { sigma: o.width },
+        ULCMS_SMOOTH_SAVITZKY_GOLAY => Smoother::SavitzkyGolay {
+            half_width: o.width,
+            order: o.order as usize,
+        },
+        _ => return 3,
+    };
+    signal_f64(x, y, n, out, |x, y| smooth(x, y, smoother))
+}
+
+/// Baseline of `y[..n]` into `out[..n]`; `x` as in [`ulcms_smooth`].
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_baseline(
+    x: *const f64,
+    y: *const f64,
+    n: usize,
+    opts: *const UlcmsBaselineOptions,
+    out: *mut f64,
+) -> c_int {
+    if opts.is_null() {
+        return 1;
+    }
+    let o = unsafe { &*opts };
+    let method = match o.method {
+        ULCMS_BASELINE_ALS => Baseline::Als {
+            lambda: o.lambda,
+            p: o.p,
+            iterations: o.iterations,
+        },
+        ULCMS_BASELINE_SNIP => Baseline::Snip {
+            half_width: o.width,
+        },
+        ULCMS_BASELINE_ROLLING_MIN => Baseline::RollingMin {
+            half_width: o.width,
+        },
+        _ => return 3,
+    };
+    signal_f64(x, y, n, out, |x, y| baseline(x, y, method))
+}
+
+/// Noise level of `y[..n]` by `method`, a `ULCMS_NOISE_*` constant; `q` is
+/// the quantile for `ULCMS_NOISE_PERCENTILE`.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_noise(
+    y: *const f64,
+    n: usize,
+    method: u32,
+    q: f64,
+    out: *mut f64,
+) -> c_int {
+    if out.is_null() || (y.is_null() && n > 0) {
+        return 1;
+    }
+    let Some(estimator) = noise_estimator(method, q) else {
+        return 3;
+    };
+    let y = if n == 0 {
+        &[][..]
+    } else {
+        unsafe { std::slice::from_raw_parts(y, n) }
+    };
+    match catch_unwind(|| noise(y, estimator)) {
+        Ok(Ok(v)) => {
+            unsafe { *out = v };
+            0
+        }
+        Ok(Err(_)) => 4,
+        Err(_) => 2,
+    }
+}
+
+/// Noise level around each point of `y[..n]`, from the points within
+/// `width` of it, into `out[..n]`; `x` as in [`ulcms_smooth`].
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_local_noise(
+    x: *const f64,
+    y: *const f64,
+    n: usize,
+    method: u32,
+    q: f64,
+    width: f64,
+    out: *mut f64,
+) -> c_int {
+    let Some(estimator) = noise_estimator(method, q) else {
+        return 3;
+    };
+    signal_f64(x, y, n, out, |x, y| local_noise(x, y, estimator, width))
+}
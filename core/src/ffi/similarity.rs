@@ -0,0 +1,232 @@
+//! Spectral similarity of peak lists and clustering of spectra.
+
+use core::ffi::c_int;
+use std::panic::{AssertUnwindSafe, catch_unwind};
+
+use crate::utilities::similarity::{
+    ClusterOptions, FilterOptions, IntensityTransform, Peaks, SimilarityMetric, SimilarityOptions,
+    cluster_spectra, similarity,
+};
+
+use super::file::UlcmsFile;
+
+pub const ULCMS_SIMILARITY_COSINE: u32 = 0;
+pub const ULCMS_SIMILARITY_MODIFIED_COSINE: u32 = 1;
+pub const ULCMS_SIMILARITY_ENTROPY: u32 = 2;
+pub const ULCMS_SIMILARITY_WEIGHTED_ENTROPY: u32 = 3;
+
+pub const ULCMS_TRANSFORM_NONE: u32 = 0;
+pub const ULCMS_TRANSFORM_SQRT: u32 = 1;
+pub const ULCMS_TRANSFORM_LOG: u32 = 2;
+
+/// Mirrors `SimilarityOptions` and the `FilterOptions` applied to both
+/// spectra; start from [`ulcms_similarity_options_default`]. `metric` is a
+/// `ULCMS_SIMILARITY_*` and `transform` a `ULCMS_TRANSFORM_*` constant.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct UlcmsSimilarityOptions {
+    pub metric: u32,
+    pub mz_tolerance: f64,
+    pub ppm: f64,
+    pub noise: f64,
+    pub top_n: usize,
+    pub transform: u32,
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_similarity_options_default() -> UlcmsSimilarityOptions {
+    let s = SimilarityOptions::default();
+    let f = FilterOptions::default();
+    UlcmsSimilarityOptions {
+        metric: ULCMS_SIMILARITY_COSINE,
+        mz_tolerance: s.mz_tolerance,
+        ppm: s.ppm,
+        noise: f.noise,
+        top_n: f.top_n,
+        transform: ULCMS_TRANSFORM_NONE,
+    }
+}
+
+pub(super) fn intensity_transform(t: u32) -> Option<IntensityTransform> {
+    match t {
+        ULCMS_TRANSFORM_NONE => Some(IntensityTransform::None),
+        ULCMS_TRANSFORM_SQRT => Some(IntensityTransform::Sqrt),
+        ULCMS_TRANSFORM_LOG => Some(IntensityTransform::Log),
+        _ => None,
+    }
+}
+
+// None for an unknown metric or transform.
+pub(super) fn similarity_options(
+    o: &UlcmsSimilarityOptions,
+) -> Option<(FilterOptions, SimilarityOptions)> {
+    let metric = match o.metric {
+        ULCMS_SIMILARITY_COSINE => SimilarityMetric::Cosine,
+        ULCMS_SIMILARITY_MODIFIED_COSINE => SimilarityMetric::ModifiedCosine,
+        ULCMS_SIMILARITY_ENTROPY => SimilarityMetric::Entropy,
+        ULCMS_SIMILARITY_WEIGHTED_ENTROPY => SimilarityMetric::WeightedEntropy,
+        _ => return None,
+    };
+    let transform = intensity_transform(o.transform)?;
+    let filter = FilterOptions {
+        noise: o.noise,
+        top_n: o.top_n,
+        transform,
+    };
+    let similarity = SimilarityOptions {
+        metric,
+        mz_tolerance: o.mz_tolerance,
+        ppm: o.ppm,
+    };
+    Some((filter, similarity))
+}
+
+/// A peak list passed in by the caller. `precursor_mz` is NaN when unknown.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct UlcmsPeakList {
+    pub mz: *const f64,
+    pub intensity: *const f64,
+    pub len: usize,
+    pub precursor_mz: f64,
+}
+
+fn peak_list(p: &UlcmsPeakList, opts: &FilterOptions) -> Option<Peaks> {
+    if p.len > 0 && (p.mz.is_null() || p.intensity.is_null()) {
+        return None;
+    }
+    let (mz, intensity): (&[f64], &[f64]) = if p.len == 0 {
+        (&[], &[])
+    } else {
+        unsafe {
+            (
+                std::slice::from_raw_parts(p.mz, p.len),
+                std::slice::from_raw_parts(p.intensity, p.len),
+            )
+        }
+    };
+    let precursor = Some(p.precursor_mz).filter(|m| m.is_finite());
+    Some(Peaks::new(mz, intensity, precursor, opts))
+}
+
+/// Similarity of two spectra, from 0 to 1, and optionally the number of
+/// matched peaks (`matched` may be null).
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_spectral_similarity(
+    a: *const UlcmsPeakList,
+    b: *const UlcmsPeakList,
+    opts: *const UlcmsSimilarityOptions,
+    score: *mut f64,
+    matched: *mut usize,
+) -> c_int {
+    if a.is_null() || b.is_null() || opts.is_null() || score.is_null() {
+        return 1;
+    }
+    let Some((filter, opts)) = similarity_options(unsafe { &*opts }) else {
+        return 3;
+    };
+
+    let res = catch_unwind(AssertUnwindSafe(|| {
+        let a = peak_list(unsafe { &*a }, &filter)?;
+        let b = peak_list(unsafe { &*b }, &filter)?;
+        Some(similarity(&a, &b, &opts))
+    }));
+
+    match res {
+        Ok(Some(s)) => {
+            unsafe {
+                *score = s.score;
+                if !matched.is_null() {
+                    *matched = s.matched_peaks;
+                }
+            }
+            0
+        }
+        Ok(None) => 1,
+        Err(_) => 2,
+    }
+}
+
+/// Mirrors `ClusterOptions`; start from [`ulcms_cluster_options_default`].
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct UlcmsClusterOptions {
+    pub similarity: UlcmsSimilarityOptions,
+    pub min_score: f64,
+    pub precursor_tolerance: f64,
+    pub min_matched_peaks: usize,
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_cluster_options_default() -> UlcmsClusterOptions {
+    let o = ClusterOptions::default();
+    UlcmsClusterOptions {
+        similarity: ulcms_similarity_options_default(),
+        min_score: o.min_score,
+        precursor_tolerance: o.precursor_tolerance,
+        min_matched_peaks: o.min_matched_peaks,
+    }
+}
+
+/// Label of spectra left out of clustering.
+pub const ULCMS_NO_CLUSTER: usize = !0;
+
+/// Clusters the MSn spectra of `file` by similarity. The first `cap`
+/// labels, one per spectrum of the file, are written to `labels`: clusters
+/// are numbered from 0 and MS1 spectra, or spectra without arrays, get
+/// `ULCMS_NO_CLUSTER`. The number of clusters goes to `n_clusters`.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_cluster_spectra(
+    file: *const UlcmsFile,
+    opts: *const UlcmsClusterOptions,
+    labels: *mut usize,
+    cap: usize,
+    n_clusters: *mut usize,
+) -> c_int {
+    if file.is_null() || opts.is_null() || n_clusters.is_null() || (cap > 0 && labels.is_null()) {
+        return 1;
+    }
+    let o = unsafe { &*opts };
+    let Some((filter, similarity)) = similarity_options(&o.similarity) else {
+        return 3;
+    };
+    let opts = ClusterOptions {
+        similarity,
+        min_score: o.min_score,
+        precursor_tolerance: o.precursor_tolerance,
+        min_matched_peaks: o.min_matched_peaks,
+    };
+
+    let res = catch_unwind(AssertUnwindSafe(|| {
+        let spectra = unsafe { &(*file).spectra };
+        let mut index = Vec::new();
+        let mut peaks = Vec::new();
+        for (i, s) in spectra.iter().enumerate() {
+            if s.ms_level.is_some_and(|l| l >= 2)
+                && let Some(p) = Peaks::from_spectrum(s, &filter)
+            {
+                index.push(i);
+                peaks.push(p);
+            }
+        }
+        let clusters = cluster_spectra(&peaks, &opts);
+        let mut out = vec![ULCMS_NO_CLUSTER; spectra.len()];
+        for (&i, &c) in index.iter().zip(&clusters) {
+            out[i] = c;
+        }
+        let n = clusters.iter().map(|&c| c + 1).max().unwrap_or(0);
+        (out, n)
+    }));
+
+    match res {
+        Ok((out, n)) => {
+            let k = out.len().min(cap);
+            unsafe {
+                std::ptr::copy_nonoverlapping(out.as_ptr(), labels, k);
+                *n_clusters = n;
+            }
+            0
+        }
+        Err(_) => 2,
+    }
+}
@@ -0,0 +1,109 @@
+//! Summary statistics over caller buffers, for C, `.C` and the wasm host.
+
+use core::ffi::c_int;
+use std::alloc::{Layout, alloc, dealloc};
+use std::panic::catch_unwind;
+
+use crate::utilities::stats::{self, NanPolicy};
+
+/// Allocates `size` bytes aligned for `f64`, for callers (the wasm host)
+/// that need to place input in this module's memory. Pair with
+/// [`ulcms_free`] using the same size.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_alloc(size: usize) -> *mut u8 {
+    let Ok(layout) = Layout::from_size_align(size.max(1), 8) else {
+        return core::ptr::null_mut();
+    };
+    unsafe { alloc(layout) }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_free(ptr: *mut u8, size: usize) {
+    if ptr.is_null() {
+        return;
+    }
+    if let Ok(layout) = Layout::from_size_align(size.max(1), 8) {
+        unsafe { dealloc(ptr, layout) };
+    }
+}
+
+// Runs a statistic over `ptr[..len]` and stores it in `out`.
+fn stat_f64(ptr: *const f64, len: usize, out: *mut f64, f: fn(&[f64]) -> f64) -> c_int {
+    if out.is_null() || (ptr.is_null() && len > 0) {
+        return 1;
+    }
+    let xs = if len == 0 {
+        &[][..]
+    } else {
+        unsafe { std::slice::from_raw_parts(ptr, len) }
+    };
+    match catch_unwind(|| f(xs)) {
+        Ok(v) => {
+            unsafe { *out = v };
+            0
+        }
+        Err(_) => 2,
+    }
+}
+
+fn median_propagate(xs: &[f64]) -> f64 {
+    stats::median(xs, NanPolicy::Propagate).unwrap_or(f64::NAN)
+}
+
+fn std_sample(xs: &[f64]) -> f64 {
+    stats::std(xs, 1)
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_mean_f64(ptr: *const f64, len: usize, out: *mut f64) -> c_int {
+    stat_f64(ptr, len, out, stats::mean)
+}
+
+/// Sample standard deviation (n - 1 denominator).
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_std_f64(ptr: *const f64, len: usize, out: *mut f64) -> c_int {
+    stat_f64(ptr, len, out, std_sample)
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_median_f64(ptr: *const f64, len: usize, out: *mut f64) -> c_int {
+    stat_f64(ptr, len, out, median_propagate)
+}
+
+/// Compensated sum of `ptr[..len]`.
+#[unsafe(no_mangle)]
+pub extern "C" fn sum_f64(ptr: *const f64, len: usize, out: *mut f64) -> c_int {
+    stat_f64(ptr, len, out, stats::sum)
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn add_i32(a: i32, b: i32) -> i32 {
+    a.wrapping_add(b)
+}
+
+// `.C` entry points: every argument arrives as a pointer and the result is
+// written through `out`.
+fn stat_f64_r(x: *const f64, n: *const c_int, out: *mut f64, f: fn(&[f64]) -> f64) {
+    if n.is_null() || out.is_null() {
+        return;
+    }
+    let len = usize::try_from(unsafe { *n }).unwrap_or(0);
+    if stat_f64(x, len, out, f) != 0 {
+        unsafe { *out = f64::NAN };
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_mean_f64_r(x: *const f64, n: *const c_int, out: *mut f64) {
+    stat_f64_r(x, n, out, stats::mean)
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_std_f64_r(x: *const f64, n: *const c_int, out: *mut f64) {
+    stat_f64_r(x, n, out, std_sample)
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_median_f64_r(x: *const f64, n: *const c_int, out: *mut f64) {
+    stat_f64_r(x, n, out, median_propagate)
+}
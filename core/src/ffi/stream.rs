@@ -0,0 +1,90 @@
+//! Push parser fed with chunks of an mzML file.
+
+use core::ffi::c_int;
+use std::panic::{AssertUnwindSafe, catch_unwind};
+
+use crate::utilities::stream::SpectrumStream;
+
+use super::file::{UlcmsFile, file_from_spectra};
+
+/// Opaque push parser handle; see [`SpectrumStream`].
+pub struct UlcmsStream {
+    inner: SpectrumStream,
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_stream_new() -> *mut UlcmsStream {
+    Box::into_raw(Box::new(UlcmsStream {
+        inner: SpectrumStream::new(),
+    }))
+}
+
+/// Feeds the next chunk of the file; chunks may split anywhere.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_stream_feed(
+    stream: *mut UlcmsStream,
+    data_ptr: *const u8,
+    data_len: usize,
+) -> c_int {
+    if stream.is_null() || (data_ptr.is_null() && data_len > 0) {
+        return 1;
+    }
+    if data_len == 0 {
+        return 0;
+    }
+
+    let res = catch_unwind(AssertUnwindSafe(|| {
+        let data = unsafe { std::slice::from_raw_parts(data_ptr, data_len) };
+        let stream = unsafe { &mut *stream };
+        stream.inner.feed(data);
+    }));
+
+    match res {
+        Ok(()) => 0,
+        Err(_) => 2,
+    }
+}
+
+/// Moves the spectra completed so far into a new file handle, read with the
+/// `ulcms_spectrum_*` accessors and released with `ulcms_file_close`.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_stream_take_spectra(
+    stream: *mut UlcmsStream,
+    out: *mut *mut UlcmsFile,
+) -> c_int {
+    if stream.is_null() || out.is_null() {
+        return 1;
+    }
+    let stream = unsafe { &mut *stream };
+    file_from_spectra(stream.inner.take_spectra(), out);
+    0
+}
+
+/// Ends the stream and frees it, handing the remaining spectra over like
+/// [`ulcms_stream_take_spectra`]. Returns 4 if the input ended inside a
+/// spectrum; the stream is freed either way.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_stream_finish(stream: *mut UlcmsStream, out: *mut *mut UlcmsFile) -> c_int {
+    if stream.is_null() || out.is_null() {
+        return 1;
+    }
+    let stream = unsafe { Box::from_raw(stream) };
+    match stream.inner.finish() {
+        Ok(spectra) => {
+            file_from_spectra(spectra, out);
+            0
+        }
+        Err(_) => 4,
+    }
+}
+
+/// Discards a stream without collecting its spectra.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_stream_free(stream: *mut UlcmsStream) {
+    if stream.is_null() {
+        return;
+    }
+    unsafe {
+        let _ = Box::from_raw(stream);
+    }
+}
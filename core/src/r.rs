@@ -0,0 +1,604 @@
+//! `.Call` entry points for the R package, built with the `r` feature.
+//!
+//! Results are allocated as R objects here, so arrays are written straight
+//! into R vectors without an intermediate copy on the R side. The R API
+//! symbols are resolved against the host R process when the library is
+//! loaded, which is why this flavour of the library is only usable from R.
+
+use core::ffi::{c_char, c_int, c_void};
+use std::ffi::CStr;
+use std::fs;
+use std::panic::{AssertUnwindSafe, catch_unwind};
+
+use crate::utilities::annotation::Polarity;
+use crate::utilities::binning::{Aggregation, BinnedMatrix, BinningOptions, MzBins, bin_spectra};
+use crate::utilities::feature_detection::{
+    ChromPeakMethod, Feature, FeatureOptions, find_features,
+};
+use crate::utilities::parse_mzml::{ArrayData, ParseOptions, SpectrumSummary, parse_mzml_with};
+
+#[allow(clippy::upper_case_acronyms)]
+type SEXP = *mut c_void;
+
+// SEXPTYPE codes from Rinternals.h.
+const INTSXP: u32 = 13;
+const REALSXP: u32 = 14;
+const STRSXP: u32 = 16;
+const VECSXP: u32 = 19;
+const RAWSXP: u32 = 24;
+
+const CE_UTF8: c_int = 1;
+
+unsafe extern "C" {
+    static R_NamesSymbol: SEXP;
+    static R_ClassSymbol: SEXP;
+    static R_RowNamesSymbol: SEXP;
+    static R_DimSymbol: SEXP;
+    static R_NaString: SEXP;
+    static R_NilValue: SEXP;
+    static R_NaInt: c_int;
+    static R_NaReal: f64;
+
+    fn Rf_allocVector(t: u32, len: isize) -> SEXP;
+    fn Rf_protect(s: SEXP) -> SEXP;
+    fn Rf_unprotect(n: c_int);
+    fn Rf_setAttrib(x: SEXP, name: SEXP, value: SEXP) -> SEXP;
+    fn Rf_mkCharLenCE(s: *const c_char, len: c_int, enc: c_int) -> SEXP;
+    fn Rf_xlength(x: SEXP) -> isize;
+    fn Rf_asLogical(x: SEXP) -> c_int;
+    fn Rf_translateCharUTF8(x: SEXP) -> *const c_char;
+    fn SET_VECTOR_ELT(x: SEXP, i: isize, v: SEXP) -> SEXP;
+    fn SET_STRING_ELT(x: SEXP, i: isize, v: SEXP);
+    fn STRING_ELT(x: SEXP, i: isize) -> SEXP;
+    fn REAL(x: SEXP) -> *mut f64;
+    fn INTEGER(x: SEXP) -> *mut c_int;
+    fn RAW(x: SEXP) -> *mut u8;
+    fn TYPEOF(x: SEXP) -> c_int;
+    fn R_MakeExternalPtr(p: *mut c_void, tag: SEXP, prot: SEXP) -> SEXP;
+    fn R_ExternalPtrAddr(s: SEXP) -> *mut c_void;
+    fn R_ClearExternalPtr(s: SEXP);
+    fn R_RegisterCFinalizerEx(s: SEXP, fun: unsafe extern "C" fn(SEXP), onexit: c_int);
+}
+
+/// Reads an mzML file into a data.frame with one row per spectrum.
+///
+/// `path` is a character(1); `ms_level` an integer vector of levels to keep
+/// (empty keeps all); `rt` an empty or length-2 double vector giving an
+/// inclusive retention time window in minutes; `arrays` a logical(1) adding
+/// `mz`, `intensity` and `mobility` list-columns; `widen` a logical(1) that
+/// turns 32-bit arrays into doubles instead of returning them as `ulcms_f32`
+/// raw vectors. On failure a character(1) holding the error message is
+/// returned instead, for the R side to raise.
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_read_mzml_r(
+    path: SEXP,
+    ms_level: SEXP,
+    rt: SEXP,
+    arrays: SEXP,
+    widen: SEXP,
+) -> SEXP {
+    // Arguments are coerced and checked by `read_mzml()` before the call.
+    let (path, ms_level, rt, with_arrays, widen) = unsafe {
+        (
+            CStr::from_ptr(Rf_translateCharUTF8(STRING_ELT(path, 0))),
+            int_slice(ms_level),
+            real_slice(rt),
+            Rf_asLogical(arrays) == 1,
+            Rf_asLogical(widen) == 1,
+        )
+    };
+    let res = read_spectra(path, ms_level, rt);
+    unsafe {
+        with_r_owned(res, |res| match res {
+            Ok(spectra) => spectra_data_frame(spectra, with_arrays, widen),
+            Err(e) => r_string(e),
+        })
+    }
+}
+
+fn read_spectra(
+    path: &CStr,
+    ms_level: &[c_int],
+    rt: &[f64],
+) -> Result<Vec<SpectrumSummary>, String> {
+    let opts = ParseOptions {
+        ms_levels: ms_level
+            .iter()
+            .filter_map(|&l| u32::try_from(l).ok())
+            .collect(),
+        rt: match rt {
+            &[lo, hi] => Some((lo, hi)),
+            _ => None,
+        },
+        ..ParseOptions::default()
+    };
+    let path = path.to_string_lossy();
+    catch_unwind(AssertUnwindSafe(|| -> Result<_, String> {
+        let data = fs::read(&*path).map_err(|e| format!("open/read {path}: {e}"))?;
+        parse_mzml_with(&data, &opts)
+    }))
+    .unwrap_or_else(|_| Err("internal error while parsing mzML".into()))
+}
+
+/// Detects features in the MS1 spectra of an mzML file and returns them as
+/// a data.frame with one row per feature.
+///
+/// `path` is a character(1) and `params` a double vector holding, in order,
+/// ppm, min_scans, max_gap, min_intensity, the peak width range (two values,
+/// minutes), snr and the method (0 for CWT, 1 for matched filter). Errors
+/// are returned as a character(1), as in [`ulcms_read_mzml_r`].
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_find_features_r(path: SEXP, params: SEXP) -> SEXP {
+    let (path, params) = unsafe {
+        (
+            CStr::from_ptr(Rf_translateCharUTF8(STRING_ELT(path, 0))),
+            real_slice(params),
+        )
+    };
+    let res = detect_features(path, params);
+    unsafe {
+        with_r_owned(res, |res| match res {
+            Ok(features) => features_data_frame(features),
+            Err(e) => r_string(e),
+        })
+    }
+}
+
+fn detect_features(path: &CStr, params: &[f64]) -> Result<Vec<Feature>, String> {
+    let opts = match *params {
+        [
+            ppm,
+            min_scans,
+            max_gap,
+            min_intensity,
+            w_lo,
+            w_hi,
+            snr,
+            method,
+        ] => FeatureOptions {
+            ppm,
+            min_scans: min_scans as usize,
+            max_gap: max_gap as usize,
+            min_intensity,
+            peak_width: (w_lo, w_hi),
+            snr,
+            method: if method == 1.0 {
+                ChromPeakMethod::MatchedFilter
+            } else {
+                ChromPeakMethod::Cwt
+            },
+        },
+        _ => return Err("params must hold 8 values".into()),
+    };
+    let path = path.to_string_lossy();
+    catch_unwind(AssertUnwindSafe(|| -> Result<_, String> {
+        let data = fs::read(&*path).map_err(|e| format!("open/read {path}: {e}"))?;
+        let ms1 = ParseOptions {
+            ms_levels: vec![1],
+            ..ParseOptions::default()
+        };
+        Ok(find_features(&parse_mzml_with(&data, &ms1)?, &opts))
+    }))
+    .unwrap_or_else(|_| Err("internal error while detecting features".into()))
+}
+
+/// Bins the spectra of an mzML file into a retention time by m/z matrix and
+/// returns a list holding `rt`, `spectrum` (1-based index of the source
+/// spectrum of each row, NA on a resampled grid), `mz_edges` and the
+/// intensities: a numeric matrix named `matrix` when `dense` is TRUE, or
+/// the 0-based CSR vectors `p`, `j` and `x` with `dim` otherwise.
+///
+/// `path` is a character(1) and `params` a double vector holding, in order,
+/// the bin width, its unit (0 for Da, 1 for ppm), the m/z range (two
+/// values), the aggregation (0 for sum, 1 for max), the retention time step,
+/// the retention time range (two values), the MS level (0 for all) and the
+/// polarity (0 for any, 1 positive, 2 negative). NA range limits are unset.
+/// Errors are returned as a character(1), as in [`ulcms_read_mzml_r`].
+#[unsafe(no_mangle)]
+pub extern "C" fn ulcms_bin_spectra_r(path: SEXP, params: SEXP, dense: SEXP) -> SEXP {
+    let (path, params, dense) = unsafe {
+        (
+            CStr::from_ptr(Rf_translateCharUTF8(STRING_ELT(path, 0))),
+            real_slice(params),
+            Rf_asLogical(dense) == 1,
+        )
+    };
+    let res = bin_run(path, params, dense);
+    unsafe {
+        with_r_owned(res, |res| match res {
+            Ok((matrix, index)) => binned_list(matrix, index, dense),
+            Err(e) => r_string(e),
+        })
+    }
+}
+
+// The matrix and the file index of each spectrum it was binned from.
+fn bin_run(path: &CStr, params: &[f64], dense: bool) -> Result<(BinnedMatrix, Vec<usize>), String> {
+    let set = |v: f64| (!v.is_nan()).then_some(v);
+    let opts = match *params {
+        [
+            width,
+            unit,
+            mz_min,
+            mz_max,
+            aggregation,
+            rt_step,
+            rt_min,
+            rt_max,
+            ms_level,
+            polarity,
+        ] => BinningOptions {
+            bins: if unit == 1.0 {
+                MzBins::Ppm(width)
+            } else {
+                MzBins::Width(width)
+            },
+            mz_min: set(mz_min),
+            mz_max: set(mz_max),
+            aggregation: if aggregation == 1.0 {
+                Aggregation::Max
+            } else {
+                Aggregation::Sum
+            },
+            rt_step,
+            rt_min: set(rt_min),
+            rt_max: set(rt_max),
+            ms_level: (ms_level > 0.0).then_some(ms_level as u32),
+            polarity: match polarity as u32 {
+                1 => Some(Polarity::Positive),
+                2 => Some(Polarity::Negative),
+                _ => None,
+            },
+        },
+        _ => return Err("params must hold 10 values".into()),
+    };
+    // Only the spectra of the binned level are decoded.
+    let parse = ParseOptions {
+        ms_levels: opts.ms_level.into_iter().collect(),
+        ..ParseOptions::default()
+    };
+    let path = path.to_string_lossy();
+    let (matrix, index) = catch_unwind(AssertUnwindSafe(|| -> Result<_, String> {
+        let data = fs::read(&*path).map_err(|e| format!("open/read {path}: {e}"))?;
+        let spectra = parse_mzml_with(&data, &parse)?;
+        let matrix = bin_spectra(&spectra, &opts)?;
+        let index = matrix.spectra.iter().map(|&i| spectra[i].index).collect();
+        Ok((matrix, index))
+    }))
+    .unwrap_or_else(|_| Err("internal error while binning spectra".into()))?;
+
+    // R matrix dimensions and integer vectors are 32-bit.
+    let int = |n: usize| i32::try_from(n).is_ok();
+    let too_large = if dense {
+        matrix
+            .n_rows()
+            .checked_mul(matrix.n_cols())
+            .is_none_or(|n| isize::try_from(n).is_err())
+    } else {
+        !int(matrix.nnz())
+    };
+    if !int(matrix.n_rows()) || !int(matrix.n_cols()) || too_large {
+        return Err(format!(
+            "a {} x {} matrix is too large for R",
+            matrix.n_rows(),
+            matrix.n_cols()
+        ));
+    }
+    Ok((matrix, index))
+}
+
+unsafe fn binned_list(m: &BinnedMatrix, index: &[usize], dense: bool) -> SEXP {
+    let (rows, cols) = (m.n_rows(), m.n_cols());
+    let names: &[&str] = if dense {
+        &["rt", "spectrum", "mz_edges", "matrix"]
+    } else {
+        &["rt", "spectrum", "mz_edges", "p", "j", "x", "dim"]
+    };
+    unsafe {
+        let out = Rf_protect(Rf_allocVector(VECSXP, names.len() as isize));
+        let rt = SET_VECTOR_ELT(out, 0, Rf_allocVector(REALSXP, rows as isize));
+        let spectrum = SET_VECTOR_ELT(out, 1, Rf_allocVector(INTSXP, rows as isize));
+        for r in 0..rows {
+            *REAL(rt).add(r) = m.rt[r];
+            *INTEGER(spectrum).add(r) = index
+                .get(r)
+                .and_then(|&i| i32::try_from(i + 1).ok())
+                .unwrap_or(R_NaInt);
+        }
+        let edges = SET_VECTOR_ELT(out, 2, Rf_allocVector(REALSXP, m.mz_edges.len() as isize));
+        for (k, &e) in m.mz_edges.iter().enumerate() {
+            *REAL(edges).add(k) = e;
+        }
+
+        if dense {
+            // Column-major, as R stores matrices.
+            let x = SET_VECTOR_ELT(out, 3, Rf_allocVector(REALSXP, (rows * cols) as isize));
+            let x_ptr = REAL(x);
+            std::ptr::write_bytes(x_ptr, 0, rows * cols);
+            for r in 0..rows {
+                for k in m.indptr[r]..m.indptr[r + 1] {
+                    *x_ptr.add(m.indices[k] as usize * rows + r) = f64::from(m.values[k]);
+                }
+            }
+            let dim = Rf_protect(Rf_allocVector(INTSXP, 2));
+            *INTEGER(dim) = rows as c_int;
+            *INTEGER(dim).add(1) = cols as c_int;
+            Rf_setAttrib(x, R_DimSymbol, dim);
+            Rf_unprotect(1);
+        } else {
+            let p = SET_VECTOR_ELT(out, 3, Rf_allocVector(INTSXP, m.indptr.len() as isize));
+            for (k, &v) in m.indptr.iter().enumerate() {
+                *INTEGER(p).add(k) = v as c_int;
+            }
+            let j = SET_VECTOR_ELT(out, 4, Rf_allocVector(INTSXP, m.nnz() as isize));
+            let x = SET_VECTOR_ELT(out, 5, Rf_allocVector(REALSXP, m.nnz() as isize));
+            for k in 0..m.nnz() {
+                *INTEGER(j).add(k) = m.indices[k] as c_int;
+                *REAL(x).add(k) = f64::from(m.values[k]);
+            }
+            let dim = SET_VECTOR_ELT(out, 6, Rf_allocVector(INTSXP, 2));
+            *INTEGER(dim) = rows as c_int;
+            *INTEGER(dim).add(1) = cols as c_int;
+        }
+
+        Rf_setAttrib(out, R_NamesSymbol, protected_strings(names));
+        Rf_unprotect(2);
+        out
+    }
+}
+
+// R reports errors, including failed allocations, by longjmp, which must not
+// cross Rust frames that own memory. `value` is therefore handed to an R
+// external pointer before `build` allocates anything, and every frame below
+// only borrows it: a jump then skips frames with nothing to drop, and the
+// garbage collector frees `value` through the finalizer.
+unsafe fn with_r_owned<T>(value: T, build: impl FnOnce(&T) -> SEXP) -> SEXP {
+    unsafe extern "C" fn release<T>(xp: SEXP) {
+        unsafe {
+            let p = R_ExternalPtrAddr(xp) as *mut T;
+            if !p.is_null() {
+                R_ClearExternalPtr(xp);
+                drop(Box::from_raw(p));
+            }
+        }
+    }
+
+    unsafe {
+        let p = Box::into_raw(Box::new(value));
+        let xp = Rf_protect(R_MakeExternalPtr(p as *mut c_void, R_NilValue, R_NilValue));
+        R_RegisterCFinalizerEx(xp, release::<T>, 1);
+        let out = build(&*p);
+        release::<T>(xp);
+        Rf_unprotect(1);
+        out
+    }
+}
+
+unsafe fn int_slice<'a>(x: SEXP) -> &'a [c_int] {
+    unsafe {
+        let n = Rf_xlength(x) as usize;
+        if TYPEOF(x) as u32 != INTSXP || n == 0 {
+            return &[];
+        }
+        std::slice::from_raw_parts(INTEGER(x), n)
+    }
+}
+
+unsafe fn real_slice<'a>(x: SEXP) -> &'a [f64] {
+    unsafe {
+        let n = Rf_xlength(x) as usize;
+        if TYPEOF(x) as u32 != REALSXP || n == 0 {
+            return &[];
+        }
+        std::slice::from_raw_parts(REAL(x), n)
+    }
+}
+
+unsafe fn mk_char(s: &str) -> SEXP {
+    unsafe { Rf_mkCharLenCE(s.as_ptr() as *const c_char, s.len() as c_int, CE_UTF8) }
+}
+
+// Character vector, left protected; the caller unprotects it.
+unsafe fn protected_strings(xs: &[&str]) -> SEXP {
+    unsafe {
+        let out = Rf_protect(Rf_allocVector(STRSXP, xs.len() as isize));
+        for (i, s) in xs.iter().enumerate() {
+            SET_STRING_ELT(out, i as isize, mk_char(s));
+        }
+        out
+    }
+}
+
+unsafe fn r_string(s: &str) -> SEXP {
+    unsafe {
+        let out = protected_strings(&[s]);
+        Rf_unprotect(1);
+        out
+    }
+}
+
+// Every column is stored into the protected data.frame right after it is
+// allocated, so only the data.frame and the attribute values, which R
+// inspects when they are set and so must be filled first, need protecting.
+unsafe fn spectra_data_frame(spectra: &[SpectrumSummary], with_arrays: bool, widen: bool) -> SEXP {
+    const NAMES: [&str; 23] = [
+        "index",
+        "id",
+        "ms_level",
+        "scan_type",
+        "polarity",
+        "spectrum_type",
+        "retention_time",
+        "scan_window_lower_limit",
+        "scan_window_upper_limit",
+        "total_ion_current",
+        "base_peak_intensity",
+        "base_peak_mz",
+        "precursor_mz",
+        "precursor_charge",
+        "isolation_window_target",
+        "isolation_window_lower",
+        "isolation_window_upper",
+        "ion_mobility",
+        "ion_mobility_type",
+        "array_length",
+        "mz",
+        "intensity",
+        "mobility",
+    ];
+    let names = if with_arrays {
+        &NAMES[..]
+    } else {
+        &NAMES[..20]
+    };
+
+    unsafe {
+        let df = Rf_protect(Rf_allocVector(VECSXP, names.len() as isize));
+
+        set_int_col(df, 0, spectra, |s| i32::try_from(s.index).ok());
+        set_str_col(df, 1, spectra, |s| Some(s.id.as_str()));
+        set_int_col(df, 2, spectra, |s| {
+            s.ms_level.and_then(|l| i32::try_from(l).ok())
+        });
+        set_str_col(df, 3, spectra, |s| s.scan_type.as_deref());
+        set_str_col(df, 4, spectra, |s| s.polarity.as_deref());
+        set_str_col(df, 5, spectra, |s| s.spectrum_type.as_deref());
+        set_real_col(df, 6, spectra, |s| s.retention_time);
+        set_real_col(df, 7, spectra, |s| s.scan_window_lower_limit);
+        set_real_col(df, 8, spectra, |s| s.scan_window_upper_limit);
+        set_real_col(df, 9, spectra, |s| s.total_ion_current);
+        set_real_col(df, 10, spectra, |s| s.base_peak_intensity);
+        set_real_col(df, 11, spectra, |s| s.base_peak_mz);
+        set_real_col(df, 12, spectra, |s| s.precursor_mz);
+        set_int_col(df, 13, spectra, |s| {
+            s.precursor_charge.and_then(|z| i32::try_from(z).ok())
+        });
+        set_real_col(df, 14, spectra, |s| s.isolation_window_target);
+        set_real_col(df, 15, spectra, |s| s.isolation_window_lower);
+        set_real_col(df, 16, spectra, |s| s.isolation_window_upper);
+        set_real_col(df, 17, spectra, |s| s.ion_mobility);
+        set_str_col(df, 18, spectra, |s| s.ion_mobility_type.as_deref());
+        set_int_col(df, 19, spectra, |s| i32::try_from(s.array_length).ok());
+        if with_arrays {
+            set_array_col(df, 20, spectra, widen, |s| s.mz_array.as_ref());
+            set_array_col(df, 21, spectra, widen, |s| s.intensity_array.as_ref());
+            set_array_col(df, 22, spectra, widen, |s| s.mobility_array.as_ref());
+        }
+
+        finish_data_frame(df, names, spectra.len())
+    }
+}
+
+// Sets the names, row names and class of the protected list `df`, then
+// unprotects it.
+unsafe fn finish_data_frame(df: SEXP, names: &[&str], nrow: usize) -> SEXP {
+    unsafe {
+        Rf_setAttrib(df, R_NamesSymbol, protected_strings(names));
+
+        // Compact row names: c(NA_integer_, -nrow).
+        let row_names = Rf_protect(Rf_allocVector(INTSXP, 2));
+        *INTEGER(row_names) = R_NaInt;
+        *INTEGER(row_names).add(1) = -(nrow as c_int);
+        Rf_setAttrib(df, R_RowNamesSymbol, row_names);
+
+        Rf_setAttrib(df, R_ClassSymbol, protected_strings(&["data.frame"]));
+
+        Rf_unprotect(4);
+        df
+    }
+}
+
+unsafe fn features_data_frame(features: &[Feature]) -> SEXP {
+    let names = [
+        "mz", "mz_min", "mz_max", "rt", "rt_min", "rt_max", "area", "height", "snr", "scans",
+    ];
+    unsafe {
+        let df = Rf_protect(Rf_allocVector(VECSXP, names.len() as isize));
+        set_real_col(df, 0, features, |f| Some(f.mz));
+        set_real_col(df, 1, features, |f| Some(f.mz_min));
+        set_real_col(df, 2, features, |f| Some(f.mz_max));
+        set_real_col(df, 3, features, |f| Some(f.rt));
+        set_real_col(df, 4, features, |f| Some(f.rt_min));
+        set_real_col(df, 5, features, |f| Some(f.rt_max));
+        set_real_col(df, 6, features, |f| Some(f.area));
+        set_real_col(df, 7, features, |f| Some(f.height));
+        set_real_col(df, 8, features, |f| Some(f.snr));
+        set_int_col(df, 9, features, |f| i32::try_from(f.scans).ok());
+        finish_data_frame(df, &names, features.len())
+    }
+}
+
+unsafe fn set_int_col<T>(df: SEXP, j: isize, rows: &[T], f: impl Fn(&T) -> Option<i32>) {
+    unsafe {
+        let col = SET_VECTOR_ELT(df, j, Rf_allocVector(INTSXP, rows.len() as isize));
+        for (i, s) in rows.iter().enumerate() {
+            *INTEGER(col).add(i) = f(s).unwrap_or(R_NaInt);
+        }
+    }
+}
+
+unsafe fn set_real_col<T>(df: SEXP, j: isize, rows: &[T], f: impl Fn(&T) -> Option<f64>) {
+    unsafe {
+        let col = SET_VECTOR_ELT(df, j, Rf_allocVector(REALSXP, rows.len() as isize));
+        for (i, s) in rows.iter().enumerate() {
+            *REAL(col).add(i) = f(s).unwrap_or(R_NaReal);
+        }
+    }
+}
+
+unsafe fn set_str_col<'a>(
+    df: SEXP,
+    j: isize,
+    spectra: &'a [SpectrumSummary],
+    f: impl Fn(&'a SpectrumSummary) -> Option<&'a str>,
+) {
+    unsafe {
+        let col = SET_VECTOR_ELT(df, j, Rf_allocVector(STRSXP, spectra.len() as isize));
+        for (i, s) in spectra.iter().enumerate() {
+            let v = match f(s) {
+                Some(v) => mk_char(v),
+                None => R_NaString,
+            };
+            SET_STRING_ELT(col, i as isize, v);
+        }
+    }
+}
+
+// List-column with one vector per spectrum; spectra without the array get
+// NULL. 32-bit arrays stay 32-bit unless `widen` is set: R has no float
+// type, so they are stored as raw vectors of native-endian bytes with class
+// `ulcms_f32`, which `as.double()` converts on the R side.
+unsafe fn set_array_col(
+    df: SEXP,
+    j: isize,
+    spectra: &[SpectrumSummary],
+    widen: bool,
+    f: impl Fn(&SpectrumSummary) -> Option<&ArrayData>,
+) {
+    unsafe {
+        let col = SET_VECTOR_ELT(df, j, Rf_allocVector(VECSXP, spectra.len() as isize));
+        for (i, s) in spectra.iter().enumerate() {
+            let Some(arr) = f(s) else {
+                continue;
+            };
+            if let (false, Some(xs)) = (widen, arr.as_f32()) {
+                let n = std::mem::size_of_val(xs);
+                let v = SET_VECTOR_ELT(col, i as isize, Rf_allocVector(RAWSXP, n as isize));
+                if n > 0 {
+                    std::ptr::copy_nonoverlapping(xs.as_ptr() as *const u8, RAW(v), n);
+                }
+                Rf_setAttrib(v, R_ClassSymbol, protected_strings(&["ulcms_f32"]));
+                Rf_unprotect(1);
+                continue;
+            }
+            let v = SET_VECTOR_ELT(col, i as isize, Rf_allocVector(REALSXP, arr.len() as isize));
+            if arr.is_empty() {
+                continue;
+            }
+            let dst = std::slice::from_raw_parts_mut(REAL(v), arr.len());
+            for (d, x) in dst.iter_mut().zip(arr.iter()) {
+                *d = x;
+            }
+        }
+    }
+}
@@ -1,6 +1,6 @@
 use std::fs;
 use std::time::Instant;
-use ulcms::utilities::parse_mzml::{SpectrumSummary, parse_mzml};
+use ulcms::utilities::parse_mzml::{ArrayData, SpectrumSummary, parse_mzml};
 
 fn main() {
     let path = "/Users/josoriom/github/josoriom/ulcms/core/data/iron_ultrairon_SER_MS-AI-RPNEG@fNMR_IROr26_IROp011_LTR_30.mzML";
@@ -24,7 +24,7 @@ fn main() {
 }
 
 fn to_json(s: &SpectrumSummary, preview: usize) -> String {
-    fn fmt_vec(v: &Option<Vec<f64>>, preview: usize) -> String {
+    fn fmt_vec(v: &Option<ArrayData>, preview: usize) -> String {
         match v {
             None => "null".to_string(),
             Some(arr) => {
@@ -67,9 +67,17 @@ fn to_json(s: &SpectrumSummary, preview: usize) -> String {
   "id": "{id}",
   "index": {i},
   "intensityArray": {int},
+  "ionMobility": {im},
+  "ionMobilityType": {imt},
+  "isolationWindowLower": {iwl},
+  "isolationWindowTarget": {iwt},
+  "isolationWindowUpper": {iwu},
+  "mobilityArray": {mob},
   "msLevel": {ms},
   "mzArray": {mz},
   "polarity": {pol},
+  "precursorCharge": {pz},
+  "precursorMZ": {pmz},
   "retentionTime": {rt},
   "scanType": {st},
   "scanWindowLowerLimit": {lw},
@@ -83,12 +91,23 @@ fn to_json(s: &SpectrumSummary, preview: usize) -> String {
         id = s.id.replace('\\', "\\\\").replace('"', "\\\""),
         i = s.index,
         int = fmt_vec(&s.intensity_array, preview),
+        im = opt_f64(s.ion_mobility),
+        imt = opt_str(s.ion_mobility_type.as_deref()),
+        iwl = opt_f64(s.isolation_window_lower),
+        iwt = opt_f64(s.isolation_window_target),
+        iwu = opt_f64(s.isolation_window_upper),
+        mob = fmt_vec(&s.mobility_array, preview),
         ms = s
             .ms_level
             .map(|v| v.to_string())
             .unwrap_or_else(|| "null".into()),
         mz = fmt_vec(&s.mz_array, preview),
         pol = opt_str(s.polarity.as_deref()),
+        pz = s
+            .precursor_charge
+            .map(|v| v.to_string())
+            .unwrap_or_else(|| "null".into()),
+        pmz = opt_f64(s.precursor_mz),
         rt = opt_f64(s.retention_time),
         st = opt_str(s.scan_type.as_deref()),
         lw = opt_f64(s.scan_window_lower_limit),
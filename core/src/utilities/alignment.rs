@@ -0,0 +1,620 @@
+//! Retention time alignment of runs against a reference run.
+//!
+//! Each run gets a [`Warp`], a monotone piecewise-linear map from its raw
+//! retention times to those of the reference. Warps come either from a
+//! LOESS fit through anchor features matched between the run and the
+//! reference ([`align_features`]), or from dynamic time warping of the MS1
+//! profiles of the two runs, in the spirit of obiwarp ([`align_profiles`]).
+
+use super::feature_detection::Feature;
+use super::parse_mzml::SpectrumSummary;
+
+/// Monotone map from raw to aligned retention times, in minutes, linear
+/// between knots. Beyond the first and last knot the offset of that knot is
+/// kept. A warp without knots is the identity.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Warp {
+    /// Strictly increasing raw retention times.
+    pub raw: Vec<f64>,
+    /// Aligned retention time of each knot; non-decreasing.
+    pub aligned: Vec<f64>,
+}
+
+impl Warp {
+    pub fn apply(&self, rt: f64) -> f64 {
+        let n = self.raw.len().min(self.aligned.len());
+        if n == 0 || rt.is_nan() {
+            return rt;
+        }
+        let (raw, aligned) = (&self.raw[..n], &self.aligned[..n]);
+        if rt <= raw[0] {
+            return rt + aligned[0] - raw[0];
+        }
+        if rt >= raw[n - 1] {
+            return rt + aligned[n - 1] - raw[n - 1];
+        }
+        let k = raw.partition_point(|&x| x <= rt);
+        let t = (rt - raw[k - 1]) / (raw[k] - raw[k - 1]);
+        aligned[k - 1] + t * (aligned[k] - aligned[k - 1])
+    }
+
+    pub fn apply_to_spectra(&self, spectra: &mut [SpectrumSummary]) {
+        for s in spectra {
+            s.retention_time = s.retention_time.map(|rt| self.apply(rt));
+        }
+    }
+
+    pub fn apply_to_features(&self, features: &mut [Feature]) {
+        for f in features {
+            f.rt = self.apply(f.rt);
+            f.rt_min = self.apply(f.rt_min);
+            f.rt_max = self.apply(f.rt_max);
+        }
+    }
+
+    // Knots from (raw, aligned) pairs in any order: equal raw times are
+    // merged and the aligned times made non-decreasing.
+    fn from_pairs(mut pairs: Vec<(f64, f64)>) -> Warp {
+        pairs.retain(|p| p.0.is_finite() && p.1.is_finite());
+        pairs.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let mut raw: Vec<f64> = Vec::with_capacity(pairs.len());
+        let mut sums: Vec<(f64, f64)> = Vec::with_capacity(pairs.len());
+        for (x, y) in pairs {
+            if raw.last() == Some(&x) {
+                let last = sums.last_mut().unwrap();
+                last.0 += y;
+                last.1 += 1.0;
+            } else {
+                raw.push(x);
+                sums.push((y, 1.0));
+            }
+        }
+        let aligned = isotonic(&sums.iter().map(|s| s.0 / s.1).collect::<Vec<_>>());
+        Warp { raw, aligned }
+    }
+}
+
+// Pool-adjacent-violators: the non-decreasing sequence closest to `y` in
+// least squares.
+fn isotonic(y: &[f64]) -> Vec<f64> {
+    // (mean, count) blocks.
+    let mut blocks: Vec<(f64, usize)> = Vec::with_capacity(y.len());
+    for &v in y {
+        blocks.push((v, 1));
+        while blocks.len() > 1 {
+            let (b, nb) = blocks[blocks.len() - 1];
+            let (a, na) = blocks[blocks.len() - 2];
+            if a <= b {
+                break;
+            }
+            blocks.pop();
+            let n = na + nb;
+            *blocks.last_mut().unwrap() = ((a * na as f64 + b * nb as f64) / n as f64, n);
+        }
+    }
+    blocks
+        .into_iter()
+        .flat_map(|(v, n)| std::iter::repeat_n(v, n))
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LoessOptions {
+    /// m/z tolerance, in ppm, for matching a feature to the reference.
+    pub ppm: f64,
+    /// Largest retention time difference, in minutes, of a match.
+    pub rt_window: f64,
+    /// Fraction of the anchors used for each local fit.
+    pub span: f64,
+    /// Runs with fewer anchors than this are left unaligned.
+    pub min_anchors: usize,
+}
+
+impl Default for LoessOptions {
+    fn default() -> Self {
+        LoessOptions {
+            ppm: 10.0,
+            rt_window: 1.0,
+            span: 0.3,
+            min_anchors: 10,
+        }
+    }
+}
+
+/// One warp per run, aligning it to `runs[reference]` by a robust LOESS fit
+/// of the retention time differences of anchor features. A reference feature
+/// and a run feature are anchors when each is the other's only match within
+/// the m/z and retention time tolerances.
+pub fn align_features(
+    runs: &[&[Feature]],
+    reference: usize,
+    opts: &LoessOptions,
+) -> Result<Vec<Warp>, String> {
+    let Some(&ref_features) = runs.get(reference) else {
+        return Err(format!(
+            "reference run {reference} out of range ({} runs)",
+            runs.len()
+        ));
+    };
+    let mut out = Vec::with_capacity(runs.len());
+    for (k, run) in runs.iter().enumerate() {
+        if k == reference {
+            out.push(Warp::default());
+            continue;
+        }
+        let mut anchors = match_anchors(run, ref_features, opts);
+        anchors.sort_by(|a, b| a.0.total_cmp(&b.0));
+        if anchors.len() < opts.min_anchors.max(2) {
+            out.push(Warp::default());
+            continue;
+        }
+        let x: Vec<f64> = anchors.iter().map(|a| a.0).collect();
+        let d: Vec<f64> = anchors.iter().map(|a| a.1 - a.0).collect();
+        let fit = loess(&x, &d, opts.span);
+        out.push(Warp::from_pairs(
+            x.iter().zip(&fit).map(|(&x, &d)| (x, x + d)).collect(),
+        ));
+    }
+    Ok(out)
+}
+
+// (run rt, reference rt) of mutually unique matches.
+fn match_anchors(run: &[Feature], reference: &[Feature], opts: &LoessOptions) -> Vec<(f64, f64)> {
+    let matches = |f: &Feature, pool: &[Feature]| -> Option<usize> {
+        let tol = f.mz * opts.ppm * 1e-6;
+        let mut hits = pool
+            .iter()
+            .enumerate()
+            .filter(|(_, g)| (g.mz - f.mz).abs() <= tol && (g.rt - f.rt).abs() <= opts.rt_window);
+        match (hits.next(), hits.next()) {
+            (Some((i, _)), None) => Some(i),
+            _ => None,
+        }
+    };
+    let mut out = Vec::new();
+    for (i, f) in reference.iter().enumerate() {
+        if let Some(j) = matches(f, run)
+            && matches(&run[j], reference) == Some(i)
+        {
+            out.push((run[j].rt, f.rt));
+        }
+    }
+    out
+}
+
+// Locally linear fit of `y` on `x` at each `x`, with tricube weights over
+// the nearest `span` fraction of points and two bisquare robustness passes.
+// `x` must be sorted, so the nearest points of each `x[i]` are a window that
+// only moves forward as `i` grows.
+fn loess(x: &[f64], y: &[f64], span: f64) -> Vec<f64> {
+    let n = x.len();
+    let k = ((span * n as f64).ceil() as usize).clamp(3.min(n), n);
+    let mut robust = vec![1.0; n];
+    let mut fit = vec![0.0; n];
+    for pass in 0..3 {
+        let mut lo = 0;
+        for i in 0..n {
+            while lo + k < n && x[i] - x[lo] > x[lo + k] - x[i] {
+                lo += 1;
+            }
+            let h = (x[i] - x[lo]).max(x[lo + k - 1] - x[i]).max(f64::EPSILON);
+            let (mut sw, mut sx, mut sy, mut sxx, mut sxy) = (0.0, 0.0, 0.0, 0.0, 0.0);
+            for j in lo..lo + k {
+                let u = (x[j] - x[i]).abs() / h;
+                if u >= 1.0 {
+                    continue;
+                }
+                let w = (1.0 - u * u * u).powi(3) * robust[j];
+                sw += w;
+                sx += w * x[j];
+                sy += w * y[j];
+                sxx += w * x[j] * x[j];
+                sxy += w * x[j] * y[j];
+            }
+            fit[i] = if sw <= 0.0 {
+                y[i]
+            } else {
+                let (mx, my) = (sx / sw, sy / sw);
+                let var = sxx / sw - mx * mx;
+                if var > 1e-12 {
+                    my + (sxy / sw - mx * my) / var * (x[i] - mx)
+                } else {
+                    my
+                }
+            };
+        }
+        if pass == 2 {
+            break;
+        }
+        let mut res: Vec<f64> = y.iter().zip(&fit).map(|(y, f)| (y - f).abs()).collect();
+        res.sort_unstable_by(f64::total_cmp);
+        let s = 6.0 * res[n / 2];
+        if s <= 0.0 {
+            break;
+        }
+        for j in 0..n {
+            let u = (y[j] - fit[j]).abs() / s;
+            robust[j] = if u < 1.0 { (1.0 - u * u).powi(2) } else { 0.0 };
+        }
+    }
+    fit
+}
+
+/// What is compared between scans of two runs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Profile {
+    /// Total ion current, scaled to the run maximum.
+    Tic,
+    /// Base peak intensity, scaled to the run maximum.
+    Bpc,
+    /// Square-root intensities summed into m/z bins of this width, compared
+    /// by cosine similarity.
+    Binned { mz_bin: f64 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DtwOptions {
+    pub profile: Profile,
+    /// Largest retention time shift, in minutes, the path may take.
+    pub max_shift: f64,
+    /// Cost added to steps that stretch one run against the other.
+    pub gap_penalty: f64,
+}
+
+impl Default for DtwOptions {
+    fn default() -> Self {
+        DtwOptions {
+            profile: Profile::Binned { mz_bin: 1.0 },
+            max_shift: 2.0,
+            gap_penalty: 0.1,
+        }
+    }
+}
+
+// MS1 scans of one run in retention time order.
+struct Trace {
+    rt: Vec<f64>,
+    values: Vec<ScanVector>,
+}
+
+enum ScanVector {
+    Scalar(f64),
+    // (bin, value) sorted by bin, unit length.
+    Sparse(Vec<(i64, f64)>),
+}
+
+impl ScanVector {
+    fn cost(&self, other: &ScanVector) -> f64 {
+        match (self, other) {
+            (ScanVector::Scalar(a), ScanVector::Scalar(b)) => (a - b).abs(),
+            (ScanVector::Sparse(a), ScanVector::Sparse(b)) => {
+                let (mut i, mut j, mut dot) = (0, 0, 0.0);
+                while i < a.len() && j < b.len() {
+                    match a[i].0.cmp(&b[j].0) {
+                        std::cmp::Ordering::Less => i += 1,
+                        std::cmp::Ordering::Greater => j += 1,
+                        std::cmp::Ordering::Equal => {
+                            dot += a[i].1 * b[j].1;
+                            i += 1;
+                            j += 1;
+                        }
+                    }
+                }
+                1.0 - dot
+            }
+            _ => 1.0,
+        }
+    }
+}
+
+fn trace(spectra: &[SpectrumSummary], profile: Profile) -> Trace {
+    let mut scans: Vec<&SpectrumSummary> = spectra
+        .iter()
+        .filter(|s| s.ms_level == Some(1) && s.retention_time.is_some_and(f64::is_finite))
+        .collect();
+    scans.sort_by(|a, b| {
+        let (a, b) = (
+            a.retention_time.unwrap_or(0.0),
+            b.retention_time.unwrap_or(0.0),
+        );
+        a.total_cmp(&b)
+    });
+    let rt = scans.iter().filter_map(|s| s.retention_time).collect();
+
+    let scalar = |f: fn(&SpectrumSummary) -> Option<f64>| {
+        let v: Vec<f64> = scans.iter().map(|s| f(s).unwrap_or(0.0)).collect();
+        let max = v.iter().copied().fold(0.0, f64::max);
+        let scale = if max > 0.0 { 1.0 / max } else { 0.0 };
+        v.into_iter()
+            .map(|y| ScanVector::Scalar(y * scale))
+            .collect()
+    };
+    let values = match profile {
+        Profile::Tic => scalar(|s| s.total_ion_current),
+        Profile::Bpc => scalar(|s| s.base_peak_intensity),
+        Profile::Binned { mz_bin } => scans
+            .iter()
+            .map(|s| ScanVector::Sparse(binned(s, mz_bin)))
+            .collect(),
+    };
+    Trace { rt, values }
+}
+
+fn binned(s: &SpectrumSummary, mz_bin: f64) -> Vec<(i64, f64)> {
+    let (Some(mz), Some(int)) = (&s.mz_array, &s.intensity_array) else {
+        return Vec::new();
+    };
+    let mut v: Vec<(i64, f64)> = Vec::new();
+    for (m, y) in mz.iter().zip(int.iter()) {
+        if y > 0.0 && m.is_finite() {
+            v.push(((m / mz_bin).floor() as i64, y));
+        }
+    }
+    v.sort_unstable_by_key(|p| p.0);
+    let mut out: Vec<(i64, f64)> = Vec::with_capacity(v.len());
+    for (b, y) in v {
+        match out.last_mut() {
+            Some(last) if last.0 == b => last.1 += y,
+            _ => out.push((b, y)),
+        }
+    }
+    let mut norm = 0.0;
+    for p in out.iter_mut() {
+        p.1 = p.1.sqrt();
+        norm += p.1 * p.1;
+    }
+    let norm = norm.sqrt();
+    if norm > 0.0 {
+        for p in out.iter_mut() {
+            p.1 /= norm;
+        }
+    }
+    out
+}
+
+/// One warp per run, aligning it to `runs[reference]` by the cheapest
+/// monotone path through the scan-to-scan cost matrix of their MS1
+/// profiles. Each run is given as its spectra; the path starts at the first
+/// MS1 scans, ends at the last and never pairs scans more than `max_shift`
+/// apart.
+pub fn align_profiles(
+    runs: &[&[SpectrumSummary]],
+    reference: usize,
+    opts: &DtwOptions,
+) -> Result<Vec<Warp>, String> {
+    if reference >= runs.len() {
+        return Err(format!(
+            "reference run {reference} out of range ({} runs)",
+            runs.len()
+        ));
+    }
+    if let Profile::Binned { mz_bin } = opts.profile
+        && (mz_bin.is_nan() || mz_bin <= 0.0)
+    {
+        return Err(format!("invalid m/z bin width {mz_bin}"));
+    }
+    let target = trace(runs[reference], opts.profile);
+    let mut out = Vec::with_capacity(runs.len());
+    for (k, run) in runs.iter().enumerate() {
+        if k == reference {
+            out.push(Warp::default());
+            continue;
+        }
+        let query = trace(run, opts.profile);
+        let path = dtw(&query, &target, opts).map_err(|e| format!("run {k}: {e}"))?;
+        out.push(Warp::from_pairs(
+            path.into_iter()
+                .map(|(i, j)| (query.rt[i], target.rt[j]))
+                .collect(),
+        ));
+    }
+    Ok(out)
+}
+
+// Pairs (query scan, target scan) along the optimal path.
+fn dtw(query: &Trace, target: &Trace, opts: &DtwOptions) -> Result<Vec<(usize, usize)>, String> {
+    const DIAG: u8 = 0;
+    const UP: u8 = 1;
+    const LEFT: u8 = 2;
+
+    let (n, m) = (query.rt.len(), target.rt.len());
+    if n == 0 || m == 0 {
+        return Err("no MS1 scans with a retention time".to_string());
+    }
+    let in_band = |i: usize, j: usize| (query.rt[i] - target.rt[j]).abs() <= opts.max_shift;
+
+    // Traceback moves of the band of each query scan, stored row after row:
+    // row `i` covers target scans `bands[i].0..` and starts at `bands[i].1`.
+    let mut bands: Vec<(usize, usize)> = Vec::with_capacity(n);
+    let mut from: Vec<u8> = Vec::new();
+    let mut prev = vec![f64::INFINITY; m];
+    let mut cur = vec![f64::INFINITY; m];
+    let mut prev_band = 0..0;
+    for i in 0..n {
+        // Only the target scans within `max_shift` of this query scan.
+        let lo = target
+            .rt
+            .partition_point(|&t| t < query.rt[i] - opts.max_shift);
+        let hi = target
+            .rt
+            .partition_point(|&t| t <= query.rt[i] + opts.max_shift)
+            .max(lo);
+        bands.push((lo, from.len()));
+        for j in lo..hi {
+            let c = query.values[i].cost(&target.values[j]);
+            if i == 0 && j == 0 {
+                cur[0] = c;
+                from.push(DIAG);
+                continue;
+            }
+            let mut best = (f64::INFINITY, DIAG);
+            if i > 0 && j > 0 && prev[j - 1] < best.0 {
+                best = (prev[j - 1], DIAG);
+            }
+            if i > 0 && prev[j] + opts.gap_penalty < best.0 {
+                best = (prev[j] + opts.gap_penalty, UP);
+            }
+            if j > 0 && cur[j - 1] + opts.gap_penalty < best.0 {
+                best = (cur[j - 1] + opts.gap_penalty, LEFT);
+            }
+            cur[j] = best.0 + c;
+            from.push(best.1);
+        }
+        // `prev` becomes the buffer of the next row; only the band it held
+        // needs clearing.
+        prev[prev_band].fill(f64::INFINITY);
+        prev_band = lo..hi;
+        std::mem::swap(&mut prev, &mut cur);
+    }
+    if !in_band(n - 1, m - 1) || !prev[m - 1].is_finite() {
+        return Err(format!(
+            "no warping path within a shift of {} min",
+            opts.max_shift
+        ));
+    }
+
+    // Every cell on the path has a finite cost, so it lies in its row's band.
+    let (mut i, mut j) = (n - 1, m - 1);
+    let mut path = vec![(i, j)];
+    while i > 0 || j > 0 {
+        let (lo, start) = bands[i];
+        match from[start + j - lo] {
+            DIAG => {
+                i -= 1;
+                j -= 1;
+            }
+            UP => i -= 1,
+            _ => j -= 1,
+        }
+        path.push((i, j));
+    }
+    path.reverse();
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utilities::parse_mzml::ArrayData;
+
+    fn feature(mz: f64, rt: f64) -> Feature {
+        Feature {
+            mz,
+            mz_min: mz,
+            mz_max: mz,
+            rt,
+            rt_min: rt - 0.05,
+            rt_max: rt + 0.05,
+            area: 1.0,
+            height: 1.0,
+            snr: 10.0,
+            scans: 5,
+        }
+    }
+
+    // MS1 scans every 0.05 min over 10 min with a Gaussian peak at each of
+    // `apexes`, the k-th at m/z 300 + 100k, over a flat m/z 100 background.
+    fn run(apexes: &[f64]) -> Vec<SpectrumSummary> {
+        (0..200)
+            .map(|k| {
+                let rt = k as f64 * 0.05;
+                let mut mz = vec![100.0];
+                let mut intensity = vec![1e-3];
+                for (p, a) in apexes.iter().enumerate() {
+                    mz.push(300.0 + 100.0 * p as f64);
+                    intensity.push((-(rt - a).powi(2) / 0.02).exp());
+                }
+                SpectrumSummary {
+                    index: k,
+                    ms_level: Some(1),
+                    retention_time: Some(rt),
+                    total_ion_current: Some(intensity.iter().sum()),
+                    mz_array: Some(ArrayData::F64(mz)),
+                    intensity_array: Some(ArrayData::F64(intensity)),
+                    ..SpectrumSummary::default()
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn warp_interpolates_and_keeps_end_offsets() {
+        let w = Warp {
+            raw: vec![1.0, 3.0],
+            aligned: vec![2.0, 3.0],
+        };
+        assert_eq!(w.apply(2.0), 2.5);
+        assert_eq!(w.apply(0.0), 1.0);
+        assert_eq!(w.apply(5.0), 5.0);
+        assert_eq!(Warp::default().apply(4.2), 4.2);
+    }
+
+    #[test]
+    fn warp_knots_are_made_monotone() {
+        let w = Warp::from_pairs(vec![(2.0, 1.0), (1.0, 3.0), (1.0, 1.0), (3.0, 4.0)]);
+        assert_eq!(w.raw, [1.0, 2.0, 3.0]);
+        assert_eq!(w.aligned, [1.5, 1.5, 4.0]);
+    }
+
+    #[test]
+    fn loess_follows_a_line_despite_an_outlier() {
+        let x: Vec<f64> = (0..40).map(|i| i as f64 * 0.25).collect();
+        let line = |x: f64| 0.2 + 0.05 * x;
+        let mut y: Vec<f64> = x
+            .iter()
+            .enumerate()
+            .map(|(i, &x)| line(x) + 0.01 * (i as f64 * 1.7).sin())
+            .collect();
+        y[20] += 3.0;
+        let fit = loess(&x, &y, 0.3);
+        for (i, (f, &x)) in fit.iter().zip(&x).enumerate() {
+            assert!((f - line(x)).abs() < 0.02, "point {i}: {f}");
+        }
+    }
+
+    #[test]
+    fn features_align_by_their_shift() {
+        let reference: Vec<Feature> = (0..20)
+            .map(|i| feature(200.0 + 10.0 * i as f64, 1.0 + 0.4 * i as f64))
+            .collect();
+        let shifted: Vec<Feature> = reference
+            .iter()
+            .map(|f| feature(f.mz, f.rt + 0.3))
+            .collect();
+        let warps = align_features(&[&reference, &shifted], 0, &LoessOptions::default()).unwrap();
+        assert_eq!(warps[0], Warp::default());
+        for f in &shifted {
+            assert!((warps[1].apply(f.rt) - (f.rt - 0.3)).abs() < 1e-9);
+        }
+        assert!(align_features(&[&reference], 1, &LoessOptions::default()).is_err());
+    }
+
+    #[test]
+    fn profiles_align_shifted_peaks() {
+        let reference = run(&[3.0, 6.0]);
+        let shifted = run(&[3.5, 6.5]);
+        for profile in [Profile::Tic, Profile::Binned { mz_bin: 1.0 }] {
+            let opts = DtwOptions {
+                profile,
+                ..DtwOptions::default()
+            };
+            let warps = align_profiles(&[&reference, &shifted], 0, &opts).unwrap();
+            assert!((warps[1].apply(3.5) - 3.0).abs() <= 0.1, "{profile:?}");
+            assert!((warps[1].apply(6.5) - 6.0).abs() <= 0.1, "{profile:?}");
+        }
+    }
+
+    #[test]
+    fn shifts_beyond_the_band_have_no_path() {
+        let reference = run(&[3.0]);
+        let mut late = run(&[3.0]);
+        for s in &mut late {
+            s.retention_time = s.retention_time.map(|rt| rt + 5.0);
+        }
+        let opts = DtwOptions {
+            max_shift: 1.0,
+            ..DtwOptions::default()
+        };
+        assert!(align_profiles(&[&reference, &late], 0, &opts).is_err());
+    }
+}
@@ -0,0 +1,613 @@
+//! Annotation of the features of one run with isotopes and adducts.
+//!
+//! Features are first split into co-eluting groups (same retention time
+//! and, when raw spectra are available, correlated peak shapes). Within a
+//! group, isotopologues are chained by their 13C spacing for each charge
+//! state, and the monoisotopic ions are then explained as adducts or
+//! in-source fragments of a common neutral mass, with the rule set chosen by
+//! the polarity of the run.
+
+use std::sync::LazyLock;
+
+use super::feature_detection::Feature;
+use super::parse_mzml::{ArrayData, SpectrumSummary};
+use crate::chem::elements::C13_SPACING;
+use crate::chem::formula::Adduct;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Polarity {
+    Positive,
+    Negative,
+}
+
+impl Polarity {
+    /// The polarity of most MS1 spectra that declare one.
+    pub fn of_spectra(spectra: &[SpectrumSummary]) -> Option<Polarity> {
+        let (mut pos, mut neg) = (0usize, 0usize);
+        for s in spectra.iter().filter(|s| s.ms_level == Some(1)) {
+            match s.polarity.as_deref() {
+                Some("positive") => pos += 1,
+                Some("negative") => neg += 1,
+                _ => {}
+            }
+        }
+        match (pos, neg) {
+            (0, 0) => None,
+            (p, n) if p >= n => Some(Polarity::Positive),
+            _ => Some(Polarity::Negative),
+        }
+    }
+}
+
+/// A named ion type of a rule set.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AdductRule {
+    pub name: &'static str,
+    pub adduct: Adduct,
+}
+
+impl AdductRule {
+    pub fn mz(&self, neutral_mass: f64) -> f64 {
+        self.adduct.mz(neutral_mass)
+    }
+
+    pub fn neutral_mass(&self, mz: f64) -> f64 {
+        self.adduct.neutral_mass(mz)
+    }
+}
+
+/// Common positive mode ions, the usual protonated molecule first.
+pub const POSITIVE_ADDUCTS: &[&str] = &[
+    "[M+H]+",
+    "[M+Na]+",
+    "[M+K]+",
+    "[M+NH4]+",
+    "[M+H-H2O]+",
+    "[M+H-NH3]+",
+    "[2M+H]+",
+    "[2M+Na]+",
+    "[M+2H]2+",
+    "[M+H+Na]2+",
+    "[M+H+NH4]2+",
+];
+
+/// Common negative mode ions, the usual deprotonated molecule first.
+pub const NEGATIVE_ADDUCTS: &[&str] = &[
+    "[M-H]-",
+    "[M+Cl]-",
+    "[M+HCOO]-",
+    "[M+CH3COO]-",
+    "[M-H-H2O]-",
+    "[M+Na-2H]-",
+    "[M+K-2H]-",
+    "[2M-H]-",
+    "[M-2H]2-",
+];
+
+/// The rule set of `polarity`, parsed once from [`POSITIVE_ADDUCTS`] or
+/// [`NEGATIVE_ADDUCTS`].
+pub fn adduct_rules(polarity: Polarity) -> &'static [AdductRule] {
+    fn parse(names: &[&'static str]) -> Vec<AdductRule> {
+        names
+            .iter()
+            .map(|&name| AdductRule {
+                name,
+                adduct: Adduct::parse(name).expect("built-in adduct"),
+            })
+            .collect()
+    }
+    static POSITIVE: LazyLock<Vec<AdductRule>> = LazyLock::new(|| parse(POSITIVE_ADDUCTS));
+    static NEGATIVE: LazyLock<Vec<AdductRule>> = LazyLock::new(|| parse(NEGATIVE_ADDUCTS));
+    match polarity {
+        Polarity::Positive => &POSITIVE,
+        Polarity::Negative => &NEGATIVE,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AnnotationOptions {
+    /// Relative m/z tolerance, in ppm, for isotope and adduct matches.
+    pub ppm: f64,
+    /// Absolute m/z tolerance added to the relative one.
+    pub mz_tolerance: f64,
+    /// Largest apex retention time difference, in minutes, of co-eluting
+    /// features.
+    pub rt_tolerance: f64,
+    /// Minimum Pearson correlation of the extracted ion chromatograms of
+    /// co-eluting features. Only checked when spectra with arrays are given.
+    pub min_correlation: f64,
+    pub max_charge: u32,
+    /// Overrides the polarity read from the spectra.
+    pub polarity: Option<Polarity>,
+}
+
+impl Default for AnnotationOptions {
+    fn default() -> Self {
+        AnnotationOptions {
+            ppm: 5.0,
+            mz_tolerance: 0.001,
+            rt_tolerance: 0.05,
+            min_correlation: 0.75,
+            max_charge: 3,
+            polarity: None,
+        }
+    }
+}
+
+impl AnnotationOptions {
+    fn tol(&self, mz: f64) -> f64 {
+        mz * self.ppm * 1e-6 + self.mz_tolerance
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IsotopeLabel {
+    /// Index of the isotope cluster within the annotation.
+    pub cluster: usize,
+    /// 0 for the monoisotopic peak, 1 for M+1 and so on.
+    pub index: usize,
+    pub charge: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct FeatureAnnotation {
+    /// Co-elution group.
+    pub pc_group: usize,
+    pub isotope: Option<IsotopeLabel>,
+    /// Index into [`Annotation::compounds`].
+    pub compound: Option<usize>,
+    /// Ion of the compound this feature, or its monoisotopic peak, is.
+    pub adduct: Option<&'static str>,
+}
+
+/// Features explained as ions of one neutral molecule.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Compound {
+    pub neutral_mass: f64,
+    pub rt: f64,
+    /// Indices of the features, isotopes included.
+    pub features: Vec<usize>,
+    /// Whether two or more ions agree on the neutral mass. A lone ion is
+    /// assumed to be the first ion of the rule set that fits its charge.
+    pub supported: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Annotation {
+    pub polarity: Polarity,
+    /// Parallel to the annotated features.
+    pub features: Vec<FeatureAnnotation>,
+    pub compounds: Vec<Compound>,
+}
+
+/// Annotates `features`, detected in `spectra`. The spectra give the
+/// polarity, unless set in `opts`, and the chromatograms for peak shape
+/// correlation; without arrays only retention times are compared.
+pub fn annotate(
+    features: &[Feature],
+    spectra: &[SpectrumSummary],
+    opts: &AnnotationOptions,
+) -> Result<Annotation, String> {
+    let polarity = opts
+        .polarity
+        .or_else(|| Polarity::of_spectra(spectra))
+        .ok_or("polarity is not declared in the spectra")?;
+
+    let shapes = Shapes::new(spectra, features, opts);
+    let mut labels = vec![FeatureAnnotation::default(); features.len()];
+    let groups = coelution_groups(features, &shapes, opts);
+    let mut n_clusters = 0;
+    let mut compounds = Vec::new();
+    for (g, members) in groups.iter().enumerate() {
+        for &i in members {
+            labels[i].pc_group = g;
+        }
+        let clusters = isotope_clusters(features, members, opts);
+        for (chain, charge) in &clusters {
+            for (index, &i) in chain.iter().enumerate() {
+                labels[i].isotope = Some(IsotopeLabel {
+                    cluster: n_clusters,
+                    index,
+                    charge: *charge,
+                });
+            }
+            n_clusters += 1;
+        }
+        annotate_adducts(
+            features,
+            members,
+            &clusters,
+            adduct_rules(polarity),
+            opts,
+            &mut labels,
+            &mut compounds,
+        );
+    }
+    Ok(Annotation {
+        polarity,
+        features: labels,
+        compounds,
+    })
+}
+
+// Extracted ion chromatograms of the features over their own bounds, as
+// (retention time, intensity) points.
+struct Shapes {
+    xics: Option<Vec<Vec<(f64, f64)>>>,
+}
+
+impl Shapes {
+    fn new(spectra: &[SpectrumSummary], features: &[Feature], opts: &AnnotationOptions) -> Shapes {
+        let mut scans: Vec<(f64, &ArrayData, &ArrayData)> = spectra
+            .iter()
+            .filter(|s| s.ms_level == Some(1))
+            .filter_map(|s| {
+                Some((
+                    s.retention_time?,
+                    s.mz_array.as_ref()?,
+                    s.intensity_array.as_ref()?,
+                ))
+            })
+            .collect();
+        if scans.is_empty() {
+            return Shapes { xics: None };
+        }
+        scans.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let xics = features
+            .iter()
+            .map(|f| {
+                let tol = opts.tol(f.mz);
+                let (lo, hi) = (f.mz_min - tol, f.mz_max + tol);
+                let a = scans.partition_point(|s| s.0 < f.rt_min);
+                let b = scans.partition_point(|s| s.0 <= f.rt_max);
+                scans[a..b]
+                    .iter()
+                    .map(|&(rt, mz, int)| {
+                        let start = mz.partition_point(|m| m < lo);
+                        let y = (start..mz.len())
+                            .map_while(|k| mz.get(k).filter(|&m| m <= hi).and(int.get(k)))
+                            .sum();
+                        (rt, y)
+                    })
+                    .collect()
+            })
+            .collect();
+        Shapes { xics: Some(xics) }
+    }
+
+    // Pearson correlation over the scans both chromatograms cover; None
+    // when there are too few to judge.
+    fn correlation(&self, a: usize, b: usize) -> Option<f64> {
+        let xics = self.xics.as_ref()?;
+        let (xa, xb) = (&xics[a], &xics[b]);
+        let start = xa.first()?.0.max(xb.first()?.0);
+        let ka = xa.partition_point(|p| p.0 < start);
+        let kb = xb.partition_point(|p| p.0 < start);
+        let pairs: Vec<(f64, f64)> = xa[ka..]
+            .iter()
+            .zip(&xb[kb..])
+            .take_while(|(p, q)| p.0 == q.0)
+            .map(|(p, q)| (p.1, q.1))
+            .collect();
+        if pairs.len() < 3 {
+            return None;
+        }
+        let n = pairs.len() as f64;
+        let (ma, mb) = pairs
+            .iter()
+            .fold((0.0, 0.0), |s, p| (s.0 + p.0 / n, s.1 + p.1 / n));
+        let (mut sab, mut saa, mut sbb) = (0.0, 0.0, 0.0);
+        for (x, y) in pairs {
+            sab += (x - ma) * (y - mb);
+            saa += (x - ma) * (x - ma);
+            sbb += (y - mb) * (y - mb);
+        }
+        (saa > 0.0 && sbb > 0.0).then(|| sab / (saa * sbb).sqrt())
+    }
+}
+
+// Greedy grouping from the most intense feature down: each unassigned
+// feature takes every unassigned one eluting with it. Members are returned
+// sorted by m/z.
+fn coelution_groups(
+    features: &[Feature],
+    shapes: &Shapes,
+    opts: &AnnotationOptions,
+) -> Vec<Vec<usize>> {
+    let mut order: Vec<usize> = (0..features.len()).collect();
+    order.sort_by(|&a, &b| features[b].height.total_cmp(&features[a].height));
+    let mut assigned = vec![false; features.len()];
+    let mut groups = Vec::new();
+    for &seed in &order {
+        if assigned[seed] {
+            continue;
+        }
+        assigned[seed] = true;
+        let mut members = vec![seed];
+        for &j in &order {
+            if assigned[j] || (features[j].rt - features[seed].rt).abs() > opts.rt_tolerance {
+                continue;
+            }
+            if shapes
+                .correlation(seed, j)
+                .is_some_and(|r| r < opts.min_correlation)
+            {
+                continue;
+            }
+            assigned[j] = true;
+            members.push(j);
+        }
+        members.sort_by(|&a, &b| features[a].mz.total_cmp(&features[b].mz));
+        groups.push(members);
+    }
+    groups
+}
+
+// Largest intensity ratio of a peak to the one before it in a cluster of
+// neutral mass `mass`: 1.1% 13C per carbon at up to one carbon per 12 Da,
+// with 50% slack, plus 0.1 for the heavier isotopes of other elements.
+fn max_isotope_ratio(mass: f64) -> f64 {
+    0.0165 * mass / 12.0 + 0.1
+}
+
+// Isotope chains (monoisotopic first) of the m/z-sorted `members`, with
+// their charge. For each unlabelled peak the charge giving the longest
+// chain wins, ties going to the lower charge.
+fn isotope_clusters(
+    features: &[Feature],
+    members: &[usize],
+    opts: &AnnotationOptions,
+) -> Vec<(Vec<usize>, u32)> {
+    let mut used = vec![false; members.len()];
+    let mut out = Vec::new();
+    for start in 0..members.len() {
+        if used[start] {
+            continue;
+        }
+        let mut best: (Vec<usize>, u32) = (Vec::new(), 0);
+        for z in 1..=opts.max_charge.max(1) {
+            let mono = &features[members[start]];
+            let ratio = max_isotope_ratio(mono.mz * z as f64);
+            let mut chain = vec![start];
+            loop {
+                let prev = &features[members[*chain.last().unwrap()]];
+                let target = mono.mz + chain.len() as f64 * C13_SPACING / z as f64;
+                let tol = opts.tol(target);
+                let next = (start + 1..members.len())
+                    .filter(|&k| !used[k] && !chain.contains(&k))
+                    .filter(|&k| {
+                        let f = &features[members[k]];
+                        (f.mz - target).abs() <= tol && f.height <= prev.height * ratio
+                    })
+                    .min_by(|&a, &b| {
+                        let d = |k: usize| (features[members[k]].mz - target).abs();
+                        d(a).total_cmp(&d(b))
+                    });
+                match next {
+                    Some(k) => chain.push(k),
+                    None => break,
+                }
+            }
+            if chain.len() > best.0.len() {
+                best = (chain, z);
+            }
+        }
+        if best.0.len() >= 2 {
+            for &k in &best.0 {
+                used[k] = true;
+            }
+            out.push((best.0.iter().map(|&k| members[k]).collect(), best.1));
+        }
+    }
+    out
+}
+
+// One neutral mass and the ions it explains, as (feature, rule) pairs.
+struct Hypothesis {
+    primary: bool,
+    height: f64,
+    support: Vec<(usize, usize)>,
+}
+
+// Repeatedly picks the neutral mass explaining the most ions of the group,
+// preferring the first (most common) rule of the set, then intensity, on
+// ties, until no mass explains two ions. Each ion left then becomes an
+// unsupported compound of its own under the first rule fitting its charge.
+// Isotope peaks follow their monoisotopic ion.
+fn annotate_adducts(
+    features: &[Feature],
+    members: &[usize],
+    clusters: &[(Vec<usize>, u32)],
+    rules: &[AdductRule],
+    opts: &AnnotationOptions,
+    labels: &mut [FeatureAnnotation],
+    compounds: &mut Vec<Compound>,
+) {
+    // Monoisotopic ions with their known charge, if any.
+    let mut ions: Vec<(usize, Option<u32>)> = members
+        .iter()
+        .filter(|&&i| labels[i].isotope.is_none_or(|l| l.index == 0))
+        .map(|&i| (i, labels[i].isotope.map(|l| l.charge)))
+        .collect();
+    let fits = |charge: Option<u32>, r: &AdductRule| {
+        charge.is_none_or(|z| z == r.adduct.charge.unsigned_abs())
+    };
+
+    loop {
+        let mut best: Option<Hypothesis> = None;
+        for &(i, zi) in &ions {
+            for (ri, r) in rules.iter().enumerate() {
+                if !fits(zi, r) {
+                    continue;
+                }
+                let mass = r.neutral_mass(features[i].mz);
+                let mut support = vec![(i, ri)];
+                for &(j, zj) in &ions {
+                    if j == i {
+                        continue;
+                    }
+                    let hit = rules
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, rj)| fits(zj, rj))
+                        .map(|(rk, rj)| (rk, (rj.mz(mass) - features[j].mz).abs()))
+                        .filter(|&(_, d)| d <= opts.tol(features[j].mz))
+                        .min_by(|a, b| a.1.total_cmp(&b.1));
+                    if let Some((rk, _)) = hit {
+                        support.push((j, rk));
+                    }
+                }
+                if support.len() < 2 {
+                    continue;
+                }
+                let h = Hypothesis {
+                    primary: support.iter().any(|&(_, rk)| rk == 0),
+                    height: support.iter().map(|&(k, _)| features[k].height).sum(),
+                    support,
+                };
+                let better = best.as_ref().is_none_or(|b| {
+                    (h.support.len(), h.primary, h.height) > (b.support.len(), b.primary, b.height)
+                });
+                if better {
+                    best = Some(h);
+                }
+            }
+        }
+        let Some(Hypothesis { support, .. }) = best else {
+            break;
+        };
+        add_compound(features, clusters, rules, &support, true, labels, compounds);
+        ions.retain(|(i, _)| !support.iter().any(|&(k, _)| k == *i));
+    }
+
+    for (i, z) in ions {
+        if let Some(ri) = rules.iter().position(|r| fits(z, r)) {
+            add_compound(
+                features,
+                clusters,
+                rules,
+                &[(i, ri)],
+                false,
+                labels,
+                compounds,
+            );
+        }
+    }
+}
+
+// Records the ions of `support`, as (feature, rule) pairs, and their
+// isotope peaks as one compound.
+fn add_compound(
+    features: &[Feature],
+    clusters: &[(Vec<usize>, u32)],
+    rules: &[AdductRule],
+    support: &[(usize, usize)],
+    supported: bool,
+    labels: &mut [FeatureAnnotation],
+    compounds: &mut Vec<Compound>,
+) {
+    let c = compounds.len();
+    let n = support.len() as f64;
+    let (mut neutral_mass, mut rt) = (0.0, 0.0);
+    let mut peaks = Vec::new();
+    for &(i, ri) in support {
+        neutral_mass += rules[ri].neutral_mass(features[i].mz) / n;
+        rt += features[i].rt / n;
+        let single = [i];
+        let chain = clusters
+            .iter()
+            .find(|(chain, _)| chain[0] == i)
+            .map_or(&single[..], |(chain, _)| chain.as_slice());
+        for &k in chain {
+            labels[k].compound = Some(c);
+            labels[k].adduct = Some(rules[ri].name);
+            peaks.push(k);
+        }
+    }
+    peaks.sort_unstable();
+    compounds.push(Compound {
+        neutral_mass,
+        rt,
+        features: peaks,
+        supported,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GLUCOSE: f64 = 180.063_388;
+
+    fn feature(mz: f64, rt: f64, height: f64) -> Feature {
+        Feature {
+            mz,
+            mz_min: mz,
+            mz_max: mz,
+            rt,
+            rt_min: rt - 0.1,
+            rt_max: rt + 0.1,
+            area: height,
+            height,
+            snr: 10.0,
+            scans: 5,
+        }
+    }
+
+    fn positive() -> AnnotationOptions {
+        AnnotationOptions {
+            polarity: Some(Polarity::Positive),
+            ..AnnotationOptions::default()
+        }
+    }
+
+    #[test]
+    fn rule_sets_parse_with_the_usual_ion_first() {
+        let pos = adduct_rules(Polarity::Positive);
+        let neg = adduct_rules(Polarity::Negative);
+        assert_eq!(pos.len(), POSITIVE_ADDUCTS.len());
+        assert_eq!(neg.len(), NEGATIVE_ADDUCTS.len());
+        assert!((pos[0].mz(GLUCOSE) - 181.070_665).abs() < 1e-5);
+        assert!((neg[0].mz(GLUCOSE) - 179.056_112).abs() < 1e-5);
+        let na = pos.iter().find(|r| r.name == "[2M+Na]+").unwrap();
+        assert!((na.neutral_mass(na.mz(GLUCOSE)) - GLUCOSE).abs() < 1e-9);
+    }
+
+    #[test]
+    fn coeluting_ions_share_a_compound() {
+        let h = adduct_rules(Polarity::Positive)[0].mz(GLUCOSE);
+        let features = [
+            feature(h, 2.0, 1000.0),
+            feature(h + C13_SPACING, 2.0, 60.0),
+            feature(203.052_609, 2.01, 400.0),
+        ];
+        let a = annotate(&features, &[], &positive()).unwrap();
+        assert_eq!(a.compounds.len(), 1);
+        let c = &a.compounds[0];
+        assert!(c.supported);
+        assert_eq!(c.features, [0, 1, 2]);
+        assert!((c.neutral_mass - GLUCOSE).abs() < 1e-4);
+        assert_eq!(
+            a.features[1].isotope.map(|l| (l.index, l.charge)),
+            Some((1, 1))
+        );
+        assert_eq!(a.features[1].adduct, Some("[M+H]+"));
+        assert_eq!(a.features[2].adduct, Some("[M+Na]+"));
+    }
+
+    #[test]
+    fn lone_ions_get_an_unsupported_compound() {
+        let features = [feature(181.070_665, 2.0, 1000.0), feature(400.0, 6.0, 10.0)];
+        let a = annotate(&features, &[], &positive()).unwrap();
+        assert_eq!(a.compounds.len(), 2);
+        assert!(a.compounds.iter().all(|c| !c.supported));
+        assert_eq!(a.features[0].adduct, Some("[M+H]+"));
+        assert!((a.compounds[a.features[0].compound.unwrap()].neutral_mass - GLUCOSE).abs() < 1e-5);
+        assert_ne!(a.features[0].pc_group, a.features[1].pc_group);
+    }
+
+    #[test]
+    fn polarity_must_be_known() {
+        assert!(annotate(&[], &[], &AnnotationOptions::default()).is_err());
+    }
+}
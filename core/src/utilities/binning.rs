@@ -0,0 +1,459 @@
+//! Binning of a run into a retention time by m/z intensity matrix, for
+//! heatmaps and machine learning.
+//!
+//! Each selected spectrum is binned along m/z into a sparse row. Rows are
+//! kept one per spectrum, or resampled onto a regular retention time grid
+//! by linear interpolation between the two scans either side of each grid
+//! point. The result is held as CSR and can be expanded to a dense `f32`
+//! buffer in either storage order.
+
+use super::annotation::Polarity;
+use super::parse_mzml::SpectrumSummary;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MzBins {
+    /// Bins of constant width, in Da.
+    Width(f64),
+    /// Bins whose width grows with m/z, each this many ppm of its lower
+    /// edge.
+    Ppm(f64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Aggregation {
+    /// Sum of the intensities falling in a bin.
+    #[default]
+    Sum,
+    /// Most intense point in a bin.
+    Max,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BinningOptions {
+    pub bins: MzBins,
+    /// m/z range; the range of the selected spectra when unset.
+    pub mz_min: Option<f64>,
+    pub mz_max: Option<f64>,
+    pub aggregation: Aggregation,
+    /// Spacing of the retention time grid, in minutes; 0 keeps one row per
+    /// spectrum.
+    pub rt_step: f64,
+    /// Retention time range; that of the selected spectra when unset.
+    pub rt_min: Option<f64>,
+    pub rt_max: Option<f64>,
+    /// Only spectra of this MS level are used; all when unset.
+    pub ms_level: Option<u32>,
+    /// Only spectra of this polarity are used; all when unset.
+    pub polarity: Option<Polarity>,
+}
+
+impl Default for BinningOptions {
+    fn default() -> Self {
+        BinningOptions {
+            bins: MzBins::Width(1.0),
+            mz_min: None,
+            mz_max: None,
+            aggregation: Aggregation::Sum,
+            rt_step: 0.0,
+            rt_min: None,
+            rt_max: None,
+            ms_level: Some(1),
+            polarity: None,
+        }
+    }
+}
+
+/// Intensity matrix in compressed sparse row form: the columns and values
+/// of row `r` are `indices[indptr[r]..indptr[r + 1]]` and the same range of
+/// `values`, with columns ascending.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct BinnedMatrix {
+    /// Retention time of each row.
+    pub rt: Vec<f64>,
+    /// Source spectrum of each row; empty on a resampled grid.
+    pub spectra: Vec<usize>,
+    /// Bin edges: column `c` covers `mz_edges[c]..mz_edges[c + 1]`.
+    pub mz_edges: Vec<f64>,
+    pub indptr: Vec<usize>,
+    pub indices: Vec<u32>,
+    pub values: Vec<f32>,
+}
+
+impl BinnedMatrix {
+    pub fn n_rows(&self) -> usize {
+        self.rt.len()
+    }
+
+    pub fn n_cols(&self) -> usize {
+        self.mz_edges.len().saturating_sub(1)
+    }
+
+    pub fn nnz(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Writes the matrix densely into `out`, which must hold
+    /// `n_rows * n_cols` values, row after row or, when `column_major`,
+    /// column after column as R and Fortran store matrices.
+    pub fn fill_dense(&self, out: &mut [f32], column_major: bool) -> Result<(), String> {
+        let (rows, cols) = (self.n_rows(), self.n_cols());
+        if out.len() != rows * cols {
+            return Err(format!(
+                "buffer of {} for a {rows} x {cols} matrix",
+                out.len()
+            ));
+        }
+        out.fill(0.0);
+        for r in 0..rows {
+            for k in self.indptr[r]..self.indptr[r + 1] {
+                let c = self.indices[k] as usize;
+                let at = if column_major {
+                    c * rows + r
+                } else {
+                    r * cols + c
+                };
+                out[at] = self.values[k];
+            }
+        }
+        Ok(())
+    }
+
+    /// The matrix as a row-major dense buffer.
+    pub fn to_dense(&self) -> Vec<f32> {
+        let mut out = vec![0.0; self.n_rows() * self.n_cols()];
+        self.fill_dense(&mut out, false).expect("sized to fit");
+        out
+    }
+}
+
+// Maps m/z to columns.
+struct Binner {
+    bins: MzBins,
+    min: f64,
+    n: usize,
+}
+
+impl Binner {
+    fn new(bins: MzBins, min: f64, max: f64) -> Result<Binner, String> {
+        let (width, span) = match bins {
+            MzBins::Width(w) => (w, max - min),
+            MzBins::Ppm(p) => {
+                if min <= 0.0 {
+                    return Err("ppm bins need a positive m/z range".to_string());
+                }
+                ((p * 1e-6).ln_1p(), (max / min).ln())
+            }
+        };
+        if width <= 0.0 || !width.is_finite() {
+            return Err("bin width must be positive".to_string());
+        }
+        if span.is_nan() || span < 0.0 {
+            return Err("empty m/z range".to_string());
+        }
+        let n = (span / width).floor() as usize + 1;
+        if n > u32::MAX as usize {
+            return Err(format!("{n} bins"));
+        }
+        Ok(Binner { bins, min, n })
+    }
+
+    fn column(&self, mz: f64) -> Option<usize> {
+        let t = match self.bins {
+            MzBins::Width(w) => (mz - self.min) / w,
+            MzBins::Ppm(p) => (mz / self.min).ln() / (p * 1e-6).ln_1p(),
+        };
+        (t >= 0.0 && t < self.n as f64).then_some(t as usize)
+    }
+
+    fn edges(&self) -> Vec<f64> {
+        (0..=self.n)
+            .map(|k| match self.bins {
+                MzBins::Width(w) => self.min + k as f64 * w,
+                MzBins::Ppm(p) => self.min * (1.0 + p * 1e-6).powi(k as i32),
+            })
+            .collect()
+    }
+}
+
+fn selected(s: &SpectrumSummary, opts: &BinningOptions) -> bool {
+    if opts.ms_level.is_some_and(|l| s.ms_level != Some(l)) {
+        return false;
+    }
+    let polarity = match s.polarity.as_deref() {
+        Some("positive") => Some(Polarity::Positive),
+        Some("negative") => Some(Polarity::Negative),
+        _ => None,
+    };
+    if opts.polarity.is_some_and(|p| polarity != Some(p)) {
+        return false;
+    }
+    let Some(rt) = s.retention_time else {
+        return false;
+    };
+    opts.rt_min.is_none_or(|m| rt >= m) && opts.rt_max.is_none_or(|m| rt <= m)
+}
+
+/// Bins the spectra of `spectra` selected by `opts`, which need a
+/// retention time and both arrays. Spectra are taken in retention time
+/// order.
+pub fn bin_spectra(
+    spectra: &[SpectrumSummary],
+    opts: &BinningOptions,
+) -> Result<BinnedMatrix, String> {
+    let mut rows: Vec<(f64, usize)> = spectra
+        .iter()
+        .enumerate()
+        .filter(|(_, s)| selected(s, opts) && s.mz_array.is_some() && s.intensity_array.is_some())
+        .filter_map(|(i, s)| Some((s.retention_time?, i)))
+        .collect();
+    rows.sort_by(|a, b| a.0.total_cmp(&b.0));
+    if rows.is_empty() {
+        return Err("no spectra selected".to_string());
+    }
+
+    let (mut lo, mut hi) = (f64::INFINITY, f64::NEG_INFINITY);
+    if opts.mz_min.is_none() || opts.mz_max.is_none() {
+        for &(_, i) in &rows {
+            for m in spectra[i]
+                .mz_array
+                .as_ref()
+                .into_iter()
+                .flat_map(|a| a.iter())
+            {
+                if m.is_finite() {
+                    lo = lo.min(m);
+                    hi = hi.max(m);
+                }
+            }
+        }
+    }
+    let binner = Binner::new(
+        opts.bins,
+        opts.mz_min.unwrap_or(lo),
+        opts.mz_max.unwrap_or(hi),
+    )?;
+
+    let binned: Vec<Vec<(u32, f32)>> = rows
+        .iter()
+        .map(|&(_, i)| bin_row(&spectra[i], &binner, opts.aggregation))
+        .collect();
+
+    let mut out = BinnedMatrix {
+        mz_edges: binner.edges(),
+        indptr: vec![0],
+        ..BinnedMatrix::default()
+    };
+    let push = |out: &mut BinnedMatrix, row: &[(u32, f32)]| {
+        for &(c, v) in row {
+            out.indices.push(c);
+            out.values.push(v);
+        }
+        out.indptr.push(out.indices.len());
+    };
+
+    if opts.rt_step > 0.0 {
+        let start = opts.rt_min.unwrap_or(rows[0].0);
+        let end = opts.rt_max.unwrap_or(rows[rows.len() - 1].0);
+        let n = ((end - start) / opts.rt_step + 1e-9).floor().max(-1.0) as i64 + 1;
+        for k in 0..n {
+            let t = start + k as f64 * opts.rt_step;
+            // Scans either side of t; grid points outside the scans are
+            // left empty.
+            let j = rows.partition_point(|r| r.0 <= t);
+            let row = if j == 0 {
+                Vec::new()
+            } else if j == rows.len() {
+                if t == rows[j - 1].0 {
+                    binned[j - 1].clone()
+                } else {
+                    Vec::new()
+                }
+            } else {
+                let (t0, t1) = (rows[j - 1].0, rows[j].0);
+                let f = if t1 > t0 { (t - t0) / (t1 - t0) } else { 0.0 };
+                interpolate(&binned[j - 1], &binned[j], f as f32)
+            };
+            out.rt.push(t);
+            push(&mut out, &row);
+        }
+    } else {
+        for (&(t, i), row) in rows.iter().zip(&binned) {
+            out.rt.push(t);
+            out.spectra.push(i);
+            push(&mut out, row);
+        }
+    }
+    Ok(out)
+}
+
+fn bin_row(s: &SpectrumSummary, binner: &Binner, aggregation: Aggregation) -> Vec<(u32, f32)> {
+    let (Some(mz), Some(intensity)) = (&s.mz_array, &s.intensity_array) else {
+        return Vec::new();
+    };
+    let mut row: Vec<(u32, f32)> = Vec::new();
+    for (m, y) in mz.iter().zip(intensity.iter()) {
+        let Some(c) = binner.column(m) else {
+            continue;
+        };
+        if !y.is_finite() {
+            continue;
+        }
+        row.push((c as u32, y as f32));
+    }
+    // Arrays are normally sorted by m/z already.
+    if !row.is_sorted_by_key(|e| e.0) {
+        row.sort_by_key(|e| e.0);
+    }
+    row.dedup_by(|next, kept| {
+        if next.0 != kept.0 {
+            return false;
+        }
+        kept.1 = match aggregation {
+            Aggregation::Sum => kept.1 + next.1,
+            Aggregation::Max => kept.1.max(next.1),
+        };
+        true
+    });
+    row
+}
+
+// (1 - f) a + f b over the union of the columns of two sorted rows.
+fn interpolate(a: &[(u32, f32)], b: &[(u32, f32)], f: f32) -> Vec<(u32, f32)> {
+    let mut out = Vec::with_capacity(a.len().max(b.len()));
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        let ca = a.get(i).map_or(u32::MAX, |e| e.0);
+        let cb = b.get(j).map_or(u32::MAX, |e| e.0);
+        let (c, va, vb) = if ca == cb {
+            i += 1;
+            j += 1;
+            (ca, a[i - 1].1, b[j - 1].1)
+        } else if ca < cb {
+            i += 1;
+            (ca, a[i - 1].1, 0.0)
+        } else {
+            j += 1;
+            (cb, 0.0, b[j - 1].1)
+        };
+        let v = (1.0 - f) * va + f * vb;
+        if v != 0.0 {
+            out.push((c, v));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utilities::parse_mzml::test_spectra::spectrum;
+
+    fn run() -> Vec<SpectrumSummary> {
+        vec![
+            spectrum(1, 2.0, &[(100.2, 1.0), (100.7, 2.0), (102.5, 4.0)]),
+            spectrum(1, 1.0, &[(101.0, 8.0), (102.9, 3.0)]),
+            spectrum(2, 1.5, &[(100.5, 50.0)]),
+        ]
+    }
+
+    #[test]
+    fn rows_follow_retention_time() {
+        let m = bin_spectra(&run(), &BinningOptions::default()).unwrap();
+        assert_eq!(m.rt, [1.0, 2.0]);
+        assert_eq!(m.spectra, [1, 0]);
+        assert_eq!(m.mz_edges, [100.2, 101.2, 102.2, 103.2]);
+        assert_eq!(m.nnz(), 4);
+        assert_eq!(m.to_dense(), [8.0, 0.0, 3.0, 3.0, 0.0, 4.0]);
+
+        let max = BinningOptions {
+            aggregation: Aggregation::Max,
+            ..BinningOptions::default()
+        };
+        assert_eq!(bin_spectra(&run(), &max).unwrap().to_dense()[3], 2.0);
+    }
+
+    #[test]
+    fn range_and_selection_options() {
+        let opts = BinningOptions {
+            mz_min: Some(100.0),
+            mz_max: Some(101.99),
+            ms_level: None,
+            ..BinningOptions::default()
+        };
+        let m = bin_spectra(&run(), &opts).unwrap();
+        assert_eq!(m.spectra, [1, 2, 0]);
+        assert_eq!(m.n_cols(), 2);
+        assert_eq!(m.to_dense(), [0.0, 8.0, 50.0, 0.0, 3.0, 0.0]);
+
+        let negative = BinningOptions {
+            polarity: Some(Polarity::Negative),
+            ..BinningOptions::default()
+        };
+        assert!(bin_spectra(&run(), &negative).is_err());
+        let late = BinningOptions {
+            rt_min: Some(1.2),
+            ..BinningOptions::default()
+        };
+        assert_eq!(bin_spectra(&run(), &late).unwrap().spectra, [0]);
+    }
+
+    #[test]
+    fn ppm_bins_widen_with_mz() {
+        let spectra = vec![spectrum(1, 1.0, &[(100.0, 1.0), (1000.0, 1.0)])];
+        let opts = BinningOptions {
+            bins: MzBins::Ppm(1000.0),
+            ..BinningOptions::default()
+        };
+        let m = bin_spectra(&spectra, &opts).unwrap();
+        let first = m.mz_edges[1] - m.mz_edges[0];
+        let last = m.mz_edges[m.n_cols()] - m.mz_edges[m.n_cols() - 1];
+        assert!((first - 0.1).abs() < 1e-9);
+        assert!((last / first - 10.0).abs() < 0.02);
+        assert_eq!(m.indices[0], 0);
+        let c = m.indices[1] as usize;
+        assert!(m.mz_edges[c] <= 1000.0 && 1000.0 < m.mz_edges[c + 1]);
+
+        let zero = BinningOptions {
+            bins: MzBins::Width(0.0),
+            ..BinningOptions::default()
+        };
+        assert!(bin_spectra(&spectra, &zero).is_err());
+        let from_zero = BinningOptions {
+            mz_min: Some(0.0),
+            ..opts
+        };
+        assert!(bin_spectra(&spectra, &from_zero).is_err());
+    }
+
+    #[test]
+    fn grid_rows_are_interpolated() {
+        let spectra = vec![
+            spectrum(1, 1.0, &[(100.0, 10.0)]),
+            spectrum(1, 2.0, &[(101.0, 20.0)]),
+        ];
+        let opts = BinningOptions {
+            rt_step: 0.25,
+            rt_min: Some(0.5),
+            rt_max: Some(2.5),
+            ..BinningOptions::default()
+        };
+        let m = bin_spectra(&spectra, &opts).unwrap();
+        assert_eq!(m.rt, [0.5, 0.75, 1.0, 1.25, 1.5, 1.75, 2.0, 2.25, 2.5]);
+        assert!(m.spectra.is_empty());
+        let dense = m.to_dense();
+        assert_eq!(dense[0..2], [0.0, 0.0]);
+        assert_eq!(dense[4..6], [10.0, 0.0]);
+        assert_eq!(dense[6..8], [7.5, 5.0]);
+        assert_eq!(dense[12..14], [0.0, 20.0]);
+        assert_eq!(dense[14..16], [0.0, 0.0]);
+    }
+
+    #[test]
+    fn dense_buffers_in_both_orders() {
+        let m = bin_spectra(&run(), &BinningOptions::default()).unwrap();
+        let mut out = vec![-1.0; 6];
+        m.fill_dense(&mut out, true).unwrap();
+        assert_eq!(out, [8.0, 3.0, 0.0, 0.0, 3.0, 4.0]);
+        assert!(m.fill_dense(&mut out[..5], false).is_err());
+    }
+}
@@ -0,0 +1,438 @@
+//! m/z recalibration against reference ions: lock masses or known
+//! compounds expected in every MS1 spectrum.
+//!
+//! Each reference is looked up in each MS1 spectrum, and the ppm errors of
+//! the hits are fitted as a polynomial in m/z: once for the whole run, or
+//! per scan from the hits of the scans around it, tricube-weighted by
+//! retention time distance so the correction follows drift smoothly.
+//! Spectra are then corrected with the model of their retention time.
+
+use super::parse_mzml::{ArrayData, SpectrumSummary};
+use super::stats::{self, NanPolicy};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CalibrationModel {
+    /// A constant ppm offset.
+    Constant,
+    /// ppm error linear in m/z.
+    #[default]
+    Linear,
+    Quadratic,
+}
+
+impl CalibrationModel {
+    fn n_coefficients(self) -> usize {
+        match self {
+            CalibrationModel::Constant => 1,
+            CalibrationModel::Linear => 2,
+            CalibrationModel::Quadratic => 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CalibrationScope {
+    /// One model for the whole run.
+    Global,
+    /// A model per scan, smoothed over retention time.
+    #[default]
+    PerScan,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CalibrationOptions {
+    /// Theoretical m/z of the reference ions.
+    pub references: Vec<f64>,
+    /// Window in which a reference is looked for, in ppm.
+    pub ppm: f64,
+    /// Peaks below this intensity are not taken as references.
+    pub min_intensity: f64,
+    pub model: CalibrationModel,
+    pub scope: CalibrationScope,
+    /// Half width, in minutes, of the retention time window whose hits
+    /// contribute to a per-scan model.
+    pub rt_window: f64,
+}
+
+impl Default for CalibrationOptions {
+    fn default() -> Self {
+        CalibrationOptions {
+            references: Vec::new(),
+            ppm: 20.0,
+            min_intensity: 0.0,
+            model: CalibrationModel::Linear,
+            scope: CalibrationScope::PerScan,
+            rt_window: 0.5,
+        }
+    }
+}
+
+/// A reference found in a spectrum.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReferenceHit {
+    pub spectrum: usize,
+    pub reference_mz: f64,
+    pub observed_mz: f64,
+    pub intensity: f64,
+    /// `(observed - reference) / reference`, in ppm.
+    pub ppm_before: f64,
+    /// The same after correction.
+    pub ppm_after: f64,
+    /// Set for hits too far off the global fit, such as noise peaks taken
+    /// for a reference; they are left out of the fits and the summary.
+    pub outlier: bool,
+}
+
+/// Fitted models, one per spectrum, and the hits they came from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Calibration {
+    /// Coefficients of the ppm error in powers of `m/z / 1000`, per
+    /// spectrum.
+    coefficients: Vec<[f64; 3]>,
+    pub hits: Vec<ReferenceHit>,
+}
+
+impl Calibration {
+    /// Fitted ppm error of spectrum `spectrum` at `mz`; 0 for a spectrum
+    /// out of range.
+    pub fn ppm_error(&self, spectrum: usize, mz: f64) -> f64 {
+        self.coefficients
+            .get(spectrum)
+            .map_or(0.0, |c| polynomial(c, mz / 1000.0))
+    }
+
+    /// Corrected value of an m/z observed in spectrum `spectrum`.
+    pub fn correct(&self, spectrum: usize, mz: f64) -> f64 {
+        mz / (1.0 + self.ppm_error(spectrum, mz) * 1e-6)
+    }
+
+    /// Rewrites the m/z arrays, as `f64`, and the precursor and base peak
+    /// m/z of `spectra`, which must be the spectra the calibration was
+    /// fitted on.
+    pub fn apply(&self, spectra: &mut [SpectrumSummary]) {
+        for (i, s) in spectra.iter_mut().enumerate() {
+            if let Some(mz) = s.mz_array.take() {
+                let v: Vec<f64> = mz.iter().map(|m| self.correct(i, m)).collect();
+                s.mz_array = Some(ArrayData::F64(v));
+            }
+            s.precursor_mz = s.precursor_mz.map(|m| self.correct(i, m));
+            s.base_peak_mz = s.base_peak_mz.map(|m| self.correct(i, m));
+        }
+    }
+
+    /// Median absolute ppm error of the hits, outliers aside, before and
+    /// after correction.
+    pub fn median_abs_ppm(&self) -> (f64, f64) {
+        let med = |f: fn(&ReferenceHit) -> f64| {
+            let v: Vec<f64> = self
+                .hits
+                .iter()
+                .filter(|h| !h.outlier)
+                .map(|h| f(h).abs())
+                .collect();
+            stats::median(&v, NanPolicy::Omit).unwrap_or(f64::NAN)
+        };
+        (med(|h| h.ppm_before), med(|h| h.ppm_after))
+    }
+}
+
+fn polynomial(c: &[f64; 3], x: f64) -> f64 {
+    c[0] + x * (c[1] + x * c[2])
+}
+
+/// Finds the references in the MS1 spectra of `spectra` and fits the
+/// correction. Every spectrum, MSn included, gets the model of its
+/// retention time; those with no hits within `rt_window` get the global
+/// model.
+pub fn calibrate(
+    spectra: &[SpectrumSummary],
+    opts: &CalibrationOptions,
+) -> Result<Calibration, String> {
+    if opts.references.is_empty() {
+        return Err("no reference ions given".to_string());
+    }
+    let mut refs = opts.references.clone();
+    refs.sort_by(f64::total_cmp);
+
+    let mut hits = Vec::new();
+    for (i, s) in spectra.iter().enumerate() {
+        if s.ms_level != Some(1) {
+            continue;
+        }
+        let (Some(mz), Some(intensity)) = (&s.mz_array, &s.intensity_array) else {
+            continue;
+        };
+        let (mz, intensity) = (mz.to_f64(), intensity.to_f64());
+        for &r in &refs {
+            let tol = r * opts.ppm * 1e-6;
+            let lo = mz.partition_point(|&m| m < r - tol);
+            let hi = mz.partition_point(|&m| m <= r + tol).min(intensity.len());
+            let best = (lo..hi)
+                .filter(|&k| intensity[k] >= opts.min_intensity && intensity[k] > 0.0)
+                .max_by(|&a, &b| intensity[a].total_cmp(&intensity[b]));
+            if let Some(k) = best {
+                let ppm = (mz[k] - r) / r * 1e6;
+                hits.push(ReferenceHit {
+                    spectrum: i,
+                    reference_mz: r,
+                    observed_mz: mz[k],
+                    intensity: intensity[k],
+                    ppm_before: ppm,
+                    ppm_after: ppm,
+                    outlier: false,
+                });
+            }
+        }
+    }
+    if hits.is_empty() {
+        return Err("no reference ion found".to_string());
+    }
+
+    let all: Vec<(f64, f64, f64)> = hits
+        .iter()
+        .map(|h| (h.observed_mz / 1000.0, h.ppm_before, 1.0))
+        .collect();
+    let mut global = fit(&all, opts.model);
+
+    // Outliers: residuals beyond 3 scaled MADs of the global fit, and at
+    // least 1 ppm. The global model is refitted without them.
+    let residuals: Vec<f64> = all.iter().map(|p| p.1 - polynomial(&global, p.0)).collect();
+    let centre = stats::median(&residuals, NanPolicy::Omit).unwrap_or(0.0);
+    let mad = stats::mad(&residuals, NanPolicy::Omit).unwrap_or(0.0) * stats::MAD_NORMAL_SCALE;
+    let limit = (3.0 * mad).max(1.0);
+    for (h, r) in hits.iter_mut().zip(&residuals) {
+        h.outlier = (r - centre).abs() > limit;
+    }
+    if hits.iter().any(|h| h.outlier) {
+        let kept: Vec<(f64, f64, f64)> = all
+            .iter()
+            .zip(&hits)
+            .filter(|(_, h)| !h.outlier)
+            .map(|(p, _)| *p)
+            .collect();
+        global = fit(&kept, opts.model);
+    }
+    let mut coefficients = vec![global; spectra.len()];
+
+    if opts.scope == CalibrationScope::PerScan && opts.rt_window > 0.0 {
+        let mut by_rt: Vec<(f64, usize)> = hits
+            .iter()
+            .enumerate()
+            .filter(|(_, h)| !h.outlier)
+            .filter_map(|(k, h)| Some((spectra[h.spectrum].retention_time?, k)))
+            .collect();
+        by_rt.sort_by(|a, b| a.0.total_cmp(&b.0));
+        for (i, s) in spectra.iter().enumerate() {
+            let Some(rt) = s.retention_time else {
+                continue;
+            };
+            let lo = by_rt.partition_point(|e| e.0 <= rt - opts.rt_window);
+            let hi = by_rt.partition_point(|e| e.0 < rt + opts.rt_window);
+            if hi - lo < 2 {
+                continue;
+            }
+            let local: Vec<(f64, f64, f64)> = by_rt[lo..hi]
+                .iter()
+                .map(|&(t, k)| {
+                    let d = (t - rt).abs() / opts.rt_window;
+                    let h = &hits[k];
+                    (
+                        h.observed_mz / 1000.0,
+                        h.ppm_before,
+                        (1.0 - d * d * d).powi(3),
+                    )
+                })
+                .collect();
+            coefficients[i] = fit(&local, opts.model);
+        }
+    }
+
+    let mut cal = Calibration { coefficients, hits };
+    for k in 0..cal.hits.len() {
+        let h = cal.hits[k];
+        let corrected = cal.correct(h.spectrum, h.observed_mz);
+        cal.hits[k].ppm_after = (corrected - h.reference_mz) / h.reference_mz * 1e6;
+    }
+    Ok(cal)
+}
+
+// Weighted least squares polynomial through (x, y, weight) points, of the
+// model's degree or lower when the points cannot support it: each degree
+// needs another reference at least 50 m/z away, or the slope fits noise and
+// extrapolates wildly, and there must be more points than coefficients.
+fn fit(points: &[(f64, f64, f64)], model: CalibrationModel) -> [f64; 3] {
+    let mut distinct: Vec<f64> = points.iter().filter(|p| p.2 > 0.0).map(|p| p.0).collect();
+    distinct.sort_by(f64::total_cmp);
+    distinct.dedup_by(|a, b| *a - *b < 0.05);
+    let n = model
+        .n_coefficients()
+        .min(distinct.len())
+        .min(points.len().saturating_sub(1))
+        .max(1);
+    for n in (1..=n).rev() {
+        if let Some(c) = solve_normal(points, n) {
+            return c;
+        }
+    }
+    [0.0; 3]
+}
+
+// Coefficients of the weighted least squares polynomial with `n` terms, or
+// None when the points cannot determine them.
+pub(crate) fn solve_normal(points: &[(f64, f64, f64)], n: usize) -> Option<[f64; 3]> {
+    // Normal equations A c = b, solved by Gaussian elimination with
+    // partial pivoting.
+    let mut a = [[0.0f64; 4]; 3];
+    for &(x, y, w) in points {
+        let powers = [1.0, x, x * x];
+        for r in 0..n {
+            for c in 0..n {
+                a[r][c] += w * powers[r] * powers[c];
+            }
+            a[r][3] += w * powers[r] * y;
+        }
+    }
+    for col in 0..n {
+        let pivot = (col..n).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))?;
+        if a[pivot][col].abs() < 1e-12 {
+            return None;
+        }
+        a.swap(col, pivot);
+        let p = a[col];
+        for (r, row) in a.iter_mut().enumerate().take(n) {
+            if r != col {
+                let f = row[col] / p[col];
+                for (x, y) in row.iter_mut().zip(p).skip(col) {
+                    *x -= f * y;
+                }
+            }
+        }
+    }
+    let mut out = [0.0; 3];
+    for (i, o) in out.iter_mut().enumerate().take(n) {
+        *o = a[i][3] / a[i][i];
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REFERENCES: [f64; 3] = [200.0, 500.0, 900.0];
+
+    // An MS1 scan with the references shifted by `ppm(mz)` and a noise
+    // peak between them.
+    fn scan(rt: f64, ppm: impl Fn(f64) -> f64) -> SpectrumSummary {
+        let mut mz = vec![350.0];
+        mz.extend(REFERENCES.iter().map(|&r| r * (1.0 + ppm(r) * 1e-6)));
+        mz.sort_by(f64::total_cmp);
+        SpectrumSummary {
+            ms_level: Some(1),
+            retention_time: Some(rt),
+            base_peak_mz: Some(mz[0]),
+            intensity_array: Some(ArrayData::F64(vec![1e5; mz.len()])),
+            mz_array: Some(ArrayData::F64(mz)),
+            ..SpectrumSummary::default()
+        }
+    }
+
+    fn options(model: CalibrationModel, scope: CalibrationScope) -> CalibrationOptions {
+        CalibrationOptions {
+            references: REFERENCES.to_vec(),
+            model,
+            scope,
+            ..CalibrationOptions::default()
+        }
+    }
+
+    #[test]
+    fn global_linear_error_is_removed() {
+        let spectra: Vec<SpectrumSummary> = (0..5)
+            .map(|i| scan(i as f64, |mz| 2.0 + 6.0 * mz / 1000.0))
+            .collect();
+        let opts = options(CalibrationModel::Linear, CalibrationScope::Global);
+        let cal = calibrate(&spectra, &opts).unwrap();
+        assert_eq!(cal.hits.len(), 15);
+        assert!(cal.hits.iter().all(|h| !h.outlier));
+        assert!((cal.ppm_error(0, 500.0) - 5.0).abs() < 1e-3);
+        assert!((cal.correct(2, 900.0 * (1.0 + 7.4e-6)) - 900.0).abs() < 1e-7);
+        let (before, after) = cal.median_abs_ppm();
+        assert!((before - 5.0).abs() < 1e-3);
+        assert!(after < 1e-3);
+        // Out of range spectra are left alone.
+        assert_eq!(cal.ppm_error(99, 500.0), 0.0);
+
+        // A constant model takes the mean offset.
+        let opts = options(CalibrationModel::Constant, CalibrationScope::Global);
+        let cal = calibrate(&spectra, &opts).unwrap();
+        assert!((cal.ppm_error(0, 200.0) - cal.ppm_error(0, 900.0)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn per_scan_models_follow_drift() {
+        // 1 ppm per minute, the same at every m/z.
+        let spectra: Vec<SpectrumSummary> = (0..21)
+            .map(|i| {
+                let rt = i as f64 * 0.1;
+                scan(rt, move |_| 10.0 * rt)
+            })
+            .collect();
+        let opts = options(CalibrationModel::Constant, CalibrationScope::PerScan);
+        let cal = calibrate(&spectra, &opts).unwrap();
+        // Symmetric windows in the middle, one-sided ones at the ends.
+        assert!((cal.ppm_error(10, 500.0) - 10.0).abs() < 1e-3);
+        assert!(cal.ppm_error(0, 500.0) < cal.ppm_error(1, 500.0));
+        assert!(cal.ppm_error(0, 500.0) < 2.0);
+        assert!(cal.ppm_error(20, 500.0) > 18.0);
+        let (before, after) = cal.median_abs_ppm();
+        assert!((before - 10.0).abs() < 1e-6);
+        assert!(after < 0.5);
+    }
+
+    #[test]
+    fn outliers_are_flagged_and_ignored() {
+        let mut spectra: Vec<SpectrumSummary> = (0..8).map(|i| scan(i as f64, |_| 3.0)).collect();
+        spectra.push(scan(8.0, |mz| if mz == 500.0 { 15.0 } else { 3.0 }));
+        let opts = options(CalibrationModel::Constant, CalibrationScope::Global);
+        let cal = calibrate(&spectra, &opts).unwrap();
+        let outliers: Vec<&ReferenceHit> = cal.hits.iter().filter(|h| h.outlier).collect();
+        assert_eq!(outliers.len(), 1);
+        assert_eq!((outliers[0].spectrum, outliers[0].reference_mz), (8, 500.0));
+        assert!((cal.ppm_error(0, 500.0) - 3.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn apply_rewrites_mz_values() {
+        let mut spectra = vec![scan(0.0, |_| 4.0), scan(1.0, |_| 4.0)];
+        spectra[1].ms_level = Some(2);
+        spectra[1].precursor_mz = Some(500.0 * (1.0 + 4e-6));
+        spectra[0].mz_array = spectra[0]
+            .mz_array
+            .as_ref()
+            .map(|a| ArrayData::F32(a.iter().map(|m| m as f32).collect()));
+        let opts = options(CalibrationModel::Constant, CalibrationScope::Global);
+        let cal = calibrate(&spectra, &opts).unwrap();
+        assert!(cal.hits.iter().all(|h| h.spectrum == 0));
+        cal.apply(&mut spectra);
+        let Some(ArrayData::F64(mz)) = &spectra[1].mz_array else {
+            panic!("m/z not rewritten as f64");
+        };
+        assert!((mz[2] - 500.0).abs() < 1e-4);
+        assert!(matches!(spectra[0].mz_array, Some(ArrayData::F64(_))));
+        assert!((spectra[1].precursor_mz.unwrap() - 500.0).abs() < 1e-4);
+        assert!((spectra[0].base_peak_mz.unwrap() - 200.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn missing_references_are_errors() {
+        let spectra = vec![scan(0.0, |_| 0.0)];
+        let none = CalibrationOptions::default();
+        assert!(calibrate(&spectra, &none).is_err());
+        let far = CalibrationOptions {
+            references: vec![1234.5],
+            ..CalibrationOptions::default()
+        };
+        assert!(calibrate(&spectra, &far).is_err());
+    }
+}
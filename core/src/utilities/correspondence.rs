@@ -0,0 +1,660 @@
+//! Correspondence of features across samples.
+//!
+//! Features of all samples are pooled, split into m/z slices and grouped
+//! along retention time by the peaks of a kernel density estimate, as in
+//! xcms' peak density method. The result is a [`FeatureTable`] of areas with
+//! one row per group and one column per sample; samples where a group was
+//! not detected can then be filled by integrating the raw signal in the
+//! group's window with [`fill_gaps`].
+//!
+//! Retention times of all samples must be on the same scale, so align the
+//! runs first (see [`alignment`](super::alignment)).
+
+use std::io::Write;
+#[cfg(feature = "parquet")]
+use std::sync::Arc;
+
+#[cfg(feature = "parquet")]
+use parquet::basic::{Repetition, Type as PhysicalType};
+#[cfg(feature = "parquet")]
+use parquet::data_type::{DataType, DoubleType, Int64Type};
+#[cfg(feature = "parquet")]
+use parquet::errors::ParquetError;
+#[cfg(feature = "parquet")]
+use parquet::file::properties::WriterProperties;
+#[cfg(feature = "parquet")]
+use parquet::file::writer::{SerializedFileWriter, SerializedRowGroupWriter};
+#[cfg(feature = "parquet")]
+use parquet::schema::types::Type;
+
+use super::feature_detection::Feature;
+use super::parse_mzml::SpectrumSummary;
+use super::stats::{self, NanPolicy};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GroupingOptions {
+    /// Features closer than this in m/z, in ppm, share an m/z slice.
+    pub ppm: f64,
+    /// Standard deviation, in minutes, of the Gaussian kernel of the
+    /// retention time density.
+    pub bandwidth: f64,
+    /// Minimum fraction of samples a group must be detected in.
+    pub min_fraction: f64,
+    /// Minimum number of samples a group must be detected in.
+    pub min_samples: usize,
+}
+
+impl Default for GroupingOptions {
+    fn default() -> Self {
+        GroupingOptions {
+            ppm: 10.0,
+            bandwidth: 0.1,
+            min_fraction: 0.5,
+            min_samples: 1,
+        }
+    }
+}
+
+/// One group of corresponding features. `mz` and `rt` are medians over the
+/// members; the ranges are the medians of the member ranges, and are the
+/// window integrated when filling gaps.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeatureGroup {
+    pub mz: f64,
+    pub mz_min: f64,
+    pub mz_max: f64,
+    pub rt: f64,
+    pub rt_min: f64,
+    pub rt_max: f64,
+    /// Number of samples with a detected feature.
+    pub n_samples: usize,
+    /// Per sample, the index of its member feature in that sample's list;
+    /// the most intense one when several fell in the group.
+    pub members: Vec<Option<usize>>,
+}
+
+/// Groups by samples, with the area of each group in each sample.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeatureTable {
+    pub groups: Vec<FeatureGroup>,
+    pub n_samples: usize,
+    /// Row-major `groups.len() x n_samples` areas; NaN where missing.
+    pub areas: Vec<f64>,
+    /// Parallel to `areas`: whether the value came from [`fill_gaps`].
+    pub filled: Vec<bool>,
+}
+
+impl FeatureTable {
+    pub fn area(&self, group: usize, sample: usize) -> f64 {
+        if sample >= self.n_samples {
+            return f64::NAN;
+        }
+        self.areas
+            .get(group * self.n_samples + sample)
+            .copied()
+            .unwrap_or(f64::NAN)
+    }
+
+    // Area column names: `sample_names`, or `sample_<i>` when it has the
+    // wrong length.
+    fn sample_columns(&self, sample_names: &[&str]) -> Vec<String> {
+        if sample_names.len() == self.n_samples {
+            sample_names.iter().map(|s| s.to_string()).collect()
+        } else {
+            (0..self.n_samples).map(|i| format!("sample_{i}")).collect()
+        }
+    }
+
+    /// Writes the table as CSV: the group columns followed by one area
+    /// column per sample, named by `sample_names` or `sample_<i>` when it
+    /// has the wrong length. Missing areas are empty fields.
+    pub fn write_csv<W: Write>(&self, w: &mut W, sample_names: &[&str]) -> Result<(), String> {
+        let names: Vec<String> = self
+            .sample_columns(sample_names)
+            .iter()
+            .map(|s| csv_field(s))
+            .collect();
+        let mut out = String::from("group,mz,mz_min,mz_max,rt,rt_min,rt_max,n_samples");
+        for n in &names {
+            out.push(',');
+            out.push_str(n);
+        }
+        out.push('\n');
+        for (g, grp) in self.groups.iter().enumerate() {
+            out.push_str(&format!(
+                "{g},{},{},{},{},{},{},{}",
+                grp.mz, grp.mz_min, grp.mz_max, grp.rt, grp.rt_min, grp.rt_max, grp.n_samples
+            ));
+            for s in 0..self.n_samples {
+                out.push(',');
+                let v = self.area(g, s);
+                if !v.is_nan() {
+                    out.push_str(&v.to_string());
+                }
+            }
+            out.push('\n');
+        }
+        w.write_all(out.as_bytes())
+            .map_err(|e| format!("write csv: {e}"))
+    }
+
+    /// Writes the table as Parquet with the columns of
+    /// [`write_csv`](Self::write_csv): `group` and `n_samples` as INT64, the
+    /// rest as DOUBLE, and missing areas as nulls. The file holds a single
+    /// uncompressed row group.
+    #[cfg(feature = "parquet")]
+    pub fn write_parquet<W: Write + Send>(
+        &self,
+        w: W,
+        sample_names: &[&str],
+    ) -> Result<(), String> {
+        self.try_write_parquet(w, sample_names)
+            .map_err(|e| format!("write parquet: {e}"))
+    }
+
+    #[cfg(feature = "parquet")]
+    fn try_write_parquet<W: Write + Send>(
+        &self,
+        w: W,
+        sample_names: &[&str],
+    ) -> Result<(), ParquetError> {
+        let column = |name: &str, ty, repetition| {
+            Type::primitive_type_builder(name, ty)
+                .with_repetition(repetition)
+                .build()
+                .map(Arc::new)
+        };
+        let mut fields = vec![column("group", PhysicalType::INT64, Repetition::REQUIRED)?];
+        for name in ["mz", "mz_min", "mz_max", "rt", "rt_min", "rt_max"] {
+            fields.push(column(name, PhysicalType::DOUBLE, Repetition::REQUIRED)?);
+        }
+        fields.push(column(
+            "n_samples",
+            PhysicalType::INT64,
+            Repetition::REQUIRED,
+        )?);
+        for name in self.sample_columns(sample_names) {
+            fields.push(column(&name, PhysicalType::DOUBLE, Repetition::OPTIONAL)?);
+        }
+        let schema = Type::group_type_builder("schema")
+            .with_fields(fields)
+            .build()?;
+        let props = WriterProperties::builder()
+            .set_created_by("ulcms".to_string())
+            .build();
+        let mut writer = SerializedFileWriter::new(w, Arc::new(schema), Arc::new(props))?;
+
+        let mut rows = writer.next_row_group()?;
+        let groups = &self.groups;
+        let index: Vec<i64> = (0..groups.len() as i64).collect();
+        write_column::<Int64Type, _>(&mut rows, &index, None)?;
+        let bounds: [fn(&FeatureGroup) -> f64; 6] = [
+            |g| g.mz,
+            |g| g.mz_min,
+            |g| g.mz_max,
+            |g| g.rt,
+            |g| g.rt_min,
+            |g| g.rt_max,
+        ];
+        for field in bounds {
+            let values: Vec<f64> = groups.iter().map(field).collect();
+            write_column::<DoubleType, _>(&mut rows, &values, None)?;
+        }
+        let counts: Vec<i64> = groups.iter().map(|g| g.n_samples as i64).collect();
+        write_column::<Int64Type, _>(&mut rows, &counts, None)?;
+        for s in 0..self.n_samples {
+            let areas: Vec<f64> = (0..groups.len()).map(|g| self.area(g, s)).collect();
+            let present: Vec<i16> = areas.iter().map(|v| i16::from(!v.is_nan())).collect();
+            let values: Vec<f64> = areas.into_iter().filter(|v| !v.is_nan()).collect();
+            write_column::<DoubleType, _>(&mut rows, &values, Some(&present))?;
+        }
+        rows.close()?;
+        writer.close()?;
+        Ok(())
+    }
+}
+
+// Writes `values` as the next column of `rows`; `def_levels` marks which
+// rows of an optional column are present.
+#[cfg(feature = "parquet")]
+fn write_column<T: DataType, W: Write + Send>(
+    rows: &mut SerializedRowGroupWriter<'_, W>,
+    values: &[T::T],
+    def_levels: Option<&[i16]>,
+) -> Result<(), ParquetError> {
+    let mut col = rows
+        .next_column()?
+        .ok_or_else(|| ParquetError::General("more columns than the schema".to_string()))?;
+    col.typed::<T>().write_batch(values, def_levels, None)?;
+    col.close()
+}
+
+pub(crate) fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+// A feature in the pooled list.
+#[derive(Clone, Copy)]
+struct Pooled<'a> {
+    sample: usize,
+    index: usize,
+    f: &'a Feature,
+}
+
+/// Groups the features of `samples` (one list per sample) and builds the
+/// table of their areas. Groups are sorted by m/z, then retention time.
+pub fn group_features(samples: &[&[Feature]], opts: &GroupingOptions) -> FeatureTable {
+    let n_samples = samples.len();
+    let mut pooled: Vec<Pooled> = samples
+        .iter()
+        .enumerate()
+        .flat_map(|(sample, fs)| {
+            fs.iter()
+                .enumerate()
+                .filter(|(_, f)| f.mz.is_finite() && f.rt.is_finite())
+                .map(move |(index, f)| Pooled { sample, index, f })
+        })
+        .collect();
+    pooled.sort_by(|a, b| a.f.mz.total_cmp(&b.f.mz));
+
+    let needed = opts
+        .min_samples
+        .max((opts.min_fraction * n_samples as f64).ceil() as usize)
+        .max(1);
+    let mut groups = Vec::new();
+    let mut start = 0;
+    for i in 1..=pooled.len() {
+        let split = i == pooled.len() || {
+            let (a, b) = (pooled[i - 1].f.mz, pooled[i].f.mz);
+            b - a > b * opts.ppm * 1e-6
+        };
+        if split {
+            density_groups(&mut pooled[start..i], n_samples, needed, opts, &mut groups);
+            start = i;
+        }
+    }
+    groups.sort_by(|a: &FeatureGroup, b| a.mz.total_cmp(&b.mz).then(a.rt.total_cmp(&b.rt)));
+
+    let mut areas = vec![f64::NAN; groups.len() * n_samples];
+    for (g, grp) in groups.iter().enumerate() {
+        for (s, m) in grp.members.iter().enumerate() {
+            if let Some(k) = m {
+                areas[g * n_samples + s] = samples[s][*k].area;
+            }
+        }
+    }
+    FeatureTable {
+        filled: vec![false; areas.len()],
+        groups,
+        n_samples,
+        areas,
+    }
+}
+
+// Splits one m/z slice at the minima of its retention time density.
+fn density_groups(
+    slice: &mut [Pooled],
+    n_samples: usize,
+    needed: usize,
+    opts: &GroupingOptions,
+    out: &mut Vec<FeatureGroup>,
+) {
+    slice.sort_by(|a, b| a.f.rt.total_cmp(&b.f.rt));
+    let bw = opts.bandwidth.max(1e-3);
+    let step = bw / 4.0;
+    let lo = slice[0].f.rt - 3.0 * bw;
+    let hi = slice[slice.len() - 1].f.rt + 3.0 * bw;
+    let n = ((hi - lo) / step).ceil() as usize + 1;
+    let mut density = vec![0.0; n];
+    for p in slice.iter() {
+        // The kernel is negligible beyond 3 bandwidths.
+        let c = (p.f.rt - lo) / step;
+        let (a, b) = (
+            (c - 12.0).max(0.0) as usize,
+            ((c + 12.0) as usize).min(n - 1),
+        );
+        for (k, d) in density.iter_mut().enumerate().take(b + 1).skip(a) {
+            let u = (lo + k as f64 * step - p.f.rt) / bw;
+            *d += (-0.5 * u * u).exp();
+        }
+    }
+
+    let mut bounds: Vec<f64> = (1..n.saturating_sub(1))
+        .filter(|&k| density[k] < density[k - 1] && density[k] <= density[k + 1])
+        .map(|k| lo + k as f64 * step)
+        .collect();
+    bounds.push(f64::INFINITY);
+
+    let mut first = 0;
+    for b in bounds {
+        let last = first + slice[first..].partition_point(|p| p.f.rt < b);
+        if last > first {
+            if let Some(g) = make_group(&slice[first..last], n_samples, needed) {
+                out.push(g);
+            }
+            first = last;
+        }
+    }
+}
+
+fn make_group(members: &[Pooled], n_samples: usize, needed: usize) -> Option<FeatureGroup> {
+    let mut best: Vec<Option<&Pooled>> = vec![None; n_samples];
+    for p in members {
+        let slot = &mut best[p.sample];
+        if slot.is_none_or(|q| p.f.area > q.f.area) {
+            *slot = Some(p);
+        }
+    }
+    let n = best.iter().filter(|b| b.is_some()).count();
+    if n < needed {
+        return None;
+    }
+    let chosen: Vec<&Feature> = best.iter().flatten().map(|p| p.f).collect();
+    let med = |f: fn(&Feature) -> f64| {
+        let v: Vec<f64> = chosen.iter().map(|x| f(x)).collect();
+        stats::median(&v, NanPolicy::Omit).unwrap_or(f64::NAN)
+    };
+    Some(FeatureGroup {
+        mz: med(|f| f.mz),
+        mz_min: med(|f| f.mz_min),
+        mz_max: med(|f| f.mz_max),
+        rt: med(|f| f.rt),
+        rt_min: med(|f| f.rt_min),
+        rt_max: med(|f| f.rt_max),
+        n_samples: n,
+        members: best.iter().map(|b| b.map(|p| p.index)).collect(),
+    })
+}
+
+/// Extracted ion chromatogram: for each MS1 spectrum with a retention time
+/// in `rt` (inclusive), the summed intensity of the points with m/z in `mz`
+/// (inclusive), as `(retention times, intensities)` in time order. The m/z
+/// arrays must be sorted, as they are in centroided data.
+pub fn xic(spectra: &[SpectrumSummary], mz: (f64, f64), rt: (f64, f64)) -> (Vec<f64>, Vec<f64>) {
+    let mut points: Vec<(f64, f64)> = spectra
+        .iter()
+        .filter(|s| s.ms_level == Some(1))
+        .filter_map(|s| {
+            let t = s.retention_time.filter(|t| (rt.0..=rt.1).contains(t))?;
+            Some((t, window_sum(s, mz)))
+        })
+        .collect();
+    points.sort_by(|a, b| a.0.total_cmp(&b.0));
+    points.into_iter().unzip()
+}
+
+// Summed intensity of the points of `s` with m/z in `mz`, found by binary
+// search in the sorted m/z array.
+fn window_sum(s: &SpectrumSummary, mz: (f64, f64)) -> f64 {
+    let (Some(m), Some(y)) = (&s.mz_array, &s.intensity_array) else {
+        return 0.0;
+    };
+    let start = m.partition_point(|x| x < mz.0);
+    (start..m.len())
+        .map_while(|k| m.get(k).filter(|&x| x <= mz.1).and(y.get(k)))
+        .sum()
+}
+
+/// Fills the missing areas of `sample` by integrating, with the trapezoid
+/// rule, the XIC of each group's window in `spectra`, the sample's raw
+/// spectra with retention times on the aligned scale. The m/z window is
+/// widened by `ppm` either side. Returns the number of cells filled.
+pub fn fill_gaps(
+    table: &mut FeatureTable,
+    sample: usize,
+    spectra: &[SpectrumSummary],
+    ppm: f64,
+) -> usize {
+    if sample >= table.n_samples {
+        return 0;
+    }
+    // MS1 scans in retention time order, so that each group only visits the
+    // scans of its window.
+    let mut scans: Vec<(f64, &SpectrumSummary)> = spectra
+        .iter()
+        .filter(|s| s.ms_level == Some(1))
+        .filter_map(|s| Some((s.retention_time?, s)))
+        .collect();
+    scans.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let mut filled = 0;
+    for (g, grp) in table.groups.iter().enumerate() {
+        let cell = g * table.n_samples + sample;
+        if !table.areas[cell].is_nan() {
+            continue;
+        }
+        let mz = (
+            grp.mz_min - grp.mz_min * ppm * 1e-6,
+            grp.mz_max + grp.mz_max * ppm * 1e-6,
+        );
+        let lo = scans.partition_point(|s| s.0 < grp.rt_min);
+        let hi = scans.partition_point(|s| s.0 <= grp.rt_max).max(lo);
+        let mut area = 0.0;
+        let mut last: Option<(f64, f64)> = None;
+        for &(t, s) in &scans[lo..hi] {
+            let y = window_sum(s, mz);
+            if let Some((t0, y0)) = last {
+                area += 0.5 * (y0 + y) * (t - t0);
+            }
+            last = Some((t, y));
+        }
+        table.areas[cell] = area;
+        table.filled[cell] = true;
+        filled += 1;
+    }
+    filled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utilities::parse_mzml::test_spectra::spectrum;
+
+    fn feature(mz: f64, rt: f64, area: f64) -> Feature {
+        Feature {
+            mz,
+            mz_min: mz - 0.001,
+            mz_max: mz + 0.001,
+            rt,
+            rt_min: rt - 0.1,
+            rt_max: rt + 0.1,
+            area,
+            height: area,
+            snr: 10.0,
+            scans: 5,
+        }
+    }
+
+    #[test]
+    fn features_group_across_samples() {
+        let a = [feature(200.0, 5.0, 10.0), feature(300.0, 2.0, 7.0)];
+        let b = [feature(200.0005, 5.02, 12.0)];
+        let table = group_features(&[&a, &b], &GroupingOptions::default());
+        assert_eq!(table.groups.len(), 2);
+        assert_eq!(table.groups[0].members, [Some(0), Some(0)]);
+        assert_eq!(table.groups[0].n_samples, 2);
+        assert_eq!((table.area(0, 0), table.area(0, 1)), (10.0, 12.0));
+        assert_eq!(table.groups[1].members, [Some(1), None]);
+        assert!(table.area(1, 1).is_nan());
+
+        let mut csv = Vec::new();
+        table.write_csv(&mut csv, &["a", "b,c"]).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert!(csv.starts_with("group,mz,mz_min,mz_max,rt,rt_min,rt_max,n_samples,a,\"b,c\"\n"));
+        assert!(csv.lines().nth(2).unwrap().ends_with(",7,"));
+    }
+
+    #[test]
+    fn gaps_are_filled_from_the_window() {
+        let a = [feature(300.0, 2.0, 7.0)];
+        let mut table = group_features(&[&a, &[]], &GroupingOptions::default());
+        let spectra = [
+            spectrum(1, 1.85, &[(300.0, 50.0)]),
+            spectrum(
+                1,
+                1.9,
+                &[(299.0, 9.0), (300.0, 4.0), (300.0005, 2.0), (301.0, 9.0)],
+            ),
+            spectrum(1, 2.0, &[(300.0, 8.0)]),
+            spectrum(1, 2.1, &[]),
+            SpectrumSummary {
+                ms_level: Some(2),
+                ..spectrum(1, 2.05, &[(300.0, 100.0)])
+            },
+        ];
+        assert_eq!(fill_gaps(&mut table, 1, &spectra, 5.0), 1);
+        // (6 + 8) / 2 * 0.1 + (8 + 0) / 2 * 0.1
+        assert!((table.area(0, 1) - 1.1).abs() < 1e-9);
+        assert!(table.filled[1] && !table.filled[0]);
+        assert_eq!(fill_gaps(&mut table, 1, &spectra, 5.0), 0);
+        assert_eq!(fill_gaps(&mut table, 2, &spectra, 5.0), 0);
+
+        let (t, y) = xic(&spectra, (299.9, 300.1), (1.9, 2.1));
+        assert_eq!(t, [1.9, 2.0, 2.1]);
+        assert_eq!(y, [6.0, 8.0, 0.0]);
+    }
+
+    #[test]
+    fn bandwidth_decides_whether_close_rts_split() {
+        let a = [feature(200.0, 5.0, 10.0), feature(200.0, 5.6, 4.0)];
+        let b = [feature(200.0005, 5.02, 12.0), feature(200.0005, 5.62, 3.0)];
+        let narrow = GroupingOptions {
+            bandwidth: 0.1,
+            ..GroupingOptions::default()
+        };
+        let table = group_features(&[&a, &b], &narrow);
+        assert_eq!(table.groups.len(), 2);
+        assert_eq!(table.groups[0].members, [Some(0), Some(0)]);
+        assert_eq!(table.groups[1].members, [Some(1), Some(1)]);
+        assert!(table.groups[0].rt_max < table.groups[1].rt_min);
+
+        // A kernel wider than the gap merges both into one group, which
+        // keeps the most intense feature of each sample.
+        let wide = GroupingOptions {
+            bandwidth: 1.0,
+            ..GroupingOptions::default()
+        };
+        let table = group_features(&[&a, &b], &wide);
+        assert_eq!(table.groups.len(), 1);
+        assert_eq!(table.groups[0].members, [Some(0), Some(0)]);
+        assert_eq!((table.area(0, 0), table.area(0, 1)), (10.0, 12.0));
+    }
+
+    #[test]
+    fn areas_are_groups_by_samples_row_major() {
+        let a = [feature(100.0, 1.0, 1.0), feature(300.0, 3.0, 3.0)];
+        let b = [feature(200.0, 2.0, 20.0)];
+        let c = [feature(300.0, 3.0, 300.0), feature(100.0, 1.0, 100.0)];
+        let opts = GroupingOptions {
+            min_fraction: 0.0,
+            ..GroupingOptions::default()
+        };
+        let table = group_features(&[&a, &b, &c], &opts);
+        assert_eq!(table.n_samples, 3);
+        let mz: Vec<f64> = table.groups.iter().map(|g| g.mz).collect();
+        assert_eq!(mz, [100.0, 200.0, 300.0]);
+        let areas: Vec<Option<f64>> = table
+            .areas
+            .iter()
+            .map(|&v| (!v.is_nan()).then_some(v))
+            .collect();
+        #[rustfmt::skip]
+        assert_eq!(areas, [
+            Some(1.0), None,       Some(100.0),
+            None,      Some(20.0), None,
+            Some(3.0), None,       Some(300.0),
+        ]);
+        assert_eq!(table.groups[2].members, [Some(1), None, Some(0)]);
+        assert_eq!(table.filled, [false; 9]);
+        assert!(table.area(0, 3).is_nan() && table.area(3, 0).is_nan());
+    }
+
+    #[test]
+    fn gap_filling_integrates_an_elution_profile() {
+        // Sample 1 has no features; its raw signal is a Gaussian at the
+        // group's m/z, next to a peak outside the m/z window.
+        let a = [feature(300.0, 2.0, 7.0)];
+        let mut table = group_features(&[&a, &[]], &GroupingOptions::default());
+        let (height, sigma) = (100.0, 0.03);
+        let spectra: Vec<SpectrumSummary> = (0..=60)
+            .map(|i| {
+                let rt = 1.7 + i as f64 * 0.01;
+                let y = height * (-0.5 * ((rt - 2.0) / sigma).powi(2)).exp();
+                spectrum(1, rt, &[(300.0002, y), (300.01, 1e6)])
+            })
+            .collect();
+        assert_eq!(fill_gaps(&mut table, 1, &spectra, 5.0), 1);
+        let want = height * sigma * (2.0 * std::f64::consts::PI).sqrt();
+        let got = table.area(0, 1);
+        assert!((got - want).abs() < 0.01 * want, "{got} vs {want}");
+        assert_eq!(table.area(0, 0), 7.0);
+        assert_eq!(table.filled, [false, true]);
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn parquet_round_trips_the_table() {
+        use parquet::file::reader::{FileReader, SerializedFileReader};
+        use parquet::record::Field;
+
+        let a = [feature(200.0, 5.0, 10.0), feature(300.0, 2.0, 7.0)];
+        let b = [feature(200.0005, 5.02, 12.0)];
+        let table = group_features(&[&a, &b], &GroupingOptions::default());
+        let path = std::env::temp_dir().join(format!("ulcms-{}.parquet", std::process::id()));
+        table
+            .write_parquet(std::fs::File::create(&path).unwrap(), &["a", "b"])
+            .unwrap();
+        let reader = SerializedFileReader::new(std::fs::File::open(&path).unwrap()).unwrap();
+        let rows: Vec<Vec<(String, Field)>> = reader
+            .get_row_iter(None)
+            .unwrap()
+            .map(|r| {
+                r.unwrap()
+                    .get_column_iter()
+                    .map(|(n, f)| (n.clone(), f.clone()))
+                    .collect()
+            })
+            .collect();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(rows.len(), 2);
+        let names: Vec<&str> = rows[0].iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "group",
+                "mz",
+                "mz_min",
+                "mz_max",
+                "rt",
+                "rt_min",
+                "rt_max",
+                "n_samples",
+                "a",
+                "b"
+            ]
+        );
+        let g = &table.groups[1];
+        let second: Vec<Field> = rows[1].iter().map(|(_, f)| f.clone()).collect();
+        assert_eq!(
+            second,
+            [
+                Field::Long(1),
+                Field::Double(g.mz),
+                Field::Double(g.mz_min),
+                Field::Double(g.mz_max),
+                Field::Double(g.rt),
+                Field::Double(g.rt_min),
+                Field::Double(g.rt_max),
+                Field::Long(1),
+                Field::Double(7.0),
+                Field::Null,
+            ]
+        );
+        assert_eq!(rows[0][9].1, Field::Double(12.0));
+    }
+}
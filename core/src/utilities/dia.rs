@@ -0,0 +1,385 @@
+//! Data-independent acquisition: isolation windows, cycle structure and
+//! fragment XICs.
+//!
+//! MSn spectra are grouped by isolation window, taken from the window
+//! target and offsets of their first precursor. Spectra are then laid out
+//! in acquisition cycles, each starting at an MS1 scan or where a window
+//! comes round again. Fragment XICs are extracted from
+//! the spectra of the window that isolates the precursor, so fragments of
+//! co-eluting precursors in other windows do not leak in.
+
+use super::parse_mzml::SpectrumSummary;
+
+/// An isolation window, as absolute m/z bounds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IsolationWindow {
+    pub target: f64,
+    pub lower: f64,
+    pub upper: f64,
+}
+
+impl IsolationWindow {
+    /// Window of the first precursor of `s`: its target, or the selected
+    /// ion m/z when the target is missing, and offsets taken as 0 when
+    /// missing.
+    pub fn of(s: &SpectrumSummary) -> Option<IsolationWindow> {
+        let target = s.isolation_window_target.or(s.precursor_mz)?;
+        Some(IsolationWindow {
+            target,
+            lower: target - s.isolation_window_lower.unwrap_or(0.0),
+            upper: target + s.isolation_window_upper.unwrap_or(0.0),
+        })
+    }
+
+    pub fn width(&self) -> f64 {
+        self.upper - self.lower
+    }
+
+    pub fn contains(&self, mz: f64) -> bool {
+        mz >= self.lower && mz <= self.upper
+    }
+}
+
+/// The MSn spectra acquired with one isolation window.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiaWindow {
+    /// Mean bounds of the grouped spectra.
+    pub window: IsolationWindow,
+    pub ms_level: Option<u32>,
+    /// Indices of the spectra, by retention time.
+    pub spectra: Vec<usize>,
+}
+
+/// One pass through the windows.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cycle {
+    /// MS1 scan opening the cycle.
+    pub ms1: Option<usize>,
+    /// Retention time of the first scan of the cycle.
+    pub rt: f64,
+    /// Spectrum of each window in this cycle, in the order of
+    /// [`DiaCycles::windows`].
+    pub spectra: Vec<Option<usize>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct DiaCycles {
+    /// Windows by lower bound.
+    pub windows: Vec<DiaWindow>,
+    pub cycles: Vec<Cycle>,
+}
+
+impl DiaCycles {
+    /// Median time between the starts of consecutive cycles, in minutes;
+    /// NaN with fewer than two cycles.
+    pub fn cycle_time(&self) -> f64 {
+        let mut d: Vec<f64> = self
+            .cycles
+            .windows(2)
+            .map(|c| c[1].rt - c[0].rt)
+            .filter(|d| d.is_finite())
+            .collect();
+        if d.is_empty() {
+            return f64::NAN;
+        }
+        d.sort_by(f64::total_cmp);
+        let m = d.len() / 2;
+        if d.len() % 2 == 1 {
+            d[m]
+        } else {
+            0.5 * (d[m - 1] + d[m])
+        }
+    }
+}
+
+/// Groups the MSn spectra with an isolation window: same MS level, and
+/// target and bounds each within `tolerance` m/z.
+pub fn group_windows(spectra: &[SpectrumSummary], tolerance: f64) -> Vec<DiaWindow> {
+    let rt = |i: usize| spectra[i].retention_time.unwrap_or(f64::NAN);
+    let mut windows: Vec<(DiaWindow, usize)> = Vec::new();
+    let mut order: Vec<usize> = (0..spectra.len()).collect();
+    order.sort_by(|&a, &b| rt(a).total_cmp(&rt(b)));
+    for i in order {
+        let s = &spectra[i];
+        if s.ms_level.is_none_or(|l| l < 2) {
+            continue;
+        }
+        let Some(w) = IsolationWindow::of(s) else {
+            continue;
+        };
+        let same = |d: &DiaWindow| {
+            d.ms_level == s.ms_level
+                && (d.window.target - w.target).abs() <= tolerance
+                && (d.window.lower - w.lower).abs() <= tolerance
+                && (d.window.upper - w.upper).abs() <= tolerance
+        };
+        match windows.iter_mut().find(|(d, _)| same(d)) {
+            Some((d, n)) => {
+                // Running mean of the bounds.
+                *n += 1;
+                let f = 1.0 / *n as f64;
+                d.window.target += (w.target - d.window.target) * f;
+                d.window.lower += (w.lower - d.window.lower) * f;
+                d.window.upper += (w.upper - d.window.upper) * f;
+                d.spectra.push(i);
+            }
+            None => windows.push((
+                DiaWindow {
+                    window: w,
+                    ms_level: s.ms_level,
+                    spectra: vec![i],
+                },
+                1,
+            )),
+        }
+    }
+    let mut out: Vec<DiaWindow> = windows.into_iter().map(|(d, _)| d).collect();
+    out.sort_by(|a, b| {
+        a.window
+            .lower
+            .total_cmp(&b.window.lower)
+            .then(a.window.upper.total_cmp(&b.window.upper))
+    });
+    out
+}
+
+/// Groups the windows of `spectra` as [`group_windows`] does and lays the
+/// run out in cycles.
+pub fn dia_cycles(spectra: &[SpectrumSummary], tolerance: f64) -> DiaCycles {
+    let windows = group_windows(spectra, tolerance);
+    let mut window_of = vec![None; spectra.len()];
+    for (w, d) in windows.iter().enumerate() {
+        for &i in &d.spectra {
+            window_of[i] = Some(w);
+        }
+    }
+    let mut order: Vec<usize> = (0..spectra.len())
+        .filter(|&i| spectra[i].retention_time.is_some())
+        .filter(|&i| spectra[i].ms_level == Some(1) || window_of[i].is_some())
+        .collect();
+    order.sort_by(|&a, &b| {
+        let rt = |i: usize| spectra[i].retention_time.unwrap_or(f64::NAN);
+        rt(a).total_cmp(&rt(b)).then(a.cmp(&b))
+    });
+
+    let mut cycles: Vec<Cycle> = Vec::new();
+    for i in order {
+        let rt = spectra[i].retention_time.unwrap_or(f64::NAN);
+        let open = |ms1: Option<usize>| Cycle {
+            ms1,
+            rt,
+            spectra: vec![None; windows.len()],
+        };
+        match window_of[i] {
+            None => cycles.push(open(Some(i))),
+            Some(w) => {
+                if cycles.last().is_none_or(|c| c.spectra[w].is_some()) {
+                    cycles.push(open(None));
+                }
+                if let Some(c) = cycles.last_mut() {
+                    c.spectra[w] = Some(i);
+                }
+            }
+        }
+    }
+    DiaCycles { windows, cycles }
+}
+
+/// Window of `windows` that isolates `precursor_mz`; of overlapping
+/// windows, the one it is most central in.
+pub fn window_for(windows: &[DiaWindow], precursor_mz: f64) -> Option<usize> {
+    let margin = |w: &IsolationWindow| (precursor_mz - w.lower).min(w.upper - precursor_mz);
+    (0..windows.len())
+        .filter(|&k| windows[k].window.contains(precursor_mz))
+        .max_by(|&a, &b| margin(&windows[a].window).total_cmp(&margin(&windows[b].window)))
+}
+
+/// A fragment ion trace.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct FragmentXic {
+    /// Window the trace was taken from; None when no window isolates the
+    /// precursor, and the trace is empty.
+    pub window: Option<usize>,
+    pub rt: Vec<f64>,
+    /// Summed intensity within `ppm` of the fragment.
+    pub intensity: Vec<f64>,
+}
+
+/// XICs of `(precursor m/z, fragment m/z)` pairs over the spectra of the
+/// window isolating each precursor, within `ppm` of the fragment and, when
+/// given, retention times in `rt`.
+pub fn fragment_xics(
+    spectra: &[SpectrumSummary],
+    windows: &[DiaWindow],
+    pairs: &[(f64, f64)],
+    ppm: f64,
+    rt: Option<(f64, f64)>,
+) -> Vec<FragmentXic> {
+    let mut out: Vec<FragmentXic> = pairs
+        .iter()
+        .map(|&(p, _)| FragmentXic {
+            window: window_for(windows, p),
+            ..FragmentXic::default()
+        })
+        .collect();
+
+    // One pass over each window's spectra serves all of its pairs.
+    for (w, d) in windows.iter().enumerate() {
+        let members: Vec<usize> = (0..pairs.len())
+            .filter(|&k| out[k].window == Some(w))
+            .collect();
+        if members.is_empty() {
+            continue;
+        }
+        for &i in &d.spectra {
+            let s = &spectra[i];
+            let Some(t) = s.retention_time else {
+                continue;
+            };
+            if rt.is_some_and(|(lo, hi)| t < lo || t > hi) {
+                continue;
+            }
+            let (mz, intensity) = match (&s.mz_array, &s.intensity_array) {
+                (Some(m), Some(y)) => (m.to_f64(), y.to_f64()),
+                _ => Default::default(),
+            };
+            let n = mz.len().min(intensity.len());
+            let sorted = mz[..n].is_sorted();
+            for &k in &members {
+                let f = pairs[k].1;
+                let tol = f * ppm * 1e-6;
+                let range = if sorted {
+                    mz[..n].partition_point(|&m| m < f - tol)
+                        ..mz[..n].partition_point(|&m| m <= f + tol)
+                } else {
+                    0..n
+                };
+                let sum = range
+                    .filter(|&j| (mz[j] - f).abs() <= tol)
+                    .fold(0.0, |acc, j| acc + intensity[j]);
+                out[k].rt.push(t);
+                out[k].intensity.push(sum);
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utilities::parse_mzml::ArrayData;
+    use crate::utilities::parse_mzml::test_spectra::spectrum;
+
+    const TARGETS: [f64; 3] = [412.5, 437.5, 462.5];
+
+    fn ms1(rt: f64) -> SpectrumSummary {
+        spectrum(1, rt, &[])
+    }
+
+    // A 25 m/z wide window around `target`, with a fragment at 200 whose
+    // intensity tells the window and the cycle apart.
+    fn ms2(rt: f64, target: f64, intensity: f64) -> SpectrumSummary {
+        SpectrumSummary {
+            isolation_window_target: Some(target),
+            isolation_window_lower: Some(12.5),
+            isolation_window_upper: Some(12.5),
+            intensity_array: Some(ArrayData::F32(vec![1.0, intensity as f32, 1.0])),
+            ..spectrum(2, rt, &[(150.0, 1.0), (200.0, intensity), (200.003, 1.0)])
+        }
+    }
+
+    // Four cycles of an MS1 scan and three windows, 0.05 min apart; the
+    // last window is missing from the third cycle.
+    fn run() -> Vec<SpectrumSummary> {
+        let mut out = Vec::new();
+        for c in 0..4 {
+            let t0 = c as f64 * 0.05;
+            out.push(ms1(t0));
+            for (w, &target) in TARGETS.iter().enumerate() {
+                if c == 2 && w == 2 {
+                    continue;
+                }
+                // Targets jitter a little from cycle to cycle.
+                let jitter = if c % 2 == 0 { 0.001 } else { -0.001 };
+                let rt = t0 + 0.01 * (w + 1) as f64;
+                out.push(ms2(rt, target + jitter, (10 * w + c) as f64));
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn windows_are_grouped() {
+        let spectra = run();
+        let windows = group_windows(&spectra, 0.01);
+        assert_eq!(windows.len(), 3);
+        assert!((windows[0].window.target - 412.5).abs() < 1e-9);
+        assert!((windows[0].window.lower - 400.0).abs() < 1e-9);
+        assert!((windows[0].window.width() - 25.0).abs() < 1e-9);
+        assert_eq!(windows[0].ms_level, Some(2));
+        assert_eq!(windows[0].spectra, [1, 5, 9, 12]);
+        assert_eq!(windows[2].spectra.len(), 3);
+        // Too tight a tolerance splits them.
+        assert_eq!(group_windows(&spectra, 1e-4).len(), 6);
+    }
+
+    #[test]
+    fn cycles_follow_the_ms1_scans() {
+        let spectra = run();
+        let dia = dia_cycles(&spectra, 0.01);
+        assert_eq!(dia.cycles.len(), 4);
+        assert_eq!(dia.cycles[1].ms1, Some(4));
+        assert_eq!(dia.cycles[1].spectra, [Some(5), Some(6), Some(7)]);
+        assert_eq!(dia.cycles[2].spectra, [Some(9), Some(10), None]);
+        assert!((dia.cycle_time() - 0.05).abs() < 1e-9);
+
+        // Without MS1 scans a cycle starts where a window comes round.
+        let msn: Vec<SpectrumSummary> = spectra
+            .into_iter()
+            .filter(|s| s.ms_level == Some(2))
+            .collect();
+        let dia = dia_cycles(&msn, 0.01);
+        assert_eq!(dia.cycles.len(), 4);
+        assert!(dia.cycles.iter().all(|c| c.ms1.is_none()));
+        assert_eq!(dia.cycles[0].spectra, [Some(0), Some(1), Some(2)]);
+        assert!(DiaCycles::default().cycle_time().is_nan());
+    }
+
+    #[test]
+    fn precursors_map_to_the_most_central_window() {
+        let window = |lower: f64, upper: f64| DiaWindow {
+            window: IsolationWindow {
+                target: 0.5 * (lower + upper),
+                lower,
+                upper,
+            },
+            ms_level: Some(2),
+            spectra: Vec::new(),
+        };
+        let windows = [window(400.0, 426.0), window(424.0, 450.0)];
+        assert_eq!(window_for(&windows, 410.0), Some(0));
+        assert_eq!(window_for(&windows, 425.5), Some(1));
+        assert_eq!(window_for(&windows, 424.5), Some(0));
+        assert_eq!(window_for(&windows, 500.0), None);
+    }
+
+    #[test]
+    fn fragment_traces_come_from_the_isolating_window() {
+        let spectra = run();
+        let windows = group_windows(&spectra, 0.01);
+        let pairs = [(440.0, 200.0), (470.0, 200.0), (600.0, 200.0)];
+        let xics = fragment_xics(&spectra, &windows, &pairs, 10.0, None);
+        assert_eq!(xics[0].window, Some(1));
+        assert_eq!(xics[0].intensity, [10.0, 11.0, 12.0, 13.0]);
+        assert!((xics[0].rt[1] - 0.07).abs() < 1e-9);
+        assert_eq!(xics[1].intensity, [20.0, 21.0, 23.0]);
+        assert_eq!(xics[2], FragmentXic::default());
+
+        let late = fragment_xics(&spectra, &windows, &pairs[..1], 10.0, Some((0.06, 0.13)));
+        assert_eq!(late[0].intensity, [11.0, 12.0]);
+        // A wider tolerance takes in the neighbouring peak.
+        let wide = fragment_xics(&spectra, &windows, &pairs[..1], 20.0, None);
+        assert_eq!(wide[0].intensity[0], 11.0);
+    }
+}
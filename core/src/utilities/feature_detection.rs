@@ -0,0 +1,519 @@
+//! Chromatographic feature detection on centroided MS1 data, in the spirit
+//! of centWave: centroids are chained across consecutive scans into regions
+//! of interest (ROIs) of stable m/z, and chromatographic peaks are then
+//! detected, measured and integrated within each ROI.
+//!
+//! Profile spectra should be centroided first, e.g. with
+//! [`ParseOptions::centroid`](super::parse_mzml::ParseOptions).
+
+use super::parse_mzml::SpectrumSummary;
+use super::peak_picking::{cwt_apexes, cwt_row, local_maxima};
+use super::stats;
+
+/// How chromatographic peaks are located within a region of interest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChromPeakMethod {
+    /// Ridge lines of a Mexican hat wavelet transform over the scales that
+    /// match `peak_width`.
+    #[default]
+    Cwt,
+    /// Maxima of the chromatogram filtered with a single Mexican hat whose
+    /// width matches the middle of `peak_width`.
+    MatchedFilter,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FeatureOptions {
+    /// m/z tolerance, in ppm, for adding a centroid to a region of interest.
+    pub ppm: f64,
+    /// Minimum number of scans with data a region of interest must span.
+    pub min_scans: usize,
+    /// Consecutive scans a region of interest may miss before it is closed.
+    pub max_gap: usize,
+    /// Centroids below this intensity are ignored.
+    pub min_intensity: f64,
+    /// Expected full width of chromatographic peaks, in minutes.
+    pub peak_width: (f64, f64),
+    /// Minimum `(height - baseline) / noise` of a reported feature.
+    pub snr: f64,
+    pub method: ChromPeakMethod,
+}
+
+impl Default for FeatureOptions {
+    fn default() -> Self {
+        FeatureOptions {
+            ppm: 10.0,
+            min_scans: 5,
+            max_gap: 1,
+            min_intensity: 0.0,
+            peak_width: (0.05, 1.0),
+            snr: 3.0,
+            method: ChromPeakMethod::default(),
+        }
+    }
+}
+
+/// One chromatographic peak. Retention times are in minutes and `area` is
+/// the trapezoidal integral of intensity over retention time.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Feature {
+    /// Intensity-weighted mean m/z over the peak.
+    pub mz: f64,
+    pub mz_min: f64,
+    pub mz_max: f64,
+    /// Retention time of the most intense scan.
+    pub rt: f64,
+    pub rt_min: f64,
+    pub rt_max: f64,
+    pub area: f64,
+    pub height: f64,
+    pub snr: f64,
+    /// Number of scans inside the peak bounds that hold a centroid.
+    pub scans: usize,
+}
+
+// A chain of centroids across scans; `scan` indexes the MS1 scan list.
+struct Roi {
+    mz_sum: f64,
+    points: Vec<RoiPoint>,
+}
+
+#[derive(Clone, Copy)]
+struct RoiPoint {
+    scan: usize,
+    mz: f64,
+    intensity: f64,
+}
+
+impl Roi {
+    fn mz(&self) -> f64 {
+        self.mz_sum / self.points.len() as f64
+    }
+
+    fn last_scan(&self) -> usize {
+        self.points.last().map_or(0, |p| p.scan)
+    }
+}
+
+/// Features of the MS1 spectra in `spectra`, sorted by m/z then retention
+/// time. Spectra without a retention time or arrays are skipped.
+pub fn find_features(spectra: &[SpectrumSummary], opts: &FeatureOptions) -> Vec<Feature> {
+    let mut scans: Vec<&SpectrumSummary> = spectra
+        .iter()
+        .filter(|s| s.ms_level == Some(1) && s.retention_time.is_some())
+        .filter(|s| s.mz_array.is_some() && s.intensity_array.is_some())
+        .collect();
+    scans.sort_by(|a, b| {
+        let (a, b) = (
+            a.retention_time.unwrap_or(0.0),
+            b.retention_time.unwrap_or(0.0),
+        );
+        a.total_cmp(&b)
+    });
+    let rts: Vec<f64> = scans.iter().filter_map(|s| s.retention_time).collect();
+
+    let mut out = Vec::new();
+    for roi in build_rois(&scans, opts) {
+        detect_in_roi(&roi, &rts, opts, &mut out);
+    }
+    out.sort_by(|a, b| a.mz.total_cmp(&b.mz).then(a.rt.total_cmp(&b.rt)));
+    out
+}
+
+fn build_rois(scans: &[&SpectrumSummary], opts: &FeatureOptions) -> Vec<Roi> {
+    // Open ROIs, kept sorted by mean m/z.
+    let mut active: Vec<Roi> = Vec::new();
+    let mut done = Vec::new();
+    let keep = |r: &Roi| r.points.len() >= opts.min_scans;
+
+    for (scan, s) in scans.iter().enumerate() {
+        let (Some(mz), Some(int)) = (&s.mz_array, &s.intensity_array) else {
+            continue;
+        };
+        for (m, y) in mz.iter().zip(int.iter()) {
+            if y < opts.min_intensity || y <= 0.0 {
+                continue;
+            }
+            let tol = m * opts.ppm * 1e-6;
+            let at = active.partition_point(|r| r.mz() < m);
+            let nearest = [at.checked_sub(1), Some(at)]
+                .into_iter()
+                .flatten()
+                .filter(|&k| k < active.len() && (active[k].mz() - m).abs() <= tol)
+                .min_by(|&a, &b| {
+                    (active[a].mz() - m)
+                        .abs()
+                        .total_cmp(&(active[b].mz() - m).abs())
+                });
+            let point = RoiPoint {
+                scan,
+                mz: m,
+                intensity: y,
+            };
+            match nearest {
+                Some(k) => {
+                    let roi = &mut active[k];
+                    match roi.points.last_mut() {
+                        // Two centroids of one scan: keep the stronger.
+                        Some(last) if last.scan == scan => {
+                            if y > last.intensity {
+                                roi.mz_sum += m - last.mz;
+                                *last = point;
+                            }
+                        }
+                        _ => {
+                            roi.mz_sum += m;
+                            roi.points.push(point);
+                        }
+                    }
+                    // The mean moved; restore the order locally.
+                    let mut k = k;
+                    while k > 0 && active[k - 1].mz() > active[k].mz() {
+                        active.swap(k - 1, k);
+                        k -= 1;
+                    }
+                    while k + 1 < active.len() && active[k + 1].mz() < active[k].mz() {
+                        active.swap(k, k + 1);
+                        k += 1;
+                    }
+                }
+                None => active.insert(
+                    at,
+                    Roi {
+                        mz_sum: m,
+                        points: vec![point],
+                    },
+                ),
+            }
+        }
+
+        let (open, closed): (Vec<Roi>, Vec<Roi>) = active
+            .into_iter()
+            .partition(|r| r.last_scan() + opts.max_gap >= scan);
+        active = open;
+        done.extend(closed.into_iter().filter(keep));
+    }
+    done.extend(active.into_iter().filter(keep));
+    done
+}
+
+fn detect_in_roi(roi: &Roi, rts: &[f64], opts: &FeatureOptions, out: &mut Vec<Feature>) {
+    // Dense chromatogram over the scans the ROI spans; missed scans are 0.
+    let first = roi.points[0].scan;
+    let last = roi.last_scan();
+    let mut chrom = vec![0.0; last - first + 1];
+    for p in &roi.points {
+        chrom[p.scan - first] = p.intensity;
+    }
+    let rt = &rts[first..=last];
+    let dt = median_step(rt);
+    if dt.is_nan() || dt <= 0.0 {
+        return;
+    }
+
+    let (baseline, noise) = baseline_noise(&chrom);
+    // Full width ~ 4 sigma, and the Mexican hat of width s matches sigma ~ s.
+    let to_scale = |w: f64| (w / dt / 4.0).max(1.0);
+    let apexes = match opts.method {
+        ChromPeakMethod::Cwt => {
+            let (lo, hi) = (to_scale(opts.peak_width.0), to_scale(opts.peak_width.1));
+            let mut scales = Vec::new();
+            let mut s = lo;
+            while s <= hi * 1.0001 {
+                scales.push(s);
+                s *= 1.5;
+            }
+            cwt_apexes(&chrom, &scales)
+        }
+        ChromPeakMethod::MatchedFilter => {
+            let s = to_scale(0.5 * (opts.peak_width.0 + opts.peak_width.1));
+            let f = cwt_row(&chrom, s);
+            local_maxima(&f)
+                .into_iter()
+                .filter(|&i| f[i] > 0.0)
+                .collect()
+        }
+    };
+
+    let smooth = gaussian_smooth(&chrom);
+    let mut last_hi = None;
+    for apex in apexes {
+        let (lo, hi) = bounds(&smooth, apex, baseline);
+        // Two ridges on one peak: keep the first.
+        if last_hi.is_some_and(|h| lo < h) {
+            continue;
+        }
+        let (top, height) = (lo..=hi).fold((apex, chrom[apex]), |b, i| {
+            if chrom[i] > b.1 { (i, chrom[i]) } else { b }
+        });
+        let snr = (height - baseline) / noise;
+        if height <= 0.0 || snr < opts.snr {
+            continue;
+        }
+
+        let mut area = 0.0;
+        for i in lo..hi {
+            area += 0.5 * (chrom[i] + chrom[i + 1]) * (rt[i + 1] - rt[i]);
+        }
+        let pts = roi
+            .points
+            .iter()
+            .filter(|p| (first + lo..=first + hi).contains(&p.scan));
+        let (mut wsum, mut mz_w, mut mz_min, mut mz_max, mut scans) =
+            (0.0, 0.0, f64::INFINITY, f64::NEG_INFINITY, 0usize);
+        for p in pts {
+            wsum += p.intensity;
+            mz_w += p.intensity * p.mz;
+            mz_min = mz_min.min(p.mz);
+            mz_max = mz_max.max(p.mz);
+            scans += 1;
+        }
+        if scans < 2 || wsum <= 0.0 {
+            continue;
+        }
+        last_hi = Some(hi);
+        out.push(Feature {
+            mz: mz_w / wsum,
+            mz_min,
+            mz_max,
+            rt: rt[top],
+            rt_min: rt[lo],
+            rt_max: rt[hi],
+            area,
+            height,
+            snr,
+            scans,
+        });
+    }
+}
+
+fn median_step(x: &[f64]) -> f64 {
+    let steps: Vec<f64> = x.windows(2).map(|w| w[1] - w[0]).collect();
+    stats::median(&steps, stats::NanPolicy::Omit).unwrap_or(f64::NAN)
+}
+
+// Baseline and noise from the lower half of the ROI intensities, which on a
+// ROI holding a peak is mostly the signal either side of it. The noise is
+// floored so a flat baseline does not make every peak infinitely strong.
+fn baseline_noise(chrom: &[f64]) -> (f64, f64) {
+    let mut v: Vec<f64> = chrom.iter().copied().filter(|&y| y > 0.0).collect();
+    v.sort_unstable_by(f64::total_cmp);
+    let lower = &v[..v.len().div_ceil(2)];
+    let baseline = stats::mean(lower);
+    let sd = stats::std(lower, 1);
+    let floor = (baseline.abs() * 1e-3).max(1.0);
+    let sd = if sd.is_finite() { sd.max(floor) } else { floor };
+    (if baseline.is_finite() { baseline } else { 0.0 }, sd)
+}
+
+// Three-point Gaussian smoothing (weights 1-2-1) used only for locating
+// peak bounds, so single-scan dips do not split a peak.
+fn gaussian_smooth(y: &[f64]) -> Vec<f64> {
+    let n = y.len();
+    (0..n)
+        .map(|i| {
+            let l = y[i.saturating_sub(1)];
+            let r = y[(i + 1).min(n - 1)];
+            0.25 * l + 0.5 * y[i] + 0.25 * r
+        })
+        .collect()
+}
+
+// Climbs from `apex` to the nearest maximum of the smoothed signal, then
+// walks down either side while it keeps falling and stays above the
+// baseline.
+fn bounds(y: &[f64], apex: usize, baseline: f64) -> (usize, usize) {
+    let mut apex = apex;
+    while apex > 0 && y[apex - 1] > y[apex] {
+        apex -= 1;
+    }
+    while apex + 1 < y.len() && y[apex + 1] > y[apex] {
+        apex += 1;
+    }
+    let mut lo = apex;
+    while lo > 0 && y[lo - 1] <= y[lo] && y[lo] > baseline {
+        lo -= 1;
+    }
+    let mut hi = apex;
+    while hi + 1 < y.len() && y[hi + 1] <= y[hi] && y[hi] > baseline {
+        hi += 1;
+    }
+    (lo, hi)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utilities::parse_mzml::test_spectra::spectrum;
+
+    // An ion eluting as a Gaussian over a baseline of 100 give or take 1.
+    #[derive(Clone, Copy)]
+    struct Ion {
+        mz: f64,
+        rt: f64,
+        sigma: f64,
+        height: f64,
+    }
+
+    const SCAN_STEP: f64 = 0.01;
+
+    // Deterministic noise in [-1, 1).
+    fn noise(seed: usize) -> f64 {
+        let mut x = (seed as u64 + 1).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        (x >> 11) as f64 / (1u64 << 52) as f64 - 1.0
+    }
+
+    // Centroided MS1 scans every 0.01 min over 3 min. Each ion has a
+    // centroid in every scan but those in `missing`, with m/z jittered by up
+    // to 1 ppm.
+    fn run(ions: &[Ion], missing: &[usize]) -> Vec<SpectrumSummary> {
+        (0..=300)
+            .map(|k| {
+                let rt = k as f64 * SCAN_STEP;
+                let mut peaks: Vec<(f64, f64)> = ions
+                    .iter()
+                    .enumerate()
+                    .filter(|_| !missing.contains(&k))
+                    .map(|(i, ion)| {
+                        let jitter = ((k * 7 + i * 3) % 5) as f64 - 2.0;
+                        let signal =
+                            ion.height * (-0.5 * ((rt - ion.rt) / ion.sigma).powi(2)).exp();
+                        (
+                            ion.mz * (1.0 + 0.5e-6 * jitter),
+                            100.0 + noise(k * ions.len() + i) + signal,
+                        )
+                    })
+                    .collect();
+                peaks.sort_by(|a, b| a.0.total_cmp(&b.0));
+                spectrum(1, rt, &peaks)
+            })
+            .collect()
+    }
+
+    fn ion(mz: f64, rt: f64, height: f64) -> Ion {
+        Ion {
+            mz,
+            rt,
+            sigma: 0.03,
+            height,
+        }
+    }
+
+    fn check(f: &Feature, ion: &Ion) {
+        let ppm = (f.mz - ion.mz).abs() / ion.mz * 1e6;
+        assert!(ppm < 1.0, "m/z {} for {}", f.mz, ion.mz);
+        assert!(f.mz_min <= f.mz && f.mz <= f.mz_max);
+        assert!((f.rt - ion.rt).abs() < 1e-9, "rt {} for {}", f.rt, ion.rt);
+        // The bounds stop where the tails sink into the noise.
+        for edge in [ion.rt - f.rt_min, f.rt_max - ion.rt] {
+            assert!((3.0 * ion.sigma..=8.0 * ion.sigma).contains(&edge), "{f:?}");
+        }
+        // The Gaussian integral plus the baseline under the peak.
+        let area = ion.height * ion.sigma * (2.0 * std::f64::consts::PI).sqrt()
+            + 100.0 * (f.rt_max - f.rt_min);
+        assert!(
+            (f.area - area).abs() < 0.01 * area,
+            "area {} for {area}",
+            f.area
+        );
+        assert!((f.height - ion.height - 100.0).abs() <= 1.0, "{f:?}");
+        assert!(f.snr > 100.0, "{f:?}");
+        assert_eq!(
+            f.scans,
+            ((f.rt_max - f.rt_min) / SCAN_STEP).round() as usize + 1
+        );
+    }
+
+    #[test]
+    fn gaussian_peaks_are_found_and_measured() {
+        let ions = [ion(300.1, 1.0, 1e5), ion(450.2, 2.0, 5e4)];
+        let spectra = run(&ions, &[]);
+        for method in [ChromPeakMethod::Cwt, ChromPeakMethod::MatchedFilter] {
+            let opts = FeatureOptions {
+                method,
+                ..FeatureOptions::default()
+            };
+            let features = find_features(&spectra, &opts);
+            assert_eq!(features.len(), 2, "{method:?}: {features:?}");
+            for (f, ion) in features.iter().zip(&ions) {
+                check(f, ion);
+            }
+        }
+    }
+
+    #[test]
+    fn snr_threshold_is_the_reported_snr() {
+        let spectra = run(&[ion(300.1, 1.0, 1e4)], &[]);
+        let found = find_features(&spectra, &FeatureOptions::default());
+        assert_eq!(found.len(), 1);
+        let snr = found[0].snr;
+        let at = |snr| {
+            let opts = FeatureOptions {
+                snr,
+                ..FeatureOptions::default()
+            };
+            find_features(&spectra, &opts).len()
+        };
+        assert_eq!(at(snr * 0.99), 1);
+        assert_eq!(at(snr * 1.01), 0);
+    }
+
+    #[test]
+    fn missing_scans_within_the_gap_tolerance() {
+        let ions = [ion(300.1, 1.0, 1e5)];
+        // Scan 97 is 0.03 min, one sigma, before the apex.
+        let spectra = run(&ions, &[97]);
+        let features = find_features(&spectra, &FeatureOptions::default());
+        assert_eq!(features.len(), 1, "{features:?}");
+        let f = &features[0];
+        assert!(f.rt_min < 0.97 && f.rt_max > 0.97);
+        assert_eq!(
+            f.scans,
+            ((f.rt_max - f.rt_min) / SCAN_STEP).round() as usize
+        );
+        assert!((f.rt - 1.0).abs() < 1e-9);
+
+        // Without a tolerance the region of interest is cut at the gap and
+        // no feature spans it.
+        let strict = FeatureOptions {
+            max_gap: 0,
+            ..FeatureOptions::default()
+        };
+        let features = find_features(&spectra, &strict);
+        assert!(!features.is_empty());
+        assert!(
+            features.iter().all(|f| f.rt_max < 0.97 || f.rt_min > 0.97),
+            "{features:?}"
+        );
+    }
+
+    #[test]
+    fn close_mz_are_kept_apart() {
+        // 20 ppm apart, co-eluting, with a 10 ppm tolerance.
+        let ions = [ion(600.0, 1.5, 8e4), ion(600.012, 1.5, 3e4)];
+        let features = find_features(&run(&ions, &[]), &FeatureOptions::default());
+        assert_eq!(features.len(), 2, "{features:?}");
+        for (f, ion) in features.iter().zip(&ions) {
+            check(f, ion);
+            assert!(f.mz_max - f.mz_min < 0.002);
+        }
+    }
+
+    #[test]
+    fn other_spectra_are_ignored() {
+        let mut spectra = run(&[ion(300.1, 1.0, 1e5)], &[]);
+        let baseline = find_features(&spectra, &FeatureOptions::default());
+        spectra.push(spectrum(2, 1.0, &[(300.1, 1e7)]));
+        spectra.push(SpectrumSummary {
+            retention_time: None,
+            ..spectrum(1, 1.0, &[(300.1, 1e7)])
+        });
+        assert_eq!(
+            find_features(&spectra, &FeatureOptions::default()),
+            baseline
+        );
+    }
+}
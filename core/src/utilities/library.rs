@@ -0,0 +1,604 @@
+//! Spectral library search: MSP and MGF readers, a library indexed by
+//! precursor m/z and optionally by fragment m/z, and search of parsed MS2
+//! spectra against it.
+//!
+//! Library and query spectra are filtered alike with the library's
+//! [`FilterOptions`], then candidates within the precursor window (and,
+//! with a fragment index, sharing enough fragments with the query) are
+//! scored with [`similarity`].
+
+use std::collections::HashMap;
+
+use super::parse_mzml::SpectrumSummary;
+use super::similarity::{FilterOptions, Peaks, SimilarityOptions, similarity};
+
+/// One library record as read, with its unfiltered peaks.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct LibrarySpectrum {
+    pub name: String,
+    pub precursor_mz: Option<f64>,
+    /// Adduct, such as `[M+H]+`.
+    pub precursor_type: Option<String>,
+    pub charge: Option<i32>,
+    /// Every other field in file order, keys as written.
+    pub metadata: Vec<(String, String)>,
+    pub mz: Vec<f64>,
+    pub intensity: Vec<f64>,
+}
+
+impl LibrarySpectrum {
+    /// Field `key`, compared ignoring case, `_` and spaces; `name` and the
+    /// precursor fields are found under their usual MSP and MGF keys.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        let key = normalize_key(key);
+        match key.as_str() {
+            "name" | "title" => return Some(&self.name),
+            "precursortype" | "adduct" => return self.precursor_type.as_deref(),
+            _ => {}
+        }
+        self.metadata
+            .iter()
+            .find(|(k, _)| normalize_key(k) == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    // Takes over the fields with a meaning of their own.
+    fn set(&mut self, key: &str, value: &str) {
+        let value = value.trim();
+        match normalize_key(key).as_str() {
+            "name" | "title" if self.name.is_empty() => self.name = value.to_string(),
+            "precursormz" | "pepmass" => {
+                // PEPMASS may be followed by the precursor intensity.
+                self.precursor_mz = value.split_whitespace().next().and_then(|v| v.parse().ok())
+            }
+            "precursortype" | "adduct" => self.precursor_type = Some(value.to_string()),
+            "charge" => self.charge = parse_charge(value),
+            _ => self
+                .metadata
+                .push((key.trim().to_string(), value.to_string())),
+        }
+    }
+}
+
+fn normalize_key(k: &str) -> String {
+    k.chars()
+        .filter(|c| !matches!(c, '_' | ' ' | '-'))
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+// `1`, `+1`, `1+`, `2-`.
+fn parse_charge(s: &str) -> Option<i32> {
+    let s = s.split([',', ' ']).next()?.trim();
+    if let Some(n) = s.strip_suffix('+') {
+        return n.parse().ok();
+    }
+    if let Some(n) = s.strip_suffix('-') {
+        return n.parse::<i32>().ok().map(|z| -z);
+    }
+    s.parse().ok()
+}
+
+// Numbers of a peak line, read in pairs. Quoted annotations are skipped
+// and `:`, `;`, `,` and whitespace all separate values.
+fn push_peaks(line: &str, spec: &mut LibrarySpectrum) {
+    let mut values = Vec::new();
+    for (k, part) in line.split('"').enumerate() {
+        if k % 2 == 1 {
+            continue;
+        }
+        for t in part.split(|c: char| c.is_whitespace() || matches!(c, ':' | ';' | ',')) {
+            if let Ok(v) = t.parse::<f64>() {
+                values.push(v);
+            }
+        }
+    }
+    for pair in values.chunks_exact(2) {
+        spec.mz.push(pair[0]);
+        spec.intensity.push(pair[1]);
+    }
+}
+
+/// Reads an MSP library (NIST, MassBank, MoNA): `Key: value` lines, a
+/// `Num Peaks` line and the peaks, records separated by blank lines.
+pub fn parse_msp(text: &str) -> Result<Vec<LibrarySpectrum>, String> {
+    let mut out = Vec::new();
+    let mut spec = LibrarySpectrum::default();
+    let mut in_peaks = false;
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            if in_peaks || !spec.name.is_empty() {
+                out.push(std::mem::take(&mut spec));
+            }
+            in_peaks = false;
+            continue;
+        }
+        if line.starts_with(|c: char| c.is_ascii_digit() || c == '.') {
+            push_peaks(line, &mut spec);
+            continue;
+        }
+        if in_peaks {
+            // A record starting without a blank line before it.
+            out.push(std::mem::take(&mut spec));
+            in_peaks = false;
+        }
+        let Some((key, value)) = line.split_once(':') else {
+            return Err(format!("line {}: expected 'key: value'", n + 1));
+        };
+        if normalize_key(key) == "numpeaks" {
+            in_peaks = true;
+        } else {
+            spec.set(key, value);
+        }
+    }
+    if in_peaks || !spec.name.is_empty() {
+        out.push(spec);
+    }
+    Ok(out)
+}
+
+/// Reads an MGF library (GNPS): records between `BEGIN IONS` and
+/// `END IONS` with `KEY=value` lines followed by the peaks.
+pub fn parse_mgf(text: &str) -> Result<Vec<LibrarySpectrum>, String> {
+    let mut out = Vec::new();
+    let mut spec: Option<LibrarySpectrum> = None;
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(['#', ';', '!', '/']) {
+            continue;
+        }
+        if line.eq_ignore_ascii_case("BEGIN IONS") {
+            spec = Some(LibrarySpectrum::default());
+            continue;
+        }
+        if line.eq_ignore_ascii_case("END IONS") {
+            out.push(
+                spec.take()
+                    .ok_or(format!("line {}: END IONS without BEGIN IONS", n + 1))?,
+            );
+            continue;
+        }
+        let Some(s) = spec.as_mut() else {
+            // Global parameters before the first record.
+            continue;
+        };
+        if line.starts_with(|c: char| c.is_ascii_digit() || c == '.') {
+            push_peaks(line, s);
+        } else if let Some((key, value)) = line.split_once('=') {
+            s.set(key, value);
+        } else {
+            return Err(format!("line {}: expected 'KEY=value'", n + 1));
+        }
+    }
+    if spec.is_some() {
+        return Err("missing END IONS".to_string());
+    }
+    Ok(out)
+}
+
+/// Reads MGF when the text has a `BEGIN IONS` line, MSP otherwise.
+pub fn parse_library(text: &str) -> Result<Vec<LibrarySpectrum>, String> {
+    if text
+        .lines()
+        .any(|l| l.trim().eq_ignore_ascii_case("BEGIN IONS"))
+    {
+        parse_mgf(text)
+    } else {
+        parse_msp(text)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LibraryOptions {
+    /// Applied to library and query spectra alike.
+    pub filter: FilterOptions,
+    /// Build the fragment index used by `SearchOptions::min_shared_fragments`.
+    pub fragment_index: bool,
+    /// Width of the fragment index bins, in Da.
+    pub fragment_bin: f64,
+}
+
+impl Default for LibraryOptions {
+    fn default() -> Self {
+        LibraryOptions {
+            filter: FilterOptions::default(),
+            fragment_index: false,
+            fragment_bin: 0.01,
+        }
+    }
+}
+
+/// A library held in memory with its filtered peaks and indexes.
+#[derive(Debug, Clone)]
+pub struct SpectralLibrary {
+    pub spectra: Vec<LibrarySpectrum>,
+    pub options: LibraryOptions,
+    peaks: Vec<Peaks>,
+    // (precursor m/z, entry), sorted; entries without precursor are left out.
+    by_precursor: Vec<(f64, usize)>,
+    // Fragment bin -> entries with a peak in it.
+    fragments: Option<HashMap<i64, Vec<usize>>>,
+}
+
+impl SpectralLibrary {
+    pub fn new(spectra: Vec<LibrarySpectrum>, options: LibraryOptions) -> SpectralLibrary {
+        let peaks: Vec<Peaks> = spectra
+            .iter()
+            .map(|s| Peaks::new(&s.mz, &s.intensity, s.precursor_mz, &options.filter))
+            .collect();
+        let mut by_precursor: Vec<(f64, usize)> = spectra
+            .iter()
+            .enumerate()
+            .filter_map(|(i, s)| Some((s.precursor_mz.filter(|m| m.is_finite())?, i)))
+            .collect();
+        by_precursor.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let fragments = (options.fragment_index && options.fragment_bin > 0.0).then(|| {
+            let mut index: HashMap<i64, Vec<usize>> = HashMap::new();
+            for (i, p) in peaks.iter().enumerate() {
+                for &m in &p.mz {
+                    let bins = index
+                        .entry((m / options.fragment_bin).floor() as i64)
+                        .or_default();
+                    if bins.last() != Some(&i) {
+                        bins.push(i);
+                    }
+                }
+            }
+            index
+        });
+
+        SpectralLibrary {
+            spectra,
+            options,
+            peaks,
+            by_precursor,
+            fragments,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.spectra.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.spectra.is_empty()
+    }
+
+    /// Filtered peaks of entry `i`, as scored.
+    pub fn peaks(&self, i: usize) -> &Peaks {
+        &self.peaks[i]
+    }
+
+    /// Best hits for `query`, which must already be filtered with
+    /// `options.filter` (see [`Peaks::from_spectrum`]).
+    pub fn search(&self, query: &Peaks, opts: &SearchOptions) -> Vec<LibraryHit> {
+        let mut candidates: Vec<usize> = if opts.precursor_ppm > 0.0 {
+            let Some(p) = query.precursor_mz else {
+                return Vec::new();
+            };
+            let tol = p * opts.precursor_ppm * 1e-6;
+            let lo = self.by_precursor.partition_point(|e| e.0 < p - tol);
+            let hi = self.by_precursor.partition_point(|e| e.0 <= p + tol);
+            self.by_precursor[lo..hi].iter().map(|e| e.1).collect()
+        } else {
+            (0..self.len()).collect()
+        };
+        if let Some(index) = &self.fragments
+            && opts.min_shared_fragments > 0
+        {
+            let shared = self.shared_fragments(index, query, &opts.similarity);
+            candidates.retain(|i| {
+                shared
+                    .get(i)
+                    .is_some_and(|&n| n >= opts.min_shared_fragments)
+            });
+        }
+
+        let mut hits: Vec<LibraryHit> = candidates
+            .into_iter()
+            .filter_map(|i| {
+                let s = similarity(query, &self.peaks[i], &opts.similarity);
+                if s.score < opts.min_score || s.matched_peaks < opts.min_matched_peaks {
+                    return None;
+                }
+                let precursor_ppm = query
+                    .precursor_mz
+                    .zip(self.spectra[i].precursor_mz)
+                    .map(|(q, l)| (q - l) / l * 1e6);
+                Some(LibraryHit {
+                    entry: i,
+                    score: s.score,
+                    matched_peaks: s.matched_peaks,
+                    precursor_ppm,
+                })
+            })
+            .collect();
+        hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+        hits.truncate(opts.top_n);
+        hits
+    }
+
+    // Per entry, how many query peaks fall in (or next to) one of its bins.
+    fn shared_fragments(
+        &self,
+        index: &HashMap<i64, Vec<usize>>,
+        query: &Peaks,
+        opts: &SimilarityOptions,
+    ) -> HashMap<usize, usize> {
+        let bin = self.options.fragment_bin;
+        let mut shared: HashMap<usize, usize> = HashMap::new();
+        let mut seen: Vec<usize> = Vec::new();
+        for &m in &query.mz {
+            let tol = opts.mz_tolerance.max(m * opts.ppm * 1e-6);
+            let (lo, hi) = (
+                ((m - tol) / bin).floor() as i64,
+                ((m + tol) / bin).floor() as i64,
+            );
+            seen.clear();
+            for b in lo..=hi {
+                for &i in index.get(&b).into_iter().flatten() {
+                    if !seen.contains(&i) {
+                        seen.push(i);
+                        *shared.entry(i).or_insert(0) += 1;
+                    }
+                }
+            }
+        }
+        shared
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SearchOptions {
+    pub similarity: SimilarityOptions,
+    /// Precursor window in ppm. A value of 0 or less searches every entry
+    /// (open search); otherwise queries without precursor m/z find nothing.
+    pub precursor_ppm: f64,
+    pub min_score: f64,
+    pub min_matched_peaks: usize,
+    /// Hits kept per query.
+    pub top_n: usize,
+    /// With a fragment index, only entries sharing this many fragments with
+    /// the query are scored; 0 scores every candidate.
+    pub min_shared_fragments: usize,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        SearchOptions {
+            similarity: SimilarityOptions::default(),
+            precursor_ppm: 10.0,
+            min_score: 0.5,
+            min_matched_peaks: 3,
+            top_n: 5,
+            min_shared_fragments: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LibraryHit {
+    /// Index into `SpectralLibrary::spectra`.
+    pub entry: usize,
+    pub score: f64,
+    pub matched_peaks: usize,
+    /// `(query - library) / library` precursor m/z, in ppm; `None` when
+    /// either is unknown.
+    pub precursor_ppm: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LibraryMatch {
+    /// Index into the searched spectra.
+    pub spectrum: usize,
+    /// 0 for the best hit of the spectrum.
+    pub rank: usize,
+    pub hit: LibraryHit,
+}
+
+/// Searches every MSn spectrum of `spectra` against `library`; matches are
+/// ordered by spectrum, then rank.
+pub fn search_spectra(
+    library: &SpectralLibrary,
+    spectra: &[SpectrumSummary],
+    opts: &SearchOptions,
+) -> Vec<LibraryMatch> {
+    let mut out = Vec::new();
+    for (i, s) in spectra.iter().enumerate() {
+        if s.ms_level.is_none_or(|l| l < 2) {
+            continue;
+        }
+        let Some(query) = Peaks::from_spectrum(s, &library.options.filter) else {
+            continue;
+        };
+        for (rank, hit) in library.search(&query, opts).into_iter().enumerate() {
+            out.push(LibraryMatch {
+                spectrum: i,
+                rank,
+                hit,
+            });
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utilities::parse_mzml::ArrayData;
+
+    const MSP: &str = "\
+NAME: Caffeine
+PRECURSORMZ: 195.0877
+Precursor_type: [M+H]+
+Charge: 1+
+Formula: C8H10N4O2
+Num Peaks: 3
+110.0713 20 \"frag a\"
+138.0662 100
+195.0877:50; 42.0 5
+Name: Theobromine
+PrecursorMZ: 181.0720
+Num Peaks: 2
+138.0662 100
+163.0614 30
+";
+
+    const MGF: &str = "\
+# comment
+CHARGE=1+
+BEGIN IONS
+TITLE=Caffeine
+PEPMASS=195.0877 12000
+CHARGE=2-
+SMILES=CN1C=NC2=C1C(=O)N(C(=O)N2C)C
+110.0713 20
+138.0662 100
+END IONS
+";
+
+    fn entry(name: &str, precursor_mz: f64, peaks: &[(f64, f64)]) -> LibrarySpectrum {
+        let (mz, intensity) = peaks.iter().copied().unzip();
+        LibrarySpectrum {
+            name: name.to_string(),
+            precursor_mz: Some(precursor_mz),
+            mz,
+            intensity,
+            ..LibrarySpectrum::default()
+        }
+    }
+
+    fn library(fragment_index: bool) -> SpectralLibrary {
+        let spectra = vec![
+            entry("a", 300.0, &[(100.0, 10.0), (150.0, 5.0), (200.0, 8.0)]),
+            entry(
+                "b",
+                300.001,
+                &[(100.0, 10.0), (150.0, 5.0), (200.0, 2.0), (250.0, 8.0)],
+            ),
+            entry("c", 500.0, &[(100.0, 10.0), (150.0, 5.0), (200.0, 8.0)]),
+        ];
+        let options = LibraryOptions {
+            fragment_index,
+            ..LibraryOptions::default()
+        };
+        SpectralLibrary::new(spectra, options)
+    }
+
+    #[test]
+    fn msp_records_and_peaks_are_read() {
+        let lib = parse_msp(MSP).unwrap();
+        assert_eq!(lib.len(), 2);
+        let caffeine = &lib[0];
+        assert_eq!(caffeine.name, "Caffeine");
+        assert_eq!(caffeine.precursor_mz, Some(195.0877));
+        assert_eq!(caffeine.precursor_type.as_deref(), Some("[M+H]+"));
+        assert_eq!(caffeine.charge, Some(1));
+        assert_eq!(caffeine.mz, [110.0713, 138.0662, 195.0877, 42.0]);
+        assert_eq!(caffeine.intensity, [20.0, 100.0, 50.0, 5.0]);
+        assert_eq!(caffeine.get("formula"), Some("C8H10N4O2"));
+        assert_eq!(caffeine.get("precursor type"), Some("[M+H]+"));
+        assert_eq!(lib[1].name, "Theobromine");
+        assert_eq!(lib[1].mz, [138.0662, 163.0614]);
+
+        assert!(parse_msp("Name: x\nno separator\n").is_err());
+    }
+
+    #[test]
+    fn mgf_records_are_read() {
+        let lib = parse_mgf(MGF).unwrap();
+        assert_eq!(lib.len(), 1);
+        let s = &lib[0];
+        assert_eq!(s.name, "Caffeine");
+        assert_eq!(s.precursor_mz, Some(195.0877));
+        assert_eq!(s.charge, Some(-2));
+        assert_eq!(s.get("smiles"), Some("CN1C=NC2=C1C(=O)N(C(=O)N2C)C"));
+        assert_eq!(s.mz, [110.0713, 138.0662]);
+
+        assert!(parse_mgf("BEGIN IONS\nTITLE=x\n").is_err());
+        assert!(parse_mgf("END IONS\n").is_err());
+        assert_eq!(parse_library(MGF).unwrap(), lib);
+        assert_eq!(parse_library(MSP).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn search_uses_the_precursor_window() {
+        let lib = library(false);
+        let query = Peaks::new(
+            &[100.0, 150.0, 200.0],
+            &[10.0, 5.0, 8.0],
+            Some(300.0),
+            &lib.options.filter,
+        );
+        let hits = lib.search(&query, &SearchOptions::default());
+        assert_eq!(hits.len(), 2);
+        assert_eq!(hits[0].entry, 0);
+        assert!((hits[0].score - 1.0).abs() < 1e-12);
+        assert_eq!(hits[0].matched_peaks, 3);
+        assert_eq!(hits[0].precursor_ppm, Some(0.0));
+        assert_eq!(hits[1].entry, 1);
+        assert!(hits[1].score < 1.0);
+        assert!((hits[1].precursor_ppm.unwrap() + 3.333).abs() < 1e-3);
+
+        let open = SearchOptions {
+            precursor_ppm: 0.0,
+            ..SearchOptions::default()
+        };
+        let entries: Vec<usize> = lib.search(&query, &open).iter().map(|h| h.entry).collect();
+        assert_eq!(entries.len(), 3);
+        assert!(entries.contains(&2));
+
+        let no_precursor = Peaks {
+            precursor_mz: None,
+            ..query.clone()
+        };
+        assert!(
+            lib.search(&no_precursor, &SearchOptions::default())
+                .is_empty()
+        );
+        let top = SearchOptions {
+            top_n: 1,
+            ..SearchOptions::default()
+        };
+        assert_eq!(lib.search(&query, &top).len(), 1);
+    }
+
+    #[test]
+    fn fragment_index_prefilters_candidates() {
+        let lib = library(true);
+        let query = Peaks::new(
+            &[100.0, 150.0, 200.0, 250.0],
+            &[10.0, 5.0, 2.0, 8.0],
+            Some(300.0),
+            &lib.options.filter,
+        );
+        let opts = SearchOptions {
+            min_shared_fragments: 4,
+            min_matched_peaks: 1,
+            min_score: 0.0,
+            ..SearchOptions::default()
+        };
+        let hits = lib.search(&query, &opts);
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].entry, 1);
+    }
+
+    #[test]
+    fn only_msn_spectra_are_searched() {
+        let lib = library(false);
+        let spectrum = |ms_level| SpectrumSummary {
+            ms_level: Some(ms_level),
+            precursor_mz: Some(300.0),
+            mz_array: Some(ArrayData::F64(vec![100.0, 150.0, 200.0])),
+            intensity_array: Some(ArrayData::F32(vec![10.0, 5.0, 8.0])),
+            ..SpectrumSummary::default()
+        };
+        let matches = search_spectra(&lib, &[spectrum(1), spectrum(2)], &SearchOptions::default());
+        assert_eq!(matches.len(), 2);
+        assert!(matches.iter().all(|m| m.spectrum == 1));
+        assert_eq!((matches[0].rank, matches[0].hit.entry), (0, 0));
+        assert_eq!(matches[1].rank, 1);
+    }
+}
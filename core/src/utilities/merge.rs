@@ -0,0 +1,350 @@
+//! Merging of several scans into one consensus spectrum, to lift weak MS1
+//! and MS2 signals above single-scan noise.
+//!
+//! The peaks of all merged spectra are pooled, sorted by m/z and clustered
+//! greedily: a peak joins the open cluster while within tolerance of its
+//! intensity-weighted m/z. Each cluster becomes one peak whose intensity is
+//! the sum or the mean, over the merged spectra, of the intensity each
+//! spectrum contributes. Spectra are chosen by index, by retention time
+//! window, or grouped by precursor with [`group_by_precursor`].
+
+use super::parse_mzml::{ArrayData, SpectrumSummary};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MergeIntensity {
+    /// Mean over the merged spectra, counting those without the peak as 0.
+    #[default]
+    Mean,
+    Sum,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MergeOptions {
+    /// Peaks cluster when within this many ppm or `mz_tolerance` Da,
+    /// whichever is wider.
+    pub ppm: f64,
+    pub mz_tolerance: f64,
+    pub intensity: MergeIntensity,
+    /// Fraction of the merged spectra a peak must be found in to be kept.
+    pub min_occurrence: f64,
+}
+
+impl Default for MergeOptions {
+    fn default() -> Self {
+        MergeOptions {
+            ppm: 10.0,
+            mz_tolerance: 0.0,
+            intensity: MergeIntensity::Mean,
+            min_occurrence: 0.0,
+        }
+    }
+}
+
+/// A consensus spectrum, sorted by m/z.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct MergedSpectrum {
+    /// Indices of the merged spectra, in the order given.
+    pub spectra: Vec<usize>,
+    pub mz: Vec<f64>,
+    pub intensity: Vec<f64>,
+    /// Number of merged spectra each peak was found in.
+    pub occurrence: Vec<usize>,
+    pub ms_level: Option<u32>,
+    /// Mean retention time of the merged spectra and their range.
+    pub retention_time: Option<f64>,
+    pub rt_min: Option<f64>,
+    pub rt_max: Option<f64>,
+    /// Mean precursor m/z of the spectra that have one.
+    pub precursor_mz: Option<f64>,
+    /// Most common precursor charge of the spectra that have one.
+    pub precursor_charge: Option<u32>,
+}
+
+impl MergedSpectrum {
+    /// The consensus as a centroid spectrum, so it can go wherever a parsed
+    /// one does. It takes the index and polarity of the first merged
+    /// spectrum and an id listing the merged ones.
+    pub fn to_summary(&self, spectra: &[SpectrumSummary]) -> SpectrumSummary {
+        let first = self.spectra.first().and_then(|&i| spectra.get(i));
+        let ids: Vec<String> = self
+            .spectra
+            .iter()
+            .filter_map(|&i| spectra.get(i))
+            .map(|s| s.id.clone())
+            .collect();
+        let base =
+            (0..self.mz.len()).max_by(|&a, &b| self.intensity[a].total_cmp(&self.intensity[b]));
+        SpectrumSummary {
+            index: first.map_or(0, |s| s.index),
+            id: format!("merged={}", ids.join(",")),
+            array_length: self.mz.len(),
+            ms_level: self.ms_level,
+            scan_type: first.and_then(|s| s.scan_type.clone()),
+            polarity: first.and_then(|s| s.polarity.clone()),
+            spectrum_type: Some("centroid".to_string()),
+            retention_time: self.retention_time,
+            scan_window_lower_limit: first.and_then(|s| s.scan_window_lower_limit),
+            scan_window_upper_limit: first.and_then(|s| s.scan_window_upper_limit),
+            total_ion_current: Some(self.intensity.iter().fold(0.0, |s, y| s + y)),
+            base_peak_intensity: base.map(|k| self.intensity[k]),
+            base_peak_mz: base.map(|k| self.mz[k]),
+            precursor_mz: self.precursor_mz,
+            precursor_charge: self.precursor_charge,
+            isolation_window_target: first.and_then(|s| s.isolation_window_target),
+            isolation_window_lower: first.and_then(|s| s.isolation_window_lower),
+            isolation_window_upper: first.and_then(|s| s.isolation_window_upper),
+            ion_mobility: None,
+            ion_mobility_type: None,
+            mz_array: Some(ArrayData::F64(self.mz.clone())),
+            intensity_array: Some(ArrayData::F64(self.intensity.clone())),
+            mobility_array: None,
+        }
+    }
+}
+
+/// Merges the spectra at `indices`, which must share an MS level.
+pub fn merge_spectra(
+    spectra: &[SpectrumSummary],
+    indices: &[usize],
+    opts: &MergeOptions,
+) -> Result<MergedSpectrum, String> {
+    if indices.is_empty() {
+        return Err("no spectra to merge".to_string());
+    }
+    if let Some(&i) = indices.iter().find(|&&i| i >= spectra.len()) {
+        return Err(format!("no spectrum {i}"));
+    }
+    let ms_level = spectra[indices[0]].ms_level;
+    if indices.iter().any(|&i| spectra[i].ms_level != ms_level) {
+        return Err("spectra of different MS levels".to_string());
+    }
+
+    // (m/z, intensity, position of the spectrum in `indices`)
+    let mut peaks: Vec<(f64, f64, usize)> = Vec::new();
+    for (k, &i) in indices.iter().enumerate() {
+        let s = &spectra[i];
+        let (Some(mz), Some(intensity)) = (&s.mz_array, &s.intensity_array) else {
+            continue;
+        };
+        peaks.extend(
+            mz.iter()
+                .zip(intensity.iter())
+                .filter(|&(m, y)| m.is_finite() && y.is_finite() && y > 0.0)
+                .map(|(m, y)| (m, y, k)),
+        );
+    }
+    peaks.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let n = indices.len();
+    let min_count = ((opts.min_occurrence.clamp(0.0, 1.0) * n as f64) - 1e-9).ceil() as usize;
+    let mut out = MergedSpectrum {
+        spectra: indices.to_vec(),
+        ms_level,
+        ..MergedSpectrum::default()
+    };
+    let mut seen = vec![false; n];
+    let mut start = 0;
+    while start < peaks.len() {
+        let (mut weighted, mut total) = (peaks[start].0 * peaks[start].1, peaks[start].1);
+        let mut end = start + 1;
+        while end < peaks.len() {
+            let centre = weighted / total;
+            let tol = opts.mz_tolerance.max(centre * opts.ppm * 1e-6);
+            if peaks[end].0 - centre > tol {
+                break;
+            }
+            weighted += peaks[end].0 * peaks[end].1;
+            total += peaks[end].1;
+            end += 1;
+        }
+        seen.fill(false);
+        for p in &peaks[start..end] {
+            seen[p.2] = true;
+        }
+        let count = seen.iter().filter(|&&s| s).count();
+        if count >= min_count.max(1) {
+            out.mz.push(weighted / total);
+            out.intensity.push(match opts.intensity {
+                MergeIntensity::Sum => total,
+                MergeIntensity::Mean => total / n as f64,
+            });
+            out.occurrence.push(count);
+        }
+        start = end;
+    }
+
+    let rts: Vec<f64> = indices
+        .iter()
+        .filter_map(|&i| spectra[i].retention_time)
+        .collect();
+    if !rts.is_empty() {
+        out.retention_time = Some(rts.iter().sum::<f64>() / rts.len() as f64);
+        out.rt_min = rts.iter().copied().reduce(f64::min);
+        out.rt_max = rts.iter().copied().reduce(f64::max);
+    }
+    let precursors: Vec<f64> = indices
+        .iter()
+        .filter_map(|&i| spectra[i].precursor_mz)
+        .collect();
+    if !precursors.is_empty() {
+        out.precursor_mz = Some(precursors.iter().sum::<f64>() / precursors.len() as f64);
+    }
+    let mut charges: Vec<u32> = indices
+        .iter()
+        .filter_map(|&i| spectra[i].precursor_charge)
+        .collect();
+    charges.sort_unstable();
+    out.precursor_charge = charges
+        .chunk_by(|a, b| a == b)
+        .max_by_key(|c| c.len())
+        .map(|c| c[0]);
+    Ok(out)
+}
+
+/// Indices of the spectra with retention time in `[rt_min, rt_max]`, of
+/// MS level `ms_level` when given.
+pub fn spectra_in_rt_window(
+    spectra: &[SpectrumSummary],
+    rt_min: f64,
+    rt_max: f64,
+    ms_level: Option<u32>,
+) -> Vec<usize> {
+    spectra
+        .iter()
+        .enumerate()
+        .filter(|(_, s)| ms_level.is_none_or(|l| s.ms_level == Some(l)))
+        .filter(|(_, s)| s.retention_time.is_some_and(|t| t >= rt_min && t <= rt_max))
+        .map(|(i, _)| i)
+        .collect()
+}
+
+/// Groups the MSn spectra that have a precursor m/z: same MS level and
+/// charge, precursor m/z chained within `ppm` of each other, split where
+/// consecutive scans are more than `rt_gap` minutes apart (0 never
+/// splits). Groups are ordered by precursor m/z, their members by retention
+/// time.
+pub fn group_by_precursor(spectra: &[SpectrumSummary], ppm: f64, rt_gap: f64) -> Vec<Vec<usize>> {
+    let mut keyed: Vec<(Option<u32>, Option<u32>, f64, usize)> = spectra
+        .iter()
+        .enumerate()
+        .filter(|(_, s)| s.ms_level.is_some_and(|l| l >= 2))
+        .filter_map(|(i, s)| Some((s.ms_level, s.precursor_charge, s.precursor_mz?, i)))
+        .collect();
+    keyed.sort_by(|a, b| (a.0, a.1).cmp(&(b.0, b.1)).then(a.2.total_cmp(&b.2)));
+
+    let rt = |i: usize| spectra[i].retention_time.unwrap_or(f64::NAN);
+    let mut groups = Vec::new();
+    let mut start = 0;
+    while start < keyed.len() {
+        let mut end = start + 1;
+        while end < keyed.len()
+            && (keyed[end].0, keyed[end].1) == (keyed[start].0, keyed[start].1)
+            && keyed[end].2 - keyed[end - 1].2 <= keyed[end - 1].2 * ppm * 1e-6
+        {
+            end += 1;
+        }
+        let mut members: Vec<usize> = keyed[start..end].iter().map(|k| k.3).collect();
+        members.sort_by(|&a, &b| rt(a).total_cmp(&rt(b)));
+        let mut first = 0;
+        for k in 1..=members.len() {
+            if k == members.len() || (rt_gap > 0.0 && rt(members[k]) - rt(members[k - 1]) > rt_gap)
+            {
+                groups.push(members[first..k].to_vec());
+                first = k;
+            }
+        }
+        start = end;
+    }
+    groups.sort_by(|a, b| {
+        let p = |g: &Vec<usize>| spectra[g[0]].precursor_mz.unwrap_or(0.0);
+        p(a).total_cmp(&p(b))
+    });
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utilities::parse_mzml::test_spectra::spectrum;
+
+    fn msn(precursor_mz: f64, rt: f64) -> SpectrumSummary {
+        SpectrumSummary {
+            precursor_mz: Some(precursor_mz),
+            precursor_charge: Some(1),
+            ..spectrum(2, rt, &[])
+        }
+    }
+
+    #[test]
+    fn peaks_within_tolerance_are_merged() {
+        let spectra = [
+            spectrum(1, 1.0, &[(100.0, 10.0), (200.0, 4.0)]),
+            spectrum(1, 2.0, &[(100.0005, 30.0)]),
+        ];
+        let m = merge_spectra(&spectra, &[0, 1], &MergeOptions::default()).unwrap();
+        assert_eq!(m.mz.len(), 2);
+        assert!((m.mz[0] - 100.000375).abs() < 1e-9);
+        assert_eq!(m.intensity, [20.0, 2.0]);
+        assert_eq!(m.occurrence, [2, 1]);
+        assert_eq!(m.retention_time, Some(1.5));
+        assert_eq!((m.rt_min, m.rt_max), (Some(1.0), Some(2.0)));
+
+        let opts = MergeOptions {
+            intensity: MergeIntensity::Sum,
+            min_occurrence: 1.0,
+            ..MergeOptions::default()
+        };
+        let m = merge_spectra(&spectra, &[0, 1], &opts).unwrap();
+        assert_eq!(m.intensity, [40.0]);
+    }
+
+    #[test]
+    fn summary_is_a_centroid_spectrum() {
+        let spectra = [spectrum(1, 1.0, &[(100.0, 10.0), (200.0, 30.0)])];
+        let m = merge_spectra(&spectra, &[0], &MergeOptions::default()).unwrap();
+        let s = m.to_summary(&spectra);
+        assert_eq!(s.spectrum_type.as_deref(), Some("centroid"));
+        assert_eq!(s.total_ion_current, Some(40.0));
+        assert_eq!(s.base_peak_mz, Some(200.0));
+        assert_eq!(s.array_length, 2);
+    }
+
+    #[test]
+    fn invalid_selections_are_rejected() {
+        let spectra = [spectrum(1, 1.0, &[]), spectrum(2, 1.0, &[])];
+        let opts = MergeOptions::default();
+        assert!(merge_spectra(&spectra, &[], &opts).is_err());
+        assert!(merge_spectra(&spectra, &[2], &opts).is_err());
+        assert!(merge_spectra(&spectra, &[0, 1], &opts).is_err());
+    }
+
+    #[test]
+    fn rt_window_selects_by_level() {
+        let spectra = [
+            spectrum(1, 1.0, &[]),
+            spectrum(2, 1.5, &[]),
+            spectrum(1, 3.0, &[]),
+        ];
+        assert_eq!(spectra_in_rt_window(&spectra, 1.0, 2.0, None), [0, 1]);
+        assert_eq!(spectra_in_rt_window(&spectra, 0.0, 5.0, Some(1)), [0, 2]);
+    }
+
+    #[test]
+    fn precursors_group_by_mz_and_rt_gap() {
+        let spectra = [
+            msn(500.0, 1.0),
+            msn(300.0, 1.0),
+            msn(500.001, 1.2),
+            msn(500.0, 4.0),
+            spectrum(1, 1.0, &[]),
+        ];
+        assert_eq!(
+            group_by_precursor(&spectra, 10.0, 0.0),
+            [vec![1], vec![0, 2, 3]]
+        );
+        assert_eq!(
+            group_by_precursor(&spectra, 10.0, 1.0),
+            [vec![1], vec![0, 2], vec![3]]
+        );
+    }
+}
@@ -0,0 +1,525 @@
+//! Ion mobility: frames, mobilograms and collision cross sections.
+//!
+//! Mobility comes either per spectrum, one spectrum per mobility scan, or
+//! per peak, one spectrum per frame with a mobility array, depending on how
+//! the run was exported. Both are read the same way here. Frames gather
+//! the mobility scans acquired at one retention time, keyed on the
+//! `frame=` token of the native id when there is one (timsTOF) and on
+//! consecutive scans sharing a retention time otherwise (Synapt).
+//!
+//! Collision cross sections follow the Mason–Schamp equation. For TIMS the
+//! observed 1/K0 is first mapped linearly onto the reference 1/K0 of the
+//! calibrants; for single-field drift tubes the arrival time is fitted as
+//! `t = t_fix + β · CCS · sqrt(m / (m + M)) / z`.
+
+use super::parse_mzml::SpectrumSummary;
+use super::quant::{CurveModel, CurveWeighting, fit_curve};
+
+/// Mass of N2, in Da.
+pub const N2_MASS: f64 = 28.0134;
+/// Mass of helium, in Da.
+pub const HE_MASS: f64 = 4.002602;
+
+const ELEMENTARY_CHARGE: f64 = 1.602_176_634e-19;
+const BOLTZMANN: f64 = 1.380_649e-23;
+/// Loschmidt constant, gas number density at 273.15 K and 101.325 kPa.
+const LOSCHMIDT: f64 = 2.686_780_111e25;
+const DALTON: f64 = 1.660_539_066_60e-27;
+
+/// Mobility scans acquired at one retention time.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub rt: Option<f64>,
+    pub ms_level: Option<u32>,
+    /// Indices of the spectra, by mobility; a single spectrum when the
+    /// frame was exported with a mobility array.
+    pub spectra: Vec<usize>,
+}
+
+// Value of `key=` in a native id such as "frame=12 scan=340".
+fn id_token<'a>(id: &'a str, key: &str) -> Option<&'a str> {
+    id.split_whitespace()
+        .find_map(|t| t.strip_prefix(key)?.strip_prefix('='))
+}
+
+/// Groups the spectra carrying ion mobility into frames, in file order.
+/// Spectra without mobility are left out.
+pub fn frames(spectra: &[SpectrumSummary]) -> Vec<Frame> {
+    let mut out: Vec<Frame> = Vec::new();
+    let mut key_of_last: Option<&str> = None;
+    for (i, s) in spectra.iter().enumerate() {
+        if s.mobility_array.is_some() {
+            out.push(Frame {
+                rt: s.retention_time,
+                ms_level: s.ms_level,
+                spectra: vec![i],
+            });
+            key_of_last = None;
+            continue;
+        }
+        if s.ion_mobility.is_none() {
+            continue;
+        }
+        let key = id_token(&s.id, "frame");
+        let same = out.last().is_some_and(|f| {
+            let j = f.spectra[f.spectra.len() - 1];
+            if spectra[j].mobility_array.is_some() {
+                return false;
+            }
+            match (key, key_of_last) {
+                (Some(a), Some(b)) => a == b,
+                (None, None) => f.ms_level == s.ms_level && f.rt == s.retention_time,
+                _ => false,
+            }
+        });
+        if same && let Some(f) = out.last_mut() {
+            f.spectra.push(i);
+        } else {
+            out.push(Frame {
+                rt: s.retention_time,
+                ms_level: s.ms_level,
+                spectra: vec![i],
+            });
+        }
+        key_of_last = key;
+    }
+    let mobility = |i: usize| spectra[i].ion_mobility.unwrap_or(f64::NAN);
+    for f in &mut out {
+        f.spectra
+            .sort_by(|&a, &b| mobility(a).total_cmp(&mobility(b)).then(a.cmp(&b)));
+    }
+    out
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MobilogramOptions {
+    /// Retention time range; the whole run when unset.
+    pub rt_min: Option<f64>,
+    pub rt_max: Option<f64>,
+    /// Only spectra of this MS level are used; all when unset.
+    pub ms_level: Option<u32>,
+    /// Width of the mobility bins, in the unit of the data; 0 sums points
+    /// of equal mobility only.
+    pub bin_width: f64,
+}
+
+impl Default for MobilogramOptions {
+    fn default() -> Self {
+        MobilogramOptions {
+            rt_min: None,
+            rt_max: None,
+            ms_level: Some(1),
+            bin_width: 0.0,
+        }
+    }
+}
+
+/// Extracted ion mobilogram.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Mobilogram {
+    /// `ion_mobility_type` of the spectra it was built from.
+    pub mobility_type: Option<String>,
+    /// Ascending; bin centres when binned.
+    pub mobility: Vec<f64>,
+    pub intensity: Vec<f64>,
+}
+
+/// Intensity within `mz_min..=mz_max` against ion mobility, summed over
+/// the selected spectra. Spectra whose mobility is of another type than
+/// the first one used are skipped.
+pub fn mobilogram(
+    spectra: &[SpectrumSummary],
+    mz_min: f64,
+    mz_max: f64,
+    opts: &MobilogramOptions,
+) -> Mobilogram {
+    let mut out = Mobilogram::default();
+    let mut points: Vec<(f64, f64)> = Vec::new();
+    for s in spectra {
+        if opts.ms_level.is_some_and(|l| s.ms_level != Some(l)) {
+            continue;
+        }
+        if s.ion_mobility.is_none() && s.mobility_array.is_none() {
+            continue;
+        }
+        if opts.rt_min.is_some() || opts.rt_max.is_some() {
+            let Some(t) = s.retention_time else {
+                continue;
+            };
+            if opts.rt_min.is_some_and(|lo| t < lo) || opts.rt_max.is_some_and(|hi| t > hi) {
+                continue;
+            }
+        }
+        match (&out.mobility_type, &s.ion_mobility_type) {
+            (None, kind) => out.mobility_type = kind.clone(),
+            (Some(a), Some(b)) if a != b => continue,
+            _ => {}
+        }
+        let (mz, intensity) = match (&s.mz_array, &s.intensity_array) {
+            (Some(m), Some(y)) => (m.to_f64(), y.to_f64()),
+            _ => Default::default(),
+        };
+        let n = mz.len().min(intensity.len());
+        let inside = (0..n).filter(|&j| mz[j] >= mz_min && mz[j] <= mz_max);
+        match (&s.mobility_array, s.ion_mobility) {
+            (Some(k), _) => {
+                let k = k.to_f64();
+                points.extend(
+                    inside
+                        .filter(|&j| j < k.len() && k[j].is_finite())
+                        .map(|j| (k[j], intensity[j])),
+                );
+            }
+            (None, Some(k)) => points.push((k, inside.fold(0.0, |acc, j| acc + intensity[j]))),
+            (None, None) => {}
+        }
+    }
+    if opts.bin_width > 0.0 {
+        for p in &mut points {
+            p.0 = ((p.0 / opts.bin_width).floor() + 0.5) * opts.bin_width;
+        }
+    }
+    points.sort_by(|a, b| a.0.total_cmp(&b.0));
+    for (k, y) in points {
+        if out.mobility.last() == Some(&k) {
+            if let Some(last) = out.intensity.last_mut() {
+                *last += y;
+            }
+        } else {
+            out.mobility.push(k);
+            out.intensity.push(y);
+        }
+    }
+    out
+}
+
+// Reduced mass of an ion of `mz` and `charge` with a gas molecule, in kg.
+fn reduced_mass(mz: f64, charge: u32, gas_mass: f64) -> f64 {
+    let m = mz * f64::from(charge);
+    m * gas_mass / (m + gas_mass) * DALTON
+}
+
+/// Collision cross section, in Å², of an ion of `mz` and `charge` with
+/// inverse reduced mobility `inverse_k0` (V·s/cm²), by the Mason–Schamp
+/// equation.
+pub fn mason_schamp_ccs(
+    mz: f64,
+    charge: u32,
+    inverse_k0: f64,
+    gas_mass: f64,
+    temperature: f64,
+) -> f64 {
+    let mu = reduced_mass(mz, charge, gas_mass);
+    // V·s/cm² to V·s/m².
+    let inverse_k0 = inverse_k0 * 1e4;
+    let ccs = 3.0 * f64::from(charge) * ELEMENTARY_CHARGE / (16.0 * LOSCHMIDT)
+        * (2.0 * std::f64::consts::PI / (mu * BOLTZMANN * temperature)).sqrt()
+        * inverse_k0;
+    ccs * 1e20
+}
+
+/// Inverse reduced mobility, in V·s/cm², of an ion of cross section `ccs`
+/// (Å²); the inverse of [`mason_schamp_ccs`].
+pub fn inverse_k0_from_ccs(mz: f64, charge: u32, ccs: f64, gas_mass: f64, temperature: f64) -> f64 {
+    ccs / mason_schamp_ccs(mz, charge, 1.0, gas_mass, temperature)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ImsMethod {
+    /// Trapped ion mobility; observed values are 1/K0 in V·s/cm².
+    #[default]
+    Tims,
+    /// Single-field drift tube; observed values are arrival times.
+    SingleField,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CcsOptions {
+    pub method: ImsMethod,
+    /// Drift gas mass, in Da.
+    pub gas_mass: f64,
+    /// Drift gas temperature, in K.
+    pub temperature: f64,
+}
+
+impl Default for CcsOptions {
+    fn default() -> Self {
+        CcsOptions {
+            method: ImsMethod::Tims,
+            gas_mass: N2_MASS,
+            temperature: 305.0,
+        }
+    }
+}
+
+/// A calibrant ion: its reference cross section and the mobility value
+/// observed for it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Calibrant {
+    pub mz: f64,
+    pub charge: u32,
+    /// Reference cross section, in Å².
+    pub ccs: f64,
+    pub observed: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CcsCalibration {
+    pub options: CcsOptions,
+    /// For TIMS, the reference 1/K0 is `intercept + slope * observed`; for
+    /// single-field, `intercept` is t_fix and `slope` is β.
+    pub intercept: f64,
+    pub slope: f64,
+    /// NaN when uncalibrated.
+    pub r_squared: f64,
+}
+
+impl CcsCalibration {
+    /// TIMS conversion taking observed 1/K0 values as they are.
+    pub fn uncalibrated(options: CcsOptions) -> CcsCalibration {
+        CcsCalibration {
+            options: CcsOptions {
+                method: ImsMethod::Tims,
+                ..options
+            },
+            intercept: 0.0,
+            slope: 1.0,
+            r_squared: f64::NAN,
+        }
+    }
+
+    /// Cross section, in Å², of an ion of `mz` and `charge` observed at
+    /// mobility `observed`; NaN for charge 0.
+    pub fn ccs(&self, mz: f64, charge: u32, observed: f64) -> f64 {
+        if charge == 0 {
+            return f64::NAN;
+        }
+        let CcsOptions {
+            method,
+            gas_mass,
+            temperature,
+        } = self.options;
+        match method {
+            ImsMethod::Tims => mason_schamp_ccs(
+                mz,
+                charge,
+                self.intercept + self.slope * observed,
+                gas_mass,
+                temperature,
+            ),
+            ImsMethod::SingleField => {
+                (observed - self.intercept) / (self.slope * gamma(mz, charge, gas_mass))
+            }
+        }
+    }
+}
+
+// Mass and charge term of the single-field calibration.
+fn gamma(mz: f64, charge: u32, gas_mass: f64) -> f64 {
+    let m = mz * f64::from(charge);
+    (m / (m + gas_mass)).sqrt() / f64::from(charge)
+}
+
+/// Fits a cross section calibration to at least two calibrants.
+pub fn calibrate_ccs(
+    calibrants: &[Calibrant],
+    opts: &CcsOptions,
+) -> Result<CcsCalibration, String> {
+    let points: Vec<(f64, f64)> = calibrants
+        .iter()
+        .filter(|c| c.charge > 0)
+        .map(|c| match opts.method {
+            ImsMethod::Tims => (
+                c.observed,
+                inverse_k0_from_ccs(c.mz, c.charge, c.ccs, opts.gas_mass, opts.temperature),
+            ),
+            ImsMethod::SingleField => (c.ccs * gamma(c.mz, c.charge, opts.gas_mass), c.observed),
+        })
+        .collect();
+    if points.len() < 2 {
+        return Err("at least two calibrants with a charge are needed".to_string());
+    }
+    let curve = fit_curve(&points, CurveModel::Linear, CurveWeighting::None)
+        .ok_or("calibrants do not determine a line")?;
+    let [intercept, slope, _] = curve.coefficients;
+    if !(slope.is_finite() && slope != 0.0) {
+        return Err("degenerate calibration".to_string());
+    }
+    Ok(CcsCalibration {
+        options: *opts,
+        intercept,
+        slope,
+        r_squared: curve.r_squared,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utilities::parse_mzml::ArrayData;
+    use crate::utilities::parse_mzml::test_spectra::spectrum;
+
+    // A mobility scan with one peak at 500 and one at 600.
+    fn scan(id: &str, rt: f64, mobility: f64, intensity: f64) -> SpectrumSummary {
+        SpectrumSummary {
+            id: id.to_string(),
+            ion_mobility: Some(mobility),
+            ion_mobility_type: Some("inverse reduced ion mobility".to_string()),
+            ..spectrum(1, rt, &[(500.0, intensity), (600.0, 1.0)])
+        }
+    }
+
+    // A frame exported with a per-peak mobility array.
+    fn frame(rt: f64) -> SpectrumSummary {
+        SpectrumSummary {
+            ion_mobility_type: Some("inverse reduced ion mobility".to_string()),
+            mobility_array: Some(ArrayData::F64(vec![0.9, 1.1, 0.9])),
+            ..spectrum(1, rt, &[(500.0, 2.0), (500.0, 3.0), (600.0, 4.0)])
+        }
+    }
+
+    #[test]
+    fn scans_are_grouped_into_frames() {
+        let tims = vec![
+            scan("frame=1 scan=2", 1.0, 0.8, 1.0),
+            scan("frame=1 scan=1", 1.0, 1.2, 1.0),
+            scan("frame=2 scan=1", 1.0, 1.2, 1.0),
+            SpectrumSummary::default(),
+            scan("frame=3 scan=1", 1.1, 1.0, 1.0),
+        ];
+        let f = frames(&tims);
+        assert_eq!(f.len(), 3);
+        assert_eq!(f[0].spectra, [0, 1]);
+        assert_eq!(f[1].spectra, [2]);
+        assert_eq!((f[2].spectra.as_slice(), f[2].rt), (&[4][..], Some(1.1)));
+
+        // Without frame ids, consecutive scans at one retention time.
+        let synapt = vec![
+            scan("scan=1", 1.0, 5.0, 1.0),
+            scan("scan=2", 1.0, 3.0, 1.0),
+            scan("scan=3", 1.02, 3.0, 1.0),
+            frame(1.04),
+            frame(1.06),
+        ];
+        let f = frames(&synapt);
+        assert_eq!(f.len(), 4);
+        assert_eq!(f[0].spectra, [1, 0]);
+        assert_eq!(f[1].spectra, [2]);
+        assert_eq!(f[3].spectra, [4]);
+    }
+
+    #[test]
+    fn mobilograms_sum_over_scans_and_peaks() {
+        let spectra = vec![
+            scan("", 1.0, 0.9, 10.0),
+            scan("", 1.0, 1.1, 20.0),
+            scan("", 2.0, 0.9, 5.0),
+            frame(1.5),
+        ];
+        let m = mobilogram(&spectra, 499.9, 500.1, &MobilogramOptions::default());
+        assert_eq!(
+            m.mobility_type.as_deref(),
+            Some("inverse reduced ion mobility")
+        );
+        assert_eq!(m.mobility, [0.9, 1.1]);
+        assert_eq!(m.intensity, [17.0, 23.0]);
+
+        let opts = MobilogramOptions {
+            rt_max: Some(1.2),
+            bin_width: 0.5,
+            ..MobilogramOptions::default()
+        };
+        let m = mobilogram(&spectra, 499.9, 500.1, &opts);
+        assert_eq!(m.mobility, [0.75, 1.25]);
+        assert_eq!(m.intensity, [10.0, 20.0]);
+
+        // Mobility of another type is skipped.
+        let mut mixed = spectra.clone();
+        mixed[1].ion_mobility_type = Some("drift time".to_string());
+        let m = mobilogram(&mixed, 499.9, 500.1, &opts);
+        assert_eq!(m.intensity, [10.0]);
+        let ms2 = MobilogramOptions {
+            ms_level: Some(2),
+            ..MobilogramOptions::default()
+        };
+        assert_eq!(mobilogram(&spectra, 0.0, 1e4, &ms2), Mobilogram::default());
+    }
+
+    #[test]
+    fn mason_schamp_round_trips() {
+        // Agilent tune mix ion 622.029, about 202 Å² in N2 at 1/K0 0.99.
+        let ccs = mason_schamp_ccs(622.029, 1, 0.992, N2_MASS, 305.0);
+        assert!((ccs - 202.0).abs() < 3.0, "{ccs}");
+        let k = inverse_k0_from_ccs(622.029, 1, ccs, N2_MASS, 305.0);
+        assert!((k - 0.992).abs() < 1e-12);
+        // Linear in 1/K0; a lighter gas gives a larger cross section.
+        let double = mason_schamp_ccs(622.029, 1, 1.984, N2_MASS, 305.0);
+        assert!((double / ccs - 2.0).abs() < 1e-12);
+        assert!(mason_schamp_ccs(622.029, 1, 0.992, HE_MASS, 305.0) > ccs);
+    }
+
+    #[test]
+    fn tims_calibration_maps_observed_values() {
+        let opts = CcsOptions::default();
+        let ions = [(322.048, 153.7), (622.029, 202.0), (922.010, 243.6)];
+        // The instrument reads 1/K0 2% high and 0.01 V·s/cm² low.
+        let calibrants: Vec<Calibrant> = ions
+            .iter()
+            .map(|&(mz, ccs)| {
+                let k = inverse_k0_from_ccs(mz, 1, ccs, N2_MASS, 305.0);
+                Calibrant {
+                    mz,
+                    charge: 1,
+                    ccs,
+                    observed: 1.02 * k - 0.01,
+                }
+            })
+            .collect();
+        let cal = calibrate_ccs(&calibrants, &opts).unwrap();
+        assert!((cal.slope - 1.0 / 1.02).abs() < 1e-9);
+        assert!((cal.r_squared - 1.0).abs() < 1e-9);
+        for c in &calibrants {
+            assert!((cal.ccs(c.mz, 1, c.observed) - c.ccs).abs() < 1e-6);
+        }
+        assert!(cal.ccs(622.029, 0, 1.0).is_nan());
+
+        let raw = CcsCalibration::uncalibrated(opts);
+        assert_eq!(
+            raw.ccs(622.029, 1, 0.992),
+            mason_schamp_ccs(622.029, 1, 0.992, N2_MASS, 305.0)
+        );
+        assert!(calibrate_ccs(&calibrants[..1], &opts).is_err());
+        let uncharged: Vec<Calibrant> = calibrants
+            .iter()
+            .map(|c| Calibrant { charge: 0, ..*c })
+            .collect();
+        assert!(calibrate_ccs(&uncharged, &opts).is_err());
+    }
+
+    #[test]
+    fn single_field_calibration_fits_arrival_times() {
+        let opts = CcsOptions {
+            method: ImsMethod::SingleField,
+            ..CcsOptions::default()
+        };
+        let (t_fix, beta) = (1.5, 0.12);
+        let calibrants: Vec<Calibrant> = [
+            (322.048, 1, 153.7),
+            (622.029, 1, 202.0),
+            (1221.991, 2, 282.2),
+        ]
+        .iter()
+        .map(|&(mz, charge, ccs)| Calibrant {
+            mz,
+            charge,
+            ccs,
+            observed: t_fix + beta * ccs * gamma(mz, charge, N2_MASS),
+        })
+        .collect();
+        let cal = calibrate_ccs(&calibrants, &opts).unwrap();
+        assert!((cal.intercept - t_fix).abs() < 1e-9);
+        assert!((cal.slope - beta).abs() < 1e-9);
+        let t = t_fix + beta * 250.0 * gamma(800.0, 1, N2_MASS);
+        assert!((cal.ccs(800.0, 1, t) - 250.0).abs() < 1e-6);
+    }
+}
@@ -1 +1,19 @@
+pub mod alignment;
+pub mod annotation;
+pub mod binning;
+pub mod calibration;
+pub mod correspondence;
+pub mod dia;
+pub mod feature_detection;
+pub mod library;
+pub mod merge;
+pub mod mobility;
 pub mod parse_mzml;
+pub mod peak_picking;
+pub mod quant;
+pub mod signal;
+pub mod simd;
+pub mod similarity;
+pub mod stats;
+pub mod stream;
+pub mod write_mzml;
@@ -1,7 +1,16 @@
-use miniz_oxide::inflate::decompress_to_vec_zlib;
+use miniz_oxide::inflate::TINFLStatus;
+use miniz_oxide::inflate::core::inflate_flags::{
+    TINFL_FLAG_PARSE_ZLIB_HEADER, TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF,
+};
+use miniz_oxide::inflate::core::{DecompressorOxide, decompress};
+use std::borrow::Cow;
 use std::io::{Cursor, Read, Seek, SeekFrom};
+use std::ops::Range;
 use std::str;
 
+use super::peak_picking::{PeakPickingOptions, centroid_in_place};
+use super::simd::{decode_base64_ws_into, is_ws, memchr, memmem};
+
 #[derive(Debug, Clone)]
 pub enum Node {
     Element(Element),
@@ -16,7 +25,7 @@ pub struct Element {
     pub children: Vec<Node>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct SpectrumSummary {
     pub index: usize,
     pub id: String,
@@ -31,22 +40,232 @@ pub struct SpectrumSummary {
     pub total_ion_current: Option<f64>,
     pub base_peak_intensity: Option<f64>,
     pub base_peak_mz: Option<f64>,
-    pub mz_array: Option<Vec<f64>>,
-    pub intensity_array: Option<Vec<f64>>,
+    /// Selected ion m/z of the first precursor.
+    pub precursor_mz: Option<f64>,
+    pub precursor_charge: Option<u32>,
+    /// Isolation window of the first precursor: target m/z and the widths
+    /// below and above it.
+    pub isolation_window_target: Option<f64>,
+    pub isolation_window_lower: Option<f64>,
+    pub isolation_window_upper: Option<f64>,
+    /// Ion mobility of the whole scan, in the unit of
+    /// `ion_mobility_type`: milliseconds for drift time (converted from the
+    /// `unitName` of the cvParam), V·s/cm² for inverse reduced ion mobility,
+    /// volts for compensation voltage.
+    pub ion_mobility: Option<f64>,
+    /// `"drift time"`, `"inverse reduced ion mobility"` or
+    /// `"compensation voltage"`; also set for per-peak mobility arrays.
+    pub ion_mobility_type: Option<String>,
+    pub mz_array: Option<ArrayData>,
+    pub intensity_array: Option<ArrayData>,
+    /// Per-peak ion mobility, parallel to `mz_array`, as exported for
+    /// combined timsTOF and Synapt frames; in the units of `ion_mobility`.
+    pub mobility_array: Option<ArrayData>,
+}
+
+/// A `<chromatogram>`: a TIC, or an SRM/MRM trace with the Q1 and Q3
+/// isolation targets of its transition.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Chromatogram {
+    pub index: usize,
+    pub id: String,
+    pub precursor_mz: Option<f64>,
+    pub product_mz: Option<f64>,
+    /// Retention times, in minutes.
+    pub time: Vec<f64>,
+    pub intensity: Vec<f64>,
+}
+
+/// Values of a binary data array in the precision they were stored with.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ArrayData {
+    F32(Vec<f32>),
+    F64(Vec<f64>),
+}
+
+impl ArrayData {
+    pub fn len(&self) -> usize {
+        match self {
+            ArrayData::F32(v) => v.len(),
+            ArrayData::F64(v) => v.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, i: usize) -> Option<f64> {
+        match self {
+            ArrayData::F32(v) => v.get(i).map(|&x| x as f64),
+            ArrayData::F64(v) => v.get(i).copied(),
+        }
+    }
+
+    pub fn as_f32(&self) -> Option<&[f32]> {
+        match self {
+            ArrayData::F32(v) => Some(v),
+            ArrayData::F64(_) => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<&[f64]> {
+        match self {
+            ArrayData::F32(_) => None,
+            ArrayData::F64(v) => Some(v),
+        }
+    }
+
+    /// Values as `f64`, borrowed when already stored that way.
+    pub fn to_f64(&self) -> Cow<'_, [f64]> {
+        match self {
+            ArrayData::F32(v) => Cow::Owned(v.iter().map(|&x| x as f64).collect()),
+            ArrayData::F64(v) => Cow::Borrowed(v),
+        }
+    }
+
+    pub fn into_f64(self) -> Vec<f64> {
+        match self {
+            ArrayData::F32(v) => v.into_iter().map(|x| x as f64).collect(),
+            ArrayData::F64(v) => v,
+        }
+    }
+
+    /// Index of the first value for which `pred` is false, for arrays
+    /// partitioned by it, as [`slice::partition_point`].
+    pub fn partition_point(&self, pred: impl Fn(f64) -> bool) -> usize {
+        match self {
+            ArrayData::F32(v) => v.partition_point(|&x| pred(x as f64)),
+            ArrayData::F64(v) => v.partition_point(|&x| pred(x)),
+        }
+    }
+
+    pub fn iter(&self) -> ArrayIter<'_> {
+        match self {
+            ArrayData::F32(v) => ArrayIter::F32(v.iter()),
+            ArrayData::F64(v) => ArrayIter::F64(v.iter()),
+        }
+    }
+}
+
+impl From<Vec<f32>> for ArrayData {
+    fn from(v: Vec<f32>) -> Self {
+        ArrayData::F32(v)
+    }
+}
+
+impl From<Vec<f64>> for ArrayData {
+    fn from(v: Vec<f64>) -> Self {
+        ArrayData::F64(v)
+    }
+}
+
+/// Iterator over an [`ArrayData`] widening every value to `f64`.
+#[derive(Debug, Clone)]
+pub enum ArrayIter<'a> {
+    F32(std::slice::Iter<'a, f32>),
+    F64(std::slice::Iter<'a, f64>),
+}
+
+impl Iterator for ArrayIter<'_> {
+    type Item = f64;
+
+    #[inline]
+    fn next(&mut self) -> Option<f64> {
+        match self {
+            ArrayIter::F32(it) => it.next().map(|&x| x as f64),
+            ArrayIter::F64(it) => it.next().copied(),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self {
+            ArrayIter::F32(it) => it.size_hint(),
+            ArrayIter::F64(it) => it.size_hint(),
+        }
+    }
+}
+
+impl ExactSizeIterator for ArrayIter<'_> {}
+
+impl SpectrumSummary {
+    /// Converts all arrays to `f64` in place.
+    pub fn widen_arrays(&mut self) {
+        for arr in [
+            &mut self.mz_array,
+            &mut self.intensity_array,
+            &mut self.mobility_array,
+        ] {
+            if let Some(ArrayData::F32(_)) = arr {
+                *arr = arr.take().map(|a| ArrayData::F64(a.into_f64()));
+            }
+        }
+    }
+}
+
+/// Optional processing applied to each spectrum as it is parsed.
+#[derive(Debug, Clone, Default)]
+pub struct ParseOptions {
+    /// Centroid profile spectra right after their arrays are decoded, so
+    /// the profile data of a whole run is never held at once.
+    pub centroid: Option<PeakPickingOptions>,
+    /// MS levels to keep; empty keeps every level. Other spectra are
+    /// skipped before their arrays are decoded.
+    pub ms_levels: Vec<u32>,
+    /// Inclusive retention time window in minutes; when set, spectra
+    /// outside it or without a retention time are skipped before decoding.
+    pub rt: Option<(f64, f64)>,
+    /// `"positive"` or `"negative"`, as in `SpectrumSummary::polarity`;
+    /// when set, spectra of the other or no polarity are skipped.
+    pub polarity: Option<String>,
+}
+
+impl ParseOptions {
+    fn keeps(&self, ms_level: Option<u32>, rt: Option<f64>, polarity: Option<&str>) -> bool {
+        if !self.ms_levels.is_empty() && !ms_level.is_some_and(|l| self.ms_levels.contains(&l)) {
+            return false;
+        }
+        if self.polarity.is_some() && self.polarity.as_deref() != polarity {
+            return false;
+        }
+        match (self.rt, rt) {
+            (None, _) => true,
+            (Some((lo, hi)), Some(rt)) => (lo..=hi).contains(&rt),
+            (Some(_), None) => false,
+        }
+    }
 }
 
-struct Scratch {
+pub(crate) struct Scratch {
     b64_buf: Vec<u8>,
     zlib_buf: Vec<u8>,
+    inflater: Box<DecompressorOxide>,
+    opts: ParseOptions,
+}
+
+impl Scratch {
+    pub(crate) fn new() -> Self {
+        Self::with_options(&ParseOptions::default())
+    }
+
+    pub(crate) fn with_options(opts: &ParseOptions) -> Self {
+        Scratch {
+            b64_buf: Vec::with_capacity(256),
+            zlib_buf: Vec::with_capacity(256),
+            inflater: Box::default(),
+            opts: opts.clone(),
+        }
+    }
 }
 
 pub fn parse_mzml(bytes: &[u8]) -> Result<Vec<SpectrumSummary>, String> {
+    parse_mzml_with(bytes, &ParseOptions::default())
+}
+
+pub fn parse_mzml_with(bytes: &[u8], opts: &ParseOptions) -> Result<Vec<SpectrumSummary>, String> {
     let file_len = bytes.len() as u64;
     let mut cursor = Cursor::new(bytes);
-    let mut scratch = Scratch {
-        b64_buf: Vec::with_capacity(256),
-        zlib_buf: Vec::with_capacity(256),
-    };
+    let mut scratch = Scratch::with_options(opts);
 
     if let Some(offsets) = read_spectrum_offsets(&mut cursor)? {
         if file_len <= 1_073_741_824 {
@@ -86,6 +305,156 @@ pub fn parse_mzml(bytes: &[u8]) -> Result<Vec<SpectrumSummary>, String> {
     linear_scan_spectra(&mut cursor, &mut scratch)
 }
 
+/// Byte ranges of every `<spectrum>` element, taken from the spectrum index
+/// when the file has one and from a linear scan otherwise.
+pub fn spectrum_spans(bytes: &[u8]) -> Result<Vec<Range<usize>>, String> {
+    let mut cursor = Cursor::new(bytes);
+    if let Some(offsets) = read_spectrum_offsets(&mut cursor)? {
+        let mut out = Vec::with_capacity(offsets.len());
+        for i in 0..offsets.len() {
+            let start = offsets[i] as usize;
+            let end = find_spectrum_end_in(bytes, start)
+                .ok_or_else(|| "no </spectrum> after offset".to_string())?;
+            if i + 1 < offsets.len() && end > offsets[i + 1] as usize {
+                return Err("spectrum offsets out of order".into());
+            }
+            out.push(start..end);
+        }
+        return Ok(out);
+    }
+
+    let mut out = Vec::new();
+    let mut cur = 0usize;
+    let open_tag = b"<spectrum ";
+    let close_tag = b"</spectrum>";
+    while let Some(p) = memmem(&bytes[cur..], open_tag) {
+        let start = cur + p;
+        let end_rel = memmem(&bytes[start..], close_tag)
+            .ok_or_else(|| "unterminated <spectrum>".to_string())?;
+        let end = start + end_rel + close_tag.len();
+        out.push(start..end);
+        cur = end;
+    }
+    Ok(out)
+}
+
+/// Every `<chromatogram>` of the file, in file order.
+pub fn parse_chromatograms(bytes: &[u8]) -> Result<Vec<Chromatogram>, String> {
+    let mut scratch = Scratch::new();
+    let mut out = Vec::new();
+    let mut cur = 0usize;
+    let open_tag = b"<chromatogram ";
+    let close_tag = b"</chromatogram>";
+    while let Some(p) = memmem(&bytes[cur..], open_tag) {
+        let start = cur + p;
+        let end_rel = memmem(&bytes[start..], close_tag)
+            .ok_or_else(|| "unterminated <chromatogram>".to_string())?;
+        let end = start + end_rel + close_tag.len();
+        out.push(parse_chromatogram_block(&bytes[start..end], &mut scratch));
+        cur = end;
+    }
+    Ok(out)
+}
+
+// <chromatogram>, <precursor>, <product>
+fn parse_chromatogram_block(block: &[u8], scratch: &mut Scratch) -> Chromatogram {
+    let header_end = memmem(block, b"<binaryDataArrayList").unwrap_or(block.len());
+    let header = &block[..header_end];
+    let target = |open: &[u8], close: &[u8]| {
+        let (s, e) = tag_body(header, open, close)?;
+        find_cv_value_f64(&header[s..e], b"isolation window target m/z")
+    };
+    let mut chrom = Chromatogram {
+        index: find_attr_usize(block, b"<chromatogram", b"index=").unwrap_or(0),
+        id: find_attr_string(block, b"<chromatogram", b"id=").unwrap_or_default(),
+        precursor_mz: target(b"<precursor", b"</precursor>"),
+        product_mz: target(b"<product", b"</product>"),
+        ..Chromatogram::default()
+    };
+    let expected_len =
+        find_attr_usize(block, b"<chromatogram", b"defaultArrayLength=").unwrap_or(0);
+    for_each_binary_array(block, expected_len, scratch, |raw| {
+        if raw.kind_time {
+            raw.write_into_vec(&mut chrom.time);
+            if raw.seconds {
+                chrom.time.iter_mut().for_each(|t| *t /= 60.0);
+            }
+        } else if raw.kind_int {
+            raw.write_into_vec(&mut chrom.intensity);
+        }
+    });
+    chrom
+}
+
+/// Decodes the m/z and intensity arrays of `<spectrum>` blocks into buffers
+/// owned by the caller. The base64, inflate and output buffers are reused
+/// across calls, so decoding a whole run allocates only while they grow.
+pub struct ArrayDecoder {
+    scratch: Scratch,
+}
+
+impl Default for ArrayDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ArrayDecoder {
+    pub fn new() -> Self {
+        ArrayDecoder {
+            scratch: Scratch::new(),
+        }
+    }
+
+    /// `defaultArrayLength` of a `<spectrum>` block, i.e. the number of
+    /// values each of its arrays holds.
+    pub fn array_length(block: &[u8]) -> usize {
+        find_attr_usize(block, b"<spectrum", b"defaultArrayLength=").unwrap_or(0)
+    }
+
+    /// Replaces the contents of `mz` and `intensity` with the arrays of
+    /// `block`. An array missing from the block leaves its buffer empty.
+    pub fn decode_into(&mut self, block: &[u8], mz: &mut Vec<f64>, intensity: &mut Vec<f64>) {
+        mz.clear();
+        intensity.clear();
+        let expected_len = Self::array_length(block);
+        for_each_binary_array(block, expected_len, &mut self.scratch, |raw| {
+            match (raw.kind_mz, raw.kind_int) {
+                (true, false) => raw.write_into_vec(mz),
+                (false, true) => raw.write_into_vec(intensity),
+                _ => {}
+            }
+        });
+    }
+
+    /// Writes the arrays of `block` into `mz` and `intensity`, returning the
+    /// full length of each array. Values past the end of a slice are dropped,
+    /// so a length above the slice's means it was too short.
+    pub fn decode_into_slices(
+        &mut self,
+        block: &[u8],
+        mz: &mut [f64],
+        intensity: &mut [f64],
+    ) -> (usize, usize) {
+        let mut written = (0usize, 0usize);
+        let expected_len = Self::array_length(block);
+        for_each_binary_array(block, expected_len, &mut self.scratch, |raw| {
+            match (raw.kind_mz, raw.kind_int) {
+                (true, false) => {
+                    raw.write_into(mz);
+                    written.0 = raw.len();
+                }
+                (false, true) => {
+                    raw.write_into(intensity);
+                    written.1 = raw.len();
+                }
+                _ => {}
+            }
+        });
+        written
+    }
+}
+
 // <indexListOffset>, <index name="spectrum">
 fn read_spectrum_offsets<R: Read + Seek>(r: &mut R) -> Result<Option<Vec<u64>>, String> {
     const TAIL: u64 = 64 * 1024;
@@ -100,14 +469,23 @@ fn read_spectrum_offsets<R: Read + Seek>(r: &mut R) -> Result<Option<Vec<u64>>,
         .read_to_end(&mut tail)
         .map_err(|e| format!("read tail: {e}"))?;
     if let Some(off) = extract_index_list_offset(&tail) {
+        if off >= end {
+            return Err(format!("indexListOffset {off} past the end of the file"));
+        }
         r.seek(SeekFrom::Start(off))
             .map_err(|e| format!("seek indexList: {e}"))?;
-        let mut buf = vec![0u8; (end - off).min(1_000_000).max(4096) as usize];
+        let mut buf = vec![0u8; (end - off).clamp(4096, 1_000_000) as usize];
         let n = r
             .read(&mut buf)
             .map_err(|e| format!("read indexList: {e}"))?;
         buf.truncate(n);
         let offs = parse_spectrum_offsets_from_index(&buf);
+        if let Some(&o) = offs.iter().find(|&&o| o >= end) {
+            return Err(format!("spectrum offset {o} past the end of the file"));
+        }
+        if offs.windows(2).any(|w| w[0] >= w[1]) {
+            return Err("spectrum offsets out of order".into());
+        }
         return Ok(Some(offs));
     }
     Ok(None)
@@ -126,28 +504,28 @@ fn extract_index_list_offset(tail: &[u8]) -> Option<u64> {
 // <index name="spectrum">, <offset>
 fn parse_spectrum_offsets_from_index(buf: &[u8]) -> Vec<u64> {
     let mut out = Vec::new();
-    if let Some(ix_start) = memmem(buf, br#"<index name="spectrum">"#) {
-        if let Some(ix_end_rel) = memmem(&buf[ix_start..], b"</index>") {
-            let block = &buf[ix_start..ix_start + ix_end_rel];
-            let mut cursor = 0usize;
-            let tag = b"<offset";
-            let endtag = b"</offset>";
-            while let Some(p) = memmem(&block[cursor..], tag) {
-                let from = cursor + p;
-                let g = match memchr(&block[from..], b'>') {
-                    Some(rel) => from + rel + 1,
-                    None => break,
-                };
-                let endp_rel = match memmem(&block[g..], endtag) {
-                    Some(v) => v,
-                    None => break,
-                };
-                let num = &block[g..g + endp_rel];
-                if let Some(v) = parse_u64_ascii(strip_ws(num)) {
-                    out.push(v);
-                }
-                cursor = g + endp_rel + endtag.len();
+    if let Some(ix_start) = memmem(buf, br#"<index name="spectrum">"#)
+        && let Some(ix_end_rel) = memmem(&buf[ix_start..], b"</index>")
+    {
+        let block = &buf[ix_start..ix_start + ix_end_rel];
+        let mut cursor = 0usize;
+        let tag = b"<offset";
+        let endtag = b"</offset>";
+        while let Some(p) = memmem(&block[cursor..], tag) {
+            let from = cursor + p;
+            let g = match memchr(&block[from..], b'>') {
+                Some(rel) => from + rel + 1,
+                None => break,
+            };
+            let endp_rel = match memmem(&block[g..], endtag) {
+                Some(v) => v,
+                None => break,
+            };
+            let num = &block[g..g + endp_rel];
+            if let Some(v) = parse_u64_ascii(strip_ws(num)) {
+                out.push(v);
             }
+            cursor = g + endp_rel + endtag.len();
         }
     }
     out
@@ -240,10 +618,10 @@ fn linear_scan_spectra<R: Read + Seek>(
 }
 
 // <spectrum>, <cvParam>, <binaryDataArray>, <binary>
-fn parse_spectrum_block(block: &[u8], scratch: &mut Scratch) -> Option<SpectrumSummary> {
-    let index = find_attr_usize(block, b"spectrum", b"index").unwrap_or(0);
-    let id = find_attr_string(block, b"spectrum", b"id").unwrap_or_default();
-    let array_len = find_attr_usize(block, b"spectrum", b"defaultArrayLength").unwrap_or(0);
+pub(crate) fn parse_spectrum_block(block: &[u8], scratch: &mut Scratch) -> Option<SpectrumSummary> {
+    let index = find_attr_usize(block, b"<spectrum", b"index=").unwrap_or(0);
+    let id = find_attr_string(block, b"<spectrum", b"id=").unwrap_or_default();
+    let array_len = find_attr_usize(block, b"<spectrum", b"defaultArrayLength=").unwrap_or(0);
 
     let header_end = memmem(block, b"<binaryDataArrayList").unwrap_or(block.len());
     let header = &block[..header_end];
@@ -273,13 +651,49 @@ fn parse_spectrum_block(block: &[u8], scratch: &mut Scratch) -> Option<SpectrumS
     let total_ion_current = find_cv_value_f64(header, b"total ion current");
     let base_peak_intensity = find_cv_value_f64(header, b"base peak intensity");
     let base_peak_mz = find_cv_value_f64(header, b"base peak m/z");
+    let precursor_mz = find_cv_value_f64(header, b"selected ion m/z");
+    let precursor_charge = find_cv_value_u32(header, b"charge state");
+    let isolation = tag_body(header, b"<isolationWindow", b"</isolationWindow>")
+        .map_or(&header[..0], |(s, e)| &header[s..e]);
+    let isolation_window_target = find_cv_value_f64(isolation, b"isolation window target m/z");
+    let isolation_window_lower = find_cv_value_f64(isolation, b"isolation window lower offset");
+    let isolation_window_upper = find_cv_value_f64(isolation, b"isolation window upper offset");
+    let (mut ion_mobility, mut ion_mobility_type) = (None, None);
+    for (name, kind) in MOBILITY_PARAMS {
+        let Some((value, unit)) = find_cv_value_unit(header, name) else {
+            continue;
+        };
+        if let Some(v) = str::from_utf8(value)
+            .ok()
+            .and_then(|v| v.parse::<f64>().ok())
+        {
+            let scale = if kind == "drift time" {
+                drift_time_scale(unit)
+            } else {
+                1.0
+            };
+            ion_mobility = Some(v * scale);
+            ion_mobility_type = Some(kind.to_string());
+            break;
+        }
+    }
     let retention_time = find_scan_start_time_min(header);
+    if !scratch
+        .opts
+        .keeps(ms_level, retention_time, polarity.as_deref())
+    {
+        return None;
+    }
     let scan_window_lower_limit = find_cv_value_f64(header, b"scan window lower limit");
     let scan_window_upper_limit = find_cv_value_f64(header, b"scan window upper limit");
 
-    let (mz_array, intensity_array) = decode_binary_arrays(block, array_len, scratch);
+    let (mz_array, intensity_array, mobility) = decode_binary_arrays(block, array_len, scratch);
+    let mobility_array = mobility.map(|(kind, arr)| {
+        ion_mobility_type.get_or_insert_with(|| kind.to_string());
+        arr
+    });
 
-    Some(SpectrumSummary {
+    let mut sum = SpectrumSummary {
         index,
         id,
         array_length: array_len,
@@ -293,9 +707,21 @@ fn parse_spectrum_block(block: &[u8], scratch: &mut Scratch) -> Option<SpectrumS
         total_ion_current,
         base_peak_intensity,
         base_peak_mz,
+        precursor_mz,
+        precursor_charge,
+        isolation_window_target,
+        isolation_window_lower,
+        isolation_window_upper,
+        ion_mobility,
+        ion_mobility_type,
         mz_array,
         intensity_array,
-    })
+        mobility_array,
+    };
+    if let Some(opts) = &scratch.opts.centroid {
+        centroid_in_place(&mut sum, opts);
+    }
+    Some(sum)
 }
 
 fn find_attr_usize(buf: &[u8], tag: &[u8], attr: &[u8]) -> Option<usize> {
@@ -307,22 +733,19 @@ fn find_attr_string(buf: &[u8], tag: &[u8], attr: &[u8]) -> Option<String> {
     String::from_utf8(b.to_vec()).ok()
 }
 
+// `tag` is the opening of the element, such as `<spectrum`, and `attr` the
+// attribute name with its `=`, such as `id=`, so nothing is built per call.
 fn find_attr_ascii<'a>(buf: &'a [u8], tag: &[u8], attr: &[u8]) -> Option<&'a [u8]> {
-    let mut pat = Vec::with_capacity(1 + tag.len());
-    pat.push(b'<');
-    pat.extend_from_slice(tag);
-    let start = memmem(buf, &pat)?;
+    let start = memmem(buf, tag)?;
     let gt = memchr(&buf[start..], b'>').map(|x| start + x)?;
     let head = &buf[start..gt];
     find_attr_value_in_tag(head, attr)
 }
 
+// `attr` includes the `=`, as in `name=`.
 fn find_attr_value_in_tag<'a>(head: &'a [u8], attr: &[u8]) -> Option<&'a [u8]> {
-    let mut pat = Vec::with_capacity(attr.len() + 1);
-    pat.extend_from_slice(attr);
-    pat.push(b'=');
-    let p = memmem(head, &pat)?;
-    let q = p + pat.len();
+    let p = memmem(head, attr)?;
+    let q = p + attr.len();
     let quote = *head.get(q)?;
     if quote != b'"' && quote != b'\'' {
         return None;
@@ -341,10 +764,10 @@ fn has_cv_name(buf: &[u8], name: &[u8]) -> bool {
         if let Some(gt_rel) = memchr(&buf[from..], b'>') {
             let gt = from + gt_rel;
             let head = &buf[from..gt];
-            if let Some(v) = find_attr_value_in_tag(head, b"name") {
-                if v == name {
-                    return true;
-                }
+            if let Some(v) = find_attr_value_in_tag(head, b"name=")
+                && v == name
+            {
+                return true;
             }
             cur = gt + 1;
             continue;
@@ -367,22 +790,44 @@ fn find_cv_value_u32(buf: &[u8], name: &[u8]) -> Option<u32> {
 
 // <cvParam>
 fn find_cv_value<'a>(buf: &'a [u8], name: &[u8]) -> Option<&'a [u8]> {
+    find_attr_value_in_tag(find_cv_param(buf, name)?, b"value=")
+}
+
+// <cvParam>; its value and `unitName`, if any.
+fn find_cv_value_unit<'a>(buf: &'a [u8], name: &[u8]) -> Option<(&'a [u8], Option<&'a [u8]>)> {
+    let head = find_cv_param(buf, name)?;
+    let value = find_attr_value_in_tag(head, b"value=")?;
+    Some((value, find_attr_value_in_tag(head, b"unitName=")))
+}
+
+// <cvParam>; the opening tag of the first one called `name`.
+fn find_cv_param<'a>(buf: &'a [u8], name: &[u8]) -> Option<&'a [u8]> {
     let mut cur = 0usize;
     const TAG: &[u8] = b"<cvParam";
     while let Some(p) = memmem(&buf[cur..], TAG) {
         let from = cur + p;
         let gt = memchr(&buf[from..], b'>').map(|x| from + x)?;
         let head = &buf[from..gt];
-        if let Some(nm) = find_attr_value_in_tag(head, b"name") {
-            if nm == name {
-                return find_attr_value_in_tag(head, b"value");
-            }
+        if let Some(nm) = find_attr_value_in_tag(head, b"name=")
+            && nm == name
+        {
+            return Some(head);
         }
         cur = gt + 1;
     }
     None
 }
 
+// Factor taking a drift time in `unit` to milliseconds, the unit drift
+// times are reported in. Milliseconds are assumed when no unit is given.
+fn drift_time_scale(unit: Option<&[u8]>) -> f64 {
+    match unit {
+        Some(b"second") => 1e3,
+        Some(b"microsecond") => 1e-3,
+        _ => 1.0,
+    }
+}
+
 // <cvParam name="scan start time">
 fn find_scan_start_time_min(buf: &[u8]) -> Option<f64> {
     let mut cur = 0usize;
@@ -391,33 +836,84 @@ fn find_scan_start_time_min(buf: &[u8]) -> Option<f64> {
         let from = cur + p;
         let gt = memchr(&buf[from..], b'>').map(|x| from + x)?;
         let head = &buf[from..gt];
-        if let Some(nm) = find_attr_value_in_tag(head, b"name") {
-            if nm == b"scan start time" {
-                let val = find_attr_value_in_tag(head, b"value")?;
-                let mut v: f64 = str::from_utf8(val).ok()?.parse().ok()?;
-                if let Some(unit) = find_attr_value_in_tag(head, b"unitName") {
-                    if unit == b"second" {
-                        v /= 60.0;
-                    }
-                }
-                return Some(v);
+        if let Some(nm) = find_attr_value_in_tag(head, b"name=")
+            && nm == b"scan start time"
+        {
+            let val = find_attr_value_in_tag(head, b"value=")?;
+            let mut v: f64 = str::from_utf8(val).ok()?.parse().ok()?;
+            if let Some(unit) = find_attr_value_in_tag(head, b"unitName=")
+                && unit == b"second"
+            {
+                v /= 60.0;
             }
+            return Some(v);
         }
         cur = gt + 1;
     }
     None
 }
 
+// Scan cvParams carrying the ion mobility of a whole spectrum, with the
+// `ion_mobility_type` each maps to.
+const MOBILITY_PARAMS: [(&[u8], &str); 3] = [
+    (
+        b"inverse reduced ion mobility",
+        "inverse reduced ion mobility",
+    ),
+    (b"ion mobility drift time", "drift time"),
+    (b"FAIMS compensation voltage", "compensation voltage"),
+];
+
+// Per-peak mobility array names, with the `ion_mobility_type` each maps to.
+pub(crate) const MOBILITY_ARRAYS: [(&[u8], &str); 6] = [
+    (
+        b"mean inverse reduced ion mobility array",
+        "inverse reduced ion mobility",
+    ),
+    (
+        b"raw inverse reduced ion mobility array",
+        "inverse reduced ion mobility",
+    ),
+    (b"mean ion mobility drift time array", "drift time"),
+    (b"raw ion mobility drift time array", "drift time"),
+    (b"mean ion mobility array", "drift time"),
+    (b"raw ion mobility array", "drift time"),
+];
+
+// The cvParams of a `<binaryDataArray>` that say what it holds and how.
+#[derive(Clone, Copy)]
+struct ArrayFlags {
+    kind_mz: bool,
+    kind_int: bool,
+    kind_time: bool,
+    /// `ion_mobility_type` of a mobility array.
+    kind_mobility: Option<&'static str>,
+    /// Time array given in seconds rather than minutes.
+    seconds: bool,
+    /// Factor taking a drift time array to milliseconds.
+    drift_scale: f64,
+    is_zlib: bool,
+    is_f64: bool,
+    is_f32: bool,
+    little: bool,
+}
+
 // <binaryDataArray>
-fn bda_flags(b: &[u8]) -> (bool, bool, bool, bool, bool, bool) {
+fn bda_flags(b: &[u8]) -> ArrayFlags {
     let stop = memmem(b, b"<binary>").unwrap_or(b.len());
     let head = &b[..stop];
-    let mut kind_mz = false;
-    let mut kind_int = false;
-    let mut is_zlib = false;
-    let mut is_f64 = false;
-    let mut is_f32 = false;
-    let mut little = true;
+    let mut flags = ArrayFlags {
+        kind_mz: false,
+        kind_int: false,
+        kind_time: false,
+        kind_mobility: None,
+        seconds: false,
+        drift_scale: 1.0,
+        is_zlib: false,
+        is_f64: false,
+        is_f32: false,
+        little: true,
+    };
     let mut cur = 0usize;
     const TAG: &[u8] = b"<cvParam";
     while let Some(p) = memmem(&head[cur..], TAG) {
@@ -425,16 +921,29 @@ fn bda_flags(b: &[u8]) -> (bool, bool, bool, bool, bool, bool) {
         if let Some(gt_rel) = memchr(&head[from..], b'>') {
             let gt = from + gt_rel;
             let tag_head = &head[from..gt];
-            if let Some(nm) = find_attr_value_in_tag(tag_head, b"name") {
+            if let Some(nm) = find_attr_value_in_tag(tag_head, b"name=") {
                 match nm {
-                    b"m/z array" => kind_mz = true,
-                    b"intensity array" => kind_int = true,
-                    b"zlib compression" => is_zlib = true,
-                    b"64-bit float" => is_f64 = true,
-                    b"32-bit float" => is_f32 = true,
-                    b"little endian" => little = true,
-                    b"big endian" => little = false,
-                    _ => {}
+                    b"m/z array" => flags.kind_mz = true,
+                    b"intensity array" => flags.kind_int = true,
+                    b"time array" => {
+                        flags.kind_time = true;
+                        flags.seconds =
+                            find_attr_value_in_tag(tag_head, b"unitName=") == Some(b"second");
+                    }
+                    b"zlib compression" => flags.is_zlib = true,
+                    b"64-bit float" => flags.is_f64 = true,
+                    b"32-bit float" => flags.is_f32 = true,
+                    b"little endian" => flags.little = true,
+                    b"big endian" => flags.little = false,
+                    nm => {
+                        if let Some((_, kind)) = MOBILITY_ARRAYS.iter().find(|(a, _)| *a == nm) {
+                            flags.kind_mobility = Some(kind);
+                            if *kind == "drift time" {
+                                let unit = find_attr_value_in_tag(tag_head, b"unitName=");
+                                flags.drift_scale = drift_time_scale(unit);
+                            }
+                        }
+                    }
                 }
             }
             cur = gt + 1;
@@ -442,17 +951,70 @@ fn bda_flags(b: &[u8]) -> (bool, bool, bool, bool, bool, bool) {
             break;
         }
     }
-    (kind_mz, kind_int, is_zlib, is_f64, is_f32, little)
+    flags
+}
+
+// One decoded `<binaryDataArray>`, still in its on-disk encoding.
+struct RawArray<'a> {
+    kind_mz: bool,
+    kind_int: bool,
+    kind_time: bool,
+    kind_mobility: Option<&'static str>,
+    seconds: bool,
+    drift_scale: f64,
+    is_f64: bool,
+    is_f32: bool,
+    little: bool,
+    bytes: &'a [u8],
+    want: usize,
+}
+
+impl RawArray<'_> {
+    fn len(&self) -> usize {
+        if self.is_f64 {
+            self.want.min(self.bytes.len() / 8)
+        } else if self.is_f32 {
+            self.want.min(self.bytes.len() / 4)
+        } else {
+            0
+        }
+    }
+
+    fn write_into(&self, out: &mut [f64]) -> usize {
+        let want = self.want.min(out.len());
+        if self.is_f64 {
+            bytes_to_f64_exact_into(self.bytes, self.little, want, out)
+        } else if self.is_f32 {
+            bytes_to_f32_as_f64_exact_into(self.bytes, self.little, want, out)
+        } else {
+            0
+        }
+    }
+
+    fn to_array_data(&self) -> ArrayData {
+        if self.is_f32 {
+            let mut out = vec![0.0f32; self.len()];
+            bytes_to_f32_exact_into(self.bytes, self.little, self.want, &mut out);
+            ArrayData::F32(out)
+        } else {
+            let mut out = Vec::new();
+            self.write_into_vec(&mut out);
+            ArrayData::F64(out)
+        }
+    }
+
+    fn write_into_vec(&self, out: &mut Vec<f64>) {
+        out.clear();
+        out.resize(self.len(), 0.0);
+        self.write_into(out);
+    }
 }
 
 // <binaryDataArray>, <binary>
-fn decode_binary_arrays(
-    block: &[u8],
-    expected_len: usize,
-    scratch: &mut Scratch,
-) -> (Option<Vec<f64>>, Option<Vec<f64>>) {
-    let mut mz: Option<Vec<f64>> = None;
-    let mut inten: Option<Vec<f64>> = None;
+fn for_each_binary_array<F>(block: &[u8], expected_len: usize, scratch: &mut Scratch, mut f: F)
+where
+    F: FnMut(&RawArray),
+{
     let mut cur = 0usize;
     const BDA: &[u8] = b"<binaryDataArray";
     while let Some(p) = memmem(&block[cur..], BDA) {
@@ -462,59 +1024,88 @@ fn decode_binary_arrays(
             None => break,
         };
         let b = &block[start..start + end_rel];
+        cur = start + end_rel + b"</binaryDataArray>".len();
+
+        let flags = bda_flags(b);
 
-        let (kind_mz, kind_int, is_zlib, is_f64, is_f32, little) = bda_flags(b);
+        let Some((bs, be)) = tag_body(b, b"<binary>", b"</binary>") else {
+            continue;
+        };
+        scratch.b64_buf.clear();
+        if !decode_base64_ws_into(&b[bs..be], &mut scratch.b64_buf) {
+            continue;
+        }
 
-        if let Some((bs, be)) = tag_body(b, b"<binary>", b"</binary>") {
-            scratch.b64_buf.clear();
-            if !decode_base64_ws_into(&b[bs..be], &mut scratch.b64_buf) {
-                cur = start + end_rel + b"</binaryDataArray>".len();
-                continue;
+        let width = if flags.is_f64 { 8 } else { 4 };
+        let bytes: &[u8] = if flags.is_zlib {
+            match inflate_zlib_into(
+                &scratch.b64_buf,
+                &mut scratch.zlib_buf,
+                &mut scratch.inflater,
+                expected_len * width,
+            ) {
+                Some(n) => &scratch.zlib_buf[..n],
+                None => continue,
             }
+        } else {
+            &scratch.b64_buf
+        };
 
-            let bytes: &[u8] = if is_zlib {
-                scratch.zlib_buf.clear();
-                match decompress_to_vec_zlib(&scratch.b64_buf) {
-                    Ok(v) => {
-                        scratch.zlib_buf = v;
-                        &scratch.zlib_buf
-                    }
-                    Err(_) => {
-                        cur = start + end_rel + b"</binaryDataArray>".len();
-                        continue;
-                    }
-                }
-            } else {
-                &scratch.b64_buf
-            };
+        let want = if expected_len > 0 {
+            expected_len
+        } else {
+            bytes.len() / width
+        };
 
-            let want = if expected_len > 0 {
-                expected_len
-            } else if is_f64 {
-                bytes.len() / 8
-            } else {
-                bytes.len() / 4
-            };
+        f(&RawArray {
+            kind_mz: flags.kind_mz,
+            kind_int: flags.kind_int,
+            kind_time: flags.kind_time,
+            kind_mobility: flags.kind_mobility,
+            seconds: flags.seconds,
+            drift_scale: flags.drift_scale,
+            is_f64: flags.is_f64,
+            is_f32: flags.is_f32,
+            little: flags.little,
+            bytes,
+            want,
+        });
+    }
+}
 
-            let vals = if is_f64 {
-                bytes_to_f64_exact_into(bytes, little, want)
-            } else if is_f32 {
-                bytes_to_f32_as_f64_exact_into(bytes, little, want)
-            } else {
-                Vec::new()
-            };
+// The m/z, intensity and mobility arrays of a spectrum, the last with its
+// `ion_mobility_type`.
+type DecodedArrays = (
+    Option<ArrayData>,
+    Option<ArrayData>,
+    Option<(&'static str, ArrayData)>,
+);
 
-            match (kind_mz, kind_int) {
-                (true, false) => mz = Some(vals),
-                (false, true) => inten = Some(vals),
-                (true, true) => {}
-                (false, false) => {}
+// <binaryDataArray>, <binary>
+fn decode_binary_arrays(block: &[u8], expected_len: usize, scratch: &mut Scratch) -> DecodedArrays {
+    let mut mz: Option<ArrayData> = None;
+    let mut inten: Option<ArrayData> = None;
+    let mut mobility = None;
+    for_each_binary_array(block, expected_len, scratch, |raw| {
+        if let Some(kind) = raw.kind_mobility {
+            let mut arr = raw.to_array_data();
+            if raw.drift_scale != 1.0 {
+                match &mut arr {
+                    ArrayData::F32(v) => v.iter_mut().for_each(|x| *x *= raw.drift_scale as f32),
+                    ArrayData::F64(v) => v.iter_mut().for_each(|x| *x *= raw.drift_scale),
+                }
             }
+            mobility = Some((kind, arr));
+            return;
         }
-
-        cur = start + end_rel + b"</binaryDataArray>".len();
-    }
-    (mz, inten)
+        let slot = match (raw.kind_mz, raw.kind_int) {
+            (true, false) => &mut mz,
+            (false, true) => &mut inten,
+            _ => return,
+        };
+        *slot = Some(raw.to_array_data());
+    });
+    (mz, inten, mobility)
 }
 
 // <binary>
@@ -524,142 +1115,84 @@ fn tag_body(hay: &[u8], open: &[u8], close: &[u8]) -> Option<(usize, usize)> {
     Some((s + open.len(), s + open.len() + e_rel))
 }
 
-fn decode_base64_ws_into(s: &[u8], out: &mut Vec<u8>) -> bool {
-    let mut useful = 0usize;
-    let mut pads = 0usize;
-    for &b in s.iter().rev() {
-        if is_ws(b) {
-            continue;
-        }
-        if b == b'=' {
-            pads += 1;
-        } else {
-            break;
-        }
-    }
-    for &b in s {
-        if !is_ws(b) {
-            useful += 1;
-        }
+// Inflates a zlib stream into `out`, which is kept at its high-water length
+// so repeated calls neither reallocate nor re-zero it. Returns the number of
+// bytes produced.
+fn inflate_zlib_into(
+    input: &[u8],
+    out: &mut Vec<u8>,
+    state: &mut DecompressorOxide,
+    size_hint: usize,
+) -> Option<usize> {
+    let flags = TINFL_FLAG_PARSE_ZLIB_HEADER | TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF;
+    let want = size_hint.max(input.len().saturating_mul(2)).max(64);
+    if out.len() < want {
+        out.resize(want, 0);
     }
-    if useful == 0 {
-        return true;
-    }
-    if useful % 4 != 0 {
-        return false;
-    }
-    let estimated = useful / 4 * 3 - pads;
-    out.reserve(estimated);
-    let inv = &BASE64_INV;
-    let mut q = [0u8; 4];
-    let mut qi = 0usize;
-    for &b in s {
-        if is_ws(b) {
-            continue;
-        }
-        q[qi] = b;
-        qi += 1;
-        if qi == 4 {
-            let v0 = inv[q[0] as usize];
-            if v0 == 255 {
-                return false;
-            }
-            let v1 = inv[q[1] as usize];
-            if v1 == 255 {
-                return false;
-            }
-            let v2 = if q[2] == b'=' {
-                0
-            } else {
-                let v = inv[q[2] as usize];
-                if v == 255 {
-                    return false;
-                }
-                v
-            };
-            let v3 = if q[3] == b'=' {
-                0
-            } else {
-                let v = inv[q[3] as usize];
-                if v == 255 {
-                    return false;
-                }
-                v
-            };
-            let n = ((v0 as u32) << 18) | ((v1 as u32) << 12) | ((v2 as u32) << 6) | (v3 as u32);
-            out.push(((n >> 16) & 0xFF) as u8);
-            if q[2] != b'=' {
-                out.push(((n >> 8) & 0xFF) as u8);
-            }
-            if q[3] != b'=' {
-                out.push((n & 0xFF) as u8);
+    state.init();
+    let mut in_pos = 0usize;
+    let mut out_pos = 0usize;
+    loop {
+        let (status, read, written) = decompress(state, &input[in_pos..], out, out_pos, flags);
+        in_pos += read;
+        out_pos += written;
+        match status {
+            TINFLStatus::Done => return Some(out_pos),
+            TINFLStatus::HasMoreOutput => {
+                let grown = out.len() * 2;
+                out.resize(grown, 0);
             }
-            qi = 0;
+            _ => return None,
         }
     }
-    qi == 0
 }
 
 #[inline]
-fn is_ws(b: u8) -> bool {
-    matches!(b, b' ' | b'\n' | b'\r' | b'\t')
-}
-
-#[inline]
-fn bytes_to_f64_exact_into(b: &[u8], little: bool, want: usize) -> Vec<f64> {
-    let len = want.min(b.len() / 8);
-    let mut out = Vec::with_capacity(len);
+fn bytes_to_f64_exact_into(b: &[u8], little: bool, want: usize, out: &mut [f64]) -> usize {
+    let len = want.min(b.len() / 8).min(out.len());
     let bytes = &b[..len * 8];
 
     if little {
-        for c in bytes.chunks_exact(8) {
-            let bits = u64::from_le_bytes([c[0], c[1], c[2], c[3], c[4], c[5], c[6], c[7]]);
-            out.push(f64::from_bits(bits));
+        for (o, c) in out.iter_mut().zip(bytes.chunks_exact(8)) {
+            *o = f64::from_le_bytes([c[0], c[1], c[2], c[3], c[4], c[5], c[6], c[7]]);
         }
     } else {
-        for c in bytes.chunks_exact(8) {
-            let bits = u64::from_be_bytes([c[0], c[1], c[2], c[3], c[4], c[5], c[6], c[7]]);
-            out.push(f64::from_bits(bits));
+        for (o, c) in out.iter_mut().zip(bytes.chunks_exact(8)) {
+            *o = f64::from_be_bytes([c[0], c[1], c[2], c[3], c[4], c[5], c[6], c[7]]);
         }
     }
-    out
+    len
 }
 
 #[inline]
-fn bytes_to_f32_as_f64_exact_into(b: &[u8], little: bool, want: usize) -> Vec<f64> {
-    let len = want.min(b.len() / 4);
-    let mut out = Vec::with_capacity(len);
+fn bytes_to_f32_exact_into(b: &[u8], little: bool, want: usize, out: &mut [f32]) -> usize {
+    let len = want.min(b.len() / 4).min(out.len());
     let words = &b[..len * 4];
 
-    for c in words.chunks_exact(4) {
-        let bits = if little {
-            u32::from_le_bytes([c[0], c[1], c[2], c[3]])
+    for (o, c) in out.iter_mut().zip(words.chunks_exact(4)) {
+        *o = if little {
+            f32::from_le_bytes([c[0], c[1], c[2], c[3]])
         } else {
-            u32::from_be_bytes([c[0], c[1], c[2], c[3]])
+            f32::from_be_bytes([c[0], c[1], c[2], c[3]])
         };
-        out.push(f32::from_bits(bits) as f64);
     }
-    out
+    len
 }
 
-fn memmem(hay: &[u8], needle: &[u8]) -> Option<usize> {
-    if needle.is_empty() {
-        return Some(0);
-    }
-    let first = needle[0];
-    let mut i = 0usize;
-    while let Some(rel) = memchr(&hay[i..], first) {
-        let p = i + rel;
-        if hay.get(p..p + needle.len()).map_or(false, |w| w == needle) {
-            return Some(p);
-        }
-        i = p + 1;
-    }
-    None
-}
+#[inline]
+fn bytes_to_f32_as_f64_exact_into(b: &[u8], little: bool, want: usize, out: &mut [f64]) -> usize {
+    let len = want.min(b.len() / 4).min(out.len());
+    let words = &b[..len * 4];
 
-fn memchr(hay: &[u8], byte: u8) -> Option<usize> {
-    hay.iter().position(|&b| b == byte)
+    for (o, c) in out.iter_mut().zip(words.chunks_exact(4)) {
+        let v = if little {
+            f32::from_le_bytes([c[0], c[1], c[2], c[3]])
+        } else {
+            f32::from_be_bytes([c[0], c[1], c[2], c[3]])
+        };
+        *o = v as f64;
+    }
+    len
 }
 
 fn strip_ws(s: &[u8]) -> &[u8] {
@@ -681,7 +1214,7 @@ fn parse_u64_ascii(s: &[u8]) -> Option<u64> {
     }
     let mut v: u64 = 0;
     for &c in t {
-        if c < b'0' || c > b'9' {
+        if !c.is_ascii_digit() {
             return None;
         }
         v = v.checked_mul(10)?.checked_add((c - b'0') as u64)?;
@@ -689,14 +1222,265 @@ fn parse_u64_ascii(s: &[u8]) -> Option<u64> {
     Some(v)
 }
 
-const fn build_b64_inv() -> [u8; 256] {
-    let mut t = [255u8; 256];
-    let alphabet = *b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
-    let mut i = 0;
-    while i < 64 {
-        t[alphabet[i] as usize] = i as u8;
-        i += 1;
+/// Fixtures for the tests of the modules that take parsed runs.
+#[cfg(test)]
+pub(crate) mod test_spectra {
+    use super::{ArrayData, SpectrumSummary};
+
+    /// Indexed mzML with four spectra: an MS1 profile spectrum with two
+    /// Gaussian peaks at m/z 400.2 and 400.35, an MS2 spectrum isolated at
+    /// 400.2 (-0.7, +0.8) with selected ion 400.21, a 400-point MS1 spectrum
+    /// in zlib-compressed 32-bit floats and an MS1 spectrum with a drift
+    /// time and no arrays.
+    pub(crate) const MZML: &[u8] = include_bytes!("../../tests/data/small.mzML");
+
+    /// A centroided positive mode spectrum of level `ms_level` at `rt`
+    /// minutes, holding `peaks` as (m/z, intensity) pairs.
+    pub(crate) fn spectrum(ms_level: u32, rt: f64, peaks: &[(f64, f64)]) -> SpectrumSummary {
+        let (mz, intensity): (Vec<f64>, Vec<f64>) = peaks.iter().copied().unzip();
+        SpectrumSummary {
+            array_length: peaks.len(),
+            ms_level: Some(ms_level),
+            polarity: Some("positive".to_string()),
+            spectrum_type: Some("centroid".to_string()),
+            retention_time: Some(rt),
+            mz_array: Some(ArrayData::F64(mz)),
+            intensity_array: Some(ArrayData::F64(intensity)),
+            ..SpectrumSummary::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An indexed file with one spectrum whose index says `offset`.
+    fn indexed(offset: Option<usize>) -> Vec<u8> {
+        let spectrum = concat!(
+            r#"<spectrum index="0" id="scan=1" defaultArrayLength="0">"#,
+            r#"<cvParam name="ms level" value="1"/></spectrum>"#,
+        );
+        let mut doc = String::from("<indexedmzML><mzML><run><spectrumList>");
+        let at = doc.len();
+        doc.push_str(spectrum);
+        doc.push_str("</spectrumList></run></mzML>");
+        let index_at = doc.len();
+        doc.push_str(&format!(
+            r#"<indexList count="1"><index name="spectrum"><offset idRef="scan=1">{}</offset></index></indexList>"#,
+            offset.unwrap_or(at)
+        ));
+        doc.push_str(&format!(
+            "<indexListOffset>{index_at}</indexListOffset></indexedmzML>"
+        ));
+        doc.into_bytes()
+    }
+
+    #[test]
+    fn index_offsets_are_used() {
+        let bytes = indexed(None);
+        let spans = spectrum_spans(&bytes).unwrap();
+        assert_eq!(spans.len(), 1);
+        assert!(bytes[spans[0].clone()].starts_with(b"<spectrum "));
+        let spectra = parse_mzml(&bytes).unwrap();
+        assert_eq!(spectra[0].id, "scan=1");
+        assert_eq!(spectra[0].ms_level, Some(1));
+    }
+
+    #[test]
+    fn offsets_past_the_end_are_rejected() {
+        let bytes = indexed(Some(1 << 40));
+        assert!(spectrum_spans(&bytes).is_err());
+        assert!(parse_mzml(&bytes).is_err());
+    }
+
+    #[test]
+    fn attributes_need_the_exact_name() {
+        let head = br#"<cvParam name="scan start time" value="1.5" unitName="minute""#;
+        assert_eq!(
+            find_attr_value_in_tag(head, b"name="),
+            Some(&b"scan start time"[..])
+        );
+        assert_eq!(find_attr_value_in_tag(head, b"value="), Some(&b"1.5"[..]));
+        assert_eq!(
+            find_attr_value_in_tag(head, b"unitName="),
+            Some(&b"minute"[..])
+        );
+        assert_eq!(find_attr_value_in_tag(head, b"accession="), None);
+    }
+
+    #[test]
+    fn filtered_spectra_are_skipped() {
+        let spectrum = |i: usize, level: u32, rt: f64, polarity: &str| {
+            format!(
+                r#"<spectrum index="{i}" id="scan={i}" defaultArrayLength="0"><cvParam name="ms level" value="{level}"/><cvParam name="{polarity} scan"/><cvParam name="scan start time" value="{rt}" unitName="minute"/></spectrum>"#
+            )
+        };
+        let doc = format!(
+            "<mzML><run><spectrumList>{}{}{}</spectrumList></run></mzML>",
+            spectrum(0, 1, 1.0, "positive"),
+            spectrum(1, 2, 1.5, "positive"),
+            spectrum(2, 1, 3.0, "negative"),
+        );
+        let kept = |opts: &ParseOptions| -> Vec<usize> {
+            let spectra = parse_mzml_with(doc.as_bytes(), opts).unwrap();
+            spectra.iter().map(|s| s.index).collect()
+        };
+        assert_eq!(kept(&ParseOptions::default()), [0, 1, 2]);
+        let ms1 = ParseOptions {
+            ms_levels: vec![1],
+            ..ParseOptions::default()
+        };
+        assert_eq!(kept(&ms1), [0, 2]);
+        let window = ParseOptions {
+            rt: Some((1.0, 2.0)),
+            ..ms1.clone()
+        };
+        assert_eq!(kept(&window), [0]);
+        let negative = ParseOptions {
+            polarity: Some("negative".into()),
+            ..ms1
+        };
+        assert_eq!(kept(&negative), [2]);
+    }
+
+    #[test]
+    fn precursor_isolation_window_and_selected_ion() {
+        let spectra = parse_mzml(test_spectra::MZML).unwrap();
+        let ms2 = &spectra[1];
+        assert_eq!(ms2.ms_level, Some(2));
+        assert_eq!(ms2.isolation_window_target, Some(400.2));
+        assert_eq!(ms2.isolation_window_lower, Some(0.7));
+        assert_eq!(ms2.isolation_window_upper, Some(0.8));
+        assert_eq!(ms2.precursor_mz, Some(400.21));
+        assert_eq!(ms2.precursor_charge, Some(2));
+        assert_eq!(ms2.retention_time, Some(0.6));
+        // The scan window is not mistaken for the isolation window.
+        assert_eq!(ms2.scan_window_lower_limit, Some(100.0));
+        assert_eq!(ms2.scan_window_upper_limit, Some(1000.0));
+        assert_eq!(spectra[0].isolation_window_target, None);
+        assert_eq!(spectra[0].precursor_mz, None);
+
+        // Offsets are only read inside `<isolationWindow>`: the ones in the
+        // scan window here, and those of the product, are ignored.
+        let doc = br#"<mzML><run><spectrumList><spectrum index="0" id="scan=1" defaultArrayLength="0">
+<cvParam name="ms level" value="2"/>
+<scanList><scan><scanWindowList><scanWindow>
+<cvParam name="scan window lower limit" value="50"/>
+<cvParam name="scan window upper limit" value="1500"/>
+<cvParam name="isolation window lower offset" value="9"/>
+</scanWindow></scanWindowList></scan></scanList>
+<precursorList><precursor><isolationWindow>
+<cvParam name="isolation window target m/z" value="622.5"/>
+<cvParam name="isolation window upper offset" value="12.5"/>
+</isolationWindow>
+<selectedIonList><selectedIon><cvParam name="selected ion m/z" value="622.8"/></selectedIon></selectedIonList>
+</precursor></precursorList>
+<productList><product><isolationWindow>
+<cvParam name="isolation window lower offset" value="3"/>
+</isolationWindow></product></productList>
+</spectrum></spectrumList></run></mzML>"#;
+        let s = &parse_mzml(doc).unwrap()[0];
+        assert_eq!(s.isolation_window_target, Some(622.5));
+        assert_eq!(s.isolation_window_lower, None);
+        assert_eq!(s.isolation_window_upper, Some(12.5));
+        assert_eq!(s.precursor_mz, Some(622.8));
+        assert_eq!(s.precursor_charge, None);
+        assert_eq!(
+            (s.scan_window_lower_limit, s.scan_window_upper_limit),
+            (Some(50.0), Some(1500.0))
+        );
+    }
+
+    #[test]
+    fn ion_mobility_params_and_drift_time_units() {
+        let spectra = parse_mzml(test_spectra::MZML).unwrap();
+        assert_eq!(spectra[3].ion_mobility, Some(21.5));
+        assert_eq!(spectra[3].ion_mobility_type.as_deref(), Some("drift time"));
+        assert_eq!(spectra[0].ion_mobility, None);
+
+        let scan = |param: &str| {
+            format!(
+                r#"<spectrum index="0" id="scan=1" defaultArrayLength="0"><cvParam name="ms level" value="1"/><scanList><scan>{param}</scan></scanList></spectrum>"#
+            )
+        };
+        let body = [
+            scan(r#"<cvParam name="inverse reduced ion mobility" value="0.85" unitName="volt-second per square centimeter"/>"#),
+            scan(r#"<cvParam name="ion mobility drift time" value="21.5" unitName="millisecond"/>"#),
+            scan(r#"<cvParam name="ion mobility drift time" value="0.0215" unitName="second"/>"#),
+            scan(r#"<cvParam name="ion mobility drift time" value="21.5"/>"#),
+        ]
+        .concat();
+        let doc = format!("<mzML><run><spectrumList>{body}</spectrumList></run></mzML>");
+        let spectra = parse_mzml(doc.as_bytes()).unwrap();
+        let mobility: Vec<_> = spectra
+            .iter()
+            .map(|s| {
+                (
+                    s.ion_mobility.unwrap(),
+                    s.ion_mobility_type.as_deref().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(mobility[0], (0.85, "inverse reduced ion mobility"));
+        for &(v, kind) in &mobility[1..] {
+            assert!((v - 21.5).abs() < 1e-9, "{v}");
+            assert_eq!(kind, "drift time");
+        }
+
+        // A drift time array in seconds comes out in milliseconds too.
+        let array = |name: &str, unit: &str, data: &str| {
+            format!(
+                r#"<binaryDataArray encodedLength="{}"><cvParam name="64-bit float"/><cvParam name="no compression"/><cvParam name="{name}"{unit}/><binary>{data}</binary></binaryDataArray>"#,
+                data.len()
+            )
+        };
+        let doc = format!(
+            r#"<mzML><run><spectrumList><spectrum index="0" id="scan=1" defaultArrayLength="2"><cvParam name="ms level" value="1"/><binaryDataArrayList count="3">{}{}{}</binaryDataArrayList></spectrum></spectrumList></run></mzML>"#,
+            array("m/z array", "", "AAAAAAAAeUAAAAAAAEB/QA=="),
+            array("intensity array", "", "AAAAAAAAJEAAAAAAAAA0QA=="),
+            array(
+                "raw ion mobility drift time array",
+                r#" unitName="second""#,
+                "arx0kxgElj+4HoXrUbiePw=="
+            ),
+        );
+        let s = &parse_mzml(doc.as_bytes()).unwrap()[0];
+        assert_eq!(s.ion_mobility_type.as_deref(), Some("drift time"));
+        let Some(ArrayData::F64(drift)) = &s.mobility_array else {
+            panic!("no drift time array: {:?}", s.mobility_array);
+        };
+        assert_eq!(drift.len(), 2);
+        assert!(
+            (drift[0] - 21.5).abs() < 1e-9 && (drift[1] - 30.0).abs() < 1e-9,
+            "{drift:?}"
+        );
+    }
+
+    #[test]
+    fn decode_into_slices_reports_full_lengths() {
+        let start = memmem(test_spectra::MZML, br#"<spectrum index="2""#).unwrap();
+        let len = memmem(&test_spectra::MZML[start..], b"</spectrum>").unwrap();
+        let block = &test_spectra::MZML[start..start + len];
+        let n = ArrayDecoder::array_length(block);
+        assert_eq!(n, 400);
+        let (mut whole_mz, mut whole_int) = (Vec::new(), Vec::new());
+        let mut decoder = ArrayDecoder::new();
+        decoder.decode_into(block, &mut whole_mz, &mut whole_int);
+
+        let (mut mz, mut intensity) = (vec![0.0; n], vec![0.0; n]);
+        assert_eq!(
+            decoder.decode_into_slices(block, &mut mz, &mut intensity),
+            (n, n)
+        );
+        assert_eq!((mz, intensity), (whole_mz.clone(), whole_int));
+
+        // Short buffers get a prefix and still learn the full length.
+        let (mut mz, mut intensity) = (vec![0.0; 10], vec![]);
+        assert_eq!(
+            decoder.decode_into_slices(block, &mut mz, &mut intensity),
+            (n, n)
+        );
+        assert_eq!(mz, whole_mz[..10]);
     }
-    t
 }
-static BASE64_INV: [u8; 256] = build_b64_inv();
@@ -0,0 +1,483 @@
+//! Centroiding of profile spectra.
+//!
+//! Two pickers are available: local maxima refined by a three-point apex
+//! fit, and a continuous wavelet transform (Mexican hat) picker that is more
+//! robust on noisy data. Both drop peaks below a signal-to-noise threshold
+//! or narrower than a minimum number of profile points.
+
+use super::parse_mzml::{ArrayData, SpectrumSummary};
+use super::stats::{self, NanPolicy};
+
+/// How the apex of a local maximum is interpolated from its three highest
+/// points.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ApexFit {
+    /// Parabola through the intensities.
+    Parabolic,
+    /// Parabola through the log intensities, exact for Gaussian peaks.
+    #[default]
+    Gaussian,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeakPicker {
+    LocalMaxima(ApexFit),
+    /// Wavelet scales `1..=max_scale`, in profile points.
+    Cwt {
+        max_scale: usize,
+    },
+}
+
+impl Default for PeakPicker {
+    fn default() -> Self {
+        PeakPicker::LocalMaxima(ApexFit::default())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PeakPickingOptions {
+    pub picker: PeakPicker,
+    /// Minimum ratio of apex intensity to the spectrum noise level, where
+    /// noise is the median of the non-zero intensities. 0 keeps every peak.
+    pub snr: f64,
+    /// Minimum number of profile points from the valley on one side of a
+    /// peak to the valley on the other, both included.
+    pub min_width: usize,
+}
+
+impl Default for PeakPickingOptions {
+    fn default() -> Self {
+        PeakPickingOptions {
+            picker: PeakPicker::default(),
+            snr: 0.0,
+            min_width: 3,
+        }
+    }
+}
+
+/// Centroids of one profile spectrum as `(mz, intensity)`, in m/z order.
+/// `mz` must be sorted ascending and as long as `intensity`.
+pub fn pick_peaks(
+    mz: &[f64],
+    intensity: &[f64],
+    opts: &PeakPickingOptions,
+) -> (Vec<f64>, Vec<f64>) {
+    let n = mz.len().min(intensity.len());
+    let (mz, intensity) = (&mz[..n], &intensity[..n]);
+    let noise = noise_level(intensity);
+    let apexes = match opts.picker {
+        PeakPicker::LocalMaxima(_) => local_maxima(intensity),
+        PeakPicker::Cwt { max_scale } => {
+            let scales: Vec<f64> = (1..=max_scale.max(1)).map(|s| s as f64).collect();
+            cwt_apexes(intensity, &scales)
+        }
+    };
+    let fit = match opts.picker {
+        PeakPicker::LocalMaxima(fit) => fit,
+        PeakPicker::Cwt { .. } => ApexFit::Gaussian,
+    };
+
+    let mut out_mz = Vec::with_capacity(apexes.len());
+    let mut out_int = Vec::with_capacity(apexes.len());
+    for i in apexes {
+        if intensity[i] < opts.snr * noise {
+            continue;
+        }
+        let (lo, hi) = peak_extent(intensity, i);
+        if hi - lo + 1 < opts.min_width {
+            continue;
+        }
+        let (x, y) = fit_apex(mz, intensity, i, fit);
+        out_mz.push(x);
+        out_int.push(y);
+    }
+    (out_mz, out_int)
+}
+
+/// Centroided copy of a profile spectrum with `spectrum_type` set to
+/// `"centroid"`. Spectra that are not profile, or lack an array, are
+/// returned unchanged. Arrays keep the precision they were stored with.
+pub fn centroid_spectrum(s: &SpectrumSummary, opts: &PeakPickingOptions) -> SpectrumSummary {
+    let mut out = s.clone();
+    centroid_in_place(&mut out, opts);
+    out
+}
+
+/// Centroids `s` in place if it is a profile spectrum.
+pub fn centroid_in_place(s: &mut SpectrumSummary, opts: &PeakPickingOptions) {
+    if s.spectrum_type.as_deref() != Some("profile") {
+        return;
+    }
+    let (Some(mz), Some(int)) = (&s.mz_array, &s.intensity_array) else {
+        return;
+    };
+    let (pmz, pint) = pick_peaks(&mz.to_f64(), &int.to_f64(), opts);
+    s.array_length = pmz.len();
+    s.mz_array = Some(same_precision(mz, pmz));
+    s.intensity_array = Some(same_precision(int, pint));
+    // Picked peaks no longer line up with per-peak mobilities.
+    s.mobility_array = None;
+    s.spectrum_type = Some("centroid".to_string());
+}
+
+fn same_precision(like: &ArrayData, v: Vec<f64>) -> ArrayData {
+    match like {
+        ArrayData::F32(_) => ArrayData::F32(v.into_iter().map(|x| x as f32).collect()),
+        ArrayData::F64(_) => ArrayData::F64(v),
+    }
+}
+
+fn noise_level(intensity: &[f64]) -> f64 {
+    let nonzero: Vec<f64> = intensity.iter().copied().filter(|&y| y > 0.0).collect();
+    stats::median(&nonzero, NanPolicy::Omit)
+        .ok()
+        .filter(|m| m.is_finite())
+        .unwrap_or(0.0)
+}
+
+// Interior points higher than the left neighbour and at least as high as the
+// right one; a flat top is reported once, at its first point.
+pub(crate) fn local_maxima(y: &[f64]) -> Vec<usize> {
+    let mut out = Vec::new();
+    for i in 1..y.len().saturating_sub(1) {
+        if y[i] > y[i - 1] && y[i] >= y[i + 1] && y[i] > 0.0 {
+            out.push(i);
+        }
+    }
+    out
+}
+
+// Indices of the valleys bounding the peak whose apex is at `i`.
+fn peak_extent(y: &[f64], i: usize) -> (usize, usize) {
+    let mut lo = i;
+    while lo > 0 && y[lo - 1] < y[lo] {
+        lo -= 1;
+    }
+    let mut hi = i;
+    while hi + 1 < y.len() && y[hi + 1] <= y[hi] && (hi == i || y[hi + 1] < y[hi]) {
+        hi += 1;
+    }
+    (lo, hi)
+}
+
+// Vertex of the parabola through the apex and its neighbours, in (x, g(y))
+// where g is the identity or ln. For a real maximum the parabola opens
+// downwards and its vertex lies between the midpoints to either neighbour.
+// Anything else, or neighbours spaced too unevenly for the fit to mean much
+// (the edge of a gap in the profile), falls back to the apex point itself.
+fn fit_apex(x: &[f64], y: &[f64], i: usize, fit: ApexFit) -> (f64, f64) {
+    let apex = (x[i], y[i]);
+    if i == 0 || i + 1 >= x.len() {
+        return apex;
+    }
+    if fit == ApexFit::Gaussian && (y[i - 1] <= 0.0 || y[i + 1] <= 0.0) {
+        return apex;
+    }
+    let g = |v: f64| match fit {
+        ApexFit::Parabolic => v,
+        ApexFit::Gaussian => v.ln(),
+    };
+    let (x0, x1, x2) = (x[i - 1], x[i], x[i + 1]);
+    let (left, right) = (x1 - x0, x2 - x1);
+    if !(left > 0.0 && right > 0.0) || left.max(right) > 2.0 * left.min(right) {
+        return apex;
+    }
+    let (g0, g1, g2) = (g(y[i - 1]), g(y[i]), g(y[i + 1]));
+
+    // Lagrange basis weights; `a` and `b` are the x^2 and x coefficients.
+    let w0 = g0 / ((x0 - x1) * (x0 - x2));
+    let w1 = g1 / ((x1 - x0) * (x1 - x2));
+    let w2 = g2 / ((x2 - x0) * (x2 - x1));
+    let a = w0 + w1 + w2;
+    let b = -(w0 * (x1 + x2) + w1 * (x0 + x2) + w2 * (x0 + x1));
+    if a >= 0.0 || !a.is_finite() {
+        return apex;
+    }
+    let xv = -b / (2.0 * a);
+    if !(0.5 * (x0 + x1)..=0.5 * (x1 + x2)).contains(&xv) {
+        return apex;
+    }
+    let gv = g1 - a * (xv - x1) * (xv - x1);
+    let yv = match fit {
+        ApexFit::Parabolic => gv,
+        ApexFit::Gaussian => gv.exp(),
+    };
+    (xv, yv.max(y[i]))
+}
+
+// Mexican hat wavelet of width `s`, normalised to unit energy.
+fn ricker(t: f64, s: f64) -> f64 {
+    let a = 2.0 / ((3.0 * s).sqrt() * std::f64::consts::PI.powf(0.25));
+    let u = t / s;
+    a * (1.0 - u * u) * (-0.5 * u * u).exp()
+}
+
+/// Transform of `y` with the Mexican hat of width `s` samples.
+pub(crate) fn cwt_row(y: &[f64], s: f64) -> Vec<f64> {
+    let half = (5.0 * s).ceil() as isize;
+    let kernel: Vec<f64> = (-half..=half).map(|k| ricker(k as f64, s)).collect();
+    let n = y.len() as isize;
+    (0..n)
+        .map(|i| {
+            let mut acc = 0.0;
+            for (j, &w) in kernel.iter().enumerate() {
+                let k = i + j as isize - half;
+                if (0..n).contains(&k) {
+                    acc += w * y[k as usize];
+                }
+            }
+            acc
+        })
+        .collect()
+}
+
+/// Ridge-line detection over ascending `scales` (in samples): maxima of the
+/// transform are linked from the largest scale down to the smallest, and
+/// ridges spanning at least half the scales become peaks. Each peak is then
+/// snapped to the highest raw point within one smallest-scale width of where
+/// its ridge ends.
+pub(crate) fn cwt_apexes(y: &[f64], scales: &[f64]) -> Vec<usize> {
+    if y.is_empty() || scales.is_empty() {
+        return Vec::new();
+    }
+    let rows: Vec<Vec<f64>> = scales.iter().map(|&s| cwt_row(y, s)).collect();
+    let maxima: Vec<Vec<usize>> = rows
+        .iter()
+        .map(|r| {
+            local_maxima(r)
+                .into_iter()
+                .filter(|&i| r[i] > 0.0)
+                .collect()
+        })
+        .collect();
+    let min_len = scales.len().div_ceil(2);
+    let reach = |s: usize| scales[s].ceil().max(1.0) as usize;
+
+    let mut used: Vec<Vec<bool>> = maxima.iter().map(|m| vec![false; m.len()]).collect();
+    let mut out = Vec::new();
+    for top in (0..scales.len()).rev() {
+        for start in 0..maxima[top].len() {
+            if used[top][start] {
+                continue;
+            }
+            used[top][start] = true;
+            let mut pos = maxima[top][start];
+            let mut len = 1usize;
+            for s in (0..top).rev() {
+                let window = reach(s);
+                let next = maxima[s]
+                    .iter()
+                    .enumerate()
+                    .filter(|&(k, &p)| !used[s][k] && p.abs_diff(pos) <= window)
+                    .min_by_key(|&(_, &p)| p.abs_diff(pos));
+                let Some((k, &p)) = next else {
+                    break;
+                };
+                used[s][k] = true;
+                pos = p;
+                len += 1;
+            }
+            if len < min_len {
+                continue;
+            }
+            let lo = pos.saturating_sub(reach(0));
+            let hi = (pos + reach(0)).min(y.len() - 1);
+            out.push((lo..=hi).fold(pos, |b, i| if y[i] > y[b] { i } else { b }));
+        }
+    }
+    out.sort_unstable();
+    out.dedup();
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utilities::parse_mzml::test_spectra::MZML;
+    use crate::utilities::parse_mzml::{ParseOptions, parse_mzml_with};
+
+    // Profile points every `step` over `lo..=hi`, summing Gaussians given
+    // as (centre, height, sigma), on a flat baseline.
+    fn profile(
+        lo: f64,
+        hi: f64,
+        step: f64,
+        peaks: &[(f64, f64, f64)],
+        base: f64,
+    ) -> (Vec<f64>, Vec<f64>) {
+        let n = ((hi - lo) / step).round() as usize + 1;
+        let mz: Vec<f64> = (0..n).map(|k| lo + k as f64 * step).collect();
+        let y = mz
+            .iter()
+            .map(|&x| {
+                base + peaks
+                    .iter()
+                    .map(|&(c, h, s)| h * (-0.5 * ((x - c) / s).powi(2)).exp())
+                    .sum::<f64>()
+            })
+            .collect();
+        (mz, y)
+    }
+
+    fn picker(picker: PeakPicker) -> PeakPickingOptions {
+        PeakPickingOptions {
+            picker,
+            ..PeakPickingOptions::default()
+        }
+    }
+
+    #[test]
+    fn apex_fits_on_sampled_gaussians() {
+        // The true apex falls between profile points.
+        let (centre, height) = (500.0037, 1000.0);
+        let (mz, y) = profile(499.9, 500.1, 0.005, &[(centre, height, 0.01)], 0.0);
+
+        let gaussian = picker(PeakPicker::LocalMaxima(ApexFit::Gaussian));
+        let (gx, gy) = pick_peaks(&mz, &y, &gaussian);
+        assert_eq!(gx.len(), 1);
+        assert!((gx[0] - centre).abs() < 1e-9, "{}", gx[0]);
+        assert!((gy[0] - height).abs() < 1e-6, "{}", gy[0]);
+
+        let parabolic = picker(PeakPicker::LocalMaxima(ApexFit::Parabolic));
+        let (px, py) = pick_peaks(&mz, &y, &parabolic);
+        assert_eq!(px.len(), 1);
+        // Close, but off where the Gaussian fit is exact.
+        assert!((px[0] - centre).abs() < 5e-4, "{}", px[0]);
+        assert!((px[0] - centre).abs() > 1e-6);
+        assert!((py[0] - height).abs() < 0.02 * height, "{}", py[0]);
+        // Either way the apex is never below the highest point.
+        let top = y.iter().copied().fold(0.0, f64::max);
+        assert!(gy[0] >= top && py[0] >= top);
+    }
+
+    #[test]
+    fn cwt_separates_close_peaks() {
+        // Two peaks 0.06 apart with a little deterministic ripple.
+        let peaks = [(400.1, 800.0, 0.01), (400.16, 500.0, 0.01)];
+        let (mz, mut y) = profile(400.0, 400.3, 0.0025, &peaks, 10.0);
+        for (k, v) in y.iter_mut().enumerate() {
+            *v += [0.0, 3.0, -2.0, 1.0][k % 4];
+        }
+        let cwt = PeakPickingOptions {
+            snr: 3.0,
+            ..picker(PeakPicker::Cwt { max_scale: 8 })
+        };
+        let (px, py) = pick_peaks(&mz, &y, &cwt);
+        assert_eq!(px.len(), 2, "{px:?}");
+        for ((x, h), &(c, height, _)) in px.iter().zip(&py).zip(&peaks) {
+            assert!((x - c).abs() < 0.002, "{x} for {c}");
+            assert!(
+                (h - (height + 10.0)).abs() < 0.05 * height,
+                "{h} for {height}"
+            );
+        }
+    }
+
+    #[test]
+    fn snr_and_width_filters() {
+        // Peaks of height 1000 and 30 over a baseline of 10, and a one-point
+        // spike of 500.
+        let (mz, mut y) = profile(
+            100.0,
+            101.0,
+            0.005,
+            &[(100.3, 1000.0, 0.01), (100.6, 30.0, 0.01)],
+            10.0,
+        );
+        let spike = 160;
+        y[spike] = 500.0;
+
+        let all = pick_peaks(
+            &mz,
+            &y,
+            &PeakPickingOptions {
+                min_width: 0,
+                ..PeakPickingOptions::default()
+            },
+        );
+        assert_eq!(all.0.len(), 3);
+
+        // The noise level is the median intensity, the baseline here.
+        let snr = PeakPickingOptions {
+            snr: 10.0,
+            min_width: 0,
+            ..PeakPickingOptions::default()
+        };
+        let (x, _) = pick_peaks(&mz, &y, &snr);
+        assert_eq!(x.len(), 2);
+        assert!((x[0] - 100.3).abs() < 1e-6, "{x:?}");
+        assert!((x[1] - mz[spike]).abs() < 1e-9, "{x:?}");
+
+        // The spike spans its two neighbours; the Gaussians many points.
+        let wide = PeakPickingOptions {
+            min_width: 4,
+            ..PeakPickingOptions::default()
+        };
+        let (x, _) = pick_peaks(&mz, &y, &wide);
+        assert_eq!(x.len(), 2);
+        assert!((x[0] - 100.3).abs() < 1e-6 && (x[1] - 100.6).abs() < 1e-6);
+    }
+
+    #[test]
+    fn centroiding_keeps_precision_and_marks_the_type() {
+        let (mz, y) = profile(300.0, 300.2, 0.004, &[(300.1, 100.0, 0.01)], 0.0);
+        let profile_spectrum = SpectrumSummary {
+            array_length: mz.len(),
+            spectrum_type: Some("profile".to_string()),
+            mz_array: Some(ArrayData::F64(mz.clone())),
+            intensity_array: Some(ArrayData::F32(y.iter().map(|&v| v as f32).collect())),
+            mobility_array: Some(ArrayData::F64(vec![1.0; mz.len()])),
+            ..SpectrumSummary::default()
+        };
+        let c = centroid_spectrum(&profile_spectrum, &PeakPickingOptions::default());
+        assert_eq!(c.spectrum_type.as_deref(), Some("centroid"));
+        assert_eq!(c.array_length, 1);
+        assert!(matches!(c.mz_array, Some(ArrayData::F64(ref v)) if (v[0] - 300.1).abs() < 1e-6));
+        assert!(matches!(c.intensity_array, Some(ArrayData::F32(ref v)) if v.len() == 1));
+        assert!(c.mobility_array.is_none());
+
+        // Anything but a profile spectrum is left alone.
+        let centroid = SpectrumSummary {
+            spectrum_type: Some("centroid".to_string()),
+            ..profile_spectrum.clone()
+        };
+        assert_eq!(
+            centroid_spectrum(&centroid, &PeakPickingOptions::default()),
+            centroid
+        );
+        let untyped = SpectrumSummary {
+            spectrum_type: None,
+            ..profile_spectrum
+        };
+        assert_eq!(
+            centroid_spectrum(&untyped, &PeakPickingOptions::default()),
+            untyped
+        );
+    }
+
+    #[test]
+    fn parse_options_centroid_profile_spectra() {
+        let opts = ParseOptions {
+            centroid: Some(PeakPickingOptions::default()),
+            ..ParseOptions::default()
+        };
+        let raw = parse_mzml_with(MZML, &ParseOptions::default()).unwrap();
+        let spectra = parse_mzml_with(MZML, &opts).unwrap();
+        assert_eq!(raw[0].spectrum_type.as_deref(), Some("profile"));
+        assert_eq!(raw[0].array_length, 151);
+
+        let s = &spectra[0];
+        assert_eq!(s.spectrum_type.as_deref(), Some("centroid"));
+        assert_eq!(s.array_length, 2);
+        let mz = s.mz_array.as_ref().unwrap();
+        assert!(matches!(mz, ArrayData::F64(_)));
+        assert!(matches!(s.intensity_array, Some(ArrayData::F32(_))));
+        let mz = mz.to_f64();
+        assert!(
+            (mz[0] - 400.2).abs() < 1e-4 && (mz[1] - 400.35).abs() < 1e-4,
+            "{mz:?}"
+        );
+        // Centroided spectra pass through unchanged.
+        assert_eq!(spectra[1..], raw[1..]);
+    }
+}
@@ -0,0 +1,1033 @@
+//! Targeted quantification from a transition list: peak integration per
+//! transition, internal standard normalization, calibration curves and a
+//! concentration table with QC flags.
+//!
+//! Each transition is traced in each run from the first source that has
+//! it: an SRM/MRM `<chromatogram>` with matching Q1 and Q3, the product ion
+//! XIC of the MS2 scans isolating its precursor (PRM), or, for transitions
+//! without a product, the MS1 XIC of the precursor. The quantifier peak
+//! area, divided by that of the internal standard when there is one, is the
+//! response; standards of known concentration give a weighted calibration
+//! curve per compound, which is inverted for every sample.
+
+use std::io::Write;
+
+use super::calibration::solve_normal;
+use super::correspondence::{csv_field, xic};
+use super::parse_mzml::{Chromatogram, SpectrumSummary, parse_chromatograms, parse_mzml};
+use super::signal::{NoiseEstimator, noise};
+
+/// One row of the transition list.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Transition {
+    pub compound: String,
+    /// Q1, or the m/z of the MS1 ion for transitions without a product.
+    pub precursor_mz: f64,
+    /// Q3; unset to quantify from MS1.
+    pub product_mz: Option<f64>,
+    /// Expected retention time, in minutes; the apex is looked for over the
+    /// whole trace when unset.
+    pub rt: Option<f64>,
+    /// Half width of the retention time window; the option's when unset.
+    pub rt_window: Option<f64>,
+    /// The transition the compound is quantified on; the others are
+    /// qualifiers.
+    pub quantifier: bool,
+    /// Compound whose quantifier area normalizes this compound's.
+    pub internal_standard: Option<String>,
+    /// Expected qualifier to quantifier area ratio; the mean over the
+    /// standards when unset.
+    pub ion_ratio: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SampleType {
+    #[default]
+    Unknown,
+    /// Calibration standard.
+    Standard,
+    Qc,
+    Blank,
+}
+
+impl SampleType {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SampleType::Unknown => "unknown",
+            SampleType::Standard => "standard",
+            SampleType::Qc => "qc",
+            SampleType::Blank => "blank",
+        }
+    }
+}
+
+/// One row of the sample list.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Sample {
+    pub name: String,
+    /// mzML file, as written in the list.
+    pub file: String,
+    pub sample_type: SampleType,
+    /// Nominal concentrations of standards and QCs, by compound.
+    pub concentrations: Vec<(String, f64)>,
+}
+
+impl Sample {
+    pub fn nominal(&self, compound: &str) -> Option<f64> {
+        self.concentrations
+            .iter()
+            .find(|(c, _)| c == compound)
+            .map(|&(_, v)| v)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CurveModel {
+    #[default]
+    Linear,
+    Quadratic,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CurveWeighting {
+    #[default]
+    None,
+    /// 1/x.
+    InverseX,
+    /// 1/x².
+    InverseX2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QuantOptions {
+    /// Q1 and Q3 tolerance, in Da, when matching SRM chromatograms and PRM
+    /// precursors.
+    pub mz_tolerance: f64,
+    /// Half width of product and MS1 XIC windows, in ppm.
+    pub ppm: f64,
+    /// Half width of the retention time window, in minutes, for
+    /// transitions that do not give one.
+    pub rt_window: f64,
+    /// Peaks below this signal to noise ratio are flagged.
+    pub min_signal_to_noise: f64,
+    /// Largest relative deviation of a qualifier ion ratio from the
+    /// expected one.
+    pub ratio_tolerance: f64,
+    pub model: CurveModel,
+    pub weighting: CurveWeighting,
+    /// Largest relative deviation of a standard or QC from its nominal
+    /// concentration.
+    pub accuracy_tolerance: f64,
+}
+
+impl Default for QuantOptions {
+    fn default() -> Self {
+        QuantOptions {
+            mz_tolerance: 0.5,
+            ppm: 10.0,
+            rt_window: 0.5,
+            min_signal_to_noise: 3.0,
+            ratio_tolerance: 0.3,
+            model: CurveModel::Linear,
+            weighting: CurveWeighting::None,
+            accuracy_tolerance: 0.15,
+        }
+    }
+}
+
+/// QC flags of a [`QuantResult`], or'ed together.
+pub const QC_NOT_FOUND: u32 = 1;
+pub const QC_LOW_SIGNAL_TO_NOISE: u32 = 1 << 1;
+pub const QC_ION_RATIO: u32 = 1 << 2;
+pub const QC_BELOW_RANGE: u32 = 1 << 3;
+pub const QC_ABOVE_RANGE: u32 = 1 << 4;
+pub const QC_NO_CALIBRATION: u32 = 1 << 5;
+pub const QC_ACCURACY: u32 = 1 << 6;
+pub const QC_INTERNAL_STANDARD: u32 = 1 << 7;
+
+const FLAG_NAMES: [(u32, &str); 8] = [
+    (QC_NOT_FOUND, "not_found"),
+    (QC_LOW_SIGNAL_TO_NOISE, "low_signal_to_noise"),
+    (QC_ION_RATIO, "ion_ratio"),
+    (QC_BELOW_RANGE, "below_range"),
+    (QC_ABOVE_RANGE, "above_range"),
+    (QC_NO_CALIBRATION, "no_calibration"),
+    (QC_ACCURACY, "accuracy"),
+    (QC_INTERNAL_STANDARD, "internal_standard"),
+];
+
+/// Names of the flags set in `flags`, joined by `;`.
+pub fn flag_names(flags: u32) -> String {
+    FLAG_NAMES
+        .iter()
+        .filter(|(f, _)| flags & f != 0)
+        .map(|&(_, n)| n)
+        .collect::<Vec<_>>()
+        .join(";")
+}
+
+/// The spectra and chromatograms of one mzML file.
+#[derive(Debug, Clone, Default)]
+pub struct Run {
+    pub spectra: Vec<SpectrumSummary>,
+    pub chromatograms: Vec<Chromatogram>,
+}
+
+impl Run {
+    pub fn from_mzml(bytes: &[u8]) -> Result<Run, String> {
+        Ok(Run {
+            spectra: parse_mzml(bytes)?,
+            chromatograms: parse_chromatograms(bytes)?,
+        })
+    }
+}
+
+/// An integrated chromatographic peak.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IntegratedPeak {
+    pub rt: f64,
+    pub rt_start: f64,
+    pub rt_end: f64,
+    /// Trapezoid area above the straight baseline joining the peak bounds.
+    pub area: f64,
+    /// Apex height above that baseline.
+    pub height: f64,
+    /// Height over the noise of the trace outside the peak; infinite on a
+    /// noiseless trace.
+    pub signal_to_noise: f64,
+}
+
+/// Integrates the most intense peak of a trace with its apex within
+/// `rt_window` of `rt`, or anywhere when `rt` is unset. The peak extends
+/// from the apex down to the first valley below half its height, or to
+/// where the signal falls under 1% of it.
+pub fn integrate_peak(
+    time: &[f64],
+    intensity: &[f64],
+    rt: Option<f64>,
+    rt_window: f64,
+) -> Option<IntegratedPeak> {
+    let n = time.len().min(intensity.len());
+    let (time, y) = (&time[..n], &intensity[..n]);
+    let apex = (0..n)
+        .filter(|&k| rt.is_none_or(|r| (time[k] - r).abs() <= rt_window))
+        .filter(|&k| y[k].is_finite())
+        .max_by(|&a, &b| y[a].total_cmp(&y[b]))?;
+    let top = y[apex];
+    if top <= 0.0 {
+        return None;
+    }
+    // Whether the peak ends at k, given the next point outwards j.
+    let stop = |k: usize, j: usize| y[k] <= 0.01 * top || (y[k] < 0.5 * top && y[j] > y[k]);
+    let mut lo = apex;
+    while lo > 0 && !stop(lo, lo - 1) {
+        lo -= 1;
+    }
+    let mut hi = apex;
+    while hi + 1 < n && !stop(hi, hi + 1) {
+        hi += 1;
+    }
+
+    let line = |t: f64| {
+        if time[hi] > time[lo] {
+            y[lo] + (y[hi] - y[lo]) * (t - time[lo]) / (time[hi] - time[lo])
+        } else {
+            y[lo].min(y[hi])
+        }
+    };
+    let mut area = 0.0;
+    for k in lo..hi {
+        let a = y[k] - line(time[k]);
+        let b = y[k + 1] - line(time[k + 1]);
+        area += 0.5 * (a + b) * (time[k + 1] - time[k]);
+    }
+    let height = top - line(time[apex]);
+
+    let outside: Vec<f64> = y[..lo].iter().chain(&y[hi + 1..]).copied().collect();
+    let level = if outside.len() >= 3 {
+        noise(&outside, NoiseEstimator::DiffMad).unwrap_or(f64::NAN)
+    } else {
+        noise(y, NoiseEstimator::DiffMad).unwrap_or(f64::NAN)
+    };
+    let signal_to_noise = if level > 0.0 {
+        height / level
+    } else {
+        f64::INFINITY
+    };
+    Some(IntegratedPeak {
+        rt: time[apex],
+        rt_start: time[lo],
+        rt_end: time[hi],
+        area: area.max(0.0),
+        height: height.max(0.0),
+        signal_to_noise,
+    })
+}
+
+/// Retention times and intensities of `t` in `run`, from the first source
+/// that has it; None when none does.
+pub fn transition_trace(
+    run: &Run,
+    t: &Transition,
+    opts: &QuantOptions,
+) -> Option<(Vec<f64>, Vec<f64>)> {
+    let near = |a: Option<f64>, b: f64| a.is_some_and(|a| (a - b).abs() <= opts.mz_tolerance);
+    let Some(q3) = t.product_mz else {
+        let tol = t.precursor_mz * opts.ppm * 1e-6;
+        let trace = xic(
+            &run.spectra,
+            (t.precursor_mz - tol, t.precursor_mz + tol),
+            (f64::NEG_INFINITY, f64::INFINITY),
+        );
+        return (!trace.0.is_empty()).then_some(trace);
+    };
+
+    if let Some(c) = run
+        .chromatograms
+        .iter()
+        .find(|c| near(c.precursor_mz, t.precursor_mz) && near(c.product_mz, q3))
+    {
+        return Some((c.time.clone(), c.intensity.clone()));
+    }
+
+    let tol = q3 * opts.ppm * 1e-6;
+    let mut points: Vec<(f64, f64)> = run
+        .spectra
+        .iter()
+        .filter(|s| s.ms_level.is_some_and(|l| l >= 2) && near(s.precursor_mz, t.precursor_mz))
+        .filter_map(|s| {
+            let rt = s.retention_time?;
+            let (Some(mz), Some(y)) = (&s.mz_array, &s.intensity_array) else {
+                return Some((rt, 0.0));
+            };
+            let sum = mz
+                .iter()
+                .zip(y.iter())
+                .filter(|(m, _)| (m - q3).abs() <= tol)
+                .fold(0.0, |acc, (_, y)| acc + y);
+            Some((rt, sum))
+        })
+        .collect();
+    if points.is_empty() {
+        return None;
+    }
+    points.sort_by(|a, b| a.0.total_cmp(&b.0));
+    Some(points.into_iter().unzip())
+}
+
+/// Response as a function of concentration, fitted on the standards.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CalibrationCurve {
+    /// Intercept, slope and quadratic coefficient.
+    pub coefficients: [f64; 3],
+    pub n_points: usize,
+    pub r_squared: f64,
+    /// Concentration range of the standards.
+    pub min_concentration: f64,
+    pub max_concentration: f64,
+}
+
+impl CalibrationCurve {
+    pub fn response(&self, concentration: f64) -> f64 {
+        let c = &self.coefficients;
+        c[0] + concentration * (c[1] + concentration * c[2])
+    }
+
+    /// Concentration giving `response`; of the two roots of a quadratic,
+    /// the one nearest the calibrated range.
+    pub fn concentration(&self, response: f64) -> Option<f64> {
+        let [c0, b, a] = self.coefficients;
+        let c = c0 - response;
+        let x = if a.abs() * self.max_concentration.abs() <= 1e-12 * b.abs() {
+            -c / b
+        } else {
+            let disc = b * b - 4.0 * a * c;
+            if disc < 0.0 {
+                return None;
+            }
+            let q = -0.5 * (b + b.signum() * disc.sqrt());
+            let roots = [q / a, if q != 0.0 { c / q } else { q / a }];
+            let distance = |x: f64| {
+                (self.min_concentration - x)
+                    .max(x - self.max_concentration)
+                    .max(0.0)
+            };
+            roots
+                .into_iter()
+                .filter(|x| x.is_finite())
+                .min_by(|x, y| distance(*x).total_cmp(&distance(*y)))?
+        };
+        x.is_finite().then_some(x)
+    }
+}
+
+/// Fits `(concentration, response)` points. Weighted fits leave out points
+/// at concentration 0. None when there are too few distinct
+/// concentrations for the model.
+pub fn fit_curve(
+    points: &[(f64, f64)],
+    model: CurveModel,
+    weighting: CurveWeighting,
+) -> Option<CalibrationCurve> {
+    let weighted: Vec<(f64, f64, f64)> = points
+        .iter()
+        .filter(|(x, y)| x.is_finite() && y.is_finite())
+        .filter_map(|&(x, y)| {
+            let w = match weighting {
+                CurveWeighting::None => 1.0,
+                CurveWeighting::InverseX => 1.0 / x,
+                CurveWeighting::InverseX2 => 1.0 / (x * x),
+            };
+            (w.is_finite() && w > 0.0).then_some((x, y, w))
+        })
+        .collect();
+    let n = match model {
+        CurveModel::Linear => 2,
+        CurveModel::Quadratic => 3,
+    };
+    let mut levels: Vec<f64> = weighted.iter().map(|p| p.0).collect();
+    levels.sort_by(f64::total_cmp);
+    levels.dedup();
+    if levels.len() < n {
+        return None;
+    }
+    let coefficients = solve_normal(&weighted, n)?;
+    let mut curve = CalibrationCurve {
+        coefficients,
+        n_points: weighted.len(),
+        r_squared: f64::NAN,
+        min_concentration: levels[0],
+        max_concentration: levels[levels.len() - 1],
+    };
+    let mean = weighted.iter().fold(0.0, |s, p| s + p.1) / weighted.len() as f64;
+    let total = weighted.iter().fold(0.0, |s, p| s + (p.1 - mean).powi(2));
+    let residual = weighted
+        .iter()
+        .fold(0.0, |s, p| s + (p.1 - curve.response(p.0)).powi(2));
+    if total > 0.0 {
+        curve.r_squared = 1.0 - residual / total;
+    }
+    Some(curve)
+}
+
+/// One compound in one sample.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuantResult {
+    pub sample: usize,
+    pub compound: usize,
+    /// Quantifier peak.
+    pub peak: Option<IntegratedPeak>,
+    /// Quantifier area of the internal standard, when the compound has one.
+    pub internal_standard_area: Option<f64>,
+    /// Area, or area ratio to the internal standard; NaN when missing.
+    pub response: f64,
+    /// Area ratio of the first qualifier to the quantifier.
+    pub ion_ratio: Option<f64>,
+    /// NaN when it cannot be computed.
+    pub concentration: f64,
+    pub nominal: Option<f64>,
+    /// `QC_*` flags.
+    pub flags: u32,
+}
+
+/// Results of every compound in every sample, sample after sample.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct QuantTable {
+    pub compounds: Vec<String>,
+    pub samples: Vec<Sample>,
+    /// Calibration curve of each compound.
+    pub curves: Vec<Option<CalibrationCurve>>,
+    pub results: Vec<QuantResult>,
+}
+
+impl QuantTable {
+    pub fn result(&self, sample: usize, compound: usize) -> Option<&QuantResult> {
+        if compound >= self.compounds.len() {
+            return None;
+        }
+        self.results.get(sample * self.compounds.len() + compound)
+    }
+
+    /// Writes one row per sample and compound. Missing values are left
+    /// empty.
+    pub fn write_csv<W: Write>(&self, w: &mut W) -> Result<(), String> {
+        let mut out = String::from(
+            "sample,type,compound,rt,area,height,signal_to_noise,internal_standard_area,\
+             response,ion_ratio,concentration,nominal,accuracy,flags\n",
+        );
+        let num = |v: Option<f64>| {
+            v.filter(|v| !v.is_nan())
+                .map_or(String::new(), |v| v.to_string())
+        };
+        for r in &self.results {
+            let sample = &self.samples[r.sample];
+            let accuracy = r.nominal.filter(|&n| n > 0.0).map(|n| r.concentration / n);
+            out.push_str(&format!(
+                "{},{},{},{},{},{},{},{},{},{},{},{},{},{}\n",
+                csv_field(&sample.name),
+                sample.sample_type.as_str(),
+                csv_field(&self.compounds[r.compound]),
+                num(r.peak.map(|p| p.rt)),
+                num(r.peak.map(|p| p.area)),
+                num(r.peak.map(|p| p.height)),
+                num(r.peak.map(|p| p.signal_to_noise)),
+                num(r.internal_standard_area),
+                num(Some(r.response)),
+                num(r.ion_ratio),
+                num(Some(r.concentration)),
+                num(r.nominal),
+                num(accuracy),
+                flag_names(r.flags),
+            ));
+        }
+        w.write_all(out.as_bytes())
+            .map_err(|e| format!("write csv: {e}"))
+    }
+}
+
+/// Quantifies every compound of `transitions` in every sample.
+/// `read_run` gives the run of a sample; runs are read one at a time, in
+/// sample order, and dropped once their transitions are integrated.
+pub fn quantify(
+    transitions: &[Transition],
+    samples: &[Sample],
+    mut read_run: impl FnMut(&Sample) -> Result<Run, String>,
+    opts: &QuantOptions,
+) -> Result<QuantTable, String> {
+    let mut compounds: Vec<String> = Vec::new();
+    for t in transitions {
+        if !compounds.contains(&t.compound) {
+            compounds.push(t.compound.clone());
+        }
+    }
+    let nc = compounds.len();
+    // Quantifier first, then the qualifiers, per compound.
+    let mut by_compound: Vec<Vec<&Transition>> = vec![Vec::new(); nc];
+    for t in transitions {
+        let c = compounds.iter().position(|c| *c == t.compound).unwrap_or(0);
+        if t.quantifier {
+            by_compound[c].insert(0, t);
+        } else {
+            by_compound[c].push(t);
+        }
+    }
+    if let Some(c) = (0..nc).find(|&c| !by_compound[c][0].quantifier) {
+        return Err(format!("no quantifier for {}", compounds[c]));
+    }
+    let internal_standard: Vec<Option<usize>> = by_compound
+        .iter()
+        .map(|ts| {
+            let name = ts[0].internal_standard.as_ref()?;
+            Some(compounds.iter().position(|c| c == name).ok_or(name))
+        })
+        .map(|r| r.transpose())
+        .collect::<Result<_, _>>()
+        .map_err(|name| format!("unknown internal standard {name}"))?;
+
+    // Peaks of every transition, in the order of `by_compound`.
+    let mut peaks: Vec<Vec<Vec<Option<IntegratedPeak>>>> = Vec::with_capacity(samples.len());
+    for sample in samples {
+        let run = read_run(sample)?;
+        peaks.push(
+            by_compound
+                .iter()
+                .map(|ts| {
+                    ts.iter()
+                        .map(|t| {
+                            let (time, y) = transition_trace(&run, t, opts)?;
+                            let window = t.rt_window.unwrap_or(opts.rt_window);
+                            integrate_peak(&time, &y, t.rt, window).filter(|p| p.area > 0.0)
+                        })
+                        .collect()
+                })
+                .collect(),
+        );
+    }
+    let area = |s: usize, c: usize, k: usize| peaks[s][c][k].map(|p| p.area);
+
+    let mut results = Vec::with_capacity(samples.len() * nc);
+    for (s, sample) in samples.iter().enumerate() {
+        for c in 0..nc {
+            let mut r = QuantResult {
+                sample: s,
+                compound: c,
+                peak: peaks[s][c][0],
+                internal_standard_area: None,
+                response: f64::NAN,
+                ion_ratio: None,
+                concentration: f64::NAN,
+                nominal: sample.nominal(&compounds[c]),
+                flags: 0,
+            };
+            match r.peak {
+                None => r.flags |= QC_NOT_FOUND,
+                Some(p) if p.signal_to_noise < opts.min_signal_to_noise => {
+                    r.flags |= QC_LOW_SIGNAL_TO_NOISE
+                }
+                Some(_) => {}
+            }
+            r.response = r.peak.map_or(f64::NAN, |p| p.area);
+            if let Some(is) = internal_standard[c] {
+                r.internal_standard_area = area(s, is, 0);
+                match r.internal_standard_area {
+                    Some(a) if a > 0.0 => r.response /= a,
+                    _ => {
+                        r.response = f64::NAN;
+                        r.flags |= QC_INTERNAL_STANDARD;
+                    }
+                }
+            }
+            if by_compound[c].len() > 1 {
+                r.ion_ratio = r.peak.and_then(|p| Some(area(s, c, 1)? / p.area));
+            }
+            results.push(r);
+        }
+    }
+
+    let is_standard: Vec<bool> = (0..nc)
+        .map(|c| internal_standard.contains(&Some(c)))
+        .collect();
+    let mut curves = Vec::with_capacity(nc);
+    for c in 0..nc {
+        let points: Vec<(f64, f64)> = samples
+            .iter()
+            .enumerate()
+            .filter(|(_, smp)| smp.sample_type == SampleType::Standard)
+            .filter_map(|(s, _)| {
+                let r = &results[s * nc + c];
+                Some((r.nominal?, r.response))
+            })
+            .collect();
+        curves.push(fit_curve(&points, opts.model, opts.weighting));
+    }
+
+    for c in 0..nc {
+        // Expected ratio of each qualifier: given, or the mean over the
+        // standards.
+        let expected: Vec<Option<f64>> = (1..by_compound[c].len())
+            .map(|k| {
+                by_compound[c][k].ion_ratio.or_else(|| {
+                    let ratios: Vec<f64> = (0..samples.len())
+                        .filter(|&s| samples[s].sample_type == SampleType::Standard)
+                        .filter_map(|s| Some(area(s, c, k)? / area(s, c, 0)?))
+                        .collect();
+                    (!ratios.is_empty()).then(|| ratios.iter().sum::<f64>() / ratios.len() as f64)
+                })
+            })
+            .collect();
+
+        for s in 0..samples.len() {
+            let r = &mut results[s * nc + c];
+            if let Some(quant) = area(s, c, 0) {
+                for (k, e) in expected.iter().enumerate() {
+                    let Some(e) = e else { continue };
+                    let ok = area(s, c, k + 1)
+                        .is_some_and(|q| (q / quant / e - 1.0).abs() <= opts.ratio_tolerance);
+                    if !ok {
+                        r.flags |= QC_ION_RATIO;
+                    }
+                }
+            }
+            let Some(curve) = &curves[c] else {
+                if !is_standard[c] {
+                    r.flags |= QC_NO_CALIBRATION;
+                }
+                continue;
+            };
+            if r.response.is_nan() {
+                continue;
+            }
+            r.concentration = curve.concentration(r.response).unwrap_or(f64::NAN);
+            // Standards define the range; their accuracy is checked instead.
+            if samples[s].sample_type != SampleType::Standard {
+                if r.concentration < curve.min_concentration {
+                    r.flags |= QC_BELOW_RANGE;
+                } else if r.concentration > curve.max_concentration {
+                    r.flags |= QC_ABOVE_RANGE;
+                }
+            }
+            let checked = matches!(
+                samples[s].sample_type,
+                SampleType::Standard | SampleType::Qc
+            );
+            if let Some(n) = r.nominal.filter(|&n| checked && n > 0.0)
+                && (r.concentration / n - 1.0)
+                    .abs()
+                    .partial_cmp(&opts.accuracy_tolerance)
+                    .is_none_or(|o| o.is_gt())
+            {
+                r.flags |= QC_ACCURACY;
+            }
+        }
+    }
+
+    Ok(QuantTable {
+        compounds,
+        samples: samples.to_vec(),
+        curves,
+        results,
+    })
+}
+
+/// Reads a transition list: CSV with a header naming, in any case and
+/// order, `compound`, `precursor_mz` (or `q1`) and optionally `product_mz`
+/// (`q3`), `rt`, `rt_window`, `type` (`quantifier` or `qualifier`),
+/// `internal_standard` and `ion_ratio`. A compound none of whose rows is
+/// typed is quantified on its first.
+pub fn parse_transitions(text: &str) -> Result<Vec<Transition>, String> {
+    let rows = csv_records(text);
+    let ((_, header), rows) = rows.split_first().ok_or("empty transition list")?;
+    let col = |names: &[&str]| {
+        header
+            .iter()
+            .position(|h| names.contains(&column_key(h).as_str()))
+    };
+    let compound = col(&["compound", "name", "analyte"]).ok_or("no compound column")?;
+    let q1 = col(&["precursor_mz", "q1"]).ok_or("no precursor_mz column")?;
+    let q3 = col(&["product_mz", "q3"]);
+    let rt = col(&["rt", "retention_time"]);
+    let rt_window = col(&["rt_window"]);
+    let kind = col(&["type", "role"]);
+    let istd = col(&["internal_standard", "istd"]);
+    let ratio = col(&["ion_ratio"]);
+
+    let mut out: Vec<Transition> = Vec::new();
+    let mut typed: Vec<String> = Vec::new();
+    for (line, row) in rows {
+        let field = |c: Option<usize>| {
+            c.and_then(|c| row.get(c))
+                .map(|v| v.trim())
+                .filter(|v| !v.is_empty())
+        };
+        let number = |c: Option<usize>, what: &str| {
+            field(c)
+                .map(|v| {
+                    v.parse::<f64>()
+                        .map_err(|_| format!("line {line}: bad {what} '{v}'"))
+                })
+                .transpose()
+        };
+        let name = field(Some(compound)).ok_or(format!("line {line}: no compound"))?;
+        let quantifier = match field(kind).map(str::to_ascii_lowercase).as_deref() {
+            None => None,
+            Some("quantifier" | "quant" | "quan") => Some(true),
+            Some("qualifier" | "qual") => Some(false),
+            Some(v) => return Err(format!("line {line}: unknown type '{v}'")),
+        };
+        if let Some(q) = quantifier {
+            if q && out.iter().any(|t| t.compound == name && t.quantifier) {
+                return Err(format!("line {line}: second quantifier for {name}"));
+            }
+            typed.push(name.to_string());
+        }
+        out.push(Transition {
+            compound: name.to_string(),
+            precursor_mz: number(Some(q1), "precursor_mz")?
+                .ok_or(format!("line {line}: no precursor_mz"))?,
+            product_mz: number(q3, "product_mz")?,
+            rt: number(rt, "rt")?,
+            rt_window: number(rt_window, "rt_window")?,
+            quantifier: quantifier.unwrap_or(false),
+            internal_standard: field(istd).map(str::to_string),
+            ion_ratio: number(ratio, "ion_ratio")?,
+        });
+    }
+    for k in 0..out.len() {
+        let name = &out[k].compound;
+        if !typed.contains(name) && !out[..k].iter().any(|t| t.compound == *name) {
+            out[k].quantifier = true;
+        }
+    }
+    Ok(out)
+}
+
+/// Reads a sample list: CSV with a `file` column and optionally `name`
+/// (the file when unset) and `type` (`unknown`, `standard`, `qc` or
+/// `blank`). Every other column is a compound, holding the nominal
+/// concentrations of standards and QCs.
+pub fn parse_sample_list(text: &str) -> Result<Vec<Sample>, String> {
+    let rows = csv_records(text);
+    let ((_, header), rows) = rows.split_first().ok_or("empty sample list")?;
+    let keys: Vec<String> = header.iter().map(|h| column_key(h)).collect();
+    let col = |names: &[&str]| keys.iter().position(|k| names.contains(&k.as_str()));
+    let file = col(&["file", "path"]).ok_or("no file column")?;
+    let name = col(&["name", "sample"]);
+    let kind = col(&["type", "sample_type"]);
+
+    let mut out = Vec::new();
+    for (line, row) in rows {
+        let field = |c: usize| row.get(c).map(|v| v.trim()).filter(|v| !v.is_empty());
+        let path = field(file).ok_or(format!("line {line}: no file"))?;
+        let sample_type = match kind.and_then(field).map(str::to_ascii_lowercase).as_deref() {
+            None | Some("unknown" | "sample") => SampleType::Unknown,
+            Some("standard" | "std" | "calibrator" | "cal") => SampleType::Standard,
+            Some("qc") => SampleType::Qc,
+            Some("blank") => SampleType::Blank,
+            Some(v) => return Err(format!("line {line}: unknown sample type '{v}'")),
+        };
+        let mut concentrations = Vec::new();
+        for (c, h) in header.iter().enumerate() {
+            if c == file || Some(c) == name || Some(c) == kind {
+                continue;
+            }
+            if let Some(v) = field(c) {
+                let v = v
+                    .parse::<f64>()
+                    .map_err(|_| format!("line {line}: bad concentration '{v}'"))?;
+                concentrations.push((h.trim().to_string(), v));
+            }
+        }
+        out.push(Sample {
+            name: name.and_then(field).unwrap_or(path).to_string(),
+            file: path.to_string(),
+            sample_type,
+            concentrations,
+        });
+    }
+    Ok(out)
+}
+
+fn column_key(h: &str) -> String {
+    h.trim().to_ascii_lowercase().replace([' ', '-'], "_")
+}
+
+// Non-empty, non-comment lines split into fields, with their line numbers.
+// Fields may be quoted, with `""` for a quote; they may not span lines.
+fn csv_records(text: &str) -> Vec<(usize, Vec<String>)> {
+    let mut out = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = Vec::new();
+        let mut field = String::new();
+        let mut quoted = false;
+        let mut chars = line.chars().peekable();
+        while let Some(ch) = chars.next() {
+            match ch {
+                '"' if quoted && chars.peek() == Some(&'"') => {
+                    field.push('"');
+                    chars.next();
+                }
+                '"' => quoted = !quoted,
+                ',' if !quoted => fields.push(std::mem::take(&mut field)),
+                _ => field.push(ch),
+            }
+        }
+        fields.push(field);
+        out.push((i + 1, fields));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A Gaussian peak of `height` at `rt` on a 0.01 min grid, with a small
+    // alternating noise.
+    fn trace(rt: f64, height: f64) -> (Vec<f64>, Vec<f64>) {
+        (0..300)
+            .map(|k| {
+                let t = k as f64 * 0.01;
+                let d = (t - rt) / 0.03;
+                let noise = if k % 2 == 0 { 1.0 } else { -1.0 };
+                (t, 10.0 + noise + height * (-0.5 * d * d).exp())
+            })
+            .unzip()
+    }
+
+    fn srm(q1: f64, q3: f64, (time, intensity): (Vec<f64>, Vec<f64>)) -> Chromatogram {
+        Chromatogram {
+            precursor_mz: Some(q1),
+            product_mz: Some(q3),
+            time,
+            intensity,
+            ..Chromatogram::default()
+        }
+    }
+
+    const TRANSITIONS: &str = "\
+Compound,Q1,Q3,RT,Type,Internal standard
+Drug,300.1,150.0,1.0,quantifier,Drug-d3
+Drug,300.1,120.0,1.0,qualifier,
+Drug-d3,303.1,153.0,1.0,,
+";
+
+    const SAMPLES: &str = "\
+File,Name,Type,Drug
+s1.mzML,Cal 1,standard,1
+s2.mzML,Cal 2,std,2
+s4.mzML,Cal 4,standard,4
+qc.mzML,QC,qc,3
+u.mzML,\"Patient, 1\",,
+";
+
+    // Drug at `amount` times 1000 counts with a qualifier at half of that,
+    // over a constant internal standard.
+    fn run(amount: f64) -> Run {
+        Run {
+            chromatograms: vec![
+                srm(300.1, 150.0, trace(1.0, 1000.0 * amount)),
+                srm(300.1, 120.0, trace(1.0, 500.0 * amount)),
+                srm(303.1, 153.0, trace(1.0, 2000.0)),
+            ],
+            ..Run::default()
+        }
+    }
+
+    #[test]
+    fn gaussian_peaks_are_integrated() {
+        let (time, y) = trace(1.5, 1000.0);
+        let p = integrate_peak(&time, &y, Some(1.45), 0.1).unwrap();
+        assert!((p.rt - 1.5).abs() < 1e-9);
+        assert!(p.rt_start < 1.45 && p.rt_end > 1.55);
+        let expected = 1000.0 * 0.03 * (2.0 * std::f64::consts::PI).sqrt();
+        assert!((p.area / expected - 1.0).abs() < 0.02);
+        assert!((p.height - 1000.0).abs() < 5.0);
+        assert!(p.signal_to_noise > 100.0);
+        // No apex in the window, or nothing above zero.
+        assert!(integrate_peak(&time, &vec![0.0; 300], None, 0.1).is_none());
+        assert!(integrate_peak(&[], &[], None, 0.1).is_none());
+    }
+
+    #[test]
+    fn curves_are_fitted_and_inverted() {
+        let points: Vec<(f64, f64)> = [1.0, 2.0, 5.0, 10.0]
+            .iter()
+            .map(|&x| (x, 0.5 + 2.0 * x))
+            .collect();
+        let c = fit_curve(&points, CurveModel::Linear, CurveWeighting::InverseX).unwrap();
+        assert!((c.coefficients[0] - 0.5).abs() < 1e-9 && (c.coefficients[1] - 2.0).abs() < 1e-9);
+        assert!((c.r_squared - 1.0).abs() < 1e-12);
+        assert_eq!((c.min_concentration, c.max_concentration), (1.0, 10.0));
+        assert!((c.concentration(8.5).unwrap() - 4.0).abs() < 1e-9);
+
+        let points: Vec<(f64, f64)> = [0.0, 1.0, 2.0, 5.0, 10.0]
+            .iter()
+            .map(|&x| (x, 1.0 + 3.0 * x - 0.1 * x * x))
+            .collect();
+        let c = fit_curve(&points, CurveModel::Quadratic, CurveWeighting::None).unwrap();
+        assert!((c.coefficients[2] + 0.1).abs() < 1e-9);
+        // Of the two roots, the one within the calibrated range.
+        assert!((c.concentration(c.response(7.0)).unwrap() - 7.0).abs() < 1e-9);
+        // 1/x² weighting leaves out the blank level.
+        let w = fit_curve(&points, CurveModel::Quadratic, CurveWeighting::InverseX2).unwrap();
+        assert_eq!((w.n_points, w.min_concentration), (4, 1.0));
+
+        assert!(fit_curve(&points[..2], CurveModel::Quadratic, CurveWeighting::None).is_none());
+        assert!(
+            fit_curve(
+                &[(1.0, 1.0), (1.0, 2.0)],
+                CurveModel::Linear,
+                CurveWeighting::None
+            )
+            .is_none()
+        );
+    }
+
+    #[test]
+    fn lists_are_parsed() {
+        let t = parse_transitions(TRANSITIONS).unwrap();
+        assert_eq!(t.len(), 3);
+        assert!(t[0].quantifier && !t[1].quantifier);
+        // Untyped compounds are quantified on their first row.
+        assert!(t[2].quantifier);
+        assert_eq!(t[0].internal_standard.as_deref(), Some("Drug-d3"));
+        assert_eq!(t[1].product_mz, Some(120.0));
+        assert_eq!(t[1].rt_window, None);
+        assert!(parse_transitions("compound,q1\nA,x\n").is_err());
+        assert!(parse_transitions("compound,q1,type\nA,1,quant\nA,2,quant\n").is_err());
+        assert!(parse_transitions("name,q3\nA,1\n").is_err());
+
+        let s = parse_sample_list(SAMPLES).unwrap();
+        assert_eq!(s.len(), 5);
+        assert_eq!(s[1].sample_type, SampleType::Standard);
+        assert_eq!(s[1].nominal("Drug"), Some(2.0));
+        assert_eq!(s[4].name, "Patient, 1");
+        assert_eq!(s[4].sample_type, SampleType::Unknown);
+        assert_eq!(s[4].nominal("Drug"), None);
+        assert!(parse_sample_list("file,type\na.mzML,patient\n").is_err());
+        assert!(parse_sample_list("name\nA\n").is_err());
+    }
+
+    #[test]
+    fn samples_are_quantified_run_by_run() {
+        let transitions = parse_transitions(TRANSITIONS).unwrap();
+        let samples = parse_sample_list(SAMPLES).unwrap();
+        let amounts = [1.0, 2.0, 4.0, 3.3, 2.5];
+        let mut read = Vec::new();
+        let table = quantify(
+            &transitions,
+            &samples,
+            |s| {
+                read.push(s.file.clone());
+                Ok(run(amounts[read.len() - 1]))
+            },
+            &QuantOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(read, ["s1.mzML", "s2.mzML", "s4.mzML", "qc.mzML", "u.mzML"]);
+        assert_eq!(table.compounds, ["Drug", "Drug-d3"]);
+        assert!(table.curves[0].is_some());
+
+        let qc = table.result(3, 0).unwrap();
+        assert!((qc.concentration - 3.3).abs() < 0.05);
+        assert!((qc.ion_ratio.unwrap() - 0.5).abs() < 0.01);
+        assert_eq!(qc.flags, 0);
+        let patient = table.result(4, 0).unwrap();
+        assert!((patient.concentration - 2.5).abs() < 0.05);
+        assert!(patient.internal_standard_area.is_some());
+        // The internal standard has no curve of its own and is not flagged
+        // for it.
+        assert_eq!(table.result(4, 1).unwrap().flags & QC_NO_CALIBRATION, 0);
+        assert!(table.result(5, 0).is_none());
+
+        let mut csv = Vec::new();
+        table.write_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(csv.lines().count(), 1 + 10);
+        assert!(csv.contains("\"Patient, 1\",unknown,Drug,"));
+    }
+
+    #[test]
+    fn missing_peaks_and_read_errors() {
+        let transitions = parse_transitions(TRANSITIONS).unwrap();
+        let samples = parse_sample_list(SAMPLES).unwrap();
+        let table = quantify(
+            &transitions,
+            &samples,
+            |s| {
+                Ok(if s.file == "u.mzML" {
+                    Run::default()
+                } else {
+                    run(1.0)
+                })
+            },
+            &QuantOptions::default(),
+        )
+        .unwrap();
+        let r = table.result(4, 0).unwrap();
+        assert_eq!(r.flags, QC_NOT_FOUND | QC_INTERNAL_STANDARD);
+        assert!(r.response.is_nan() && r.concentration.is_nan());
+        assert_eq!(flag_names(r.flags), "not_found;internal_standard");
+
+        let mut reads = 0;
+        let err = quantify(
+            &transitions,
+            &samples,
+            |s| {
+                reads += 1;
+                if s.file == "s2.mzML" {
+                    Err("read s2.mzML: gone".to_string())
+                } else {
+                    Ok(run(1.0))
+                }
+            },
+            &QuantOptions::default(),
+        );
+        assert_eq!(err.unwrap_err(), "read s2.mzML: gone");
+        assert_eq!(reads, 2);
+    }
+}
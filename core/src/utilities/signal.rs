@@ -0,0 +1,631 @@
+//! One-dimensional signal processing for profile spectra and chromatograms:
+//! smoothing, baseline estimation and noise estimation.
+//!
+//! Every function takes the intensities `y` and optional positions `x`,
+//! which must be non-decreasing and as long as `y`. Widths are in units of
+//! `x`, so the same call works on m/z profiles and on retention time traces
+//! whatever their spacing; without `x` the points are taken as evenly
+//! spaced and widths are in points, which gives the textbook filters.
+
+use std::collections::VecDeque;
+
+use super::stats::{self, MAD_NORMAL_SCALE, NanPolicy};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Smoother {
+    /// Mean of the points within `half_width`.
+    MovingAverage { half_width: f64 },
+    /// Gaussian-weighted mean, truncated at 4 `sigma`.
+    Gaussian { sigma: f64 },
+    /// Value at each point of the least squares polynomial of degree
+    /// `order` through the points within `half_width`. Near the ends the
+    /// window is truncated rather than padded.
+    SavitzkyGolay { half_width: f64, order: usize },
+}
+
+impl Default for Smoother {
+    fn default() -> Self {
+        Smoother::SavitzkyGolay {
+            half_width: 3.0,
+            order: 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Baseline {
+    /// Asymmetric least squares (Eilers and Boelens, 2005): a smooth curve
+    /// with second difference penalty `lambda`, refitted `iterations` times
+    /// with weight `p` on points above it and `1 - p` below.
+    Als {
+        lambda: f64,
+        p: f64,
+        iterations: usize,
+    },
+    /// Statistics-sensitive non-linear iterative peak clipping (Ryan et
+    /// al., 1988) on log-log-square-root intensities, with clipping windows
+    /// growing up to `half_width`.
+    Snip { half_width: f64 },
+    /// Morphological opening: the rolling maximum of the rolling minimum
+    /// over `half_width`. The signal less this is its top-hat transform.
+    RollingMin { half_width: f64 },
+}
+
+impl Default for Baseline {
+    fn default() -> Self {
+        Baseline::Als {
+            lambda: 1e5,
+            p: 0.01,
+            iterations: 10,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum NoiseEstimator {
+    /// Scaled MAD of the intensities; expects a signal with its baseline
+    /// removed.
+    #[default]
+    Mad,
+    /// Scaled MAD of the point-to-point differences over `sqrt(2)`, which a
+    /// slowly varying baseline does not affect.
+    DiffMad,
+    /// Quantile of the intensities, in `[0, 1]`.
+    Percentile(f64),
+}
+
+fn check(x: Option<&[f64]>, y: &[f64]) -> Result<(), String> {
+    if let Some(x) = x {
+        if x.len() != y.len() {
+            return Err(format!("{} positions for {} intensities", x.len(), y.len()));
+        }
+        if x.windows(2)
+            .any(|w| w[0].partial_cmp(&w[1]).is_none_or(|o| o.is_gt()))
+        {
+            return Err("positions not sorted".to_string());
+        }
+    }
+    Ok(())
+}
+
+fn positive(name: &str, v: f64) -> Result<(), String> {
+    if v > 0.0 && v.is_finite() {
+        Ok(())
+    } else {
+        Err(format!("{name} must be positive"))
+    }
+}
+
+// Position of point `i`.
+fn at(x: Option<&[f64]>, i: usize) -> f64 {
+    x.map_or(i as f64, |x| x[i])
+}
+
+// Index ranges `lo[i]..hi[i]` of the points within `half_width` of each
+// point; both ends only move forward, so this is linear.
+fn windows(x: Option<&[f64]>, n: usize, half_width: f64) -> Vec<(usize, usize)> {
+    let mut out = Vec::with_capacity(n);
+    let (mut lo, mut hi) = (0, 0);
+    for i in 0..n {
+        let c = at(x, i);
+        while at(x, lo) < c - half_width {
+            lo += 1;
+        }
+        while hi < n && at(x, hi) <= c + half_width {
+            hi += 1;
+        }
+        out.push((lo, hi.max(i + 1)));
+    }
+    out
+}
+
+pub fn smooth(x: Option<&[f64]>, y: &[f64], smoother: Smoother) -> Result<Vec<f64>, String> {
+    check(x, y)?;
+    let n = y.len();
+    match smoother {
+        Smoother::MovingAverage { half_width } => {
+            positive("half_width", half_width)?;
+            // Prefix sums keep this linear whatever the width.
+            let mut prefix = Vec::with_capacity(n + 1);
+            prefix.push(0.0);
+            for &v in y {
+                prefix.push(prefix[prefix.len() - 1] + v);
+            }
+            Ok(windows(x, n, half_width)
+                .into_iter()
+                .map(|(lo, hi)| (prefix[hi] - prefix[lo]) / (hi - lo) as f64)
+                .collect())
+        }
+        Smoother::Gaussian { sigma } => {
+            positive("sigma", sigma)?;
+            let s2 = 2.0 * sigma * sigma;
+            Ok(windows(x, n, 4.0 * sigma)
+                .into_iter()
+                .enumerate()
+                .map(|(i, (lo, hi))| {
+                    let c = at(x, i);
+                    let (mut num, mut den) = (0.0, 0.0);
+                    for (j, &v) in y.iter().enumerate().take(hi).skip(lo) {
+                        let d = at(x, j) - c;
+                        let w = (-d * d / s2).exp();
+                        num += w * v;
+                        den += w;
+                    }
+                    num / den
+                })
+                .collect())
+        }
+        Smoother::SavitzkyGolay { half_width, order } => {
+            positive("half_width", half_width)?;
+            if order > 6 {
+                return Err("Savitzky-Golay order above 6".to_string());
+            }
+            Ok(windows(x, n, half_width)
+                .into_iter()
+                .enumerate()
+                .map(|(i, (lo, hi))| local_polynomial(x, y, i, lo..hi, order, half_width))
+                .collect())
+        }
+    }
+}
+
+// Least squares polynomial through points `range`, evaluated at point `i`.
+// Positions are centred on point `i` and scaled by the half width, so the
+// value is the constant term; the degree drops when the window has too few
+// points.
+fn local_polynomial(
+    x: Option<&[f64]>,
+    y: &[f64],
+    i: usize,
+    range: std::ops::Range<usize>,
+    order: usize,
+    half_width: f64,
+) -> f64 {
+    let c = at(x, i);
+    let m = order.min(range.len() - 1) + 1;
+    let mut a = [[0.0f64; 8]; 7];
+    for j in range {
+        let t = (at(x, j) - c) / half_width;
+        let mut powers = [0.0f64; 7];
+        let mut p = 1.0;
+        for v in powers.iter_mut().take(m) {
+            *v = p;
+            p *= t;
+        }
+        for r in 0..m {
+            for k in 0..m {
+                a[r][k] += powers[r] * powers[k];
+            }
+            a[r][7] += powers[r] * y[j];
+        }
+    }
+    solve(&mut a, m).unwrap_or(y[i])
+}
+
+// Gauss-Jordan elimination with partial pivoting of the `m` by `m` system
+// whose right hand side is column 7; returns the first unknown.
+fn solve(a: &mut [[f64; 8]; 7], m: usize) -> Option<f64> {
+    for col in 0..m {
+        let pivot = (col..m).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))?;
+        if a[pivot][col].abs() < 1e-12 {
+            return None;
+        }
+        a.swap(col, pivot);
+        let p = a[col];
+        for (r, row) in a.iter_mut().enumerate().take(m) {
+            if r != col {
+                let f = row[col] / p[col];
+                for (v, q) in row.iter_mut().zip(p).skip(col) {
+                    *v -= f * q;
+                }
+            }
+        }
+    }
+    Some(a[0][7] / a[0][0])
+}
+
+pub fn baseline(x: Option<&[f64]>, y: &[f64], method: Baseline) -> Result<Vec<f64>, String> {
+    check(x, y)?;
+    if y.is_empty() {
+        return Ok(Vec::new());
+    }
+    match method {
+        Baseline::Als {
+            lambda,
+            p,
+            iterations,
+        } => {
+            positive("lambda", lambda)?;
+            if !(0.0 < p && p < 1.0) {
+                return Err("p must be within (0, 1)".to_string());
+            }
+            Ok(als(x, y, lambda, p, iterations.max(1)))
+        }
+        Baseline::Snip { half_width } => {
+            positive("half_width", half_width)?;
+            Ok(snip(x, y, half_width))
+        }
+        Baseline::RollingMin { half_width } => {
+            positive("half_width", half_width)?;
+            let w = windows(x, y.len(), half_width);
+            let eroded = rolling(y, &w, |a, b| a <= b);
+            Ok(rolling(&eroded, &w, |a, b| a >= b))
+        }
+    }
+}
+
+/// `y` less its baseline.
+pub fn subtract_baseline(
+    x: Option<&[f64]>,
+    y: &[f64],
+    method: Baseline,
+) -> Result<Vec<f64>, String> {
+    let b = baseline(x, y, method)?;
+    Ok(y.iter().zip(&b).map(|(v, b)| v - b).collect())
+}
+
+// Rolling extreme over the windows `w`, with a monotonic deque; `keep(a, b)`
+// is true when `a` beats or ties `b`.
+fn rolling(y: &[f64], w: &[(usize, usize)], keep: fn(f64, f64) -> bool) -> Vec<f64> {
+    let mut out = Vec::with_capacity(y.len());
+    let mut q: VecDeque<usize> = VecDeque::new();
+    let mut next = 0;
+    for &(lo, hi) in w {
+        while next < hi {
+            while q.back().is_some_and(|&b| keep(y[next], y[b])) {
+                q.pop_back();
+            }
+            q.push_back(next);
+            next += 1;
+        }
+        while q.front().is_some_and(|&f| f < lo) {
+            q.pop_front();
+        }
+        out.push(y[q[0]]);
+    }
+    out
+}
+
+// Penalised least squares with the second differences divided by the
+// spacing, relative to the median spacing so `lambda` means the same as on
+// evenly spaced data. The system is pentadiagonal and solved by a banded
+// LDL' factorisation.
+fn als(x: Option<&[f64]>, y: &[f64], lambda: f64, p: f64, iterations: usize) -> Vec<f64> {
+    let n = y.len();
+    if n < 3 {
+        return y.to_vec();
+    }
+    let step = x.map_or(1.0, |x| {
+        let steps: Vec<f64> = x.windows(2).map(|w| w[1] - w[0]).collect();
+        stats::median(&steps, NanPolicy::Omit)
+            .ok()
+            .filter(|s| *s > 0.0)
+            .unwrap_or(1.0)
+    });
+    // Rows of D: coefficients on points k, k+1, k+2.
+    let d: Vec<[f64; 3]> = (0..n - 2)
+        .map(|k| {
+            let h1 = ((at(x, k + 1) - at(x, k)) / step).max(1e-6);
+            let h2 = ((at(x, k + 2) - at(x, k + 1)) / step).max(1e-6);
+            let s = 2.0 / (h1 + h2);
+            [s / h1, -s * (1.0 / h1 + 1.0 / h2), s / h2]
+        })
+        .collect();
+    // Bands 0, 1 and 2 of lambda D'D.
+    let mut penalty = vec![[0.0f64; 3]; n];
+    for (k, r) in d.iter().enumerate() {
+        for a in 0..3 {
+            for b in a..3 {
+                penalty[k + a][b - a] += lambda * r[a] * r[b];
+            }
+        }
+    }
+
+    let mut w = vec![1.0; n];
+    let mut z = y.to_vec();
+    for _ in 0..iterations {
+        let mut bands = penalty.clone();
+        for (b, &wi) in bands.iter_mut().zip(&w) {
+            b[0] += wi;
+        }
+        let rhs: Vec<f64> = w.iter().zip(y).map(|(w, y)| w * y).collect();
+        z = solve_pentadiagonal(&mut bands, rhs);
+        let mut changed = false;
+        for i in 0..n {
+            let wi = if y[i] > z[i] { p } else { 1.0 - p };
+            changed |= wi != w[i];
+            w[i] = wi;
+        }
+        if !changed {
+            break;
+        }
+    }
+    z
+}
+
+// Solves the symmetric positive definite system whose row `i` holds the
+// diagonal and the two entries right of it in `bands[i]`.
+fn solve_pentadiagonal(bands: &mut [[f64; 3]], mut b: Vec<f64>) -> Vec<f64> {
+    let n = bands.len();
+    // In place LDL': bands[i][0] becomes d_i, bands[i][k] becomes l_{i+k,i}.
+    for i in 0..n {
+        let mut di = bands[i][0];
+        if i >= 1 {
+            di -= bands[i - 1][1] * bands[i - 1][1] * bands[i - 1][0];
+        }
+        if i >= 2 {
+            di -= bands[i - 2][2] * bands[i - 2][2] * bands[i - 2][0];
+        }
+        bands[i][0] = di;
+        if i + 1 < n {
+            let mut e = bands[i][1];
+            if i >= 1 {
+                e -= bands[i - 1][1] * bands[i - 1][2] * bands[i - 1][0];
+            }
+            bands[i][1] = e / di;
+        }
+        if i + 2 < n {
+            bands[i][2] /= di;
+        }
+    }
+    for i in 0..n {
+        if i >= 1 {
+            b[i] -= bands[i - 1][1] * b[i - 1];
+        }
+        if i >= 2 {
+            b[i] -= bands[i - 2][2] * b[i - 2];
+        }
+    }
+    for (v, band) in b.iter_mut().zip(bands.iter()) {
+        *v /= band[0];
+    }
+    for i in (0..n).rev() {
+        if i + 1 < n {
+            b[i] -= bands[i][1] * b[i + 1];
+        }
+        if i + 2 < n {
+            b[i] -= bands[i][2] * b[i + 2];
+        }
+    }
+    b
+}
+
+// Each pass clips every point to the mean of the signal `w` either side of
+// it, linearly interpolated between points, for `w` growing by the median
+// spacing up to `half_width`.
+fn snip(x: Option<&[f64]>, y: &[f64], half_width: f64) -> Vec<f64> {
+    let n = y.len();
+    let step = x.map_or(1.0, |x| {
+        let steps: Vec<f64> = x.windows(2).map(|w| w[1] - w[0]).collect();
+        stats::median(&steps, NanPolicy::Omit)
+            .ok()
+            .filter(|s| *s > 0.0)
+            .unwrap_or(half_width)
+    });
+    let lls = |v: f64| ((v.max(0.0) + 1.0).sqrt() + 1.0).ln().ln_1p();
+    let inverse = |v: f64| {
+        let s = v.exp_m1().exp() - 1.0;
+        s * s - 1.0
+    };
+    let mut v: Vec<f64> = y.iter().map(|&y| lls(y)).collect();
+    let passes = (half_width / step).round().max(1.0) as usize;
+    let mut next = v.clone();
+    for k in 1..=passes {
+        let w = k as f64 * step;
+        for i in 0..n {
+            let c = at(x, i);
+            let (Some(l), Some(r)) = (interpolate(x, &v, c - w), interpolate(x, &v, c + w)) else {
+                next[i] = v[i];
+                continue;
+            };
+            next[i] = v[i].min(0.5 * (l + r));
+        }
+        std::mem::swap(&mut v, &mut next);
+    }
+    v.into_iter()
+        .zip(y)
+        .map(|(b, &y)| inverse(b).min(y.max(0.0)))
+        .collect()
+}
+
+// Value at position `t`, linear between points; `None` outside the data.
+fn interpolate(x: Option<&[f64]>, v: &[f64], t: f64) -> Option<f64> {
+    let n = v.len();
+    let (first, last) = (at(x, 0), at(x, n - 1));
+    if t < first || t > last {
+        return None;
+    }
+    let hi = match x {
+        Some(x) => x.partition_point(|&p| p < t),
+        None => t.ceil() as usize,
+    }
+    .min(n - 1);
+    if hi == 0 {
+        return Some(v[0]);
+    }
+    let (x0, x1) = (at(x, hi - 1), at(x, hi));
+    if x1 <= x0 {
+        return Some(v[hi]);
+    }
+    let f = (t - x0) / (x1 - x0);
+    Some(v[hi - 1] + f * (v[hi] - v[hi - 1]))
+}
+
+/// Noise level of `y`; NaN when empty.
+pub fn noise(y: &[f64], estimator: NoiseEstimator) -> Result<f64, String> {
+    match estimator {
+        NoiseEstimator::Mad => Ok(stats::mad(y, NanPolicy::Omit)? * MAD_NORMAL_SCALE),
+        NoiseEstimator::DiffMad => {
+            let d: Vec<f64> = y.windows(2).map(|w| w[1] - w[0]).collect();
+            Ok(stats::mad(&d, NanPolicy::Omit)? * MAD_NORMAL_SCALE / std::f64::consts::SQRT_2)
+        }
+        NoiseEstimator::Percentile(q) => stats::quantile(y, q, NanPolicy::Omit),
+    }
+}
+
+/// Noise level around each point, estimated from the points within
+/// `half_width`.
+pub fn local_noise(
+    x: Option<&[f64]>,
+    y: &[f64],
+    estimator: NoiseEstimator,
+    half_width: f64,
+) -> Result<Vec<f64>, String> {
+    check(x, y)?;
+    positive("half_width", half_width)?;
+    windows(x, y.len(), half_width)
+        .into_iter()
+        .map(|(lo, hi)| noise(&y[lo..hi], estimator))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: &[f64], b: &[f64], tol: f64) -> bool {
+        a.len() == b.len() && a.iter().zip(b).all(|(a, b)| (a - b).abs() <= tol)
+    }
+
+    // A ramp with a Gaussian peak at 50 on 101 points.
+    fn ramp_with_peak() -> (Vec<f64>, Vec<f64>) {
+        let ramp: Vec<f64> = (0..101).map(|i| 10.0 + 0.1 * i as f64).collect();
+        let y = ramp
+            .iter()
+            .enumerate()
+            .map(|(i, b)| {
+                let d = (i as f64 - 50.0) / 3.0;
+                b + 1000.0 * (-0.5 * d * d).exp()
+            })
+            .collect();
+        (ramp, y)
+    }
+
+    #[test]
+    fn moving_average_and_gaussian_weights() {
+        let y = [0.0, 3.0, 6.0, 0.0, 3.0];
+        let s = smooth(None, &y, Smoother::MovingAverage { half_width: 1.0 }).unwrap();
+        assert!(close(&s, &[1.5, 3.0, 3.0, 3.0, 1.5], 1e-12));
+        // Widths are in units of x: only the near neighbour is in reach.
+        let x = [0.0, 0.5, 3.0, 3.5, 10.0];
+        let s = smooth(Some(&x), &y, Smoother::MovingAverage { half_width: 1.0 }).unwrap();
+        assert!(close(&s, &[1.5, 1.5, 3.0, 3.0, 3.0], 1e-12));
+
+        let flat = [2.0; 9];
+        let s = smooth(None, &flat, Smoother::Gaussian { sigma: 1.5 }).unwrap();
+        assert!(close(&s, &flat, 1e-12));
+        let spike = [0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0];
+        let s = smooth(None, &spike, Smoother::Gaussian { sigma: 1.0 }).unwrap();
+        assert!(s[3] < 1.0 && s[2] > 0.0 && (s[2] - s[4]).abs() < 1e-12);
+    }
+
+    #[test]
+    fn savitzky_golay_keeps_polynomials() {
+        let y: Vec<f64> = (0..11)
+            .map(|i| 1.0 + i as f64 - 0.5 * (i * i) as f64)
+            .collect();
+        let sg = Smoother::SavitzkyGolay {
+            half_width: 2.0,
+            order: 2,
+        };
+        assert!(close(&smooth(None, &y, sg).unwrap(), &y, 1e-9));
+        let x: Vec<f64> = (0..11).map(|i| (i as f64).powf(1.3)).collect();
+        let y: Vec<f64> = x.iter().map(|t| 2.0 * t * t - t).collect();
+        let sg = Smoother::SavitzkyGolay {
+            half_width: 4.0,
+            order: 2,
+        };
+        assert!(close(&smooth(Some(&x), &y, sg).unwrap(), &y, 1e-8));
+
+        // The textbook 5-point quadratic filter, (-3, 12, 17, 12, -3) / 35.
+        let spike = [0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0];
+        let s = smooth(
+            None,
+            &spike,
+            Smoother::SavitzkyGolay {
+                half_width: 2.0,
+                order: 2,
+            },
+        )
+        .unwrap();
+        let expected = [-3.0, 12.0, 17.0, 12.0, -3.0].map(|c| c / 35.0);
+        assert!(close(&s[2..7], &expected, 1e-12));
+    }
+
+    #[test]
+    fn bad_arguments_are_errors() {
+        let y = [1.0, 2.0, 3.0];
+        let ma = Smoother::MovingAverage { half_width: 1.0 };
+        assert!(smooth(Some(&[0.0, 1.0]), &y, ma).is_err());
+        assert!(smooth(Some(&[0.0, 2.0, 1.0]), &y, ma).is_err());
+        assert!(smooth(Some(&[0.0, f64::NAN, 1.0]), &y, ma).is_err());
+        assert!(smooth(None, &y, Smoother::MovingAverage { half_width: 0.0 }).is_err());
+        assert!(
+            smooth(
+                None,
+                &y,
+                Smoother::Gaussian {
+                    sigma: f64::INFINITY
+                }
+            )
+            .is_err()
+        );
+        let sg = Smoother::SavitzkyGolay {
+            half_width: 3.0,
+            order: 7,
+        };
+        assert!(smooth(None, &y, sg).is_err());
+        let als = Baseline::Als {
+            lambda: 1e3,
+            p: 1.0,
+            iterations: 5,
+        };
+        assert!(baseline(None, &y, als).is_err());
+        assert!(baseline(None, &y, Baseline::Snip { half_width: -1.0 }).is_err());
+        assert!(local_noise(None, &y, NoiseEstimator::Mad, 0.0).is_err());
+        assert_eq!(baseline(None, &[], Baseline::default()).unwrap(), []);
+    }
+
+    #[test]
+    fn baselines_follow_the_ramp_under_a_peak() {
+        let (ramp, y) = ramp_with_peak();
+        let als = baseline(None, &y, Baseline::default()).unwrap();
+        // Within a few counts under a peak of 1000.
+        assert!(close(&als, &ramp, 5.0));
+        let snip = baseline(None, &y, Baseline::Snip { half_width: 20.0 }).unwrap();
+        assert!(snip.iter().zip(&y).all(|(b, y)| b <= y));
+        assert!(close(&snip, &ramp, 1.0));
+        let opened = baseline(None, &y, Baseline::RollingMin { half_width: 15.0 }).unwrap();
+        assert!(opened.iter().zip(&y).all(|(b, y)| b <= y));
+        assert!((opened[50] - ramp[50]).abs() < 2.0);
+
+        let flat = subtract_baseline(None, &y, Baseline::default()).unwrap();
+        assert!(flat[50] > 990.0);
+        assert!(flat[0].abs() < 5.0 && flat[100].abs() < 5.0);
+    }
+
+    #[test]
+    fn noise_estimators() {
+        let y = [1.0, -1.0, 1.0, -1.0, 1.0, -1.0, 1.0, -1.0];
+        assert!((noise(&y, NoiseEstimator::Mad).unwrap() - MAD_NORMAL_SCALE).abs() < 1e-12);
+        let trend: Vec<f64> = y
+            .iter()
+            .enumerate()
+            .map(|(i, v)| v + 100.0 * i as f64)
+            .collect();
+        let diff = noise(&trend, NoiseEstimator::DiffMad).unwrap();
+        assert!(diff.is_finite() && diff < noise(&trend, NoiseEstimator::Mad).unwrap());
+        let q = noise(&[1.0, 2.0, 3.0, 4.0, 5.0], NoiseEstimator::Percentile(0.5)).unwrap();
+        assert_eq!(q, 3.0);
+        assert!(noise(&[], NoiseEstimator::Mad).unwrap().is_nan());
+
+        let mut y = vec![0.0; 40];
+        for (i, v) in y.iter_mut().enumerate() {
+            let amplitude = if i < 20 { 1.0 } else { 10.0 };
+            *v = amplitude * [1.0, 0.0, -1.0][i % 3];
+        }
+        let local = local_noise(None, &y, NoiseEstimator::Mad, 3.0).unwrap();
+        assert!((local[5] - MAD_NORMAL_SCALE).abs() < 1e-12);
+        assert!((local[35] - 10.0 * MAD_NORMAL_SCALE).abs() < 1e-12);
+    }
+}
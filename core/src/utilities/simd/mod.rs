@@ -0,0 +1,342 @@
+//! Vectorized byte scanning and base64 decoding for the spectrum hot path.
+//!
+//! Each entry point uses the widest vector unit available: AVX2 (runtime
+//! detected) or SSE2 on x86_64, NEON on aarch64, and simd128 on wasm32 when
+//! the module is built with `-C target-feature=+simd128`. Anything else falls
+//! back to [`scalar`], which is also the reference the vector paths match.
+
+pub mod scalar;
+
+#[cfg(target_arch = "x86_64")]
+mod x86;
+#[cfg(target_arch = "x86_64")]
+use x86 as imp;
+
+#[cfg(target_arch = "aarch64")]
+mod neon;
+#[cfg(target_arch = "aarch64")]
+use neon as imp;
+
+#[cfg(all(target_arch = "wasm32", target_feature = "simd128"))]
+mod wasm;
+#[cfg(all(target_arch = "wasm32", target_feature = "simd128"))]
+use wasm as imp;
+
+#[cfg(not(any(
+    target_arch = "x86_64",
+    target_arch = "aarch64",
+    all(target_arch = "wasm32", target_feature = "simd128")
+)))]
+use scalar as imp;
+
+pub(crate) use scalar::is_ws;
+
+/// Position of the first `byte` in `hay`.
+#[inline]
+pub fn memchr(hay: &[u8], byte: u8) -> Option<usize> {
+    imp::memchr(hay, byte)
+}
+
+/// Position of the first occurrence of `needle` in `hay`.
+#[inline]
+pub fn memmem(hay: &[u8], needle: &[u8]) -> Option<usize> {
+    imp::memmem(hay, needle)
+}
+
+/// Decodes standard base64 interleaved with ASCII whitespace, appending the
+/// bytes to `out`. Returns `false` on malformed input.
+#[inline]
+pub fn decode_base64_ws_into(s: &[u8], out: &mut Vec<u8>) -> bool {
+    imp::decode_base64_ws_into(s, out)
+}
+
+/// Bytes a block decoder may write past the end of its decoded output.
+#[cfg(any(
+    target_arch = "x86_64",
+    target_arch = "aarch64",
+    all(target_arch = "wasm32", target_feature = "simd128")
+))]
+const BLOCK_SLACK: usize = 32;
+
+/// Shared driver for the vector base64 decoders.
+///
+/// Runs of `BLOCK` alphabet characters that start on a quad boundary go
+/// through `block`, which decodes them into `BLOCK / 4 * 3` bytes (writing
+/// at most [`BLOCK_SLACK`] bytes beyond) and returns `false` if any input
+/// byte is whitespace, padding or invalid. Those runs, and the tail, are
+/// handled a byte at a time exactly like [`scalar::decode_base64_ws_into`].
+///
+/// # Safety
+/// `block` must read exactly `BLOCK` bytes from its source pointer and write
+/// no more than `BLOCK / 4 * 3 + BLOCK_SLACK` bytes to its destination.
+#[cfg(any(
+    target_arch = "x86_64",
+    target_arch = "aarch64",
+    all(target_arch = "wasm32", target_feature = "simd128")
+))]
+#[inline(always)]
+unsafe fn decode_blocks<const BLOCK: usize, F>(s: &[u8], out: &mut Vec<u8>, mut block: F) -> bool
+where
+    F: FnMut(*const u8, *mut u8) -> bool,
+{
+    out.reserve(s.len() / 4 * 3 + 3 + BLOCK_SLACK);
+    let mut q = [0u8; 4];
+    let mut qi = 0usize;
+    let mut i = 0usize;
+    while i < s.len() {
+        if qi == 0 && i + BLOCK <= s.len() {
+            let dst = out.len();
+            debug_assert!(out.capacity() - dst >= BLOCK / 4 * 3 + BLOCK_SLACK);
+            // SAFETY: with qi == 0 every decoded byte so far came from at most
+            // 4/3 input bytes, so the reservation above still covers this block
+            // plus its slack.
+            let ok = block(unsafe { s.as_ptr().add(i) }, unsafe {
+                out.as_mut_ptr().add(dst)
+            });
+            if ok {
+                unsafe { out.set_len(dst + BLOCK / 4 * 3) };
+                i += BLOCK;
+                continue;
+            }
+        }
+        let b = s[i];
+        i += 1;
+        if is_ws(b) {
+            continue;
+        }
+        q[qi] = b;
+        qi += 1;
+        if qi == 4 {
+            if !scalar::push_quad(&q, out) {
+                return false;
+            }
+            qi = 0;
+        }
+    }
+    qi == 0
+}
+
+/// One implementation of the entry points, under test.
+#[cfg(test)]
+#[derive(Clone, Copy)]
+struct Kernels {
+    name: &'static str,
+    memchr: fn(&[u8], u8) -> Option<usize>,
+    memmem: fn(&[u8], &[u8]) -> Option<usize>,
+    decode_base64_ws_into: fn(&[u8], &mut Vec<u8>) -> bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The kernels to hold against scalar: each x86 one the CPU supports,
+    // elsewhere whichever the dispatch above compiles in.
+    fn kernels() -> Vec<Kernels> {
+        #[cfg(target_arch = "x86_64")]
+        let kernels = x86::kernels();
+        #[cfg(not(target_arch = "x86_64"))]
+        let kernels = vec![Kernels {
+            name: "dispatch",
+            memchr,
+            memmem,
+            decode_base64_ws_into,
+        }];
+        assert!(!kernels.is_empty());
+        kernels
+    }
+
+    /// Widest block any vector decoder consumes (AVX2).
+    const BLOCK: usize = 32;
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    // xorshift64*, so the suite needs no dependency and is reproducible.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 >> 12;
+            self.0 ^= self.0 << 25;
+            self.0 ^= self.0 >> 27;
+            self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+
+        fn byte(&mut self) -> u8 {
+            self.next() as u8
+        }
+    }
+
+    fn encode(data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for chunk in data.chunks(3) {
+            let b = [
+                chunk[0],
+                *chunk.get(1).unwrap_or(&0),
+                *chunk.get(2).unwrap_or(&0),
+            ];
+            let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+            out.push(ALPHABET[(n >> 18) as usize & 63]);
+            out.push(ALPHABET[(n >> 12) as usize & 63]);
+            out.push(if chunk.len() > 1 {
+                ALPHABET[(n >> 6) as usize & 63]
+            } else {
+                b'='
+            });
+            out.push(if chunk.len() > 2 {
+                ALPHABET[n as usize & 63]
+            } else {
+                b'='
+            });
+        }
+        out
+    }
+
+    // Random base64 text of about `len` characters, ending in 0, 1 or 2
+    // padding characters, with whitespace sprinkled in at `ws_rate` per 256.
+    fn base64_text(rng: &mut Rng, len: usize, ws_rate: u8) -> Vec<u8> {
+        let data: Vec<u8> = (0..len / 4 * 3 + rng.below(3))
+            .map(|_| rng.byte())
+            .collect();
+        let mut out = Vec::new();
+        for c in encode(&data) {
+            while rng.byte() < ws_rate {
+                out.push(b" \n\r\t"[rng.below(4)]);
+            }
+            out.push(c);
+        }
+        out
+    }
+
+    // Decodes `s` at every start offset within a 16-byte window of a larger
+    // buffer, so vector loads see every alignment.
+    fn check_decode(s: &[u8]) {
+        let mut want = Vec::new();
+        let ok = scalar::decode_base64_ws_into(s, &mut want);
+        for k in kernels() {
+            for offset in 0..16 {
+                let mut buf = vec![b'A'; offset];
+                buf.extend_from_slice(s);
+                let mut got = Vec::new();
+                let got_ok = (k.decode_base64_ws_into)(&buf[offset..], &mut got);
+                let text = String::from_utf8_lossy(s);
+                assert_eq!(got_ok, ok, "{}: validity of {text:?}", k.name);
+                if ok {
+                    assert_eq!(got, want, "{}: bytes of {text:?}", k.name);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn decode_matches_scalar_on_clean_input() {
+        let mut rng = Rng(0x9E37_79B9_7F4A_7C15);
+        for len in 0..=3 * BLOCK {
+            for _ in 0..8 {
+                check_decode(&base64_text(&mut rng, len, 0));
+            }
+        }
+    }
+
+    #[test]
+    fn decode_matches_scalar_with_whitespace() {
+        let mut rng = Rng(7);
+        for len in 0..=3 * BLOCK {
+            for rate in [8, 64, 160] {
+                check_decode(&base64_text(&mut rng, len, rate));
+            }
+        }
+    }
+
+    #[test]
+    fn decode_matches_scalar_on_padding() {
+        for data in [&b""[..], b"a", b"ab", b"abc", b"abcd", b"abcde"] {
+            check_decode(&encode(data));
+        }
+        let mut rng = Rng(11);
+        for len in 0..=3 * BLOCK {
+            let data: Vec<u8> = (0..len).map(|_| rng.byte()).collect();
+            let mut text = encode(&data);
+            check_decode(&text);
+            // Trailing whitespace after the padding.
+            text.extend_from_slice(b" \n");
+            check_decode(&text);
+        }
+    }
+
+    #[test]
+    fn decode_matches_scalar_on_invalid_input() {
+        let mut rng = Rng(13);
+        for len in 1..=3 * BLOCK {
+            for _ in 0..8 {
+                let mut text = base64_text(&mut rng, len, 16);
+                if text.is_empty() {
+                    continue;
+                }
+                let at = rng.below(text.len());
+                text[at] = match rng.below(4) {
+                    0 => b'=',
+                    1 => b'-',
+                    2 => 0x80 | rng.byte(),
+                    _ => rng.byte(),
+                };
+                check_decode(&text);
+                // Missing or extra characters break the quad structure.
+                text.push(b'Q');
+                check_decode(&text);
+            }
+        }
+    }
+
+    #[test]
+    fn memchr_matches_scalar() {
+        let mut rng = Rng(17);
+        let buf: Vec<u8> = (0..4 * BLOCK + 16)
+            .map(|_| b"abcxyz"[rng.below(6)])
+            .collect();
+        for k in kernels() {
+            for start in 0..16 {
+                for len in 0..=3 * BLOCK {
+                    let hay = &buf[start..start + len];
+                    for byte in [b'a', b'x', b'z', b'q'] {
+                        let want = scalar::memchr(hay, byte);
+                        assert_eq!((k.memchr)(hay, byte), want, "{}: {byte} in {hay:?}", k.name);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn memmem_matches_scalar() {
+        for k in kernels() {
+            let mut rng = Rng(19);
+            // A small alphabet makes partial matches, where only the first
+            // and last needle bytes agree, common.
+            let buf: Vec<u8> = (0..4 * BLOCK + 16).map(|_| b"ab<"[rng.below(3)]).collect();
+            for start in 0..16 {
+                for len in 0..=3 * BLOCK {
+                    let hay = &buf[start..start + len];
+                    let mut needles: Vec<Vec<u8>> = (0..=6)
+                        .map(|n| (0..n).map(|_| b"ab<"[rng.below(3)]).collect())
+                        .collect();
+                    if len >= 4 {
+                        let at = rng.below(len - 3);
+                        needles.push(hay[at..at + 4].to_vec());
+                    }
+                    for needle in &needles {
+                        assert_eq!(
+                            (k.memmem)(hay, needle),
+                            scalar::memmem(hay, needle),
+                            "{}: {needle:?} in {hay:?}",
+                            k.name
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,136 @@
+//! NEON kernels for aarch64.
+
+use core::arch::aarch64::*;
+
+use super::{decode_blocks, scalar};
+
+#[inline]
+fn has_neon() -> bool {
+    std::arch::is_aarch64_feature_detected!("neon")
+}
+
+pub fn memchr(hay: &[u8], byte: u8) -> Option<usize> {
+    if has_neon() {
+        unsafe { memchr_neon(hay, byte) }
+    } else {
+        scalar::memchr(hay, byte)
+    }
+}
+
+pub fn memmem(hay: &[u8], needle: &[u8]) -> Option<usize> {
+    match needle.len() {
+        0 => Some(0),
+        1 => memchr(hay, needle[0]),
+        _ if has_neon() => unsafe { memmem_neon(hay, needle) },
+        _ => scalar::memmem(hay, needle),
+    }
+}
+
+pub fn decode_base64_ws_into(s: &[u8], out: &mut Vec<u8>) -> bool {
+    if has_neon() {
+        unsafe { decode_base64_neon(s, out) }
+    } else {
+        scalar::decode_base64_ws_into(s, out)
+    }
+}
+
+// Narrows a byte-wise compare result to a u64 holding 4 bits per lane.
+#[target_feature(enable = "neon")]
+fn nibble_mask(eq: uint8x16_t) -> u64 {
+    let narrowed = vshrn_n_u16::<4>(vreinterpretq_u16_u8(eq));
+    vget_lane_u64::<0>(vreinterpret_u64_u8(narrowed))
+}
+
+#[target_feature(enable = "neon")]
+unsafe fn memchr_neon(hay: &[u8], byte: u8) -> Option<usize> {
+    let needle = vdupq_n_u8(byte);
+    let mut i = 0usize;
+    while i + 16 <= hay.len() {
+        let v = unsafe { vld1q_u8(hay.as_ptr().add(i)) };
+        let m = nibble_mask(vceqq_u8(v, needle));
+        if m != 0 {
+            return Some(i + (m.trailing_zeros() / 4) as usize);
+        }
+        i += 16;
+    }
+    scalar::memchr(&hay[i..], byte).map(|p| i + p)
+}
+
+// Candidates are positions where both the first and the last needle byte
+// match; only those are verified byte-wise.
+#[target_feature(enable = "neon")]
+unsafe fn memmem_neon(hay: &[u8], needle: &[u8]) -> Option<usize> {
+    let n = needle.len();
+    let first = vdupq_n_u8(needle[0]);
+    let last = vdupq_n_u8(needle[n - 1]);
+    let mut i = 0usize;
+    while i + n - 1 + 16 <= hay.len() {
+        let a = unsafe { vld1q_u8(hay.as_ptr().add(i)) };
+        let b = unsafe { vld1q_u8(hay.as_ptr().add(i + n - 1)) };
+        let mut m = nibble_mask(vandq_u8(vceqq_u8(a, first), vceqq_u8(b, last)));
+        while m != 0 {
+            let lane = (m.trailing_zeros() / 4) as usize;
+            let p = i + lane;
+            if hay[p + 1..p + n - 1] == needle[1..n - 1] {
+                return Some(p);
+            }
+            m &= !(0xF << (lane * 4));
+        }
+        i += 16;
+    }
+    scalar::memmem(&hay[i..], needle).map(|p| i + p)
+}
+
+#[target_feature(enable = "neon")]
+unsafe fn decode_base64_neon(s: &[u8], out: &mut Vec<u8>) -> bool {
+    unsafe { decode_blocks::<64, _>(s, out, |src, dst| block_neon(src, dst)) }
+}
+
+// Maps base64 characters to their 6-bit values; the second value is 0xFF in
+// every lane holding a valid character.
+#[target_feature(enable = "neon")]
+fn translate_neon(v: uint8x16_t) -> (uint8x16_t, uint8x16_t) {
+    let range = |lo: u8, hi: u8| vandq_u8(vcgeq_u8(v, vdupq_n_u8(lo)), vcleq_u8(v, vdupq_n_u8(hi)));
+    let upper = range(b'A', b'Z');
+    let lower = range(b'a', b'z');
+    let digit = range(b'0', b'9');
+    let plus = vceqq_u8(v, vdupq_n_u8(b'+'));
+    let slash = vceqq_u8(v, vdupq_n_u8(b'/'));
+    let valid = vorrq_u8(
+        vorrq_u8(vorrq_u8(upper, lower), vorrq_u8(digit, plus)),
+        slash,
+    );
+    let shift = vorrq_u8(
+        vorrq_u8(
+            vandq_u8(upper, vdupq_n_u8(65u8.wrapping_neg())),
+            vandq_u8(lower, vdupq_n_u8(71u8.wrapping_neg())),
+        ),
+        vorrq_u8(
+            vorrq_u8(
+                vandq_u8(digit, vdupq_n_u8(4)),
+                vandq_u8(plus, vdupq_n_u8(19)),
+            ),
+            vandq_u8(slash, vdupq_n_u8(16)),
+        ),
+    );
+    (vaddq_u8(v, shift), valid)
+}
+
+// De-interleaving load puts characters 0, 4, 8, .. in `a`, 1, 5, 9, .. in
+// `b` and so on, so each output byte is a pair of shifts across vectors.
+#[target_feature(enable = "neon")]
+fn block_neon(src: *const u8, dst: *mut u8) -> bool {
+    let x = unsafe { vld4q_u8(src) };
+    let (a, va) = translate_neon(x.0);
+    let (b, vb) = translate_neon(x.1);
+    let (c, vc) = translate_neon(x.2);
+    let (d, vd) = translate_neon(x.3);
+    if vminvq_u8(vandq_u8(vandq_u8(va, vb), vandq_u8(vc, vd))) != 0xFF {
+        return false;
+    }
+    let o0 = vorrq_u8(vshlq_n_u8::<2>(a), vshrq_n_u8::<4>(b));
+    let o1 = vorrq_u8(vshlq_n_u8::<4>(b), vshrq_n_u8::<2>(c));
+    let o2 = vorrq_u8(vshlq_n_u8::<6>(c), d);
+    unsafe { vst3q_u8(dst, uint8x16x3_t(o0, o1, o2)) };
+    true
+}
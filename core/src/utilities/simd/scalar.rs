@@ -0,0 +1,123 @@
+//! Portable reference implementations. These are the fallbacks used when no
+//! vector unit is available and the baseline the vector paths must match.
+
+pub fn memmem(hay: &[u8], needle: &[u8]) -> Option<usize> {
+    if needle.is_empty() {
+        return Some(0);
+    }
+    let first = needle[0];
+    let mut i = 0usize;
+    while let Some(rel) = memchr(&hay[i..], first) {
+        let p = i + rel;
+        if hay.get(p..p + needle.len()).is_some_and(|w| w == needle) {
+            return Some(p);
+        }
+        i = p + 1;
+    }
+    None
+}
+
+pub fn memchr(hay: &[u8], byte: u8) -> Option<usize> {
+    hay.iter().position(|&b| b == byte)
+}
+
+pub fn decode_base64_ws_into(s: &[u8], out: &mut Vec<u8>) -> bool {
+    let mut useful = 0usize;
+    let mut pads = 0usize;
+    for &b in s.iter().rev() {
+        if is_ws(b) {
+            continue;
+        }
+        if b == b'=' {
+            pads += 1;
+        } else {
+            break;
+        }
+    }
+    for &b in s {
+        if !is_ws(b) {
+            useful += 1;
+        }
+    }
+    if useful == 0 {
+        return true;
+    }
+    if !useful.is_multiple_of(4) {
+        return false;
+    }
+    let estimated = (useful / 4 * 3).saturating_sub(pads);
+    out.reserve(estimated);
+    let mut q = [0u8; 4];
+    let mut qi = 0usize;
+    for &b in s {
+        if is_ws(b) {
+            continue;
+        }
+        q[qi] = b;
+        qi += 1;
+        if qi == 4 {
+            if !push_quad(&q, out) {
+                return false;
+            }
+            qi = 0;
+        }
+    }
+    qi == 0
+}
+
+#[inline]
+pub(super) fn push_quad(q: &[u8; 4], out: &mut Vec<u8>) -> bool {
+    let inv = &BASE64_INV;
+    let v0 = inv[q[0] as usize];
+    if v0 == 255 {
+        return false;
+    }
+    let v1 = inv[q[1] as usize];
+    if v1 == 255 {
+        return false;
+    }
+    let v2 = if q[2] == b'=' {
+        0
+    } else {
+        let v = inv[q[2] as usize];
+        if v == 255 {
+            return false;
+        }
+        v
+    };
+    let v3 = if q[3] == b'=' {
+        0
+    } else {
+        let v = inv[q[3] as usize];
+        if v == 255 {
+            return false;
+        }
+        v
+    };
+    let n = ((v0 as u32) << 18) | ((v1 as u32) << 12) | ((v2 as u32) << 6) | (v3 as u32);
+    out.push(((n >> 16) & 0xFF) as u8);
+    if q[2] != b'=' {
+        out.push(((n >> 8) & 0xFF) as u8);
+    }
+    if q[3] != b'=' {
+        out.push((n & 0xFF) as u8);
+    }
+    true
+}
+
+#[inline]
+pub(crate) fn is_ws(b: u8) -> bool {
+    matches!(b, b' ' | b'\n' | b'\r' | b'\t')
+}
+
+const fn build_b64_inv() -> [u8; 256] {
+    let mut t = [255u8; 256];
+    let alphabet = *b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut i = 0;
+    while i < 64 {
+        t[alphabet[i] as usize] = i as u8;
+        i += 1;
+    }
+    t
+}
+static BASE64_INV: [u8; 256] = build_b64_inv();
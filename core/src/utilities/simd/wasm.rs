@@ -0,0 +1,93 @@
+//! simd128 kernels for wasm32. WebAssembly has no runtime feature detection,
+//! so this module is only compiled when simd128 is enabled for the build.
+
+use core::arch::wasm32::*;
+
+use super::{decode_blocks, scalar};
+
+pub fn memchr(hay: &[u8], byte: u8) -> Option<usize> {
+    let needle = u8x16_splat(byte);
+    let mut i = 0usize;
+    while i + 16 <= hay.len() {
+        let v = unsafe { v128_load(hay.as_ptr().add(i) as *const v128) };
+        let m = u8x16_bitmask(u8x16_eq(v, needle)) as u32;
+        if m != 0 {
+            return Some(i + m.trailing_zeros() as usize);
+        }
+        i += 16;
+    }
+    scalar::memchr(&hay[i..], byte).map(|p| i + p)
+}
+
+// Candidates are positions where both the first and the last needle byte
+// match; only those are verified byte-wise.
+pub fn memmem(hay: &[u8], needle: &[u8]) -> Option<usize> {
+    let n = needle.len();
+    match n {
+        0 => return Some(0),
+        1 => return memchr(hay, needle[0]),
+        _ => {}
+    }
+    let first = u8x16_splat(needle[0]);
+    let last = u8x16_splat(needle[n - 1]);
+    let mut i = 0usize;
+    while i + n - 1 + 16 <= hay.len() {
+        let a = unsafe { v128_load(hay.as_ptr().add(i) as *const v128) };
+        let b = unsafe { v128_load(hay.as_ptr().add(i + n - 1) as *const v128) };
+        let mut m = u8x16_bitmask(v128_and(u8x16_eq(a, first), u8x16_eq(b, last))) as u32;
+        while m != 0 {
+            let p = i + m.trailing_zeros() as usize;
+            if hay[p + 1..p + n - 1] == needle[1..n - 1] {
+                return Some(p);
+            }
+            m &= m - 1;
+        }
+        i += 16;
+    }
+    scalar::memmem(&hay[i..], needle).map(|p| i + p)
+}
+
+pub fn decode_base64_ws_into(s: &[u8], out: &mut Vec<u8>) -> bool {
+    unsafe { decode_blocks::<16, _>(s, out, block_simd128) }
+}
+
+fn block_simd128(src: *const u8, dst: *mut u8) -> bool {
+    let v = unsafe { v128_load(src as *const v128) };
+    let range =
+        |lo: u8, hi: u8| v128_and(u8x16_ge(v, u8x16_splat(lo)), u8x16_le(v, u8x16_splat(hi)));
+    let upper = range(b'A', b'Z');
+    let lower = range(b'a', b'z');
+    let digit = range(b'0', b'9');
+    let plus = u8x16_eq(v, u8x16_splat(b'+'));
+    let slash = u8x16_eq(v, u8x16_splat(b'/'));
+    let valid = v128_or(v128_or(v128_or(upper, lower), v128_or(digit, plus)), slash);
+    if !u8x16_all_true(valid) {
+        return false;
+    }
+    let shift = v128_or(
+        v128_or(
+            v128_and(upper, i8x16_splat(-65)),
+            v128_and(lower, i8x16_splat(-71)),
+        ),
+        v128_or(
+            v128_or(
+                v128_and(digit, i8x16_splat(4)),
+                v128_and(plus, i8x16_splat(19)),
+            ),
+            v128_and(slash, i8x16_splat(16)),
+        ),
+    );
+    let vals = i8x16_add(v, shift);
+    // [a b] -> a << 6 | b per 16-bit lane, then [ab cd] -> ab << 12 | cd.
+    let pairs = v128_or(
+        i16x8_shl(v128_and(vals, u16x8_splat(0x00FF)), 6),
+        u16x8_shr(vals, 8),
+    );
+    let quads = i32x4_dot_i16x8(pairs, i32x4_splat(0x0001_1000));
+    let packed = i8x16_swizzle(
+        quads,
+        i8x16(2, 1, 0, 6, 5, 4, 10, 9, 8, 14, 13, 12, -1, -1, -1, -1),
+    );
+    unsafe { v128_store(dst as *mut v128, packed) };
+    true
+}
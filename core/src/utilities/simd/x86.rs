@@ -0,0 +1,266 @@
+//! SSE2 (baseline on x86_64) and AVX2 (runtime detected) kernels.
+
+use core::arch::x86_64::*;
+
+use super::{decode_blocks, scalar};
+
+#[inline]
+fn has_avx2() -> bool {
+    std::is_x86_feature_detected!("avx2")
+}
+
+pub fn memchr(hay: &[u8], byte: u8) -> Option<usize> {
+    if has_avx2() {
+        unsafe { memchr_avx2(hay, byte) }
+    } else {
+        unsafe { memchr_sse2(hay, byte) }
+    }
+}
+
+pub fn memmem(hay: &[u8], needle: &[u8]) -> Option<usize> {
+    match needle.len() {
+        0 => Some(0),
+        1 => memchr(hay, needle[0]),
+        _ if has_avx2() => unsafe { memmem_avx2(hay, needle) },
+        _ => unsafe { memmem_sse2(hay, needle) },
+    }
+}
+
+pub fn decode_base64_ws_into(s: &[u8], out: &mut Vec<u8>) -> bool {
+    if has_avx2() {
+        unsafe { decode_base64_avx2(s, out) }
+    } else {
+        unsafe { decode_base64_sse2(s, out) }
+    }
+}
+
+#[target_feature(enable = "sse2")]
+unsafe fn memchr_sse2(hay: &[u8], byte: u8) -> Option<usize> {
+    let needle = _mm_set1_epi8(byte as i8);
+    let mut i = 0usize;
+    while i + 16 <= hay.len() {
+        let v = unsafe { _mm_loadu_si128(hay.as_ptr().add(i) as *const __m128i) };
+        let m = _mm_movemask_epi8(_mm_cmpeq_epi8(v, needle)) as u32;
+        if m != 0 {
+            return Some(i + m.trailing_zeros() as usize);
+        }
+        i += 16;
+    }
+    scalar::memchr(&hay[i..], byte).map(|p| i + p)
+}
+
+#[target_feature(enable = "avx2")]
+unsafe fn memchr_avx2(hay: &[u8], byte: u8) -> Option<usize> {
+    let needle = _mm256_set1_epi8(byte as i8);
+    let mut i = 0usize;
+    while i + 32 <= hay.len() {
+        let v = unsafe { _mm256_loadu_si256(hay.as_ptr().add(i) as *const __m256i) };
+        let m = _mm256_movemask_epi8(_mm256_cmpeq_epi8(v, needle)) as u32;
+        if m != 0 {
+            return Some(i + m.trailing_zeros() as usize);
+        }
+        i += 32;
+    }
+    unsafe { memchr_sse2(&hay[i..], byte) }.map(|p| i + p)
+}
+
+// Candidates are positions where both the first and the last needle byte
+// match; only those are verified byte-wise.
+#[target_feature(enable = "sse2")]
+unsafe fn memmem_sse2(hay: &[u8], needle: &[u8]) -> Option<usize> {
+    let n = needle.len();
+    let first = _mm_set1_epi8(needle[0] as i8);
+    let last = _mm_set1_epi8(needle[n - 1] as i8);
+    let mut i = 0usize;
+    while i + n - 1 + 16 <= hay.len() {
+        let a = unsafe { _mm_loadu_si128(hay.as_ptr().add(i) as *const __m128i) };
+        let b = unsafe { _mm_loadu_si128(hay.as_ptr().add(i + n - 1) as *const __m128i) };
+        let eq = _mm_and_si128(_mm_cmpeq_epi8(a, first), _mm_cmpeq_epi8(b, last));
+        let mut m = _mm_movemask_epi8(eq) as u32;
+        while m != 0 {
+            let p = i + m.trailing_zeros() as usize;
+            if hay[p + 1..p + n - 1] == needle[1..n - 1] {
+                return Some(p);
+            }
+            m &= m - 1;
+        }
+        i += 16;
+    }
+    scalar::memmem(&hay[i..], needle).map(|p| i + p)
+}
+
+#[target_feature(enable = "avx2")]
+unsafe fn memmem_avx2(hay: &[u8], needle: &[u8]) -> Option<usize> {
+    let n = needle.len();
+    let first = _mm256_set1_epi8(needle[0] as i8);
+    let last = _mm256_set1_epi8(needle[n - 1] as i8);
+    let mut i = 0usize;
+    while i + n - 1 + 32 <= hay.len() {
+        let a = unsafe { _mm256_loadu_si256(hay.as_ptr().add(i) as *const __m256i) };
+        let b = unsafe { _mm256_loadu_si256(hay.as_ptr().add(i + n - 1) as *const __m256i) };
+        let eq = _mm256_and_si256(_mm256_cmpeq_epi8(a, first), _mm256_cmpeq_epi8(b, last));
+        let mut m = _mm256_movemask_epi8(eq) as u32;
+        while m != 0 {
+            let p = i + m.trailing_zeros() as usize;
+            if hay[p + 1..p + n - 1] == needle[1..n - 1] {
+                return Some(p);
+            }
+            m &= m - 1;
+        }
+        i += 32;
+    }
+    unsafe { memmem_sse2(&hay[i..], needle) }.map(|p| i + p)
+}
+
+#[target_feature(enable = "sse2")]
+unsafe fn decode_base64_sse2(s: &[u8], out: &mut Vec<u8>) -> bool {
+    unsafe { decode_blocks::<16, _>(s, out, |src, dst| block_sse2(src, dst)) }
+}
+
+#[target_feature(enable = "avx2")]
+unsafe fn decode_base64_avx2(s: &[u8], out: &mut Vec<u8>) -> bool {
+    unsafe { decode_blocks::<32, _>(s, out, |src, dst| block_avx2(src, dst)) }
+}
+
+// Maps base64 characters to their 6-bit values. Bytes >= 0x80 are negative
+// under the signed compares and therefore fall outside every range.
+#[target_feature(enable = "sse2")]
+fn translate_sse2(v: __m128i) -> (__m128i, bool) {
+    let range = |lo: u8, hi: u8| {
+        _mm_and_si128(
+            _mm_cmpgt_epi8(v, _mm_set1_epi8(lo as i8 - 1)),
+            _mm_cmplt_epi8(v, _mm_set1_epi8(hi as i8 + 1)),
+        )
+    };
+    let upper = range(b'A', b'Z');
+    let lower = range(b'a', b'z');
+    let digit = range(b'0', b'9');
+    let plus = _mm_cmpeq_epi8(v, _mm_set1_epi8(b'+' as i8));
+    let slash = _mm_cmpeq_epi8(v, _mm_set1_epi8(b'/' as i8));
+    let valid = _mm_or_si128(
+        _mm_or_si128(_mm_or_si128(upper, lower), _mm_or_si128(digit, plus)),
+        slash,
+    );
+    let shift = _mm_or_si128(
+        _mm_or_si128(
+            _mm_and_si128(upper, _mm_set1_epi8(-65)),
+            _mm_and_si128(lower, _mm_set1_epi8(-71)),
+        ),
+        _mm_or_si128(
+            _mm_or_si128(
+                _mm_and_si128(digit, _mm_set1_epi8(4)),
+                _mm_and_si128(plus, _mm_set1_epi8(19)),
+            ),
+            _mm_and_si128(slash, _mm_set1_epi8(16)),
+        ),
+    );
+    (_mm_add_epi8(v, shift), _mm_movemask_epi8(valid) == 0xFFFF)
+}
+
+#[target_feature(enable = "sse2")]
+fn block_sse2(src: *const u8, dst: *mut u8) -> bool {
+    let v = unsafe { _mm_loadu_si128(src as *const __m128i) };
+    let (vals, ok) = translate_sse2(v);
+    if !ok {
+        return false;
+    }
+    // [a b] -> a << 6 | b per 16-bit lane, then [ab cd] -> ab << 12 | cd.
+    let pairs = _mm_or_si128(
+        _mm_slli_epi16(_mm_and_si128(vals, _mm_set1_epi16(0x00FF)), 6),
+        _mm_srli_epi16(vals, 8),
+    );
+    let quads = _mm_madd_epi16(pairs, _mm_set1_epi32(0x0001_1000));
+    let mut words = [0u32; 4];
+    unsafe { _mm_storeu_si128(words.as_mut_ptr() as *mut __m128i, quads) };
+    for (k, w) in words.iter().enumerate() {
+        let b = w.to_be_bytes();
+        unsafe { core::ptr::copy_nonoverlapping(b.as_ptr().add(1), dst.add(k * 3), 3) };
+    }
+    true
+}
+
+#[target_feature(enable = "avx2")]
+fn block_avx2(src: *const u8, dst: *mut u8) -> bool {
+    let v = unsafe { _mm256_loadu_si256(src as *const __m256i) };
+    let range = |lo: u8, hi: u8| {
+        _mm256_and_si256(
+            _mm256_cmpgt_epi8(v, _mm256_set1_epi8(lo as i8 - 1)),
+            _mm256_cmpgt_epi8(_mm256_set1_epi8(hi as i8 + 1), v),
+        )
+    };
+    let upper = range(b'A', b'Z');
+    let lower = range(b'a', b'z');
+    let digit = range(b'0', b'9');
+    let plus = _mm256_cmpeq_epi8(v, _mm256_set1_epi8(b'+' as i8));
+    let slash = _mm256_cmpeq_epi8(v, _mm256_set1_epi8(b'/' as i8));
+    let valid = _mm256_or_si256(
+        _mm256_or_si256(_mm256_or_si256(upper, lower), _mm256_or_si256(digit, plus)),
+        slash,
+    );
+    if _mm256_movemask_epi8(valid) != -1 {
+        return false;
+    }
+    let shift = _mm256_or_si256(
+        _mm256_or_si256(
+            _mm256_and_si256(upper, _mm256_set1_epi8(-65)),
+            _mm256_and_si256(lower, _mm256_set1_epi8(-71)),
+        ),
+        _mm256_or_si256(
+            _mm256_or_si256(
+                _mm256_and_si256(digit, _mm256_set1_epi8(4)),
+                _mm256_and_si256(plus, _mm256_set1_epi8(19)),
+            ),
+            _mm256_and_si256(slash, _mm256_set1_epi8(16)),
+        ),
+    );
+    let vals = _mm256_add_epi8(v, shift);
+    let pairs = _mm256_maddubs_epi16(vals, _mm256_set1_epi32(0x0140_0140));
+    let quads = _mm256_madd_epi16(pairs, _mm256_set1_epi32(0x0001_1000));
+    let packed = _mm256_shuffle_epi8(
+        quads,
+        _mm256_setr_epi8(
+            2, 1, 0, 6, 5, 4, 10, 9, 8, 14, 13, 12, -1, -1, -1, -1, //
+            2, 1, 0, 6, 5, 4, 10, 9, 8, 14, 13, 12, -1, -1, -1, -1,
+        ),
+    );
+    unsafe {
+        _mm_storeu_si128(dst as *mut __m128i, _mm256_castsi256_si128(packed));
+        _mm_storeu_si128(
+            dst.add(12) as *mut __m128i,
+            _mm256_extracti128_si256(packed, 1),
+        );
+    }
+    true
+}
+
+/// Every kernel set the CPU runs, so the tests can hold SSE2 and AVX2 each
+/// against [`scalar`] rather than only the one dispatch picks.
+#[cfg(test)]
+pub(super) fn kernels() -> Vec<super::Kernels> {
+    let mut kernels = Vec::new();
+    if std::is_x86_feature_detected!("sse2") {
+        kernels.push(super::Kernels {
+            name: "sse2",
+            memchr: |hay, byte| unsafe { memchr_sse2(hay, byte) },
+            memmem: |hay, needle| match needle.len() {
+                0 => Some(0),
+                1 => unsafe { memchr_sse2(hay, needle[0]) },
+                _ => unsafe { memmem_sse2(hay, needle) },
+            },
+            decode_base64_ws_into: |s, out| unsafe { decode_base64_sse2(s, out) },
+        });
+    }
+    if std::is_x86_feature_detected!("avx2") {
+        kernels.push(super::Kernels {
+            name: "avx2",
+            memchr: |hay, byte| unsafe { memchr_avx2(hay, byte) },
+            memmem: |hay, needle| match needle.len() {
+                0 => Some(0),
+                1 => unsafe { memchr_avx2(hay, needle[0]) },
+                _ => unsafe { memmem_avx2(hay, needle) },
+            },
+            decode_base64_ws_into: |s, out| unsafe { decode_base64_avx2(s, out) },
+        });
+    }
+    kernels
+}
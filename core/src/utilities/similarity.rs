@@ -0,0 +1,414 @@
+//! Similarity of fragment spectra: cosine, modified cosine and spectral
+//! entropy, and clustering of the MS2 spectra of a run by them.
+//!
+//! Spectra are first reduced to [`Peaks`]: noise and all but the most
+//! intense peaks dropped, intensities transformed. Peaks of two spectra are
+//! matched one to one when within tolerance, greedily from the most intense
+//! pairs down, as in matchms and GNPS.
+
+use super::parse_mzml::SpectrumSummary;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IntensityTransform {
+    #[default]
+    None,
+    Sqrt,
+    /// `ln(1 + x)`.
+    Log,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FilterOptions {
+    /// Peaks below this fraction of the base peak are dropped.
+    pub noise: f64,
+    /// Keep only the most intense peaks; 0 keeps all.
+    pub top_n: usize,
+    /// Applied after filtering. Entropy similarity expects raw intensities.
+    pub transform: IntensityTransform,
+}
+
+impl Default for FilterOptions {
+    fn default() -> Self {
+        FilterOptions {
+            noise: 0.01,
+            top_n: 0,
+            transform: IntensityTransform::None,
+        }
+    }
+}
+
+/// A filtered peak list, sorted by m/z.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Peaks {
+    pub mz: Vec<f64>,
+    pub intensity: Vec<f64>,
+    pub precursor_mz: Option<f64>,
+}
+
+impl Peaks {
+    /// Filters `mz` and `intensity`, which need not be sorted; non-finite
+    /// and non-positive peaks are dropped.
+    pub fn new(
+        mz: &[f64],
+        intensity: &[f64],
+        precursor_mz: Option<f64>,
+        opts: &FilterOptions,
+    ) -> Peaks {
+        let mut peaks: Vec<(f64, f64)> = mz
+            .iter()
+            .zip(intensity)
+            .map(|(&m, &y)| (m, y))
+            .filter(|&(m, y)| m.is_finite() && y.is_finite() && y > 0.0)
+            .collect();
+        let base = peaks.iter().fold(0.0f64, |b, p| b.max(p.1));
+        peaks.retain(|p| p.1 >= opts.noise * base);
+        if opts.top_n > 0 && peaks.len() > opts.top_n {
+            peaks.sort_by(|a, b| b.1.total_cmp(&a.1));
+            peaks.truncate(opts.top_n);
+        }
+        peaks.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let f: fn(f64) -> f64 = match opts.transform {
+            IntensityTransform::None => |y| y,
+            IntensityTransform::Sqrt => f64::sqrt,
+            IntensityTransform::Log => f64::ln_1p,
+        };
+        Peaks {
+            mz: peaks.iter().map(|p| p.0).collect(),
+            intensity: peaks.iter().map(|p| f(p.1)).collect(),
+            precursor_mz,
+        }
+    }
+
+    /// Peaks of a parsed spectrum; `None` without both arrays.
+    pub fn from_spectrum(s: &SpectrumSummary, opts: &FilterOptions) -> Option<Peaks> {
+        let mz = s.mz_array.as_ref()?.to_f64();
+        let intensity = s.intensity_array.as_ref()?.to_f64();
+        Some(Peaks::new(&mz, &intensity, s.precursor_mz, opts))
+    }
+
+    pub fn len(&self) -> usize {
+        self.mz.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mz.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SimilarityMetric {
+    #[default]
+    Cosine,
+    /// Cosine that also matches fragments shifted by the difference of the
+    /// precursor m/z; plain cosine when either precursor is unknown.
+    ModifiedCosine,
+    /// Spectral entropy similarity of Li et al. (2021).
+    Entropy,
+    /// Entropy similarity with low-entropy spectra reweighted towards
+    /// their minor peaks, as recommended by Li et al.
+    WeightedEntropy,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SimilarityOptions {
+    pub metric: SimilarityMetric,
+    /// Peaks match when within this many Da or `ppm`, whichever is wider.
+    pub mz_tolerance: f64,
+    pub ppm: f64,
+}
+
+impl Default for SimilarityOptions {
+    fn default() -> Self {
+        SimilarityOptions {
+            metric: SimilarityMetric::Cosine,
+            mz_tolerance: 0.02,
+            ppm: 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Similarity {
+    /// From 0 to 1.
+    pub score: f64,
+    pub matched_peaks: usize,
+}
+
+pub fn similarity(a: &Peaks, b: &Peaks, opts: &SimilarityOptions) -> Similarity {
+    match opts.metric {
+        SimilarityMetric::Cosine => cosine(a, b, opts, None),
+        SimilarityMetric::ModifiedCosine => {
+            let shift = a.precursor_mz.zip(b.precursor_mz).map(|(p, q)| q - p);
+            cosine(a, b, opts, shift)
+        }
+        SimilarityMetric::Entropy => entropy(a, b, opts, false),
+        SimilarityMetric::WeightedEntropy => entropy(a, b, opts, true),
+    }
+}
+
+// Pairs (i, j) with b.mz[j] - a.mz[i] within tolerance of `shift`.
+fn candidate_pairs(
+    a: &[f64],
+    b: &[f64],
+    shift: f64,
+    opts: &SimilarityOptions,
+    out: &mut Vec<(usize, usize)>,
+) {
+    let mut lo = 0;
+    for (i, &m) in a.iter().enumerate() {
+        let target = m + shift;
+        let tol = opts.mz_tolerance.max(target.abs() * opts.ppm * 1e-6);
+        while lo < b.len() && b[lo] < target - tol {
+            lo += 1;
+        }
+        let mut j = lo;
+        while j < b.len() && b[j] <= target + tol {
+            out.push((i, j));
+            j += 1;
+        }
+    }
+}
+
+// One-to-one matches, taking the pairs with the largest intensity product
+// first.
+fn match_peaks(a: &[f64], b: &[f64], pairs: Vec<(usize, usize)>) -> Vec<(usize, usize)> {
+    let mut pairs = pairs;
+    pairs.sort_by(|&(i, j), &(k, l)| (b[l] * a[k]).total_cmp(&(a[i] * b[j])));
+    let mut used_a = vec![false; a.len()];
+    let mut used_b = vec![false; b.len()];
+    let mut out = Vec::new();
+    for (i, j) in pairs {
+        if !used_a[i] && !used_b[j] {
+            used_a[i] = true;
+            used_b[j] = true;
+            out.push((i, j));
+        }
+    }
+    out
+}
+
+fn cosine(a: &Peaks, b: &Peaks, opts: &SimilarityOptions, shift: Option<f64>) -> Similarity {
+    let mut pairs = Vec::new();
+    candidate_pairs(&a.mz, &b.mz, 0.0, opts, &mut pairs);
+    if let Some(s) = shift
+        && s.abs() > opts.mz_tolerance
+    {
+        candidate_pairs(&a.mz, &b.mz, s, opts, &mut pairs);
+    }
+    let matched = match_peaks(&a.intensity, &b.intensity, pairs);
+    let norm = |y: &[f64]| y.iter().map(|v| v * v).sum::<f64>().sqrt();
+    let denom = norm(&a.intensity) * norm(&b.intensity);
+    if denom <= 0.0 {
+        return Similarity::default();
+    }
+    let dot = matched
+        .iter()
+        .fold(0.0, |s, &(i, j)| s + a.intensity[i] * b.intensity[j]);
+    Similarity {
+        score: (dot / denom).min(1.0),
+        matched_peaks: matched.len(),
+    }
+}
+
+// Intensities normalised to sum to 1, optionally reweighted: spectra with
+// entropy S below 3 have intensities raised to 0.25 + 0.25 S.
+fn entropy_intensities(y: &[f64], weighted: bool) -> Vec<f64> {
+    let total: f64 = y.iter().sum();
+    let mut p: Vec<f64> = y.iter().map(|v| v / total).collect();
+    if weighted {
+        let s = -p
+            .iter()
+            .filter(|&&v| v > 0.0)
+            .map(|v| v * v.ln())
+            .sum::<f64>();
+        if s < 3.0 {
+            let w = 0.25 + 0.25 * s;
+            p.iter_mut().for_each(|v| *v = v.powf(w));
+            let total: f64 = p.iter().sum();
+            p.iter_mut().for_each(|v| *v /= total);
+        }
+    }
+    p
+}
+
+// 1 - (2 S(AB) - S(A) - S(B)) / ln 4, where AB is the average of the two
+// spectra. Only matched peaks contribute, each by
+// (a + b) ln(a + b) - a ln a - b ln b.
+fn entropy(a: &Peaks, b: &Peaks, opts: &SimilarityOptions, weighted: bool) -> Similarity {
+    if a.is_empty() || b.is_empty() {
+        return Similarity::default();
+    }
+    let pa = entropy_intensities(&a.intensity, weighted);
+    let pb = entropy_intensities(&b.intensity, weighted);
+    let mut pairs = Vec::new();
+    candidate_pairs(&a.mz, &b.mz, 0.0, opts, &mut pairs);
+    let matched = match_peaks(&pa, &pb, pairs);
+    let xlnx = |x: f64| if x > 0.0 { x * x.ln() } else { 0.0 };
+    let s = matched.iter().fold(0.0, |s, &(i, j)| {
+        s + xlnx(pa[i] + pb[j]) - xlnx(pa[i]) - xlnx(pb[j])
+    });
+    Similarity {
+        score: (s / 4f64.ln()).clamp(0.0, 1.0),
+        matched_peaks: matched.len(),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClusterOptions {
+    pub similarity: SimilarityOptions,
+    /// Spectra scoring at least this are linked.
+    pub min_score: f64,
+    /// Only spectra whose precursors are within this many Da are compared;
+    /// ignored when either precursor is unknown.
+    pub precursor_tolerance: f64,
+    /// Links also need at least this many matched peaks.
+    pub min_matched_peaks: usize,
+}
+
+impl Default for ClusterOptions {
+    fn default() -> Self {
+        ClusterOptions {
+            similarity: SimilarityOptions::default(),
+            min_score: 0.7,
+            precursor_tolerance: 0.02,
+            min_matched_peaks: 3,
+        }
+    }
+}
+
+/// Single linkage clusters of `spectra`: one label per spectrum, numbered
+/// from 0 in order of first appearance. Empty peak lists stay on their own.
+pub fn cluster_spectra(spectra: &[Peaks], opts: &ClusterOptions) -> Vec<usize> {
+    let n = spectra.len();
+    let mut parent: Vec<usize> = (0..n).collect();
+    fn root(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+
+    for i in 0..n {
+        if spectra[i].is_empty() {
+            continue;
+        }
+        for j in i + 1..n {
+            let (a, b) = (&spectra[i], &spectra[j]);
+            if b.is_empty() {
+                continue;
+            }
+            if let (Some(p), Some(q)) = (a.precursor_mz, b.precursor_mz)
+                && (p - q).abs() > opts.precursor_tolerance
+            {
+                continue;
+            }
+            let (ri, rj) = (root(&mut parent, i), root(&mut parent, j));
+            if ri == rj {
+                continue;
+            }
+            let s = similarity(a, b, &opts.similarity);
+            if s.score >= opts.min_score && s.matched_peaks >= opts.min_matched_peaks {
+                parent[ri.max(rj)] = ri.min(rj);
+            }
+        }
+    }
+
+    let mut labels = vec![usize::MAX; n];
+    let mut next = 0;
+    for i in 0..n {
+        let r = root(&mut parent, i);
+        if labels[r] == usize::MAX {
+            labels[r] = next;
+            next += 1;
+        }
+        labels[i] = labels[r];
+    }
+    labels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peaks(p: &[(f64, f64)], precursor_mz: Option<f64>) -> Peaks {
+        let (mz, y): (Vec<f64>, Vec<f64>) = p.iter().copied().unzip();
+        Peaks::new(&mz, &y, precursor_mz, &FilterOptions::default())
+    }
+
+    fn score(a: &Peaks, b: &Peaks, metric: SimilarityMetric) -> Similarity {
+        let opts = SimilarityOptions {
+            metric,
+            ..SimilarityOptions::default()
+        };
+        similarity(a, b, &opts)
+    }
+
+    #[test]
+    fn peaks_are_filtered_and_sorted() {
+        let opts = FilterOptions {
+            noise: 0.05,
+            top_n: 2,
+            transform: IntensityTransform::Sqrt,
+        };
+        let mz = [300.0, 100.0, 200.0, 150.0, f64::NAN, 250.0];
+        let y = [16.0, 100.0, 4.0, 1.0, 50.0, 0.0];
+        let p = Peaks::new(&mz, &y, Some(400.0), &opts);
+        assert_eq!(p.mz, [100.0, 300.0]);
+        assert_eq!(p.intensity, [10.0, 4.0]);
+        assert_eq!(p.precursor_mz, Some(400.0));
+    }
+
+    #[test]
+    fn cosine_matches_peaks_one_to_one() {
+        let a = peaks(&[(100.0, 3.0), (200.0, 4.0)], None);
+        let b = peaks(&[(100.005, 3.0), (100.01, 3.0)], None);
+        let s = score(&a, &b, SimilarityMetric::Cosine);
+        assert_eq!(s.matched_peaks, 1);
+        assert!((s.score - 9.0 / (5.0 * 18f64.sqrt())).abs() < 1e-12);
+        let s = score(&a, &a, SimilarityMetric::Cosine);
+        assert!((s.score - 1.0).abs() < 1e-12);
+        let far = peaks(&[(500.0, 1.0)], None);
+        assert_eq!(score(&a, &far, SimilarityMetric::Cosine).score, 0.0);
+    }
+
+    #[test]
+    fn modified_cosine_follows_the_precursor_shift() {
+        let a = peaks(&[(100.0, 1.0), (150.0, 1.0), (250.0, 1.0)], Some(300.0));
+        let b = peaks(&[(100.0, 1.0), (164.0, 1.0), (264.0, 1.0)], Some(314.0));
+        let plain = score(&a, &b, SimilarityMetric::Cosine);
+        let modified = score(&a, &b, SimilarityMetric::ModifiedCosine);
+        assert_eq!(plain.matched_peaks, 1);
+        assert_eq!(modified.matched_peaks, 3);
+        assert!((modified.score - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn entropy_similarity() {
+        let a = peaks(&[(100.0, 1.0), (200.0, 1.0)], None);
+        let b = peaks(&[(100.0, 1.0), (300.0, 1.0)], None);
+        assert!((score(&a, &a, SimilarityMetric::Entropy).score - 1.0).abs() < 1e-12);
+        assert!((score(&a, &b, SimilarityMetric::Entropy).score - 0.5).abs() < 1e-12);
+        // Two equal peaks have entropy ln 2 < 3, so weighting leaves them be.
+        let w = score(&a, &b, SimilarityMetric::WeightedEntropy).score;
+        assert!((w - 0.5).abs() < 1e-12);
+        assert_eq!(
+            score(&a, &Peaks::default(), SimilarityMetric::Entropy).score,
+            0.0
+        );
+    }
+
+    #[test]
+    fn similar_spectra_cluster_together() {
+        let base = [(100.0, 5.0), (150.0, 3.0), (200.0, 2.0), (250.0, 1.0)];
+        let spectra = [
+            peaks(&base, Some(300.0)),
+            peaks(&[(400.0, 1.0), (410.0, 1.0), (420.0, 1.0)], Some(500.0)),
+            peaks(&base[..3], Some(300.01)),
+            Peaks::default(),
+            peaks(&base, Some(310.0)),
+        ];
+        let labels = cluster_spectra(&spectra, &ClusterOptions::default());
+        assert_eq!(labels, [0, 1, 0, 2, 3]);
+    }
+}
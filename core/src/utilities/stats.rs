@@ -0,0 +1,231 @@
+//! Descriptive statistics over `f64` slices.
+//!
+//! `sum`, `mean`, `variance` and `std` propagate NaN like plain arithmetic.
+//! Order statistics take a [`NanPolicy`] since sorting has no natural place
+//! for NaN.
+
+/// Scale factor that makes the MAD a consistent estimator of the standard
+/// deviation for normally distributed data.
+pub const MAD_NORMAL_SCALE: f64 = 1.482_602_218_505_602;
+
+/// How order statistics treat NaN values in their input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NanPolicy {
+    /// Any NaN makes the result NaN.
+    #[default]
+    Propagate,
+    /// NaN values are dropped before computing.
+    Omit,
+    /// Any NaN is an error.
+    Error,
+}
+
+/// Neumaier-compensated sum, accurate even when terms cancel.
+pub fn sum(xs: &[f64]) -> f64 {
+    let mut s = 0.0f64;
+    let mut c = 0.0f64;
+    for &x in xs {
+        let t = s + x;
+        if s.abs() >= x.abs() {
+            c += (s - t) + x;
+        } else {
+            c += (x - t) + s;
+        }
+        s = t;
+    }
+    s + c
+}
+
+/// Arithmetic mean; NaN for an empty slice.
+pub fn mean(xs: &[f64]) -> f64 {
+    if xs.is_empty() {
+        return f64::NAN;
+    }
+    sum(xs) / xs.len() as f64
+}
+
+/// Variance with `ddof` delta degrees of freedom (0 for the population
+/// variance, 1 for the sample variance). NaN when `len <= ddof`.
+pub fn variance(xs: &[f64], ddof: usize) -> f64 {
+    let n = xs.len();
+    if n <= ddof {
+        return f64::NAN;
+    }
+    let m = mean(xs);
+    let mut sq = 0.0f64;
+    let mut comp = 0.0f64;
+    for &x in xs {
+        let d = x - m;
+        sq += d * d;
+        comp += d;
+    }
+    // The correction term removes the rounding error left in `m`.
+    (sq - comp * comp / n as f64) / (n - ddof) as f64
+}
+
+/// Standard deviation with `ddof` delta degrees of freedom.
+pub fn std(xs: &[f64], ddof: usize) -> f64 {
+    variance(xs, ddof).sqrt()
+}
+
+/// Median; NaN for an empty slice.
+pub fn median(xs: &[f64], nan: NanPolicy) -> Result<f64, String> {
+    quantile(xs, 0.5, nan)
+}
+
+/// Quantile `q` in `[0, 1]` using linear interpolation between order
+/// statistics (R type 7, NumPy `linear`). NaN for an empty slice.
+pub fn quantile(xs: &[f64], q: f64, nan: NanPolicy) -> Result<f64, String> {
+    Ok(quantiles(xs, &[q], nan)?[0])
+}
+
+/// Several quantiles of the same data, sorting it only once.
+pub fn quantiles(xs: &[f64], qs: &[f64], nan: NanPolicy) -> Result<Vec<f64>, String> {
+    if let Some(q) = qs.iter().find(|q| !(0.0..=1.0).contains(*q)) {
+        return Err(format!("quantile {q} outside [0, 1]"));
+    }
+    let Some(mut v) = clean(xs, nan)? else {
+        return Ok(vec![f64::NAN; qs.len()]);
+    };
+    if v.is_empty() {
+        return Ok(vec![f64::NAN; qs.len()]);
+    }
+    v.sort_unstable_by(f64::total_cmp);
+    Ok(qs.iter().map(|&q| quantile_sorted(&v, q)).collect())
+}
+
+/// Quantile `q` of data already sorted ascending and free of NaN.
+pub fn quantile_sorted(sorted: &[f64], q: f64) -> f64 {
+    if sorted.is_empty() {
+        return f64::NAN;
+    }
+    let h = (sorted.len() - 1) as f64 * q;
+    let lo = h.floor() as usize;
+    let hi = h.ceil() as usize;
+    sorted[lo] + (h - lo as f64) * (sorted[hi] - sorted[lo])
+}
+
+/// Median absolute deviation from the median, unscaled. Multiply by
+/// [`MAD_NORMAL_SCALE`] to estimate a standard deviation.
+pub fn mad(xs: &[f64], nan: NanPolicy) -> Result<f64, String> {
+    let Some(mut v) = clean(xs, nan)? else {
+        return Ok(f64::NAN);
+    };
+    if v.is_empty() {
+        return Ok(f64::NAN);
+    }
+    let m = select_median(&mut v);
+    for x in v.iter_mut() {
+        *x = (*x - m).abs();
+    }
+    Ok(select_median(&mut v))
+}
+
+// Applies the NaN policy to a copy of `xs`. `None` means the result is NaN.
+fn clean(xs: &[f64], nan: NanPolicy) -> Result<Option<Vec<f64>>, String> {
+    let has_nan = xs.iter().any(|x| x.is_nan());
+    if !has_nan {
+        return Ok(Some(xs.to_vec()));
+    }
+    match nan {
+        NanPolicy::Propagate => Ok(None),
+        NanPolicy::Omit => Ok(Some(xs.iter().copied().filter(|x| !x.is_nan()).collect())),
+        NanPolicy::Error => Err("NaN in input".into()),
+    }
+}
+
+// Median of a non-empty, NaN-free buffer in linear time; reorders `v`.
+fn select_median(v: &mut [f64]) -> f64 {
+    let n = v.len();
+    let mid = n / 2;
+    let (lower, m, _) = v.select_nth_unstable_by(mid, f64::total_cmp);
+    let m = *m;
+    if n % 2 == 1 {
+        return m;
+    }
+    let below = lower.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    (below + m) / 2.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compensated_sum_keeps_cancelled_terms() {
+        let xs = [1e16, 1.0, -1e16];
+        assert_eq!(xs.iter().sum::<f64>(), 0.0);
+        assert_eq!(sum(&xs), 1.0);
+        assert_eq!(sum(&[0.1; 10]), 1.0);
+        assert_eq!(sum(&[]), 0.0);
+        assert!(sum(&[1.0, f64::NAN]).is_nan());
+        assert!(mean(&[]).is_nan());
+        assert_eq!(mean(&[1.0, 2.0, 6.0]), 3.0);
+    }
+
+    #[test]
+    fn variance_and_std_with_ddof() {
+        let xs = [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0];
+        assert_eq!(variance(&xs, 0), 4.0);
+        assert!((variance(&xs, 1) - 32.0 / 7.0).abs() < 1e-12);
+        assert_eq!(std(&xs, 0), 2.0);
+        assert!((std(&xs, 1) - (32.0f64 / 7.0).sqrt()).abs() < 1e-12);
+
+        // A large offset must not swamp the spread.
+        let shifted: Vec<f64> = [4.0, 7.0, 13.0, 16.0].iter().map(|x| x + 1e9).collect();
+        assert!((variance(&shifted, 1) - 30.0).abs() < 1e-6);
+
+        assert!(variance(&[1.0], 1).is_nan());
+        assert_eq!(variance(&[1.0], 0), 0.0);
+        assert!(std(&[], 0).is_nan());
+    }
+
+    #[test]
+    fn quantiles_interpolate_linearly() {
+        let xs = [4.0, 1.0, 3.0, 2.0];
+        assert_eq!(quantile(&xs, 0.0, NanPolicy::Propagate), Ok(1.0));
+        assert_eq!(quantile(&xs, 0.25, NanPolicy::Propagate), Ok(1.75));
+        assert_eq!(median(&xs, NanPolicy::Propagate), Ok(2.5));
+        assert_eq!(median(&[3.0, 1.0, 2.0], NanPolicy::Propagate), Ok(2.0));
+        let qs = quantiles(&xs, &[0.9, 1.0, 0.5], NanPolicy::Propagate).unwrap();
+        assert!((qs[0] - 3.7).abs() < 1e-12);
+        assert_eq!(qs[1..], [4.0, 2.5]);
+        assert_eq!(quantile_sorted(&[1.0, 2.0, 3.0, 4.0], 0.25), 1.75);
+
+        assert!(quantile(&xs, 1.5, NanPolicy::Propagate).is_err());
+        assert!(quantile(&xs, -0.1, NanPolicy::Propagate).is_err());
+        assert!(median(&[], NanPolicy::Propagate).unwrap().is_nan());
+        assert!(quantile_sorted(&[], 0.5).is_nan());
+    }
+
+    #[test]
+    fn nan_policies() {
+        let xs = [3.0, f64::NAN, 1.0, 2.0];
+        assert!(median(&xs, NanPolicy::Propagate).unwrap().is_nan());
+        assert_eq!(median(&xs, NanPolicy::Omit), Ok(2.0));
+        assert!(median(&xs, NanPolicy::Error).is_err());
+        assert!(mad(&xs, NanPolicy::Propagate).unwrap().is_nan());
+        assert_eq!(mad(&xs, NanPolicy::Omit), Ok(1.0));
+        assert!(mad(&xs, NanPolicy::Error).is_err());
+        let qs = quantiles(&xs, &[0.0, 1.0], NanPolicy::Propagate).unwrap();
+        assert!(qs.iter().all(|q| q.is_nan()));
+
+        // All NaN is empty once omitted; without NaN every policy agrees.
+        assert!(median(&[f64::NAN], NanPolicy::Omit).unwrap().is_nan());
+        assert_eq!(median(&[1.0, 2.0], NanPolicy::Error), Ok(1.5));
+    }
+
+    #[test]
+    fn median_absolute_deviation() {
+        let xs = [1.0, 1.0, 2.0, 2.0, 4.0, 6.0, 9.0];
+        assert_eq!(mad(&xs, NanPolicy::Propagate), Ok(1.0));
+        assert_eq!(mad(&[1.0, 2.0, 3.0, 4.0], NanPolicy::Propagate), Ok(1.0));
+        assert_eq!(mad(&[5.0; 4], NanPolicy::Propagate), Ok(0.0));
+        assert!(mad(&[], NanPolicy::Propagate).unwrap().is_nan());
+
+        // Scaled, the MAD of data spread like N(0, 1) quartiles is about 1.
+        let normal_quantiles = [-1.5, -0.6745, -0.3, 0.0, 0.3, 0.6745, 1.5];
+        let sigma = mad(&normal_quantiles, NanPolicy::Propagate).unwrap() * MAD_NORMAL_SCALE;
+        assert!((sigma - 1.0).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,252 @@
+//! Push-style mzML parsing for data that arrives in chunks (browser
+//! `ReadableStream`s, sockets) and cannot be held or seeked as one buffer.
+
+use super::parse_mzml::{ParseOptions, Scratch, SpectrumSummary, parse_spectrum_block};
+use super::simd::memmem;
+
+const OPEN_TAG: &[u8] = b"<spectrum ";
+const CLOSE_TAG: &[u8] = b"</spectrum>";
+
+/// Incremental `<spectrum>` parser. Feed it chunks split at arbitrary byte
+/// boundaries; every spectrum is parsed as soon as its `</spectrum>` has
+/// arrived. Only the spectrum currently being received is buffered, and the
+/// index at the end of the file is never needed.
+pub struct SpectrumStream {
+    buf: Vec<u8>,
+    // Offset in `buf` where the next tag search resumes.
+    search_from: usize,
+    in_spectrum: bool,
+    scratch: Scratch,
+    ready: Vec<SpectrumSummary>,
+}
+
+impl Default for SpectrumStream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SpectrumStream {
+    pub fn new() -> Self {
+        Self::with_options(&ParseOptions::default())
+    }
+
+    pub fn with_options(opts: &ParseOptions) -> Self {
+        SpectrumStream {
+            buf: Vec::new(),
+            search_from: 0,
+            in_spectrum: false,
+            scratch: Scratch::with_options(opts),
+            ready: Vec::new(),
+        }
+    }
+
+    /// Appends `chunk` and parses every spectrum it completes.
+    pub fn feed(&mut self, chunk: &[u8]) {
+        self.buf.extend_from_slice(chunk);
+        // Start of the bytes still needed; everything before it is dropped
+        // once, after the loop, so a chunk holding many spectra is not
+        // shifted once per spectrum.
+        let mut start = 0usize;
+        loop {
+            if !self.in_spectrum {
+                match memmem(&self.buf[self.search_from..], OPEN_TAG) {
+                    Some(rel) => {
+                        start = self.search_from + rel;
+                        self.search_from = start + OPEN_TAG.len();
+                        self.in_spectrum = true;
+                    }
+                    None => {
+                        // Keep just enough to match a tag split by the chunk edge.
+                        start = self.buf.len().saturating_sub(OPEN_TAG.len() - 1);
+                        self.search_from = start;
+                        break;
+                    }
+                }
+            }
+
+            match memmem(&self.buf[self.search_from..], CLOSE_TAG) {
+                Some(rel) => {
+                    let end = self.search_from + rel + CLOSE_TAG.len();
+                    if let Some(sum) =
+                        parse_spectrum_block(&self.buf[start..end], &mut self.scratch)
+                    {
+                        self.ready.push(sum);
+                    }
+                    start = end;
+                    self.search_from = end;
+                    self.in_spectrum = false;
+                }
+                None => {
+                    self.search_from = self
+                        .buf
+                        .len()
+                        .saturating_sub(CLOSE_TAG.len() - 1)
+                        .max(start + OPEN_TAG.len());
+                    break;
+                }
+            }
+        }
+        self.buf.drain(..start);
+        self.search_from -= start;
+    }
+
+    /// Spectra completed since the last call, in file order.
+    pub fn take_spectra(&mut self) -> Vec<SpectrumSummary> {
+        std::mem::take(&mut self.ready)
+    }
+
+    /// Ends the stream, returning the spectra not yet taken. Fails if the
+    /// input stopped in the middle of a spectrum.
+    pub fn finish(mut self) -> Result<Vec<SpectrumSummary>, String> {
+        if self.in_spectrum {
+            return Err("unterminated <spectrum>".into());
+        }
+        Ok(self.take_spectra())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utilities::parse_mzml::parse_mzml;
+    use crate::utilities::parse_mzml::test_spectra::MZML;
+
+    fn streamed(chunks: impl IntoIterator<Item = std::ops::Range<usize>>) -> Vec<SpectrumSummary> {
+        let mut stream = SpectrumStream::new();
+        for r in chunks {
+            stream.feed(&MZML[r]);
+        }
+        stream.finish().unwrap()
+    }
+
+    fn split_at(cuts: &[usize]) -> Vec<std::ops::Range<usize>> {
+        let mut edges = vec![0];
+        edges.extend(cuts.iter().copied().filter(|&c| c < MZML.len()));
+        edges.push(MZML.len());
+        edges.sort_unstable();
+        edges.windows(2).map(|w| w[0]..w[1]).collect()
+    }
+
+    fn offset(needle: &[u8], nth: usize) -> usize {
+        (0..MZML.len())
+            .filter(|&i| MZML[i..].starts_with(needle))
+            .nth(nth)
+            .unwrap()
+    }
+
+    #[test]
+    fn any_split_matches_the_whole_file() {
+        let whole = parse_mzml(MZML).unwrap();
+        assert_eq!(whole.len(), 4);
+
+        assert_eq!(streamed(std::iter::once(0..MZML.len())), whole);
+        assert_eq!(streamed((0..MZML.len()).map(|i| i..i + 1)), whole);
+        for size in [2, 3, 7, 64, 1000, 4096] {
+            let chunks = (0..MZML.len())
+                .step_by(size)
+                .map(|i| i..(i + size).min(MZML.len()));
+            assert_eq!(streamed(chunks), whole, "chunks of {size}");
+        }
+
+        // Random cut points from a fixed xorshift sequence.
+        let mut x = 0x2545_f491_4f6c_dd1du64;
+        for _ in 0..50 {
+            let cuts: Vec<usize> = (0..20)
+                .map(|_| {
+                    x ^= x << 13;
+                    x ^= x >> 7;
+                    x ^= x << 17;
+                    (x % MZML.len() as u64) as usize
+                })
+                .collect();
+            assert_eq!(streamed(split_at(&cuts)), whole, "cuts at {cuts:?}");
+        }
+    }
+
+    #[test]
+    fn tags_and_payloads_split_across_chunks() {
+        let whole = parse_mzml(MZML).unwrap();
+        let open = offset(b"<spectrum ", 2);
+        let close = offset(b"</spectrum>", 1);
+        let binary = offset(b"<binary>", 4) + b"<binary>".len();
+        for cuts in [
+            // Inside `<spectrum ` and `</spectrum>`, at each byte.
+            (1..b"<spectrum ".len())
+                .map(|k| open + k)
+                .collect::<Vec<_>>(),
+            (1..b"</spectrum>".len()).map(|k| close + k).collect(),
+            // Base64 payload of the third spectrum, cut inside a quantum.
+            vec![binary + 1, binary + 6, binary + 403],
+            vec![open + 3, binary + 2, close + 5],
+        ] {
+            for &c in &cuts {
+                assert_eq!(streamed(split_at(&[c])), whole, "cut at {c}");
+            }
+            assert_eq!(streamed(split_at(&cuts)), whole, "cuts at {cuts:?}");
+        }
+    }
+
+    #[test]
+    fn spectra_are_taken_as_they_complete() {
+        let whole = parse_mzml(MZML).unwrap();
+        let mut stream = SpectrumStream::new();
+        let mut taken = Vec::new();
+        let mut counts = Vec::new();
+        for chunk in MZML.chunks(1024) {
+            stream.feed(chunk);
+            let ready = stream.take_spectra();
+            counts.push(ready.len());
+            taken.extend(ready);
+        }
+        assert!(stream.take_spectra().is_empty());
+        assert!(stream.finish().unwrap().is_empty());
+        assert_eq!(taken, whole);
+        // Each spectrum is handed over once, by the chunk that completes it.
+        assert_eq!(counts.iter().sum::<usize>(), 4);
+        assert!(counts.iter().filter(|&&n| n > 0).count() > 1);
+
+        // Spectra not taken are returned by `finish`.
+        let mut stream = SpectrumStream::new();
+        let second = offset(b"<spectrum ", 1);
+        stream.feed(&MZML[..second]);
+        assert_eq!(stream.take_spectra(), whole[..1]);
+        stream.feed(&MZML[second..]);
+        assert_eq!(stream.finish().unwrap(), whole[1..]);
+    }
+
+    #[test]
+    fn truncated_spectrum_is_an_error() {
+        let whole = parse_mzml(MZML).unwrap();
+        let open = offset(b"<spectrum ", 2);
+        let binary = offset(b"<binary>", 4) + 100;
+        let close = offset(b"</spectrum>", 2);
+        for end in [open + b"<spectrum ".len(), binary, close + 5] {
+            let mut stream = SpectrumStream::new();
+            stream.feed(&MZML[..end]);
+            assert_eq!(stream.take_spectra(), whole[..2]);
+            assert!(stream.finish().is_err(), "cut at {end}");
+        }
+
+        // Ending between spectra, or before any, is not.
+        let mut stream = SpectrumStream::new();
+        stream.feed(&MZML[..open]);
+        assert_eq!(stream.finish().unwrap(), whole[..2]);
+        assert!(SpectrumStream::new().finish().unwrap().is_empty());
+    }
+
+    #[test]
+    fn options_filter_streamed_spectra() {
+        let opts = ParseOptions {
+            ms_levels: vec![2],
+            ..ParseOptions::default()
+        };
+        let mut stream = SpectrumStream::with_options(&opts);
+        for chunk in MZML.chunks(100) {
+            stream.feed(chunk);
+        }
+        let spectra = stream.finish().unwrap();
+        assert_eq!(spectra.len(), 1);
+        assert_eq!(spectra[0].id, "scan=2");
+    }
+}
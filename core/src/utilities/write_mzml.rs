@@ -0,0 +1,328 @@
+//! Writes mzML whose spectra carry arrays processed in memory, such as
+//! recalibrated m/z values.
+//!
+//! The source file is copied unchanged except for the m/z and intensity
+//! arrays of each spectrum, written uncompressed in the precision of the
+//! new [`ArrayData`], and its `defaultArrayLength`. Per-peak ion mobility
+//! arrays are rewritten the same way, or dropped when the spectrum no
+//! longer has one, as after centroiding. The output is plain
+//! mzML: the `indexedmzML` wrapper and index of the source, whose offsets
+//! no longer hold, are left out.
+
+use std::io::Write;
+
+use super::parse_mzml::{ArrayData, MOBILITY_ARRAYS, SpectrumSummary, spectrum_spans};
+use super::simd::{memchr, memmem};
+
+/// Copies `source` to `out` with the arrays of its spectra replaced by
+/// those of `spectra`, given in file order. Spectra with neither array are
+/// copied as they are.
+pub fn write_mzml<W: Write>(
+    source: &[u8],
+    spectra: &[SpectrumSummary],
+    out: &mut W,
+) -> Result<(), String> {
+    let spans = spectrum_spans(source)?;
+    if spans.len() != spectra.len() {
+        return Err(format!(
+            "{} spectra given for a file with {}",
+            spectra.len(),
+            spans.len()
+        ));
+    }
+    let start = memmem(source, b"<mzML").ok_or("no <mzML> element")?;
+    let end = rfind(source, b"</mzML>").ok_or("no </mzML>")? + b"</mzML>".len();
+
+    let io = |e: std::io::Error| format!("write: {e}");
+    out.write_all(b"<?xml version=\"1.0\" encoding=\"utf-8\"?>\n")
+        .map_err(io)?;
+    let mut pos = start;
+    // The namespace may have been declared on the dropped wrapper only.
+    let tag_end = start + memchr(&source[start..], b'>').ok_or("unterminated <mzML>")?;
+    if memmem(&source[start..tag_end], b"xmlns=").is_none() {
+        out.write_all(b"<mzML xmlns=\"http://psi.hupo.org/ms/mzml\"")
+            .map_err(io)?;
+        pos = start + b"<mzML".len();
+    }
+    for (span, s) in spans.iter().zip(spectra) {
+        out.write_all(&source[pos..span.start]).map_err(io)?;
+        out.write_all(&rewrite_spectrum(&source[span.clone()], s))
+            .map_err(io)?;
+        pos = span.end;
+    }
+    out.write_all(&source[pos..end]).map_err(io)?;
+    out.write_all(b"\n").map_err(io)
+}
+
+fn rfind(hay: &[u8], needle: &[u8]) -> Option<usize> {
+    hay.windows(needle.len()).rposition(|w| w == needle)
+}
+
+fn rewrite_spectrum(block: &[u8], s: &SpectrumSummary) -> Vec<u8> {
+    let len = match (&s.mz_array, &s.intensity_array) {
+        (None, None) => return block.to_vec(),
+        (Some(a), _) | (None, Some(a)) => a.len(),
+    };
+    let mut out = Vec::with_capacity(block.len());
+
+    // defaultArrayLength in the opening tag.
+    let tag_end = memchr(block, b'>').unwrap_or(0);
+    let attr = b"defaultArrayLength=\"";
+    let mut pos = 0;
+    if let Some(p) = memmem(&block[..tag_end], attr) {
+        let v = p + attr.len();
+        let q = v + memchr(&block[v..], b'"').unwrap_or(0);
+        out.extend_from_slice(&block[..v]);
+        out.extend_from_slice(len.to_string().as_bytes());
+        pos = q;
+    }
+
+    const OPEN: &[u8] = b"<binaryDataArray";
+    const CLOSE: &[u8] = b"</binaryDataArray>";
+    let mut dropped = 0;
+    let mut cur = pos;
+    while let Some(p) = memmem(&block[cur..], OPEN) {
+        let from = cur + p;
+        // Skip <binaryDataArrayList.
+        if block.get(from + OPEN.len()) == Some(&b'L') {
+            cur = from + OPEN.len();
+            continue;
+        }
+        let Some(e) = memmem(&block[from..], CLOSE) else {
+            break;
+        };
+        let to = from + e + CLOSE.len();
+        let element = &block[from..to];
+        let replacement = if memmem(element, b"name=\"m/z array\"").is_some() {
+            s.mz_array.as_ref().map(|a| (a, MZ_PARAM))
+        } else if memmem(element, b"name=\"intensity array\"").is_some() {
+            s.intensity_array.as_ref().map(|a| (a, INTENSITY_PARAM))
+        } else if let Some(param) = mobility_param(element) {
+            out.extend_from_slice(&block[pos..from]);
+            match &s.mobility_array {
+                Some(a) => binary_data_array(a, param, &mut out),
+                None => dropped += 1,
+            }
+            pos = to;
+            None
+        } else {
+            None
+        };
+        if let Some((a, param)) = replacement {
+            out.extend_from_slice(&block[pos..from]);
+            binary_data_array(a, param, &mut out);
+            pos = to;
+        }
+        cur = to;
+    }
+    out.extend_from_slice(&block[pos..]);
+    if dropped > 0 {
+        fix_array_count(&mut out, dropped);
+    }
+    out
+}
+
+// The `<cvParam>` naming a per-peak mobility array, kept as written so its
+// unit carries over.
+fn mobility_param(element: &[u8]) -> Option<&str> {
+    let at = MOBILITY_ARRAYS.iter().find_map(|(name, _)| {
+        let mut pat = b"name=\"".to_vec();
+        pat.extend_from_slice(name);
+        pat.push(b'"');
+        memmem(element, &pat)
+    })?;
+    let from = element[..at].iter().rposition(|&c| c == b'<')?;
+    let to = at + memchr(&element[at..], b'>')? + 1;
+    str::from_utf8(&element[from..to]).ok()
+}
+
+// Lowers the `count` of `<binaryDataArrayList>` by `dropped`.
+fn fix_array_count(block: &mut Vec<u8>, dropped: usize) {
+    const ATTR: &[u8] = b"count=\"";
+    let Some(list) = memmem(block, b"<binaryDataArrayList") else {
+        return;
+    };
+    let Some(p) = memmem(&block[list..], ATTR) else {
+        return;
+    };
+    let v = list + p + ATTR.len();
+    let Some(q) = memchr(&block[v..], b'"').map(|q| v + q) else {
+        return;
+    };
+    let Some(n) = str::from_utf8(&block[v..q])
+        .ok()
+        .and_then(|n| n.parse::<usize>().ok())
+    else {
+        return;
+    };
+    block.splice(v..q, n.saturating_sub(dropped).to_string().into_bytes());
+}
+
+const MZ_PARAM: &str = r#"<cvParam cvRef="MS" accession="MS:1000514" name="m/z array" value="" unitCvRef="MS" unitAccession="MS:1000040" unitName="m/z"/>"#;
+const INTENSITY_PARAM: &str = r#"<cvParam cvRef="MS" accession="MS:1000515" name="intensity array" value="" unitCvRef="MS" unitAccession="MS:1000131" unitName="number of detector counts"/>"#;
+
+fn binary_data_array(a: &ArrayData, param: &str, out: &mut Vec<u8>) {
+    let (bytes, precision): (Vec<u8>, &str) = match a {
+        ArrayData::F32(v) => (
+            v.iter().flat_map(|x| x.to_le_bytes()).collect(),
+            r#"<cvParam cvRef="MS" accession="MS:1000521" name="32-bit float" value=""/>"#,
+        ),
+        ArrayData::F64(v) => (
+            v.iter().flat_map(|x| x.to_le_bytes()).collect(),
+            r#"<cvParam cvRef="MS" accession="MS:1000523" name="64-bit float" value=""/>"#,
+        ),
+    };
+    let encoded = encode_base64(&bytes);
+    out.extend_from_slice(
+        format!("<binaryDataArray encodedLength=\"{}\">\n", encoded.len()).as_bytes(),
+    );
+    out.extend_from_slice(precision.as_bytes());
+    out.extend_from_slice(
+        b"\n<cvParam cvRef=\"MS\" accession=\"MS:1000576\" name=\"no compression\" value=\"\"/>\n",
+    );
+    out.extend_from_slice(param.as_bytes());
+    out.extend_from_slice(b"\n<binary>");
+    out.extend_from_slice(&encoded);
+    out.extend_from_slice(b"</binary>\n</binaryDataArray>");
+}
+
+fn encode_base64(data: &[u8]) -> Vec<u8> {
+    const TABLE: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = Vec::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        out.push(TABLE[(n >> 18) as usize & 63]);
+        out.push(TABLE[(n >> 12) as usize & 63]);
+        out.push(if chunk.len() > 1 {
+            TABLE[(n >> 6) as usize & 63]
+        } else {
+            b'='
+        });
+        out.push(if chunk.len() > 2 {
+            TABLE[n as usize & 63]
+        } else {
+            b'='
+        });
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utilities::parse_mzml::parse_mzml;
+
+    const MOBILITY_PARAM: &str = r#"<cvParam cvRef="MS" accession="MS:1003008" name="raw inverse reduced ion mobility array" value="" unitCvRef="MS" unitAccession="MS:1002814" unitName="volt-second per square centimeter"/>"#;
+
+    // A wrapped file with an MS1 scan carrying m/z, intensity and
+    // mobility arrays, and a spectrum without arrays.
+    fn source() -> Vec<u8> {
+        let mut arrays = Vec::new();
+        binary_data_array(
+            &ArrayData::F64(vec![100.0, 200.0, 300.0]),
+            MZ_PARAM,
+            &mut arrays,
+        );
+        binary_data_array(
+            &ArrayData::F32(vec![1.0, 2.0, 3.0]),
+            INTENSITY_PARAM,
+            &mut arrays,
+        );
+        binary_data_array(
+            &ArrayData::F32(vec![0.8, 0.9, 1.0]),
+            MOBILITY_PARAM,
+            &mut arrays,
+        );
+        let mut doc = String::from(concat!(
+            r#"<?xml version="1.0" encoding="utf-8"?>"#,
+            "\n",
+            r#"<indexedmzML xmlns="http://psi.hupo.org/ms/mzml">"#,
+            r#"<mzML version="1.1.0"><run id="r"><spectrumList count="2">"#,
+            r#"<spectrum index="0" id="scan=1" defaultArrayLength="3">"#,
+            r#"<cvParam cvRef="MS" accession="MS:1000511" name="ms level" value="1"/>"#,
+            r#"<binaryDataArrayList count="3">"#,
+        ));
+        doc.push_str(str::from_utf8(&arrays).unwrap());
+        doc.push_str(concat!(
+            "</binaryDataArrayList></spectrum>",
+            r#"<spectrum index="1" id="scan=2" defaultArrayLength="0">"#,
+            r#"<cvParam cvRef="MS" accession="MS:1000511" name="ms level" value="2"/>"#,
+            "</spectrum></spectrumList></run></mzML>",
+            "</indexedmzML>",
+        ));
+        doc.into_bytes()
+    }
+
+    fn write(source: &[u8], spectra: &[SpectrumSummary]) -> Vec<u8> {
+        let mut out = Vec::new();
+        write_mzml(source, spectra, &mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn base64_matches_the_standard_alphabet() {
+        assert_eq!(encode_base64(b""), b"");
+        assert_eq!(encode_base64(b"f"), b"Zg==");
+        assert_eq!(encode_base64(b"fo"), b"Zm8=");
+        assert_eq!(encode_base64(b"foobar"), b"Zm9vYmFy");
+    }
+
+    #[test]
+    fn arrays_are_replaced() {
+        let src = source();
+        let mut spectra = parse_mzml(&src).unwrap();
+        assert_eq!(spectra[0].mobility_array.as_ref().map(|a| a.len()), Some(3));
+        spectra[0].mz_array = Some(ArrayData::F64(vec![100.5, 200.5]));
+        spectra[0].intensity_array = Some(ArrayData::F32(vec![5.0, 6.0]));
+        spectra[0].mobility_array = Some(ArrayData::F64(vec![0.85, 0.95]));
+        let out = write(&src, &spectra);
+
+        assert!(out.starts_with(b"<?xml"));
+        assert!(memmem(&out, b"indexedmzML").is_none());
+        assert!(memmem(&out, b"defaultArrayLength=\"2\"").is_some());
+        let back = parse_mzml(&out).unwrap();
+        assert_eq!(back.len(), 2);
+        assert_eq!(back[0].array_length, 2);
+        assert!(matches!(&back[0].mz_array, Some(ArrayData::F64(v)) if v == &[100.5, 200.5]));
+        assert!(matches!(&back[0].intensity_array, Some(ArrayData::F32(v)) if v == &[5.0, 6.0]));
+        assert_eq!(
+            back[0].mobility_array.as_ref().map(|a| a.to_f64().to_vec()),
+            Some(vec![0.85, 0.95])
+        );
+        assert_eq!(back[1].ms_level, Some(2));
+    }
+
+    #[test]
+    fn dropped_mobility_arrays_lower_the_count() {
+        let src = source();
+        let mut spectra = parse_mzml(&src).unwrap();
+        spectra[0].mobility_array = None;
+        let out = write(&src, &spectra);
+        assert!(memmem(&out, b"<binaryDataArrayList count=\"2\"").is_some());
+        assert!(memmem(&out, b"ion mobility array").is_none());
+        let back = parse_mzml(&out).unwrap();
+        assert!(back[0].mobility_array.is_none());
+        assert_eq!(back[0].mz_array.as_ref().map(|a| a.len()), Some(3));
+    }
+
+    #[test]
+    fn namespace_is_kept_and_counts_must_match() {
+        let src = source();
+        let spectra = parse_mzml(&src).unwrap();
+        let out = write(&src, &spectra);
+        assert!(
+            memmem(
+                &out,
+                b"<mzML xmlns=\"http://psi.hupo.org/ms/mzml\" version="
+            )
+            .is_some()
+        );
+        let mut out = Vec::new();
+        assert!(write_mzml(&src, &spectra[..1], &mut out).is_err());
+    }
+}
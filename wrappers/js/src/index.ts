@@ -7,6 +7,14 @@ declare var require: any;
 
 type Api = ReturnType<typeof makeApi>;
 export let utilities: Api["utilities"];
+export let parseMzML: Api["parseMzML"];
+export let createMzMLStream: Api["createMzMLStream"];
+export type {
+  MzMLFile,
+  MzMLStream,
+  NumericArray,
+  Spectrum,
+} from "./makeApi.js";
 
 function dataUrlToBytes(dataUrl: string): Uint8Array {
   const b64 = dataUrl.split(",")[1] ?? "";
@@ -28,6 +36,8 @@ function instantiateSync(bytes: Uint8Array, imports: WebAssembly.Imports = {}) {
   const instance = new WebAssembly.Instance(mod, imports);
   const api = makeApi(instance);
   utilities = api.utilities;
+  parseMzML = api.parseMzML;
+  createMzMLStream = api.createMzMLStream;
 }
 
 (function main() {
@@ -1,14 +1,84 @@
 // src/makeApi.ts
+export type NumericArray = Float64Array | Float32Array;
+
+export interface Spectrum {
+  index: number;
+  id: string;
+  arrayLength: number;
+  msLevel: number | null;
+  scanType: string | null;
+  polarity: string | null;
+  spectrumType: string | null;
+  retentionTime: number;
+  scanWindowLowerLimit: number;
+  scanWindowUpperLimit: number;
+  totalIonCurrent: number;
+  basePeakIntensity: number;
+  basePeakMz: number;
+  precursorMz: number;
+  precursorCharge: number | null;
+  isolationWindowTarget: number;
+  isolationWindowLower: number;
+  isolationWindowUpper: number;
+  ionMobility: number;
+  ionMobilityType: string | null;
+  // Views into wasm memory, valid until the owning file is closed.
+  readonly mz: NumericArray | null;
+  readonly intensity: NumericArray | null;
+  // Per-peak ion mobility, when the file carries it.
+  readonly mobility: NumericArray | null;
+}
+
+export interface MzMLFile {
+  readonly spectra: Spectrum[];
+  close: () => void;
+}
+
+export interface MzMLStream {
+  // Spectra completed by this chunk; chunks may split anywhere.
+  push: (chunk: Uint8Array) => MzMLFile;
+  // Remaining spectra; throws if the input ended inside a spectrum.
+  finish: () => MzMLFile;
+}
+
 export interface RawApi {
   utilities: {
     mean: (xs: number[]) => number;
     std: (xs: number[]) => number;
     median: (xs: number[]) => number;
   };
+  parseMzML: (bytes: Uint8Array) => MzMLFile;
+  createMzMLStream: () => MzMLStream;
 }
 
 type Fn = (ptr: number, len: number, out: number) => number;
 
+// Mirrors ULCMS_FIELD_* and ULCMS_ARRAY_* in core/include/ulcms.h.
+const FIELD = {
+  INDEX: 0,
+  ARRAY_LENGTH: 1,
+  MS_LEVEL: 2,
+  RT: 3,
+  SCAN_WINDOW_LOWER: 4,
+  SCAN_WINDOW_UPPER: 5,
+  TIC: 6,
+  BASE_PEAK_INTENSITY: 7,
+  BASE_PEAK_MZ: 8,
+  PRECURSOR_MZ: 9,
+  PRECURSOR_CHARGE: 10,
+  ISOLATION_WINDOW_TARGET: 11,
+  ISOLATION_WINDOW_LOWER: 12,
+  ISOLATION_WINDOW_UPPER: 13,
+  ION_MOBILITY: 14,
+  ID: 100,
+  SCAN_TYPE: 101,
+  POLARITY: 102,
+  SPECTRUM_TYPE: 103,
+  ION_MOBILITY_TYPE: 104,
+} as const;
+const ARRAY_F32 = 1;
+const ARRAY_F64 = 2;
+
 export function makeApi(instance: WebAssembly.Instance): RawApi {
   const exp = instance.exports as Record<string, any>;
   const memory = exp.memory as WebAssembly.Memory;
@@ -17,6 +87,35 @@ export function makeApi(instance: WebAssembly.Instance): RawApi {
   const fMean = exp.ulcms_mean_f64 as Fn;
   const fStd = exp.ulcms_std_f64 as Fn;
   const fMed = exp.ulcms_median_f64 as Fn;
+  const fileOpen = exp.ulcms_file_open_from_bytes as Fn;
+  const fileClose = exp.ulcms_file_close as (file: number) => void;
+  const count = exp.ulcms_spectrum_count as (file: number) => number;
+  const getF64 = exp.ulcms_spectrum_get_f64 as (
+    file: number,
+    index: number,
+    field: number,
+  ) => number;
+  const getStr = exp.ulcms_spectrum_get_str as (
+    file: number,
+    index: number,
+    field: number,
+    outPtr: number,
+    outLen: number,
+  ) => number;
+  const arrData = exp.ulcms_spectrum_array_data as (
+    file: number,
+    index: number,
+    which: number,
+  ) => number;
+  const arrLen = exp.ulcms_spectrum_array_len as typeof arrData;
+  const arrType = exp.ulcms_spectrum_array_type as typeof arrData;
+  const streamNew = exp.ulcms_stream_new as () => number;
+  const streamFeed = exp.ulcms_stream_feed as Fn;
+  const streamTake = exp.ulcms_stream_take_spectra as (
+    stream: number,
+    out: number,
+  ) => number;
+  const streamFinish = exp.ulcms_stream_finish as typeof streamTake;
 
   const call1 = (xs: number[], f: Fn): number => {
     const n = xs.length;
@@ -35,11 +134,149 @@ export function makeApi(instance: WebAssembly.Instance): RawApi {
     }
   };
 
+  const decoder = new TextDecoder();
+
+  // Calls `f(outPtr)` and reads back the file handle it stores there.
+  const withFileOut = (f: (outPtr: number) => number): number => {
+    const outPtr = alloc(4);
+    try {
+      const rc = f(outPtr);
+      if (rc !== 0) throw new Error(`ULCMS FFI error (rc=${rc})`);
+      return new Uint32Array(memory.buffer, outPtr, 1)[0];
+    } finally {
+      free_(outPtr, 4);
+    }
+  };
+
+  // Runs `f` with `bytes` copied into wasm memory.
+  const withBytes = <T>(bytes: Uint8Array, f: (ptr: number) => T): T => {
+    const ptr = alloc(bytes.length);
+    try {
+      new Uint8Array(memory.buffer, ptr, bytes.length).set(bytes);
+      return f(ptr);
+    } finally {
+      free_(ptr, bytes.length);
+    }
+  };
+
+  const parseMzML = (bytes: Uint8Array): MzMLFile =>
+    wrapFile(
+      withBytes(bytes, (ptr) =>
+        withFileOut((out) => fileOpen(ptr, bytes.length, out)),
+      ),
+    );
+
+  const createMzMLStream = (): MzMLStream => {
+    let stream = streamNew();
+    return {
+      push: (chunk) => {
+        if (stream === 0) throw new Error("ULCMS: stream is finished");
+        withBytes(chunk, (ptr) => {
+          const rc = streamFeed(stream, ptr, chunk.length);
+          if (rc !== 0) throw new Error(`ULCMS FFI error (rc=${rc})`);
+        });
+        return wrapFile(withFileOut((out) => streamTake(stream, out)));
+      },
+      finish: () => {
+        if (stream === 0) throw new Error("ULCMS: stream is finished");
+        const s = stream;
+        stream = 0;
+        return wrapFile(withFileOut((out) => streamFinish(s, out)));
+      },
+    };
+  };
+
+  function wrapFile(file: number): MzMLFile {
+    const strPtr = alloc(8);
+    const str = (i: number, field: number): string | null => {
+      if (getStr(file, i, field, strPtr, strPtr + 4) !== 0) return null;
+      const [ptr, len] = new Uint32Array(memory.buffer, strPtr, 2);
+      if (ptr === 0) return null;
+      return decoder.decode(new Uint8Array(memory.buffer, ptr, len));
+    };
+
+    let open = true;
+    // Views are rebuilt on access because growing wasm memory detaches
+    // every existing view of the old buffer.
+    const view = (i: number, which: number): NumericArray | null => {
+      if (!open) throw new Error("ULCMS: file is closed");
+      const ptr = arrData(file, i, which);
+      if (ptr === 0) return null;
+      const len = arrLen(file, i, which);
+      switch (arrType(file, i, which)) {
+        case ARRAY_F32:
+          return new Float32Array(memory.buffer, ptr, len);
+        case ARRAY_F64:
+          return new Float64Array(memory.buffer, ptr, len);
+        default:
+          return null;
+      }
+    };
+
+    const spectra: Spectrum[] = [];
+    try {
+      const n = count(file);
+      for (let i = 0; i < n; i++) {
+        const msLevel = getF64(file, i, FIELD.MS_LEVEL);
+        const charge = getF64(file, i, FIELD.PRECURSOR_CHARGE);
+        spectra.push({
+          index: getF64(file, i, FIELD.INDEX),
+          id: str(i, FIELD.ID) ?? "",
+          arrayLength: getF64(file, i, FIELD.ARRAY_LENGTH),
+          msLevel: Number.isNaN(msLevel) ? null : msLevel,
+          scanType: str(i, FIELD.SCAN_TYPE),
+          polarity: str(i, FIELD.POLARITY),
+          spectrumType: str(i, FIELD.SPECTRUM_TYPE),
+          retentionTime: getF64(file, i, FIELD.RT),
+          scanWindowLowerLimit: getF64(file, i, FIELD.SCAN_WINDOW_LOWER),
+          scanWindowUpperLimit: getF64(file, i, FIELD.SCAN_WINDOW_UPPER),
+          totalIonCurrent: getF64(file, i, FIELD.TIC),
+          basePeakIntensity: getF64(file, i, FIELD.BASE_PEAK_INTENSITY),
+          basePeakMz: getF64(file, i, FIELD.BASE_PEAK_MZ),
+          precursorMz: getF64(file, i, FIELD.PRECURSOR_MZ),
+          precursorCharge: Number.isNaN(charge) ? null : charge,
+          isolationWindowTarget: getF64(file, i, FIELD.ISOLATION_WINDOW_TARGET),
+          isolationWindowLower: getF64(file, i, FIELD.ISOLATION_WINDOW_LOWER),
+          isolationWindowUpper: getF64(file, i, FIELD.ISOLATION_WINDOW_UPPER),
+          ionMobility: getF64(file, i, FIELD.ION_MOBILITY),
+          ionMobilityType: str(i, FIELD.ION_MOBILITY_TYPE),
+          get mz() {
+            return view(i, 0);
+          },
+          get intensity() {
+            return view(i, 1);
+          },
+          get mobility() {
+            return view(i, 2);
+          },
+        });
+      }
+    } catch (e) {
+      // The caller never sees this file, so nothing else can close it.
+      open = false;
+      fileClose(file);
+      throw e;
+    } finally {
+      free_(strPtr, 8);
+    }
+
+    return {
+      spectra,
+      close: () => {
+        if (!open) return;
+        open = false;
+        fileClose(file);
+      },
+    };
+  }
+
   return {
     utilities: {
       mean: (xs) => call1(xs, fMean),
       std: (xs) => call1(xs, fStd),
       median: (xs) => call1(xs, fMed),
     },
+    parseMzML,
+    createMzMLStream,
   };
 }
@@ -1,5 +1,5 @@
-import ctypes, os, sys
-from ctypes import c_double, c_int, c_size_t, POINTER, byref
+import ctypes, os, sys, weakref
+from ctypes import c_char_p, c_double, c_int, c_size_t, c_uint32, c_void_p, POINTER, Structure, byref
 
 # Resolve artifacts path relative to this file
 _root = os.path.abspath(os.path.join(os.path.dirname(__file__), "..", ".."))
@@ -10,10 +10,85 @@ if sys.platform.startswith("win"):
 elif sys.platform == "darwin":
     libpath = os.path.join(_art, f"macos-{os.uname().machine}", "libulcms.dylib")
 else:
-    libpath = os.path.join(_art, f"linux-{os.uname().machine}", "libulcms.so")
+    _machine = {"aarch64": "arm64"}.get(os.uname().machine, os.uname().machine)
+    libpath = os.path.join(_art, f"linux-{_machine}", "libulcms.so")
+
+# Optional override for development builds
+libpath = os.environ.get("ULCMS_LIB_PATH", libpath)
 
 _lib = ctypes.CDLL(libpath)
 
+# Mirrors ArrayFFI, ULCMS_ARRAY_* and ULCMS_FIELD_* in core/include/ulcms.h.
+# Spectrum fields are read through accessors rather than a mirrored struct,
+# so new fields in the library never shift what this module reads.
+ARRAY_NONE, ARRAY_F32, ARRAY_F64 = 0, 1, 2
+
+
+POLARITY_ANY, POLARITY_POSITIVE, POLARITY_NEGATIVE = 0, 1, 2
+_POLARITIES = {None: POLARITY_ANY, "positive": POLARITY_POSITIVE, "negative": POLARITY_NEGATIVE}
+
+
+class _ParseOptions(Structure):
+    _fields_ = [
+        ("ms_levels", POINTER(c_uint32)),
+        ("n_ms_levels", c_size_t),
+        ("rt_min", c_double),
+        ("rt_max", c_double),
+        ("polarity", c_uint32),
+    ]
+
+
+class _BinningOptions(Structure):
+    _fields_ = [
+        ("bin_width", c_double),
+        ("bin_unit", c_uint32),
+        ("mz_min", c_double),
+        ("mz_max", c_double),
+        ("aggregation", c_uint32),
+        ("rt_step", c_double),
+        ("rt_min", c_double),
+        ("rt_max", c_double),
+        ("ms_level", c_uint32),
+        ("polarity", c_uint32),
+    ]
+
+
+class _ArrayFFI(Structure):
+    _fields_ = [
+        ("data", c_void_p),
+        ("len", c_size_t),
+        ("element_type", c_uint32),
+    ]
+
+
+_F64_FIELDS = {
+    "index": 0,
+    "array_length": 1,
+    "ms_level": 2,
+    "retention_time": 3,
+    "scan_window_lower_limit": 4,
+    "scan_window_upper_limit": 5,
+    "total_ion_current": 6,
+    "base_peak_intensity": 7,
+    "base_peak_mz": 8,
+    "precursor_mz": 9,
+    "precursor_charge": 10,
+    "isolation_window_target": 11,
+    "isolation_window_lower": 12,
+    "isolation_window_upper": 13,
+    "ion_mobility": 14,
+}
+_STR_FIELDS = {
+    "id": 100,
+    "scan_type": 101,
+    "polarity": 102,
+    "spectrum_type": 103,
+    "ion_mobility_type": 104,
+}
+_INT_FIELDS = ("index", "array_length", "ms_level", "precursor_charge")
+_ARRAYS = (("mz", 0), ("intensity", 1), ("mobility", 2))
+
+
 # Signatures
 _lib.add_i32.argtypes = (c_int, c_int)
 _lib.add_i32.restype  = c_int
@@ -21,6 +96,71 @@ _lib.add_i32.restype  = c_int
 _lib.sum_f64.argtypes = (POINTER(c_double), c_size_t, POINTER(c_double))
 _lib.sum_f64.restype  = c_int
 
+_lib.ulcms_file_open.argtypes = (c_char_p, POINTER(c_void_p))
+_lib.ulcms_file_open.restype  = c_int
+
+_lib.ulcms_parse_options_default.argtypes = ()
+_lib.ulcms_parse_options_default.restype  = _ParseOptions
+
+_lib.ulcms_file_open_with.argtypes = (c_char_p, POINTER(_ParseOptions), POINTER(c_void_p))
+_lib.ulcms_file_open_with.restype  = c_int
+
+_lib.ulcms_file_close.argtypes = (c_void_p,)
+_lib.ulcms_file_close.restype  = None
+
+_lib.ulcms_spectrum_count.argtypes = (c_void_p,)
+_lib.ulcms_spectrum_count.restype  = c_size_t
+
+_lib.ulcms_spectrum_get_f64.argtypes = (c_void_p, c_size_t, c_uint32)
+_lib.ulcms_spectrum_get_f64.restype  = c_double
+
+_lib.ulcms_spectrum_get_str.argtypes = (c_void_p, c_size_t, c_uint32, POINTER(c_void_p), POINTER(c_size_t))
+_lib.ulcms_spectrum_get_str.restype  = c_int
+
+_lib.ulcms_spectrum_array.argtypes = (c_void_p, c_size_t, c_uint32, POINTER(_ArrayFFI))
+_lib.ulcms_spectrum_array.restype  = c_int
+
+_lib.ulcms_reader_open.argtypes = (c_char_p, POINTER(c_void_p))
+_lib.ulcms_reader_open.restype  = c_int
+
+_lib.ulcms_reader_len.argtypes = (c_void_p,)
+_lib.ulcms_reader_len.restype  = c_size_t
+
+_lib.ulcms_reader_array_length.argtypes = (c_void_p, c_size_t, POINTER(c_size_t))
+_lib.ulcms_reader_array_length.restype  = c_int
+
+_lib.ulcms_reader_decode_into.argtypes = (
+    c_void_p, c_size_t, c_void_p, c_size_t, c_void_p, c_size_t, POINTER(c_size_t), POINTER(c_size_t)
+)
+_lib.ulcms_reader_decode_into.restype  = c_int
+
+_lib.ulcms_reader_free.argtypes = (c_void_p,)
+_lib.ulcms_reader_free.restype  = None
+
+_lib.ulcms_binning_options_default.argtypes = ()
+_lib.ulcms_binning_options_default.restype  = _BinningOptions
+
+_lib.ulcms_bin_spectra.argtypes = (c_void_p, POINTER(_BinningOptions), POINTER(c_void_p))
+_lib.ulcms_bin_spectra.restype  = c_int
+
+_lib.ulcms_binned_shape.argtypes = (c_void_p, POINTER(c_size_t), POINTER(c_size_t), POINTER(c_size_t))
+_lib.ulcms_binned_shape.restype  = c_int
+
+_lib.ulcms_binned_axes.argtypes = (c_void_p, POINTER(c_void_p), POINTER(c_void_p))
+_lib.ulcms_binned_axes.restype  = c_int
+
+_lib.ulcms_binned_row_spectrum.argtypes = (c_void_p, c_size_t)
+_lib.ulcms_binned_row_spectrum.restype  = c_size_t
+
+_lib.ulcms_binned_csr.argtypes = (c_void_p, POINTER(c_void_p), POINTER(c_void_p), POINTER(c_void_p))
+_lib.ulcms_binned_csr.restype  = c_int
+
+_lib.ulcms_binned_to_dense.argtypes = (c_void_p, ctypes.c_bool, c_void_p, c_size_t)
+_lib.ulcms_binned_to_dense.restype  = c_int
+
+_lib.ulcms_binned_free.argtypes = (c_void_p,)
+_lib.ulcms_binned_free.restype  = None
+
 def add(a: int, b: int) -> int:
     return _lib.add_i32(int(a), int(b))
 
@@ -32,3 +172,335 @@ def sum_array(xs) -> float:
     if rc != 0:
         raise RuntimeError(f"sum_f64 failed with code {rc}")
     return float(out.value)
+
+
+HEADER_COLUMNS = (
+    "index",
+    "id",
+    "ms_level",
+    "scan_type",
+    "polarity",
+    "spectrum_type",
+    "retention_time",
+    "scan_window_lower_limit",
+    "scan_window_upper_limit",
+    "total_ion_current",
+    "base_peak_intensity",
+    "base_peak_mz",
+    "precursor_mz",
+    "precursor_charge",
+    "isolation_window_target",
+    "isolation_window_lower",
+    "isolation_window_upper",
+    "ion_mobility",
+    "ion_mobility_type",
+    "array_length",
+)
+
+
+class _File:
+    """Owns one `ulcms_file_open_with` handle; closed once nothing refers
+    to it. `opts`, a `_ParseOptions`, selects the spectra that are parsed."""
+
+    def __init__(self, path, opts=None):
+        handle = c_void_p()
+        if opts is None:
+            opts = _lib.ulcms_parse_options_default()
+        rc = _lib.ulcms_file_open_with(os.fsencode(path), byref(opts), byref(handle))
+        if rc != 0:
+            raise RuntimeError(f"ulcms_file_open_with failed with code {rc}")
+        self.handle = handle
+        self._finalizer = weakref.finalize(self, _lib.ulcms_file_close, handle)
+
+    def __len__(self):
+        return _lib.ulcms_spectrum_count(self.handle)
+
+    def close(self):
+        self._finalizer()
+
+    def f64(self, i, field):
+        return _lib.ulcms_spectrum_get_f64(self.handle, i, field)
+
+    def str(self, i, field):
+        ptr, n = c_void_p(), c_size_t()
+        rc = _lib.ulcms_spectrum_get_str(self.handle, i, field, byref(ptr), byref(n))
+        if rc != 0 or not ptr.value:
+            return None
+        return ctypes.string_at(ptr.value, n.value).decode("utf-8")
+
+    def array(self, i, which, widen):
+        # NumPy view over the buffer owned by the handle. The ctypes array
+        # keeps this object, and so the buffer, alive for as long as any view
+        # of it exists. With `widen`, float32 arrays are copied to float64.
+        a = _ArrayFFI()
+        rc = _lib.ulcms_spectrum_array(self.handle, i, which, byref(a))
+        if rc != 0 or a.element_type == ARRAY_NONE or not a.data:
+            return None
+        import numpy as np
+
+        ctype, dtype = (ctypes.c_float, np.float32) if a.element_type == ARRAY_F32 else (c_double, np.float64)
+        if a.len == 0:
+            return np.empty(0, dtype=np.float64 if widen else dtype)
+        buf = (ctype * a.len).from_address(a.data)
+        buf._owner = self
+        arr = np.frombuffer(buf, dtype=dtype)
+        if widen and dtype is np.float32:
+            arr = arr.astype(np.float64)
+        arr.flags.writeable = False
+        return arr
+
+
+class Spectrum:
+    """One spectrum. `mz`, `intensity` and `mobility` are read-only NumPy
+    arrays in the precision stored in the file, sharing memory with the
+    parser's buffers, or float64 copies when read with `widen=True`;
+    `mobility` is None unless the file has per-peak ion mobility."""
+
+    __slots__ = HEADER_COLUMNS + ("mz", "intensity", "mobility")
+
+    def __init__(self, file, i, arrays=True, widen=False):
+        for name, field in _F64_FIELDS.items():
+            v = file.f64(i, field)
+            v = None if v != v else v
+            setattr(self, name, int(v) if v is not None and name in _INT_FIELDS else v)
+        for name, field in _STR_FIELDS.items():
+            setattr(self, name, file.str(i, field))
+        for name, which in _ARRAYS:
+            setattr(self, name, file.array(i, which, widen) if arrays else None)
+
+    def __repr__(self):
+        return f"Spectrum(id={self.id!r}, ms_level={self.ms_level}, retention_time={self.retention_time})"
+
+
+class SpectrumList(list):
+    """List of `Spectrum` objects with a column-oriented header table."""
+
+    def header(self):
+        """Header fields as a dict of columns, ready for `pandas.DataFrame`."""
+        return {c: [getattr(s, c) for s in self] for c in HEADER_COLUMNS}
+
+    def to_pandas(self):
+        import pandas as pd
+
+        return pd.DataFrame(self.header())
+
+
+def _parse_options(ms_level=None, rt=None, polarity=None):
+    opts = _lib.ulcms_parse_options_default()
+    if ms_level is not None:
+        levels = [int(ms_level)] if isinstance(ms_level, int) else sorted({int(x) for x in ms_level})
+        if not levels:
+            raise ValueError("`ms_level` must hold at least one level")
+        buf = (c_uint32 * len(levels))(*levels)
+        # Keeps the levels alive for as long as the options are.
+        opts._levels = buf
+        opts.ms_levels = ctypes.cast(buf, POINTER(c_uint32))
+        opts.n_ms_levels = len(levels)
+    if rt is not None:
+        opts.rt_min, opts.rt_max = float(rt[0]), float(rt[1])
+    if polarity not in _POLARITIES:
+        raise ValueError('`polarity` must be None, "positive" or "negative"')
+    opts.polarity = _POLARITIES[polarity]
+    return opts
+
+
+def read_mzml(path, ms_level=None, rt=None, polarity=None, arrays=True, widen=False) -> SpectrumList:
+    """Parses an mzML file.
+
+    `ms_level` keeps one level or an iterable of levels, `rt` an inclusive
+    `(min, max)` retention time window in minutes, `polarity` either
+    "positive" or "negative". With `arrays=False` the spectra carry no
+    arrays and the parsed buffers are released immediately. Arrays keep the
+    precision stored in the file unless `widen=True`, which copies float32
+    arrays to float64. The filters are applied by the parser, so skipped
+    spectra are never decoded.
+    """
+    file = _File(path, _parse_options(ms_level, rt, polarity))
+    out = SpectrumList(Spectrum(file, i, arrays, widen) for i in range(len(file)))
+    if not arrays:
+        file.close()
+    return out
+
+
+class MzMLReader:
+    """Decodes the arrays of one spectrum at a time into float64 NumPy
+    arrays that Python owns, without parsing the whole file up front.
+
+    Passing the same `out` arrays to every `arrays()` call reuses them, so a
+    pass over a run allocates nothing once they are large enough.
+    """
+
+    def __init__(self, path):
+        handle = c_void_p()
+        rc = _lib.ulcms_reader_open(os.fsencode(path), byref(handle))
+        if rc != 0:
+            raise RuntimeError(f"ulcms_reader_open failed with code {rc}")
+        self._handle = handle
+        self._finalizer = weakref.finalize(self, _lib.ulcms_reader_free, handle)
+
+    def __len__(self):
+        return _lib.ulcms_reader_len(self._handle)
+
+    def close(self):
+        self._finalizer()
+
+    def __enter__(self):
+        return self
+
+    def __exit__(self, *exc):
+        self.close()
+
+    def array_length(self, index):
+        """Number of values in each array of spectrum `index`."""
+        if not self._finalizer.alive:
+            raise ValueError("reader is closed")
+        n = c_size_t()
+        rc = _lib.ulcms_reader_array_length(self._handle, index, byref(n))
+        if rc != 0:
+            raise IndexError(f"no spectrum {index}")
+        return n.value
+
+    def arrays(self, index, out=None):
+        """`(mz, intensity)` of spectrum `index`, as views of the first
+        values of `out` when given: a pair of contiguous float64 arrays of at
+        least `array_length(index)` values."""
+        import numpy as np
+
+        n = self.array_length(index)
+        if out is None:
+            mz, intensity = np.empty(n), np.empty(n)
+        else:
+            mz, intensity = out
+            for a in (mz, intensity):
+                if a.dtype != np.float64 or not a.flags.c_contiguous or not a.flags.writeable:
+                    raise TypeError("out arrays must be writeable, contiguous float64")
+                if a.size < n:
+                    raise ValueError(f"out arrays hold fewer than {n} values")
+        mz_len, int_len = c_size_t(), c_size_t()
+        rc = _lib.ulcms_reader_decode_into(
+            self._handle,
+            index,
+            mz.ctypes.data,
+            mz.size,
+            intensity.ctypes.data,
+            intensity.size,
+            byref(mz_len),
+            byref(int_len),
+        )
+        if rc == 5:
+            raise ValueError(f"out arrays hold fewer than {max(mz_len.value, int_len.value)} values")
+        if rc != 0:
+            raise RuntimeError(f"ulcms_reader_decode_into failed with code {rc}")
+        return mz[: mz_len.value], intensity[: int_len.value]
+
+
+class BinnedMatrix:
+    """Retention time by m/z intensity matrix from `bin_spectra()`.
+
+    `rt` holds the retention time of each row, `mz_edges` the bin edges
+    (column `c` covers `mz_edges[c]` to `mz_edges[c + 1]`) and `spectra`
+    the source spectrum of each row, or None on a resampled grid. `rt`,
+    `mz_edges` and the arrays of `csr()` are read-only views of the
+    library's buffers.
+    """
+
+    def __init__(self, handle):
+        self._handle = handle
+        self._finalizer = weakref.finalize(self, _lib.ulcms_binned_free, handle)
+        rows, cols, nnz = c_size_t(), c_size_t(), c_size_t()
+        _lib.ulcms_binned_shape(handle, byref(rows), byref(cols), byref(nnz))
+        self.shape = (rows.value, cols.value)
+        self.nnz = nnz.value
+        rt, edges = c_void_p(), c_void_p()
+        _lib.ulcms_binned_axes(handle, byref(rt), byref(edges))
+        self.rt = self._view(rt, c_double, rows.value)
+        self.mz_edges = self._view(edges, c_double, cols.value + 1)
+        no_spectrum = ctypes.c_size_t(-1).value
+        self.spectra = [_lib.ulcms_binned_row_spectrum(handle, r) for r in range(rows.value)]
+        self.spectra = [None if i == no_spectrum else i for i in self.spectra]
+
+    def _view(self, ptr, ctype, n):
+        import numpy as np
+
+        if n == 0 or not ptr.value:
+            return np.empty(0, dtype=np.dtype(ctype))
+        buf = (ctype * n).from_address(ptr.value)
+        buf._owner = self
+        arr = np.frombuffer(buf, dtype=np.dtype(ctype))
+        arr.flags.writeable = False
+        return arr
+
+    def csr(self):
+        """`(indptr, indices, values)` of the matrix in compressed sparse
+        row form, as read-only views: the columns and intensities of row
+        `r` are `indices[indptr[r]:indptr[r + 1]]` and the same slice of
+        `values`."""
+        indptr, indices, values = c_void_p(), c_void_p(), c_void_p()
+        _lib.ulcms_binned_csr(self._handle, byref(indptr), byref(indices), byref(values))
+        return (
+            self._view(indptr, c_size_t, self.shape[0] + 1),
+            self._view(indices, c_uint32, self.nnz),
+            self._view(values, ctypes.c_float, self.nnz),
+        )
+
+    def to_scipy(self):
+        """The matrix as a `scipy.sparse.csr_matrix` sharing the CSR arrays."""
+        from scipy.sparse import csr_matrix
+
+        indptr, indices, values = self.csr()
+        return csr_matrix((values, indices, indptr), shape=self.shape, copy=False)
+
+    def dense(self, column_major=False):
+        """The matrix as a new float32 NumPy array of `shape`, written by
+        the library in C or, with `column_major`, Fortran order."""
+        import numpy as np
+
+        out = np.empty(self.shape, dtype=np.float32, order="F" if column_major else "C")
+        rc = _lib.ulcms_binned_to_dense(self._handle, bool(column_major), out.ctypes.data, out.size)
+        if rc != 0:
+            raise RuntimeError(f"ulcms_binned_to_dense failed with code {rc}")
+        return out
+
+
+_BIN_UNITS = {"da": 0, "ppm": 1}
+_AGGREGATIONS = {"sum": 0, "max": 1}
+
+
+def bin_spectra(path, bin_width=1.0, unit="da", mz_range=None, aggregation="sum",
+                rt_step=0.0, rt=None, ms_level=1, polarity=None) -> BinnedMatrix:
+    """Bins the spectra of an mzML file into a retention time by m/z matrix.
+
+    `bin_width` is in Da or ppm as `unit` says; `mz_range` and `rt` are
+    `(min, max)` pairs, the range of the selected spectra when None. Bins
+    hold the sum or the maximum of their intensities as `aggregation` says.
+    With `rt_step` > 0 rows are resampled onto a regular retention time grid
+    of that spacing in minutes; otherwise there is one row per spectrum.
+    `ms_level` and `polarity` select the spectra used (None for all).
+    """
+    opts = _lib.ulcms_binning_options_default()
+    opts.bin_width = float(bin_width)
+    if unit not in _BIN_UNITS:
+        raise ValueError('`unit` must be "da" or "ppm"')
+    opts.bin_unit = _BIN_UNITS[unit]
+    if aggregation not in _AGGREGATIONS:
+        raise ValueError('`aggregation` must be "sum" or "max"')
+    opts.aggregation = _AGGREGATIONS[aggregation]
+    if mz_range is not None:
+        opts.mz_min, opts.mz_max = float(mz_range[0]), float(mz_range[1])
+    if rt is not None:
+        opts.rt_min, opts.rt_max = float(rt[0]), float(rt[1])
+    opts.rt_step = float(rt_step)
+    opts.ms_level = 0 if ms_level is None else int(ms_level)
+    if polarity not in _POLARITIES:
+        raise ValueError('`polarity` must be None, "positive" or "negative"')
+    opts.polarity = _POLARITIES[polarity]
+
+    file = _File(path)
+    try:
+        handle = c_void_p()
+        rc = _lib.ulcms_bin_spectra(file.handle, byref(opts), byref(handle))
+        if rc != 0:
+            raise RuntimeError(f"ulcms_bin_spectra failed with code {rc}")
+    finally:
+        file.close()
+    return BinnedMatrix(handle)
@@ -1,4 +1,11 @@
-from __init__ import add, sum_array
+import sys
+
+from __init__ import add, read_mzml, sum_array
 
 print("add(2, 40) =", add(2, 40))
 print("sum_array([1.0, 2.5, 3.5]) =", sum_array([1.0, 2.5, 3.5]))
+
+if len(sys.argv) > 1:
+    spectra = read_mzml(sys.argv[1], ms_level=1)
+    print(len(spectra), "MS1 spectra; first:", spectra[0])
+    print("m/z:", spectra[0].mz[:5])